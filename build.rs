@@ -0,0 +1,11 @@
+fn main() {
+    // Vendored protoc, so building doesn't need one installed
+    let protoc =
+        protoc_bin_vendored::protoc_bin_path().expect("No vendored protoc for this platform");
+    // SAFETY: build scripts are single-threaded
+    unsafe { std::env::set_var("PROTOC", protoc) };
+    tonic_build::configure()
+        .build_client(false)
+        .compile_protos(&["proto/orange.proto"], &["proto"])
+        .expect("Failed to compile proto/orange.proto");
+}
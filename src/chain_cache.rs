@@ -0,0 +1,163 @@
+use crate::config::{ChainCacheConfig, ChainSourceConfig};
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct Entry {
+    body: Bytes,
+    content_type: Option<HeaderValue>,
+    expires_at: Instant,
+}
+
+struct Proxy {
+    upstream: String,
+    client: reqwest::Client,
+    ttl: Duration,
+    immutable_ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+/// Starts a caching proxy for the esplora server in `chain_source` on a local port and returns
+/// its URL, to be used as the wallet's chain source instead
+pub async fn start(
+    chain_source: &ChainSourceConfig,
+    config: &ChainCacheConfig,
+) -> Result<String, String> {
+    if chain_source.source_type != "esplora" {
+        return Err("chain_cache only supports esplora chain sources".to_string());
+    }
+    let upstream = chain_source
+        .url
+        .as_deref()
+        .ok_or("esplora chain_source requires 'url'")?
+        .trim_end_matches('/')
+        .to_string();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| format!("Failed to start chain cache: {e}"))?;
+    let addr = listener
+        .local_addr()
+        .map_err(|e| format!("Failed to start chain cache: {e}"))?;
+
+    let proxy = Arc::new(Proxy {
+        upstream,
+        client: reqwest::Client::new(),
+        ttl: Duration::from_secs(config.ttl_secs),
+        immutable_ttl: Duration::from_secs(config.immutable_ttl_secs),
+        max_entries: config.max_entries,
+        entries: Mutex::new(HashMap::new()),
+    });
+    let app = Router::new().fallback(forward).with_state(proxy);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Chain cache stopped: {e}");
+        }
+    });
+
+    Ok(format!("http://{addr}"))
+}
+
+async fn forward(
+    State(proxy): State<Arc<Proxy>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+    // Broadcasts and other writes always go straight through
+    let ttl = (method == Method::GET).then(|| proxy.ttl_for(uri.path()));
+
+    if ttl.is_some()
+        && let Some(hit) = proxy.lookup(path)
+    {
+        return hit;
+    }
+
+    let mut request = proxy
+        .client
+        .request(method, format!("{}{path}", proxy.upstream))
+        .body(body);
+    for name in [AUTHORIZATION, CONTENT_TYPE] {
+        if let Some(value) = headers.get(&name) {
+            request = request.header(name, value);
+        }
+    }
+
+    let response = match request.send().await {
+        Ok(r) => r,
+        Err(e) => return (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+    };
+    let status = response.status();
+    let content_type = response.headers().get(CONTENT_TYPE).cloned();
+    let body = match response.bytes().await {
+        Ok(b) => b,
+        Err(e) => return (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+    };
+
+    if let Some(ttl) = ttl
+        && status == StatusCode::OK
+    {
+        proxy.insert(path, &body, content_type.clone(), ttl);
+    }
+    build_response(status, body, content_type)
+}
+
+impl Proxy {
+    fn ttl_for(&self, path: &str) -> Duration {
+        let immutable = path.starts_with("/block/")
+            || (path.starts_with("/tx/") && (path.ends_with("/hex") || path.ends_with("/raw")));
+        if immutable {
+            self.immutable_ttl
+        } else {
+            self.ttl
+        }
+    }
+
+    fn lookup(&self, key: &str) -> Option<Response> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        (entry.expires_at > Instant::now()).then(|| {
+            build_response(
+                StatusCode::OK,
+                entry.body.clone(),
+                entry.content_type.clone(),
+            )
+        })
+    }
+
+    fn insert(&self, key: &str, body: &Bytes, content_type: Option<HeaderValue>, ttl: Duration) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries {
+            entries.retain(|_, e| e.expires_at > now);
+            if entries.len() >= self.max_entries {
+                entries.clear();
+            }
+        }
+        entries.insert(
+            key.to_string(),
+            Entry {
+                body: body.clone(),
+                content_type,
+                expires_at: now + ttl,
+            },
+        );
+    }
+}
+
+fn build_response(status: StatusCode, body: Bytes, content_type: Option<HeaderValue>) -> Response {
+    let mut response = (status, body).into_response();
+    if let Some(content_type) = content_type {
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+    }
+    response
+}
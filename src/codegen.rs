@@ -0,0 +1,608 @@
+use crate::schema;
+use clap::ArgAction;
+use serde_json::{Value, json};
+use std::fmt::Write;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Language {
+    /// `orange.ts`, for Node.js
+    Typescript,
+    /// `orange.py`, for Python 3.11 or later
+    Python,
+}
+
+impl Language {
+    fn as_str(self) -> &'static str {
+        match self {
+            Language::Typescript => "typescript",
+            Language::Python => "python",
+        }
+    }
+}
+
+/// A command of the generated client: the words typed after `orange`, its arguments from the
+/// CLI definition and its output schema
+struct Method {
+    words: Vec<String>,
+    positionals: Vec<Param>,
+    options: Vec<Param>,
+    output: Value,
+}
+
+struct Param {
+    /// Argument name in snake_case
+    name: String,
+    /// `--name` of an option, none for positionals
+    flag: Option<String>,
+    required: bool,
+    kind: Kind,
+}
+
+enum Kind {
+    Value,
+    Values,
+    Switch,
+}
+
+/// Writes a client for every command with an output schema into `out`. The client runs the
+/// `orange` binary, which passes commands to a running daemon by itself.
+pub fn write(language: Language, cli: &clap::Command, out: &Path) -> Result<Value, String> {
+    let methods = methods(cli)?;
+    let (file, source) = match language {
+        Language::Typescript => ("orange.ts", typescript(&methods)),
+        Language::Python => ("orange.py", python(&methods)),
+    };
+    std::fs::create_dir_all(out).map_err(|e| format!("Failed to create {}: {e}", out.display()))?;
+    let path = out.join(file);
+    std::fs::write(&path, source)
+        .map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+    Ok(json!({
+        "language": language.as_str(),
+        "files": [path.display().to_string()],
+        "commands": methods.len(),
+    }))
+}
+
+fn methods(cli: &clap::Command) -> Result<Vec<Method>, String> {
+    schema::COMMANDS
+        .iter()
+        .filter(|command| !matches!(**command, "error" | "codegen"))
+        .map(|command| {
+            let words: Vec<String> = command.split(' ').map(String::from).collect();
+            let mut subcommand = cli;
+            for word in &words {
+                subcommand = subcommand
+                    .find_subcommand(word)
+                    .ok_or_else(|| format!("No command {command} for its schema"))?;
+            }
+            let (positionals, options): (Vec<Param>, Vec<Param>) = subcommand
+                .get_arguments()
+                .filter(|arg| {
+                    !arg.is_global_set() && !matches!(arg.get_id().as_str(), "help" | "version")
+                })
+                .map(param)
+                .partition(|param| param.flag.is_none());
+            Ok(Method {
+                words,
+                positionals,
+                options,
+                output: schema::output_schema(command)?,
+            })
+        })
+        .collect()
+}
+
+fn param(arg: &clap::Arg) -> Param {
+    let kind = match arg.get_action() {
+        ArgAction::SetTrue => Kind::Switch,
+        ArgAction::Append => Kind::Values,
+        _ => Kind::Value,
+    };
+    let flag = if arg.is_positional() {
+        None
+    } else {
+        arg.get_long().map(|long| format!("--{long}"))
+    };
+    Param {
+        name: arg.get_id().as_str().replace('-', "_"),
+        flag,
+        required: arg.is_required_set() && arg.get_default_values().is_empty(),
+        kind,
+    }
+}
+
+/// Type name of a command's output, e.g. `WebhooksDeadLetterList`
+fn type_name(method: &Method) -> String {
+    pascal(&method.words.join(" "))
+}
+
+fn pascal(name: &str) -> String {
+    name.split([' ', '-', '_'])
+        .filter(|word| !word.is_empty())
+        .map(|word| word[..1].to_uppercase() + &word[1..])
+        .collect()
+}
+
+fn camel(name: &str) -> String {
+    let pascal = pascal(name);
+    match pascal.chars().next() {
+        Some(first) => first.to_lowercase().collect::<String>() + &pascal[first.len_utf8()..],
+        None => pascal,
+    }
+}
+
+fn snake(name: &str) -> String {
+    name.replace([' ', '-'], "_")
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn required(schema: &Value) -> Vec<&str> {
+    schema["required"]
+        .as_array()
+        .map(|names| names.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+/// The JSON types of a schema, e.g. `["integer", "null"]`
+fn types(schema: &Value) -> Vec<&str> {
+    match &schema["type"] {
+        Value::String(name) => vec![name.as_str()],
+        Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+const TS_HEADER: &str = r#"// Typed client for the orange wallet CLI.
+//
+// Generated by `orange codegen typescript`, do not edit. Each method runs `orange` with the
+// command's arguments and resolves with its parsed JSON output. While `orange daemon` runs, the
+// CLI passes the command to it, so calls don't start the wallet.
+
+import { execFile } from "node:child_process";
+
+"#;
+
+const TS_CLIENT: &str = r#"export interface OrangeOptions {
+  /** Path of the orange binary, "orange" on the PATH by default */
+  binary?: string;
+  config?: string;
+  network?: string;
+  tz?: string;
+  replica?: boolean;
+}
+
+/** An error printed by `orange`, or its failure to run */
+export class OrangeError extends Error {}
+
+export class Orange {
+  constructor(private readonly options: OrangeOptions = {}) {}
+
+  private run<T>(args: string[]): Promise<T> {
+    const base: string[] = [];
+    if (this.options.config !== undefined) base.push("--config", this.options.config);
+    if (this.options.network !== undefined) base.push("--network", this.options.network);
+    if (this.options.tz !== undefined) base.push("--tz", this.options.tz);
+    if (this.options.replica) base.push("--replica");
+    const binary = this.options.binary ?? "orange";
+    return new Promise((resolve, reject) => {
+      const callback = (error: Error | null, stdout: string, stderr: string) => {
+        let value: any;
+        try {
+          value = JSON.parse(stdout);
+        } catch {
+          const message = stderr || error?.message || "orange printed no output";
+          reject(new OrangeError(message.trim()));
+          return;
+        }
+        if (error) {
+          reject(new OrangeError(value?.error ?? stderr.trim()));
+        } else {
+          resolve(value as T);
+        }
+      };
+      execFile(binary, [...base, ...args], { maxBuffer: 64 * 1024 * 1024 }, callback);
+    });
+  }
+"#;
+
+fn typescript(methods: &[Method]) -> String {
+    let mut out = String::from(TS_HEADER);
+    for method in methods {
+        let _ = writeln!(
+            out,
+            "export type {} = {};\n",
+            type_name(method),
+            ts_type(&method.output, 0)
+        );
+    }
+    out.push_str(TS_CLIENT);
+    for method in methods {
+        ts_method(&mut out, method);
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn ts_type(schema: &Value, indent: usize) -> String {
+    if let Some(variants) = schema["oneOf"].as_array() {
+        let variants: Vec<String> = variants.iter().map(|v| ts_type(v, indent)).collect();
+        return variants.join(" | ");
+    }
+    let types: Vec<String> = types(schema)
+        .into_iter()
+        .map(|name| match name {
+            "integer" | "number" => "number".to_string(),
+            "string" => "string".to_string(),
+            "boolean" => "boolean".to_string(),
+            "null" => "null".to_string(),
+            "array" => format!("Array<{}>", ts_type(&schema["items"], indent)),
+            "object" => ts_object(schema, indent),
+            _ => "unknown".to_string(),
+        })
+        .collect();
+    if types.is_empty() {
+        return "unknown".to_string();
+    }
+    types.join(" | ")
+}
+
+fn ts_object(schema: &Value, indent: usize) -> String {
+    let Some(properties) = schema["properties"].as_object() else {
+        return match &schema["additionalProperties"] {
+            Value::Object(_) => format!(
+                "Record<string, {}>",
+                ts_type(&schema["additionalProperties"], indent)
+            ),
+            _ => "Record<string, unknown>".to_string(),
+        };
+    };
+    let required = required(schema);
+    let pad = "  ".repeat(indent + 1);
+    let mut out = String::from("{\n");
+    for (name, property) in properties {
+        let key = if is_identifier(name) {
+            name.clone()
+        } else {
+            json!(name).to_string()
+        };
+        let optional = if required.contains(&name.as_str()) {
+            ""
+        } else {
+            "?"
+        };
+        let _ = writeln!(
+            out,
+            "{pad}{key}{optional}: {};",
+            ts_type(property, indent + 1)
+        );
+    }
+    if schema["additionalProperties"] == true {
+        let _ = writeln!(out, "{pad}[key: string]: unknown;");
+    }
+    out.push_str(&"  ".repeat(indent));
+    out.push('}');
+    out
+}
+
+fn ts_param_type(param: &Param) -> &'static str {
+    match param.kind {
+        Kind::Value => "string | number",
+        Kind::Values => "Array<string | number>",
+        Kind::Switch => "boolean",
+    }
+}
+
+/// Required positionals become parameters, everything else goes in an `options` object
+fn ts_method(out: &mut String, method: &Method) {
+    let (positionals, optional): (Vec<&Param>, Vec<&Param>) =
+        method.positionals.iter().partition(|param| param.required);
+    let optional: Vec<&Param> = optional.into_iter().chain(&method.options).collect();
+
+    let mut params: Vec<String> = positionals
+        .iter()
+        .map(|param| format!("{}: {}", camel(&param.name), ts_param_type(param)))
+        .collect();
+    if !optional.is_empty() {
+        let fields: Vec<String> = optional
+            .iter()
+            .map(|param| {
+                let mark = if param.required { "" } else { "?" };
+                format!("{}{mark}: {}", camel(&param.name), ts_param_type(param))
+            })
+            .collect();
+        let default = if optional.iter().any(|param| param.required) {
+            ""
+        } else {
+            " = {}"
+        };
+        params.push(format!("options: {{ {} }}{default}", fields.join("; ")));
+    }
+
+    let words: Vec<String> = method.words.iter().map(|w| json!(w).to_string()).collect();
+    let _ = writeln!(
+        out,
+        "\n  {}({}): Promise<{}> {{",
+        camel(&method.words.join(" ")),
+        params.join(", "),
+        type_name(method)
+    );
+    let _ = writeln!(out, "    const args: string[] = [{}];", words.join(", "));
+    for param in &positionals {
+        let name = camel(&param.name);
+        match param.kind {
+            Kind::Values => {
+                let _ = writeln!(out, "    args.push(...{name}.map(String));");
+            }
+            _ => {
+                let _ = writeln!(out, "    args.push(String({name}));");
+            }
+        }
+    }
+    for param in &optional {
+        let name = format!("options.{}", camel(&param.name));
+        let _ = match (&param.kind, &param.flag) {
+            (Kind::Switch, Some(flag)) => writeln!(out, "    if ({name}) args.push(\"{flag}\");"),
+            (Kind::Values, Some(flag)) => writeln!(
+                out,
+                "    for (const value of {name} ?? []) args.push(\"{flag}\", String(value));"
+            ),
+            (_, Some(flag)) => writeln!(
+                out,
+                "    if ({name} !== undefined) args.push(\"{flag}\", String({name}));"
+            ),
+            (Kind::Values, None) => {
+                writeln!(out, "    args.push(...({name} ?? []).map(String));")
+            }
+            (_, None) => writeln!(
+                out,
+                "    if ({name} !== undefined) args.push(String({name}));"
+            ),
+        };
+    }
+    let _ = writeln!(out, "    return this.run(args);\n  }}");
+}
+
+const PY_HEADER: &str = r#""""Typed client for the orange wallet CLI.
+
+Generated by `orange codegen python`, do not edit. Each method runs `orange` with the command's
+arguments and returns its parsed JSON output. While `orange daemon` runs, the CLI passes the
+command to it, so calls don't start the wallet.
+"""
+
+from __future__ import annotations
+
+import json
+import subprocess
+from typing import Any, NotRequired, TypedDict
+"#;
+
+const PY_CLIENT: &str = r#"class OrangeError(Exception):
+    """An error printed by `orange`, or its failure to run"""
+
+
+class Orange:
+    def __init__(
+        self,
+        binary: str = "orange",
+        config: str | None = None,
+        network: str | None = None,
+        tz: str | None = None,
+        replica: bool = False,
+    ) -> None:
+        self.binary = binary
+        self.base_args: list[str] = []
+        if config is not None:
+            self.base_args += ["--config", config]
+        if network is not None:
+            self.base_args += ["--network", network]
+        if tz is not None:
+            self.base_args += ["--tz", tz]
+        if replica:
+            self.base_args.append("--replica")
+
+    def _run(self, args: list[str]) -> Any:
+        proc = subprocess.run(
+            [self.binary, *self.base_args, *args], capture_output=True, text=True
+        )
+        try:
+            value = json.loads(proc.stdout)
+        except json.JSONDecodeError:
+            raise OrangeError(
+                proc.stderr.strip() or f"orange exited with status {proc.returncode}"
+            ) from None
+        if proc.returncode != 0:
+            raise OrangeError(value.get("error", proc.stderr.strip()))
+        return value
+"#;
+
+const PYTHON_KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue",
+    "def", "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import",
+    "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while",
+    "with", "yield",
+];
+
+fn python(methods: &[Method]) -> String {
+    let mut classes = Vec::new();
+    for method in methods {
+        let name = type_name(method);
+        let output = py_type(&method.output, &name, &mut classes);
+        if output != name {
+            classes.push(format!("{name} = {output}\n"));
+        }
+    }
+    let mut out = String::from(PY_HEADER);
+    for class in classes {
+        let _ = write!(out, "\n\n{class}");
+    }
+    let _ = write!(out, "\n\n{PY_CLIENT}");
+    for method in methods {
+        py_method(&mut out, method);
+    }
+    out
+}
+
+/// Python type of `schema`, adding a TypedDict called `name` (or derived from it) to `classes`
+/// for every object, nested ones first
+fn py_type(schema: &Value, name: &str, classes: &mut Vec<String>) -> String {
+    if let Some(variants) = schema["oneOf"].as_array() {
+        let variants: Vec<String> = variants
+            .iter()
+            .enumerate()
+            .map(|(i, v)| py_type(v, &format!("{name}{}", i + 1), classes))
+            .collect();
+        return variants.join(" | ");
+    }
+    let types: Vec<String> = types(schema)
+        .into_iter()
+        .map(|type_name| match type_name {
+            "integer" => "int".to_string(),
+            "number" => "float".to_string(),
+            "string" => "str".to_string(),
+            "boolean" => "bool".to_string(),
+            "null" => "None".to_string(),
+            "array" => format!("list[{}]", py_type(&schema["items"], name, classes)),
+            "object" => py_object(schema, name, classes),
+            _ => "Any".to_string(),
+        })
+        .collect();
+    if types.is_empty() {
+        return "Any".to_string();
+    }
+    types.join(" | ")
+}
+
+fn py_object(schema: &Value, name: &str, classes: &mut Vec<String>) -> String {
+    let Some(properties) = schema["properties"].as_object() else {
+        return match &schema["additionalProperties"] {
+            Value::Object(_) => format!(
+                "dict[str, {}]",
+                py_type(&schema["additionalProperties"], name, classes)
+            ),
+            _ => "dict[str, Any]".to_string(),
+        };
+    };
+    let required = required(schema);
+    let fields: Vec<(&String, String)> = properties
+        .iter()
+        .map(|(field, property)| {
+            let field_type = py_type(property, &format!("{name}{}", pascal(field)), classes);
+            if required.contains(&field.as_str()) {
+                (field, field_type)
+            } else {
+                (field, format!("NotRequired[{field_type}]"))
+            }
+        })
+        .collect();
+
+    // Keys that aren't valid attribute names need the functional syntax
+    let mut class = String::new();
+    if fields
+        .iter()
+        .all(|(field, _)| is_identifier(field) && !PYTHON_KEYWORDS.contains(&field.as_str()))
+    {
+        let _ = writeln!(class, "class {name}(TypedDict):");
+        if fields.is_empty() {
+            let _ = writeln!(class, "    pass");
+        }
+        for (field, field_type) in &fields {
+            let _ = writeln!(class, "    {field}: {field_type}");
+        }
+    } else {
+        let _ = writeln!(class, "{name} = TypedDict(\n    \"{name}\",\n    {{");
+        for (field, field_type) in &fields {
+            let _ = writeln!(class, "        {}: {field_type},", json!(field));
+        }
+        let _ = writeln!(class, "    }},\n)");
+    }
+    classes.push(class);
+    name.to_string()
+}
+
+fn py_param_name(param: &Param) -> String {
+    if PYTHON_KEYWORDS.contains(&param.name.as_str()) {
+        format!("{}_", param.name)
+    } else {
+        param.name.clone()
+    }
+}
+
+/// Required positionals are positional parameters, everything else keyword-only
+fn py_method(out: &mut String, method: &Method) {
+    let (positionals, optional): (Vec<&Param>, Vec<&Param>) =
+        method.positionals.iter().partition(|param| param.required);
+    let optional: Vec<&Param> = optional.into_iter().chain(&method.options).collect();
+
+    let mut params = vec!["self".to_string()];
+    for param in &positionals {
+        let param_type = match param.kind {
+            Kind::Values => "list[str | int]",
+            _ => "str | int",
+        };
+        params.push(format!("{}: {param_type}", py_param_name(param)));
+    }
+    if !optional.is_empty() {
+        params.push("*".to_string());
+    }
+    for param in &optional {
+        let name = py_param_name(param);
+        params.push(match (&param.kind, param.required) {
+            (Kind::Switch, _) => format!("{name}: bool = False"),
+            (Kind::Values, true) => format!("{name}: list[str | int]"),
+            (Kind::Values, false) => format!("{name}: list[str | int] | None = None"),
+            (Kind::Value, true) => format!("{name}: str | int"),
+            (Kind::Value, false) => format!("{name}: str | int | None = None"),
+        });
+    }
+
+    let words: Vec<String> = method.words.iter().map(|w| json!(w).to_string()).collect();
+    let _ = writeln!(
+        out,
+        "\n    def {}({}) -> {}:",
+        snake(&method.words.join(" ")),
+        params.join(", "),
+        type_name(method)
+    );
+    let _ = writeln!(out, "        args = [{}]", words.join(", "));
+    for param in &positionals {
+        let name = py_param_name(param);
+        let _ = match param.kind {
+            Kind::Values => writeln!(out, "        args += [str(v) for v in {name}]"),
+            _ => writeln!(out, "        args.append(str({name}))"),
+        };
+    }
+    for param in &optional {
+        let name = py_param_name(param);
+        let _ = match (&param.kind, &param.flag) {
+            (Kind::Switch, Some(flag)) => {
+                writeln!(
+                    out,
+                    "        if {name}:\n            args.append(\"{flag}\")"
+                )
+            }
+            (Kind::Values, Some(flag)) => writeln!(
+                out,
+                "        for v in {name} or []:\n            args += [\"{flag}\", str(v)]"
+            ),
+            (_, Some(flag)) => writeln!(
+                out,
+                "        if {name} is not None:\n            args += [\"{flag}\", str({name})]"
+            ),
+            (Kind::Values, None) => {
+                writeln!(out, "        args += [str(v) for v in {name} or []]")
+            }
+            (_, None) => writeln!(
+                out,
+                "        if {name} is not None:\n            args.append(str({name}))"
+            ),
+        };
+    }
+    let _ = writeln!(out, "        return self._run(args)");
+}
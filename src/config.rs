@@ -1,9 +1,13 @@
+use crate::log;
 use orange_sdk::bitcoin::Network;
+use orange_sdk::bitcoin::hashes::{Hash, sha256};
+use orange_sdk::bitcoin::hex::DisplayHex;
 use orange_sdk::{
     ChainSource, ExtraConfig, LoggerType, Mnemonic, Seed, SparkWalletConfig, StorageConfig,
     Tunables, WalletConfig,
 };
 use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 use std::str::FromStr;
 
@@ -11,10 +15,86 @@ use std::str::FromStr;
 pub struct Config {
     pub network: String,
     pub storage_path: String,
+    /// Open a decoy wallet, with a passphrase derived from the seed, when `ORANGE_PASSPHRASE`
+    /// is not set
+    #[serde(default)]
+    pub duress_wallet: bool,
+    /// Plaintext decoy passphrase, still read for decoy wallets made before `duress_wallet`
+    pub duress_passphrase: Option<String>,
+    /// IANA time zone of the `*_iso` timestamps in command output
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(default)]
+    pub mode: Mode,
     pub chain_source: ChainSourceConfig,
     pub lsp: LspConfig,
     #[serde(default)]
     pub spark: SparkConfig,
+    #[serde(default)]
+    pub receive: ReceiveConfig,
+    #[serde(default)]
+    pub receive_filter: ReceiveFilterConfig,
+    #[serde(default)]
+    pub fiat: FiatConfig,
+    #[serde(default)]
+    pub chain_cache: ChainCacheConfig,
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+    #[serde(default)]
+    pub stats: StatsConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    #[serde(default)]
+    pub authorizations: AuthorizationsConfig,
+    #[serde(default)]
+    pub serve: ServeConfig,
+    #[serde(default)]
+    pub faucet: FaucetConfig,
+    #[serde(default)]
+    pub mcp: McpConfig,
+    #[serde(default)]
+    pub privacy: PrivacyConfig,
+    /// Local commands the daemon runs on events, `[[exec_hooks]]`
+    #[serde(default)]
+    pub exec_hooks: Vec<ExecHookConfig>,
+    /// Long-running processes the daemon streams events to, `[[plugins]]`
+    #[serde(default)]
+    pub plugins: Vec<PluginConfig>,
+    pub dead_man_switch: Option<DeadManSwitchConfig>,
+    pub smtp: Option<SmtpConfig>,
+    pub mqtt: Option<MqttConfig>,
+    pub nwc: Option<NwcConfig>,
+    pub nostr: Option<NostrConfig>,
+    pub zaps: Option<ZapsConfig>,
+    pub lnurl: Option<LnurlConfig>,
+    /// Per-network overrides selected with `--network`
+    #[serde(default)]
+    pub profiles: HashMap<String, ProfileConfig>,
+    /// Passphrase supplied through `orange unlock`, takes precedence over everything else
+    #[serde(skip)]
+    pub unlocked_passphrase: Option<String>,
+}
+
+/// What this process may do with the wallet
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    #[default]
+    Full,
+    /// Issue invoices and report payments, but never pay anything, e.g. on a public invoice
+    /// server
+    ReceiveOnly,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProfileConfig {
+    /// Defaults to `{storage_path}/{network}`
+    pub storage_path: Option<String>,
+    pub chain_source: Option<ChainSourceConfig>,
+    pub lsp: Option<LspConfig>,
+    pub spark: Option<SparkConfig>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -54,18 +134,887 @@ impl Default for SparkConfig {
     }
 }
 
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
 fn default_sync_interval() -> u32 {
     60
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ReceiveConfig {
+    /// `receive --meta order_id=...` returns the order's open invoice instead of issuing another
+    #[serde(default = "default_unique_order_id")]
+    pub unique_order_id: bool,
+}
+
+impl Default for ReceiveConfig {
+    fn default() -> Self {
+        ReceiveConfig {
+            unique_order_id: default_unique_order_id(),
+        }
+    }
+}
+
+fn default_unique_order_id() -> bool {
+    true
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReceiveFilterConfig {
+    /// Incoming payments below this amount are treated as dust
+    #[serde(default)]
+    pub min_amount_sats: u64,
+    #[serde(default)]
+    pub dust_action: DustAction,
+    /// Most an LSP may take for opening a channel to deliver a payment before the daemon
+    /// posts `lsp_fee_exceeded`
+    pub max_lsp_fee_msats: Option<u64>,
+}
+
+/// What the daemon does with an incoming payment below `min_amount_sats`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DustAction {
+    /// Deliver the event with `"dust": true` added
+    #[default]
+    Flag,
+    /// Acknowledge the event without delivering it
+    Ignore,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FiatConfig {
+    /// Currency code looked up in the price source response
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    /// URL returning a JSON object of BTC prices keyed by currency code
+    #[serde(default = "default_price_url")]
+    pub price_url: String,
+    /// The daemon emits a `fiat_drift` event when drift exceeds this percentage
+    pub drift_alert_percent: Option<f64>,
+    #[serde(default = "default_drift_check_interval")]
+    pub drift_check_interval_secs: u64,
+}
+
+impl Default for FiatConfig {
+    fn default() -> Self {
+        FiatConfig {
+            currency: default_currency(),
+            price_url: default_price_url(),
+            drift_alert_percent: None,
+            drift_check_interval_secs: default_drift_check_interval(),
+        }
+    }
+}
+
+fn default_currency() -> String {
+    "USD".to_string()
+}
+
+fn default_price_url() -> String {
+    "https://mempool.space/api/v1/prices".to_string()
+}
+
+fn default_drift_check_interval() -> u64 {
+    3600
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebhooksConfig {
+    /// Endpoints posted to alongside any `--webhook` flags, reloaded by the daemon on SIGHUP
+    #[serde(default)]
+    pub endpoints: Vec<WebhookEndpointConfig>,
+    /// Stop posting to an endpoint that has failed continuously for this long, 0 to never disable
+    #[serde(default = "default_disable_after_hours")]
+    pub disable_after_hours: u64,
+    /// Delay before the first retry of a failed delivery, doubled on every further failure
+    #[serde(default = "default_retry_base")]
+    pub retry_base_secs: u64,
+    #[serde(default = "default_retry_max")]
+    pub retry_max_secs: u64,
+    /// Failed attempts after which a delivery moves to the dead-letter queue, 0 to retry forever
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// How long `webhooks deliveries` history is kept
+    #[serde(default = "default_delivery_log_days")]
+    pub delivery_log_days: u64,
+    /// A POST that hasn't got a response by then counts as a failed attempt
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout_secs: u64,
+    /// POSTs in flight per endpoint, further deliveries wait in the outbox
+    #[serde(default = "default_max_concurrent")]
+    pub max_concurrent: usize,
+    /// When the daemon acknowledges a wallet event it posted
+    #[serde(default)]
+    pub ack_policy: AckPolicy,
+    /// Coalesce events posted within this window into one POST of a JSON array, 0 to post each
+    /// event on its own
+    #[serde(default)]
+    pub batch_window_ms: u64,
+    /// A batch is posted right away once it holds this many events
+    #[serde(default = "default_batch_max_events")]
+    pub batch_max_events: usize,
+    /// POSTs started per second per endpoint, further deliveries wait in the outbox. 0 for no
+    /// limit.
+    #[serde(default)]
+    pub max_per_second: f64,
+    /// Severity of event types, overriding the defaults (e.g. `payment_failed = "critical"`)
+    #[serde(default)]
+    pub severity: HashMap<String, Severity>,
+}
+
+/// How urgent an event is, set as its `severity`. Endpoints with a `min_severity` only get
+/// events at least that urgent.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+/// When the daemon marks a wallet event handled, taking it off the SDK's queue
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AckPolicy {
+    /// Once its deliveries are written to the outbox, which retries them
+    #[default]
+    Outbox,
+    /// Once at least one webhook returned 2xx
+    AtLeastOnce,
+    /// Once every webhook returned 2xx
+    All,
+}
+
+/// A webhook in `[webhooks] endpoints`, either in the `--webhook` format ("url", "url|token"
+/// or "url|token|secret") or as a table for endpoints with stricter auth
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum WebhookEndpointConfig {
+    Spec(String),
+    Table(WebhookTableConfig),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookTableConfig {
+    pub url: String,
+    /// Sent as `Authorization: Bearer <token>`
+    pub token: Option<String>,
+    /// Key for the `X-Orange-Signature` HMAC
+    pub secret: Option<String>,
+    /// Static headers added to every POST
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub basic_auth: Option<BasicAuthConfig>,
+    /// PEM client certificate presented for mutual TLS, together with `client_key`
+    pub client_cert: Option<PathBuf>,
+    /// PEM private key of `client_cert`
+    pub client_key: Option<PathBuf>,
+    /// Overrides `[webhooks] max_per_second` for this endpoint
+    pub max_per_second: Option<f64>,
+    /// Less urgent events aren't posted here, e.g. "critical" for a pager
+    #[serde(default)]
+    pub min_severity: Severity,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BasicAuthConfig {
+    pub username: String,
+    pub password: Option<String>,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        WebhooksConfig {
+            endpoints: Vec::new(),
+            disable_after_hours: default_disable_after_hours(),
+            retry_base_secs: default_retry_base(),
+            retry_max_secs: default_retry_max(),
+            max_attempts: default_max_attempts(),
+            delivery_log_days: default_delivery_log_days(),
+            timeout_secs: default_webhook_timeout(),
+            max_concurrent: default_max_concurrent(),
+            ack_policy: AckPolicy::default(),
+            batch_window_ms: 0,
+            batch_max_events: default_batch_max_events(),
+            max_per_second: 0.0,
+            severity: HashMap::new(),
+        }
+    }
+}
+
+fn default_disable_after_hours() -> u64 {
+    24
+}
+
+fn default_retry_base() -> u64 {
+    5
+}
+
+fn default_retry_max() -> u64 {
+    3600
+}
+
+fn default_max_attempts() -> u32 {
+    30
+}
+
+fn default_delivery_log_days() -> u64 {
+    30
+}
+
+fn default_webhook_timeout() -> u64 {
+    30
+}
+
+fn default_max_concurrent() -> usize {
+    8
+}
+
+fn default_batch_max_events() -> usize {
+    100
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventsConfig {
+    /// How long posted events are kept for `events replay`, 0 to keep them forever
+    #[serde(default = "default_history_days")]
+    pub history_days: u64,
+    /// Most events the daemon holds for `get-events` when no webhooks are configured, 0 for
+    /// no limit
+    #[serde(default)]
+    pub max_queued: u64,
+    /// Held events older than this are dropped, 0 to keep them until acknowledged
+    #[serde(default)]
+    pub max_age_hours: u64,
+    #[serde(default)]
+    pub overflow: Overflow,
+}
+
+impl EventsConfig {
+    /// Whether the daemon should take events off the wallet queue to enforce the limits
+    pub fn limits_queue(&self) -> bool {
+        self.max_queued > 0 || self.max_age_hours > 0
+    }
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig {
+            history_days: default_history_days(),
+            max_queued: 0,
+            max_age_hours: 0,
+            overflow: Overflow::default(),
+        }
+    }
+}
+
+/// What happens to events arriving while `max_queued` events are held
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overflow {
+    /// Queue the new event and drop the oldest held one
+    #[default]
+    DropOldest,
+    /// Don't queue the new event
+    DropNewest,
+    /// Leave new events in the wallet's own queue until there is room
+    Block,
+}
+
+impl Overflow {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Overflow::DropOldest => "drop-oldest",
+            Overflow::DropNewest => "drop-newest",
+            Overflow::Block => "block",
+        }
+    }
+}
+
+fn default_history_days() -> u64 {
+    90
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DaemonConfig {
+    /// How long shutdown waits for webhook deliveries and in-flight payments to finish
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout_secs: u64,
+    /// Overridden by `daemon --log-format`
+    #[serde(default)]
+    pub log_format: log::Format,
+    /// Overridden by `daemon --log-level`
+    #[serde(default)]
+    pub log_level: log::Level,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        DaemonConfig {
+            drain_timeout_secs: default_drain_timeout(),
+            log_format: log::Format::default(),
+            log_level: log::Level::default(),
+        }
+    }
+}
+
+fn default_drain_timeout() -> u64 {
+    30
+}
+
+/// Local timing of wallet commands, shown by `orange stats`. Nothing leaves the machine.
+#[derive(Debug, Default, Deserialize)]
+pub struct StatsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// The HTTP API of `orange serve` and the gRPC API of `orange grpc`
+#[derive(Debug, Default, Deserialize)]
+pub struct ServeConfig {
+    /// Bearer token every request must carry, required by `serve` and `grpc`
+    pub token: Option<String>,
+}
+
+/// Guardrails of the tools `orange mcp` hands to agents
+#[derive(Debug, Default, Deserialize)]
+pub struct McpConfig {
+    /// Most a single `pay` call may spend, in satoshis. 0, the default, leaves `pay` out.
+    #[serde(default)]
+    pub max_spend_sats: u64,
+}
+
+/// Nostr relays the daemon answers Nostr Wallet Connect requests on
+#[derive(Debug, Deserialize)]
+pub struct NwcConfig {
+    /// Put in every connection string made by `nwc create`, so changing them means new ones
+    pub relays: Vec<String>,
+}
+
+/// The wallet's own nostr identity, separate from the keys derived for NWC
+#[derive(Debug, Deserialize)]
+pub struct NostrConfig {
+    /// nsec or hex. Its public key is the `nostrPubkey` a lightning address advertises for zaps.
+    pub secret_key: String,
+}
+
+/// Publishing NIP-57 zap receipts for zapped payments, signed with the `[nostr]` key
+#[derive(Debug, Default, Deserialize)]
+pub struct ZapsConfig {
+    /// Also sent every receipt, on top of the relays the zap request lists
+    #[serde(default)]
+    pub relays: Vec<String>,
+}
+
+/// Lightning addresses on your own domain, served by `orange lnurl-server`
+#[derive(Debug, Clone, Deserialize)]
+pub struct LnurlConfig {
+    /// Host of the addresses, `name@domain`. Wallets call back to `https://{domain}`.
+    pub domain: String,
+    /// Names served, any valid name when empty
+    #[serde(default)]
+    pub names: Vec<String>,
+    /// Shown by the payer's wallet, "Payment to name@domain" by default
+    pub description: Option<String>,
+    #[serde(default = "default_lnurl_min_sats")]
+    pub min_sats: u64,
+    #[serde(default = "default_lnurl_max_sats")]
+    pub max_sats: u64,
+    /// Longest comment a payer may send (LUD-12), 0 for none
+    #[serde(default = "default_lnurl_comment_allowed")]
+    pub comment_allowed: u16,
+    /// Invoices one client address may request per minute, 0 for no limit
+    #[serde(default = "default_lnurl_ip_invoices_per_minute")]
+    pub ip_invoices_per_minute: u32,
+    /// Invoices one name may hand out per minute, 0 for no limit
+    #[serde(default = "default_lnurl_name_invoices_per_minute")]
+    pub name_invoices_per_minute: u32,
+    /// Take the client address from `X-Forwarded-For`, set when behind a reverse proxy that
+    /// sets it. Otherwise every request has the proxy's address.
+    #[serde(default)]
+    pub behind_proxy: bool,
+}
+
+fn default_lnurl_min_sats() -> u64 {
+    1
+}
+
+fn default_lnurl_max_sats() -> u64 {
+    1_000_000
+}
+
+fn default_lnurl_comment_allowed() -> u16 {
+    255
+}
+
+fn default_lnurl_ip_invoices_per_minute() -> u32 {
+    10
+}
+
+fn default_lnurl_name_invoices_per_minute() -> u32 {
+    60
+}
+
+/// Makes automated payouts (`payroll run`, vault sends) harder to pick out
+#[derive(Debug, Deserialize)]
+pub struct PrivacyConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Longest random wait before each payroll payment, and added to each vault send's time
+    #[serde(default = "default_privacy_max_delay_secs")]
+    pub max_delay_secs: u64,
+    /// Pay round amounts to lightning addresses and offers in uneven parts
+    #[serde(default = "default_split_round_amounts")]
+    pub split_round_amounts: bool,
+    #[serde(default = "default_privacy_max_parts")]
+    pub max_parts: u8,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        PrivacyConfig {
+            enabled: false,
+            max_delay_secs: default_privacy_max_delay_secs(),
+            split_round_amounts: default_split_round_amounts(),
+            max_parts: default_privacy_max_parts(),
+        }
+    }
+}
+
+fn default_privacy_max_delay_secs() -> u64 {
+    120
+}
+
+fn default_split_round_amounts() -> bool {
+    true
+}
+
+fn default_privacy_max_parts() -> u8 {
+    3
+}
+
+/// Where `orange dev faucet` gets test coins, a faucet with mutinynet's API
+#[derive(Debug, Deserialize)]
+pub struct FaucetConfig {
+    /// Base URL, requests go to `{url}/api/lightning` and `{url}/api/onchain`
+    #[serde(default = "default_faucet_url")]
+    pub url: String,
+    /// Bearer token, for faucets that require signing in
+    pub token: Option<String>,
+}
+
+impl Default for FaucetConfig {
+    fn default() -> Self {
+        FaucetConfig {
+            url: default_faucet_url(),
+            token: None,
+        }
+    }
+}
+
+fn default_faucet_url() -> String {
+    "https://faucet.mutinynet.com".to_string()
+}
+
+/// Signers whose `orange authorize` documents `orange execute` will pay
+#[derive(Debug, Default, Deserialize)]
+pub struct AuthorizationsConfig {
+    /// x-only public keys, as printed by `orange authorize` on the signing machine
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+}
+
+/// A local command run by the daemon with an event's JSON on stdin
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExecHookConfig {
+    /// Path of the program, not run through a shell
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Event types to run on, all events when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// The command is killed after this long
+    #[serde(default = "default_exec_timeout")]
+    pub timeout_secs: u64,
+}
+
+fn default_exec_timeout() -> u64 {
+    30
+}
+
+/// A transport plugin the daemon keeps running, writing each event's JSON to its stdin as
+/// one line
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig {
+    /// Path of the program, not run through a shell
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Event types to send, all events when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+/// Sweeps the wallet when `orange heartbeat` hasn't been run for `after_days`
+#[derive(Debug, Deserialize)]
+pub struct DeadManSwitchConfig {
+    pub after_days: u64,
+    /// Lightning address, BOLT12 offer or on-chain address that receives the funds
+    pub sweep_to: String,
+    /// Extra webhooks ("url" or "url|token") notified when the switch fires
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+/// Mail server the daemon sends payment receipts through, to payers who gave `receive --email`
+#[derive(Debug, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// Submission port, STARTTLS is required
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender mailbox, e.g. "Shop <billing@example.com>"
+    pub from: String,
+    #[serde(default = "default_receipt_subject")]
+    pub subject: String,
+    /// Plain text body with `{field}` placeholders, defaults to a built-in receipt
+    pub template: Option<String>,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_receipt_subject() -> String {
+    "Payment receipt".to_string()
+}
+
+/// MQTT broker the daemon publishes events to, e.g. for home automation
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    /// `host`, `host:port`, `mqtt://host:port` or `mqtts://host:port` for TLS
+    pub broker: String,
+    /// Events go to `{topic_prefix}/{event type}`
+    #[serde(default = "default_mqtt_topic_prefix")]
+    pub topic_prefix: String,
+    /// 0 (at most once), 1 (at least once) or 2 (exactly once)
+    #[serde(default = "default_mqtt_qos")]
+    pub qos: u8,
+    /// Keep the last event of each type on the broker for new subscribers
+    #[serde(default)]
+    pub retain: bool,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+fn default_mqtt_topic_prefix() -> String {
+    "orange".to_string()
+}
+
+fn default_mqtt_qos() -> u8 {
+    1
+}
+
+fn default_mqtt_client_id() -> String {
+    "orange".to_string()
+}
+
+/// Local caching proxy in front of an esplora chain source
+#[derive(Debug, Deserialize)]
+pub struct ChainCacheConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// How long tip, fee estimate, address and transaction status responses are reused
+    #[serde(default = "default_cache_ttl")]
+    pub ttl_secs: u64,
+    /// How long responses that can only change in a reorg (blocks, raw transactions) are reused
+    #[serde(default = "default_cache_immutable_ttl")]
+    pub immutable_ttl_secs: u64,
+    #[serde(default = "default_cache_max_entries")]
+    pub max_entries: usize,
+}
+
+impl Default for ChainCacheConfig {
+    fn default() -> Self {
+        ChainCacheConfig {
+            enabled: false,
+            ttl_secs: default_cache_ttl(),
+            immutable_ttl_secs: default_cache_immutable_ttl(),
+            max_entries: default_cache_max_entries(),
+        }
+    }
+}
+
+fn default_cache_ttl() -> u64 {
+    10
+}
+
+fn default_cache_immutable_ttl() -> u64 {
+    3600
+}
+
+fn default_cache_max_entries() -> usize {
+    10_000
+}
+
+/// Environment variables starting with this override config.toml, see `env_overrides`
+const ENV_PREFIX: &str = "ORANGE_";
+
+/// Variables with the prefix that aren't config fields
+const ENV_RESERVED: &[&str] = &["ORANGE_PASSPHRASE", "ORANGE_EVENT_TYPE"];
+
+/// Config fields set through the environment, as key paths and values in name order, so a
+/// whole section is set before the fields overriding parts of it. `ORANGE_NETWORK` sets
+/// `network`, `ORANGE_CHAIN_SOURCE__URL` sets `url` in `[chain_source]`.
+fn env_overrides() -> Vec<(Vec<String>, toml::Value)> {
+    let mut overrides: Vec<(String, toml::Value)> = std::env::vars()
+        .filter(|(name, _)| !ENV_RESERVED.contains(&name.as_str()))
+        .filter_map(|(name, raw)| {
+            let name = name.strip_prefix(ENV_PREFIX)?.to_lowercase();
+            Some((name, env_value(&raw)))
+        })
+        .collect();
+    overrides.sort_by(|a, b| a.0.cmp(&b.0));
+    overrides
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let keys: Vec<String> = name.split("__").map(String::from).collect();
+            keys.iter()
+                .all(|key| !key.is_empty())
+                .then_some((keys, value))
+        })
+        .collect()
+}
+
+/// Reads a variable as a TOML value (number, boolean, array, inline table or quoted string),
+/// anything else as a plain string
+fn env_value(raw: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {raw}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+fn set_path(table: &mut toml::Table, keys: &[String], value: toml::Value) {
+    let [key, rest @ ..] = keys else {
+        return;
+    };
+    if rest.is_empty() {
+        table.insert(key.clone(), value);
+        return;
+    }
+    let entry = table
+        .entry(key.clone())
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+    if !entry.is_table() {
+        *entry = toml::Value::Table(toml::Table::new());
+    }
+    if let toml::Value::Table(section) = entry {
+        set_path(section, rest, value);
+    }
+}
+
 impl Config {
-    pub fn load(path: &str) -> Result<Self, String> {
-        let content =
-            std::fs::read_to_string(path).map_err(|e| format!("Failed to read config: {e}"))?;
-        toml::from_str(&content).map_err(|e| format!("Failed to parse config: {e}"))
+    /// Loads the config, applying `ORANGE_*` environment variables on top, then
+    /// `[profiles.<network>]` when a different network is requested. Without a config file
+    /// everything can come from the environment.
+    pub fn load(path: &str, network: Option<&str>) -> Result<Self, String> {
+        let overrides = env_overrides();
+        let mut table: toml::Table = match std::fs::read_to_string(path) {
+            Ok(content) => {
+                toml::from_str(&content).map_err(|e| format!("Failed to parse config: {e}"))?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !overrides.is_empty() => {
+                toml::Table::new()
+            }
+            Err(e) => return Err(format!("Failed to read config: {e}")),
+        };
+        for (keys, value) in overrides {
+            set_path(&mut table, &keys, value);
+        }
+        let mut config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| format!("Failed to parse config: {e}"))?;
+        if let Some(network) = network {
+            config.apply_profile(network)?;
+        }
+        if config.duress_wallet && config.duress_passphrase.is_some() {
+            return Err("Set either duress_wallet or duress_passphrase, not both".to_string());
+        }
+        if config.duress_passphrase.is_some() {
+            warn!(
+                "duress_passphrase is stored in plaintext in the config, anyone who reads it can \
+                 tell there is a decoy wallet and open it. See duress_wallet."
+            );
+        }
+        if config.fiat.drift_check_interval_secs == 0 {
+            return Err("[fiat] drift_check_interval_secs must be at least 1".to_string());
+        }
+        if config.exec_hooks.iter().any(|hook| hook.timeout_secs == 0) {
+            return Err("[[exec_hooks]] timeout_secs must be at least 1".to_string());
+        }
+        if config.privacy.max_parts > crate::privacy::MAX_PARTS {
+            return Err(format!(
+                "[privacy] max_parts can be at most {}",
+                crate::privacy::MAX_PARTS
+            ));
+        }
+        Ok(config)
+    }
+
+    fn apply_profile(&mut self, network: &str) -> Result<(), String> {
+        if network == self.network {
+            return Ok(());
+        }
+        let profile = self
+            .profiles
+            .remove(network)
+            .ok_or_else(|| format!("No [profiles.{network}] section in config"))?;
+        self.storage_path = match profile.storage_path {
+            Some(path) => path,
+            None => format!("{}/{network}", self.storage_path.trim_end_matches('/')),
+        };
+        self.network = network.to_string();
+        if let Some(chain_source) = profile.chain_source {
+            self.chain_source = chain_source;
+        }
+        if let Some(lsp) = profile.lsp {
+            self.lsp = lsp;
+        }
+        if let Some(spark) = profile.spark {
+            self.spark = spark;
+        }
+        Ok(())
     }
 
-    pub fn into_wallet_config(self) -> Result<WalletConfig, String> {
+    /// BIP39 passphrase for this session: the unlocked one, else `ORANGE_PASSPHRASE` if set,
+    /// else the decoy wallet's. An empty passphrase means none.
+    pub fn passphrase(&self) -> Option<String> {
+        let passphrase = match (
+            &self.unlocked_passphrase,
+            std::env::var("ORANGE_PASSPHRASE"),
+        ) {
+            (Some(p), _) => p.clone(),
+            (None, Ok(p)) => p,
+            (None, Err(_)) => return self.duress_passphrase(),
+        };
+        Some(passphrase).filter(|p| !p.is_empty())
+    }
+
+    fn duress_passphrase(&self) -> Option<String> {
+        if let Some(passphrase) = &self.duress_passphrase {
+            return Some(passphrase.clone());
+        }
+        // Without a seed no wallet opens, so there's nothing to fall back to here
+        self.duress_wallet
+            .then(|| self.mnemonic().ok())
+            .flatten()
+            .map(|mnemonic| crate::signing::duress_passphrase(&mnemonic))
+    }
+
+    /// Directory holding the wallet data for this session's passphrase
+    pub fn storage_dir(&self) -> Result<PathBuf, String> {
+        let dir = self.wallet_dir(self.passphrase().as_deref())?;
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create storage directory: {e}"))?;
+        Ok(dir)
+    }
+
+    /// Where the wallet for `passphrase` keeps its data, without creating it. Passphrase
+    /// wallets live in `{storage_path}/wallets/<seed fingerprint>` so they never share state
+    /// with each other or with the passphrase-less wallet.
+    pub fn wallet_dir(&self, passphrase: Option<&str>) -> Result<PathBuf, String> {
+        let root = self.storage_root()?;
+        let Some(passphrase) = passphrase.filter(|p| !p.is_empty()) else {
+            return Ok(root);
+        };
+        let seed = self.mnemonic()?.to_seed(passphrase);
+        let fingerprint = &sha256::Hash::hash(&seed).to_byte_array()[..4];
+        Ok(root.join("wallets").join(fingerprint.to_lower_hex_string()))
+    }
+
+    /// Resolves `storage_path` (expanding `~`) and makes sure the directory exists
+    pub fn storage_root(&self) -> Result<PathBuf, String> {
+        let storage_dir = if self.storage_path.starts_with("~/") {
+            let home = std::env::var("HOME")
+                .map_err(|_| "storage_path uses ~ but HOME is not set".to_string())?;
+            PathBuf::from(home).join(&self.storage_path[2..])
+        } else {
+            PathBuf::from(&self.storage_path)
+        };
+        std::fs::create_dir_all(&storage_dir)
+            .map_err(|e| format!("Failed to create storage directory: {e}"))?;
+        self.check_storage_network(&storage_dir)?;
+        Ok(storage_dir)
+    }
+
+    /// Refuses to use a storage directory that was created for a different network
+    fn check_storage_network(&self, storage_dir: &std::path::Path) -> Result<(), String> {
+        let network: Network = self
+            .network
+            .parse()
+            .map_err(|_| format!("Invalid network: {}", self.network))?;
+        let marker = storage_dir.join("network");
+        if marker.exists() {
+            let existing = std::fs::read_to_string(&marker)
+                .map_err(|e| format!("Failed to read {}: {e}", marker.display()))?;
+            if existing.trim() != network.to_string() {
+                return Err(format!(
+                    "Storage directory {} belongs to {}, refusing to use it for {network}",
+                    storage_dir.display(),
+                    existing.trim()
+                ));
+            }
+        } else {
+            std::fs::write(&marker, network.to_string())
+                .map_err(|e| format!("Failed to write {}: {e}", marker.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Loads the wallet mnemonic from `{storage_path}/seed`, generating one on first run
+    pub fn mnemonic(&self) -> Result<Mnemonic, String> {
+        let storage_dir = self.storage_root()?;
+        let seed_path = storage_dir.join("seed");
+        if seed_path.exists() {
+            let content = std::fs::read_to_string(&seed_path)
+                .map_err(|e| format!("Failed to read seed file: {e}"))?;
+            Mnemonic::from_str(content.trim())
+                .map_err(|e| format!("Invalid mnemonic in seed file: {e}"))
+        } else {
+            let m = Mnemonic::generate(12)
+                .map_err(|e| format!("Failed to generate mnemonic: {e}"))?;
+            std::fs::write(&seed_path, m.to_string())
+                .map_err(|e| format!("Failed to write seed file: {e}"))?;
+            info!("Generated new wallet seed at {}", seed_path.display());
+            Ok(m)
+        }
+    }
+
+    pub fn wallet_config(&self) -> Result<WalletConfig, String> {
         let network: Network = self
             .network
             .parse()
@@ -76,17 +1025,19 @@ impl Config {
                 let url = self
                     .chain_source
                     .url
+                    .clone()
                     .ok_or("esplora chain_source requires 'url'")?;
                 ChainSource::Esplora {
                     url,
-                    username: self.chain_source.username,
-                    password: self.chain_source.password,
+                    username: self.chain_source.username.clone(),
+                    password: self.chain_source.password.clone(),
                 }
             }
             "electrum" => {
                 let url = self
                     .chain_source
                     .url
+                    .clone()
                     .ok_or("electrum chain_source requires 'url'")?;
                 ChainSource::Electrum(url)
             }
@@ -94,6 +1045,7 @@ impl Config {
                 let host = self
                     .chain_source
                     .host
+                    .clone()
                     .ok_or("bitcoind_rpc chain_source requires 'host'")?;
                 let port = self
                     .chain_source
@@ -102,10 +1054,12 @@ impl Config {
                 let user = self
                     .chain_source
                     .username
+                    .clone()
                     .ok_or("bitcoind_rpc chain_source requires 'username'")?;
                 let password = self
                     .chain_source
                     .password
+                    .clone()
                     .ok_or("bitcoind_rpc chain_source requires 'password'")?;
                 ChainSource::BitcoindRPC {
                     host,
@@ -128,30 +1082,9 @@ impl Config {
             .parse()
             .map_err(|e| format!("Invalid LSP node_id: {e}"))?;
 
-        let storage_dir = if self.storage_path.starts_with("~/") {
-            let home = std::env::var("HOME")
-                .map_err(|_| "storage_path uses ~ but HOME is not set".to_string())?;
-            PathBuf::from(home).join(&self.storage_path[2..])
-        } else {
-            PathBuf::from(&self.storage_path)
-        };
-        std::fs::create_dir_all(&storage_dir)
-            .map_err(|e| format!("Failed to create storage directory: {e}"))?;
+        let storage_dir = self.storage_dir()?;
 
-        let seed_path = storage_dir.join("seed");
-        let mnemonic = if seed_path.exists() {
-            let content = std::fs::read_to_string(&seed_path)
-                .map_err(|e| format!("Failed to read seed file: {e}"))?;
-            Mnemonic::from_str(content.trim())
-                .map_err(|e| format!("Invalid mnemonic in seed file: {e}"))?
-        } else {
-            let m = Mnemonic::generate(12)
-                .map_err(|e| format!("Failed to generate mnemonic: {e}"))?;
-            std::fs::write(&seed_path, m.to_string())
-                .map_err(|e| format!("Failed to write seed file: {e}"))?;
-            eprintln!("Generated new wallet seed at {}", seed_path.display());
-            m
-        };
+        let mnemonic = self.mnemonic()?;
 
         let log_path = storage_dir.join("wallet.log");
 
@@ -159,19 +1092,19 @@ impl Config {
             storage_config: StorageConfig::LocalSQLite(storage_dir.to_string_lossy().into_owned()),
             logger_type: LoggerType::File { path: log_path },
             chain_source,
-            lsp: (lsp_address, lsp_pubkey, self.lsp.token),
+            lsp: (lsp_address, lsp_pubkey, self.lsp.token.clone()),
             scorer_url: None,
             rgs_url: None,
             network,
             seed: Seed::Mnemonic {
                 mnemonic,
-                passphrase: None,
+                passphrase: self.passphrase(),
             },
             tunables: Tunables::default(),
             extra_config: ExtraConfig::Spark(SparkWalletConfig {
                 sync_interval_secs: self.spark.sync_interval_secs,
                 prefer_spark_over_lightning: self.spark.prefer_spark_over_lightning,
-                lnurl_domain: self.spark.lnurl_domain,
+                lnurl_domain: self.spark.lnurl_domain.clone(),
             }),
         })
     }
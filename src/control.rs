@@ -0,0 +1,90 @@
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+/// A client that connected but hasn't sent its request by then is dropped, so it doesn't hold
+/// one of the daemon's request slots
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One line of JSON from the client: the command line it was started with
+#[derive(Serialize, Deserialize)]
+pub struct Request {
+    pub args: Vec<String>,
+}
+
+/// One line of JSON back: `{"result": ...}` or `{"error": "..."}`
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Response {
+    Result(serde_json::Value),
+    Error(String),
+}
+
+pub fn socket_path(dir: &Path) -> PathBuf {
+    dir.join("daemon.sock")
+}
+
+/// Listens on `{dir}/daemon.sock`, readable by the wallet's owner only. Must only be called
+/// while holding the daemon lock, since a socket left behind by another daemon is replaced.
+pub fn bind(dir: &Path) -> Result<UnixListener, String> {
+    let path = socket_path(dir);
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)
+        .map_err(|e| format!("Failed to listen on {}: {e}", path.display()))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("Failed to set permissions on {}: {e}", path.display()))?;
+    Ok(listener)
+}
+
+pub async fn read_request(stream: &mut UnixStream) -> Result<Request, String> {
+    let mut line = String::new();
+    let mut reader = BufReader::new(stream);
+    tokio::time::timeout(REQUEST_TIMEOUT, reader.read_line(&mut line))
+        .await
+        .map_err(|_| "Timed out reading request".to_string())?
+        .map_err(|e| format!("Failed to read request: {e}"))?;
+    serde_json::from_str(&line).map_err(|e| format!("Invalid request: {e}"))
+}
+
+pub async fn respond(stream: &mut UnixStream, response: &Response) -> Result<(), String> {
+    let mut line = serde_json::to_string(response).unwrap();
+    line.push('\n');
+    stream
+        .write_all(line.as_bytes())
+        .await
+        .map_err(|e| format!("Failed to send response: {e}"))
+}
+
+/// Runs `args` on the daemon listening in `dir`. Returns `None` when no daemon is listening,
+/// so the caller can open the wallet itself.
+pub async fn proxy(dir: &Path, args: Vec<String>) -> Option<Result<serde_json::Value, String>> {
+    let mut stream = UnixStream::connect(socket_path(dir)).await.ok()?;
+    Some(exchange(&mut stream, Request { args }).await)
+}
+
+async fn exchange(stream: &mut UnixStream, request: Request) -> Result<serde_json::Value, String> {
+    let mut line = serde_json::to_string(&request).unwrap();
+    line.push('\n');
+    stream
+        .write_all(line.as_bytes())
+        .await
+        .map_err(|e| format!("Failed to send request to the daemon: {e}"))?;
+
+    // No timeout here, a send waits for the daemon as long as it would for the wallet
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .await
+        .map_err(|e| format!("Lost connection to the daemon: {e}"))?;
+    if line.is_empty() {
+        return Err("The daemon closed the connection without responding".to_string());
+    }
+    match serde_json::from_str(&line) {
+        Ok(Response::Result(value)) => Ok(value),
+        Ok(Response::Error(e)) => Err(e),
+        Err(e) => Err(format!("Invalid response from the daemon: {e}")),
+    }
+}
@@ -0,0 +1,201 @@
+use crate::config::{FiatConfig, SmtpConfig};
+use crate::fiat;
+use crate::signing::Signer;
+use crate::store::{Receive, Store};
+use lettre::message::Mailbox;
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const DEFAULT_TEMPLATE: &str = "Thank you for your payment.
+
+Amount: {amount_sats} sats
+Value: {fiat_amount} {currency}
+Reference: {purpose}
+Received at: {received_at} (unix time)
+Invoice: {invoice}
+Payment hash: {payment_hash}
+
+The receipt below is signed by the merchant's wallet key. Keep it as proof of payment.
+
+{signed_receipt}
+";
+
+/// Emails signed payment receipts to payers who left an address with `receive --email`
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    subject: String,
+    template: String,
+    signer: Signer,
+    fiat: FiatConfig,
+    /// Wallet directory, to give back the claim on a receipt that failed to send
+    dir: PathBuf,
+}
+
+impl Mailer {
+    pub fn new(
+        config: &SmtpConfig,
+        signer: Signer,
+        fiat: FiatConfig,
+        dir: PathBuf,
+    ) -> Result<Self, String> {
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+            .map_err(|e| format!("Invalid SMTP host {}: {e}", config.host))?
+            .port(config.port);
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        let from = config
+            .from
+            .parse()
+            .map_err(|e| format!("Invalid smtp from address {}: {e}", config.from))?;
+        Ok(Mailer {
+            transport: transport.build(),
+            from,
+            subject: config.subject.clone(),
+            template: config
+                .template
+                .clone()
+                .unwrap_or_else(|| DEFAULT_TEMPLATE.to_string()),
+            signer,
+            fiat,
+            dir,
+        })
+    }
+
+    /// Emails the receipt for `receive`, settled by `payment_id`, without blocking the caller
+    pub fn send_receipt(
+        self: &Arc<Self>,
+        receive: Receive,
+        payment_id: String,
+        amount_msat: u64,
+        received_at: u64,
+    ) {
+        let Some(to) = receive.payer_email.clone() else {
+            return;
+        };
+        let mailer = Arc::clone(self);
+        let id = receive.id;
+        tokio::spawn(async move {
+            match mailer
+                .deliver(&to, receive, payment_id, amount_msat, received_at)
+                .await
+            {
+                Ok(()) => info!("Emailed receipt to {to}"),
+                Err(e) => {
+                    error!("Failed to email receipt to {to}, retrying later: {e}");
+                    // The daemon's periodic check picks up receipts whose claim was released
+                    if let Err(e) =
+                        Store::open(&mailer.dir).and_then(|store| store.release_receipt_email(id))
+                    {
+                        error!("{e}");
+                    }
+                }
+            }
+        });
+    }
+
+    async fn deliver(
+        &self,
+        to: &str,
+        receive: Receive,
+        payment_id: String,
+        amount_msat: u64,
+        received_at: u64,
+    ) -> Result<(), String> {
+        let amount_sats = amount_msat / 1000;
+        // Fiat-priced receives state what was promised, others are valued at today's price
+        let (fiat_amount, currency) = match (receive.fiat_amount, receive.currency) {
+            (Some(amount), Some(currency)) => (Some(amount), currency),
+            _ => {
+                let value = fiat::fetch_btc_price(&self.fiat)
+                    .await
+                    .map(|price| amount_sats as f64 * price / 100_000_000.0);
+                if let Err(ref e) = value {
+                    warn!("Receipt for {payment_id} has no fiat value: {e}");
+                }
+                (value.ok(), self.fiat.currency.clone())
+            }
+        };
+
+        let payload = json!({
+            "type": "payment_receipt",
+            "payment_id": payment_id,
+            "payment_hash": receive.payment_hash,
+            "invoice": receive.invoice,
+            "amount_sats": amount_sats,
+            "fiat_amount": fiat_amount.map(|a| (a * 100.0).round() / 100.0),
+            "currency": currency,
+            "purpose": receive.purpose,
+            "received_at": received_at,
+        });
+        let signed = self.signer.sign_json(payload.clone());
+        let mut fields = match payload {
+            serde_json::Value::Object(fields) => fields,
+            _ => unreachable!(),
+        };
+        fields.insert(
+            "signed_receipt".to_string(),
+            json!(serde_json::to_string_pretty(&signed).unwrap()),
+        );
+
+        let to: Mailbox = to
+            .parse()
+            .map_err(|e| format!("Invalid payer email {to}: {e}"))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(render(&self.subject, &fields))
+            .header(ContentType::TEXT_PLAIN)
+            .body(render(&self.template, &fields))
+            .map_err(|e| format!("Failed to build receipt email: {e}"))?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| format!("SMTP error: {e}"))?;
+        Ok(())
+    }
+}
+
+/// Rejects addresses the mailer would not be able to send to
+pub fn validate_address(address: &str) -> Result<(), String> {
+    address
+        .parse::<Mailbox>()
+        .map(|_| ())
+        .map_err(|e| format!("Invalid email address {address}: {e}"))
+}
+
+/// Replaces each `{name}` in `template` with the field of that name, leaving null fields empty
+/// and unknown names as they are. Done in one pass, so braces in a field's value (a purpose set
+/// by the payer's merchant page, say) are never expanded themselves.
+fn render(template: &str, fields: &serde_json::Map<String, serde_json::Value>) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+        let field = placeholder
+            .find('}')
+            .and_then(|end| Some((fields.get(&placeholder[1..end])?, end)));
+        match field {
+            Some((value, end)) => {
+                match value {
+                    serde_json::Value::String(s) => text.push_str(s),
+                    serde_json::Value::Null => {}
+                    other => text.push_str(&other.to_string()),
+                }
+                rest = &placeholder[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &placeholder[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
@@ -0,0 +1,117 @@
+use crate::config::ExecHookConfig;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+
+/// Runs the `[[exec_hooks]]` commands matching an event, each in the background with the
+/// event's JSON on stdin. Unlike webhooks there is no outbox: a failed run is retried a few
+/// times while the daemon holds the event, then logged and given up on.
+#[derive(Default)]
+pub struct ExecHooks {
+    hooks: Vec<ExecHookConfig>,
+}
+
+/// Runs of a failing hook before it is given up on
+const ATTEMPTS: u32 = 3;
+
+impl ExecHooks {
+    pub fn new(hooks: &[ExecHookConfig]) -> Self {
+        ExecHooks {
+            hooks: hooks.to_vec(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub fn hooks(&self) -> &[ExecHookConfig] {
+        &self.hooks
+    }
+
+    /// Starts every hook subscribed to the event's type, without waiting for them. The
+    /// returned task finishes once they all succeeded or ran out of attempts.
+    pub fn run(&self, event: &serde_json::Value) -> Option<JoinHandle<()>> {
+        let event_type = event["type"].as_str().unwrap_or_default().to_string();
+        let hooks: Vec<ExecHookConfig> = self
+            .hooks
+            .iter()
+            .filter(|hook| hook.events.is_empty() || hook.events.contains(&event_type))
+            .cloned()
+            .collect();
+        if hooks.is_empty() {
+            return None;
+        }
+        let body = format!("{event}\n");
+        Some(tokio::spawn(async move {
+            let runs = hooks
+                .iter()
+                .map(|hook| run_with_retries(hook, &event_type, body.as_bytes()));
+            futures_util::future::join_all(runs).await;
+        }))
+    }
+}
+
+async fn run_with_retries(hook: &ExecHookConfig, event_type: &str, body: &[u8]) {
+    let command = &hook.command;
+    for attempt in 1..=ATTEMPTS {
+        match run(hook, event_type, body).await {
+            Ok(()) => {
+                debug!(
+                    { "command": command, "event_type": event_type },
+                    "Exec hook {command} handled {event_type}"
+                );
+                return;
+            }
+            Err(e) if attempt < ATTEMPTS => {
+                warn!(
+                    { "command": command, "event_type": event_type },
+                    "Exec hook {command} failed on {event_type}, retrying: {e}"
+                );
+                tokio::time::sleep(Duration::from_secs(5 * attempt as u64)).await;
+            }
+            Err(e) => error!(
+                { "command": command, "event_type": event_type },
+                "Exec hook {command} failed on {event_type} {ATTEMPTS} times, giving up: {e}"
+            ),
+        }
+    }
+}
+
+async fn run(hook: &ExecHookConfig, event_type: &str, body: &[u8]) -> Result<(), String> {
+    let mut child = Command::new(&hook.command)
+        .args(&hook.args)
+        .env("ORANGE_EVENT_TYPE", event_type)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("failed to start: {e}"))?;
+    let stdin = child.stdin.take();
+    // The timeout covers writing stdin too, a command that never reads it would block us
+    let finished = async move {
+        // A command that exits without reading stdin is fine, its exit status decides
+        if let Some(mut stdin) = stdin {
+            let _ = stdin.write_all(body).await;
+        }
+        child.wait_with_output().await
+    };
+    let timeout = Duration::from_secs(hook.timeout_secs);
+    let output = tokio::time::timeout(timeout, finished)
+        .await
+        .map_err(|_| format!("timed out after {}s", hook.timeout_secs))?
+        .map_err(|e| format!("failed to wait: {e}"))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr = stderr.trim();
+    if stderr.is_empty() {
+        Err(output.status.to_string())
+    } else {
+        Err(format!("{}: {stderr}", output.status))
+    }
+}
@@ -0,0 +1,110 @@
+use crate::config::FiatConfig;
+use crate::store::Store;
+use serde_json::json;
+
+/// Fetches the current BTC price in the configured currency from `price_url`
+pub async fn fetch_btc_price(config: &FiatConfig) -> Result<f64, String> {
+    let prices: serde_json::Value = reqwest::get(&config.price_url)
+        .await
+        .map_err(|e| format!("Failed to fetch BTC price: {e}"))?
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse BTC price: {e}"))?;
+    prices[config.currency.as_str()]
+        .as_f64()
+        .filter(|p| *p > 0.0)
+        .ok_or_else(|| format!("Price source has no {} price", config.currency))
+}
+
+pub fn fiat_to_sats(fiat_amount: f64, btc_price: f64) -> u64 {
+    (fiat_amount / btc_price * 100_000_000.0).round() as u64
+}
+
+/// Fiat obligations from `receive --amount-fiat` that have been paid, valued at today's price
+pub struct Exposure {
+    pub currency: String,
+    pub btc_price: f64,
+    pub obligations: usize,
+    pub fiat_obligated: f64,
+    pub received_sats: u64,
+}
+
+impl Exposure {
+    pub fn load(store: &Store, currency: &str, btc_price: f64) -> Result<Self, String> {
+        let settled = store.settled_fiat_obligations(currency)?;
+        Ok(Exposure {
+            currency: currency.to_string(),
+            btc_price,
+            obligations: settled.len(),
+            fiat_obligated: settled.iter().map(|(fiat, _)| fiat).sum(),
+            received_sats: settled.iter().map(|(_, msat)| msat / 1000).sum(),
+        })
+    }
+
+    pub fn current_value(&self) -> f64 {
+        self.received_sats as f64 * self.btc_price / 100_000_000.0
+    }
+
+    /// Current value minus the obligations, negative when the sats are worth less than promised
+    pub fn drift(&self) -> f64 {
+        self.current_value() - self.fiat_obligated
+    }
+
+    pub fn drift_percent(&self) -> f64 {
+        if self.fiat_obligated == 0.0 {
+            return 0.0;
+        }
+        self.drift() / self.fiat_obligated * 100.0
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let target_sats = fiat_to_sats(self.fiat_obligated, self.btc_price);
+        let suggestion = match target_sats.cmp(&self.received_sats) {
+            std::cmp::Ordering::Greater => json!({
+                "action": "add_sats",
+                "amount_sats": target_sats - self.received_sats,
+            }),
+            std::cmp::Ordering::Less => json!({
+                "action": "convert_to_fiat",
+                "amount_sats": self.received_sats - target_sats,
+            }),
+            std::cmp::Ordering::Equal => serde_json::Value::Null,
+        };
+        json!({
+            "currency": self.currency,
+            "btc_price": self.btc_price,
+            "obligations": self.obligations,
+            "fiat_obligated": self.fiat_obligated,
+            "received_sats": self.received_sats,
+            "current_value": self.current_value(),
+            "drift": self.drift(),
+            "drift_percent": self.drift_percent(),
+            "suggestion": suggestion,
+        })
+    }
+}
+
+/// Payment hashes of the paid fiat receives not alerted on yet whose value drifted at least
+/// `threshold` percent from the fiat amount they were priced at
+pub fn drifted_obligations(
+    store: &Store,
+    currency: &str,
+    btc_price: f64,
+    threshold: f64,
+) -> Result<Vec<String>, String> {
+    let unalerted = store.fiat_obligations_to_alert(currency)?;
+    Ok(unalerted
+        .into_iter()
+        .filter(|(_, fiat_amount, amount_msat)| {
+            let exposure = Exposure {
+                currency: currency.to_string(),
+                btc_price,
+                obligations: 1,
+                fiat_obligated: *fiat_amount,
+                received_sats: amount_msat / 1000,
+            };
+            exposure.drift_percent().abs() >= threshold
+        })
+        .map(|(payment_hash, _, _)| payment_hash)
+        .collect())
+}
@@ -0,0 +1,243 @@
+use crate::control;
+use crate::serve;
+use crate::store::Store;
+use crate::tz;
+use axum::http::StatusCode;
+use futures_util::{Stream, StreamExt};
+use prost_types::value::Kind;
+use serde_json::{Value, json};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use tonic::{Request, Response, Status};
+
+mod pb {
+    tonic::include_proto!("orange.v1");
+}
+
+use pb::orange_server::{Orange, OrangeServer};
+
+struct Service {
+    cli: clap::Command,
+    /// Wallet directory holding the daemon's socket
+    dir: PathBuf,
+    tz: chrono_tz::Tz,
+}
+
+/// Serves `proto/orange.proto` on `addr` until Ctrl+C, running each call on the daemon through
+/// its control socket like `orange serve`. The token is required even on loopback, since any
+/// local user could otherwise spend from the wallet.
+pub async fn run(
+    addr: &str,
+    token: Option<String>,
+    cli: clap::Command,
+    dir: PathBuf,
+    tz: chrono_tz::Tz,
+) -> Result<(), String> {
+    let socket: SocketAddr = addr
+        .parse()
+        .map_err(|e| format!("Invalid listen address {addr}: {e}"))?;
+    let Some(token) = token.filter(|token| !token.is_empty()) else {
+        return Err("Set [serve] token, every call must carry it".to_string());
+    };
+    let service = OrangeServer::with_interceptor(Service { cli, dir, tz }, move |request| {
+        let given = request
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if given.is_some_and(|given| crate::serve::token_matches(given, &token)) {
+            Ok(request)
+        } else {
+            Err(Status::unauthenticated("Missing or wrong Bearer token"))
+        }
+    });
+    info!("Serving the wallet gRPC API on {addr}");
+    tonic::transport::Server::builder()
+        .add_service(service)
+        .serve_with_shutdown(socket, async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+        .map_err(|e| format!("gRPC server stopped: {e}"))
+}
+
+impl Service {
+    async fn call(
+        &self,
+        command: &str,
+        request: Request<prost_types::Struct>,
+    ) -> Result<Response<prost_types::Struct>, Status> {
+        let words: Vec<String> = command.split(' ').map(String::from).collect();
+        let params = from_struct(request.into_inner());
+        let args = match serve::command_args(&self.cli, &words, &params) {
+            Ok(args) => args,
+            Err((StatusCode::NOT_FOUND, e)) => return Err(Status::unimplemented(e)),
+            Err((_, e)) => return Err(Status::invalid_argument(e)),
+        };
+        match control::proxy(&self.dir, args).await {
+            Some(Ok(mut value)) => {
+                tz::annotate(&mut value, self.tz);
+                // Every command prints an object, anything else is wrapped to fit a Struct
+                let fields = match value {
+                    Value::Object(fields) => fields,
+                    value => serde_json::Map::from_iter([("value".to_string(), value)]),
+                };
+                Ok(Response::new(to_struct(fields)))
+            }
+            Some(Err(e)) => Err(Status::failed_precondition(e)),
+            None => Err(Status::unavailable(
+                "The daemon isn't running, start `orange daemon`",
+            )),
+        }
+    }
+
+    fn subscribe(
+        &self,
+        request: pb::SubscribeEventsRequest,
+    ) -> Result<Response<EventStream>, Status> {
+        let store = Store::open(&self.dir).map_err(Status::internal)?;
+        let next = match request.from_seq {
+            Some(seq) => seq,
+            None => store.last_event_seq().map_err(Status::internal)? + 1,
+        };
+        let stream =
+            serve::follow_events(store, next, request.types).map(|(seq, event_type, value)| {
+                let event = match value {
+                    Value::Object(fields) => Some(to_struct(fields)),
+                    _ => None,
+                };
+                Ok(pb::Event {
+                    seq,
+                    r#type: event_type,
+                    event,
+                })
+            });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<pb::Event, Status>> + Send>>;
+
+/// One method per command, each named after it as in `proto/orange.proto`
+macro_rules! commands {
+    ($($method:ident => $command:literal,)*) => {
+        #[tonic::async_trait]
+        impl Orange for Service {
+            $(
+                async fn $method(
+                    &self,
+                    request: Request<prost_types::Struct>,
+                ) -> Result<Response<prost_types::Struct>, Status> {
+                    self.call($command, request).await
+                }
+            )*
+
+            type SubscribeEventsStream = EventStream;
+
+            async fn subscribe_events(
+                &self,
+                request: Request<pb::SubscribeEventsRequest>,
+            ) -> Result<Response<EventStream>, Status> {
+                self.subscribe(request.into_inner())
+            }
+        }
+    };
+}
+
+commands! {
+    balance => "balance",
+    receive => "receive",
+    receive_offer => "receive-offer",
+    send => "send",
+    parse => "parse",
+    transactions => "transactions",
+    pending => "pending",
+    reconcile => "reconcile",
+    channels => "channels",
+    info => "info",
+    audit_snapshot => "audit-snapshot",
+    selftest => "selftest",
+    dev_faucet => "dev faucet",
+    estimate_fee => "estimate-fee",
+    lightning_address => "lightning-address",
+    register_lightning_address => "register-lightning-address",
+    addresses => "addresses",
+    report => "report",
+    fiat_exposure => "fiat-exposure",
+    heartbeat => "heartbeat",
+    vault_send => "vault send",
+    vault_list => "vault list",
+    vault_cancel => "vault cancel",
+    nwc_create => "nwc create",
+    nwc_list => "nwc list",
+    nwc_revoke => "nwc revoke",
+    withdraw_link => "withdraw-link",
+    withdraw_links_list => "withdraw-links list",
+    withdraw_links_revoke => "withdraw-links revoke",
+    withdraw_links_redeem => "withdraw-links redeem",
+    webhooks_list => "webhooks list",
+    webhooks_deliveries => "webhooks deliveries",
+    webhooks_test => "webhooks test",
+    webhooks_enable => "webhooks enable",
+    webhooks_dead_letter_list => "webhooks dead-letter list",
+    webhooks_dead_letter_redeliver => "webhooks dead-letter redeliver",
+    outbox_list => "outbox list",
+    outbox_purge => "outbox purge",
+    events_list => "events list",
+    events_replay => "events replay",
+    events_peek => "events peek",
+    analytics_latency => "analytics latency",
+    stats => "stats",
+    get_event => "get-event",
+    event_handled => "event-handled",
+    get_events => "get-events",
+    events_handled => "events-handled",
+}
+
+fn to_struct(fields: serde_json::Map<String, Value>) -> prost_types::Struct {
+    prost_types::Struct {
+        fields: fields
+            .into_iter()
+            .map(|(key, value)| (key, to_value(value)))
+            .collect(),
+    }
+}
+
+fn to_value(value: Value) -> prost_types::Value {
+    let kind = match value {
+        Value::Null => Kind::NullValue(0),
+        Value::Bool(b) => Kind::BoolValue(b),
+        Value::Number(n) => Kind::NumberValue(n.as_f64().unwrap_or_default()),
+        Value::String(s) => Kind::StringValue(s),
+        Value::Array(items) => Kind::ListValue(prost_types::ListValue {
+            values: items.into_iter().map(to_value).collect(),
+        }),
+        Value::Object(fields) => Kind::StructValue(to_struct(fields)),
+    };
+    prost_types::Value { kind: Some(kind) }
+}
+
+fn from_struct(params: prost_types::Struct) -> serde_json::Map<String, Value> {
+    params
+        .fields
+        .into_iter()
+        .map(|(key, value)| (key, from_value(value)))
+        .collect()
+}
+
+/// Whole numbers come back as integers, since they end up as command line arguments where
+/// `5000.0` wouldn't parse as an amount
+fn from_value(value: prost_types::Value) -> Value {
+    match value.kind {
+        None | Some(Kind::NullValue(_)) => Value::Null,
+        Some(Kind::BoolValue(b)) => json!(b),
+        Some(Kind::NumberValue(n)) if n.fract() == 0.0 && n.abs() < 9.0e15 => json!(n as i64),
+        Some(Kind::NumberValue(n)) => json!(n),
+        Some(Kind::StringValue(s)) => json!(s),
+        Some(Kind::ListValue(list)) => {
+            Value::Array(list.values.into_iter().map(from_value).collect())
+        }
+        Some(Kind::StructValue(fields)) => Value::Object(from_struct(fields)),
+    }
+}
@@ -0,0 +1,234 @@
+use crate::store::{SendTotal, WebhookEndpoint};
+use axum::Router;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::get;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+/// `/health` fails once the daemon loop hasn't refreshed the status for this long
+const STALE_SECS: u64 = 30;
+
+/// What the daemon loop last observed, served by the status endpoints
+#[derive(Default)]
+pub struct Status {
+    pub node_id: String,
+    pub lsp_connected: bool,
+    pub started_at: u64,
+    pub updated_at: u64,
+    /// Webhook deliveries queued or waiting for a retry
+    pub webhook_backlog: u64,
+    pub dead_letters: u64,
+    /// `seq` and time of the last event added to the history
+    pub last_event: Option<(i64, u64)>,
+    /// Trusted, lightning and pending balance, unset until the wallet first reports it
+    pub balance_sats: Option<(u64, u64, u64)>,
+    pub channels: u64,
+    pub usable_channels: u64,
+    pub inbound_capacity_sats: u64,
+    pub outbound_capacity_sats: u64,
+    pub received_payments: u64,
+    pub received_sats: u64,
+    pub sends: Vec<SendTotal>,
+    pub webhooks: Vec<WebhookEndpoint>,
+}
+
+impl Status {
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "node_id": self.node_id,
+            "lsp_connected": self.lsp_connected,
+            "started_at": self.started_at,
+            "updated_at": self.updated_at,
+            "webhook_backlog": self.webhook_backlog,
+            "dead_letters": self.dead_letters,
+            "last_event_seq": self.last_event.map(|(seq, _)| seq),
+            "last_event_at": self.last_event.map(|(_, at)| at),
+        })
+    }
+}
+
+pub type SharedStatus = Arc<Mutex<Status>>;
+
+/// Serves `/health`, `/status` and `/metrics` on `addr` until the process exits
+pub async fn start(addr: &str, status: SharedStatus) -> Result<(), String> {
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Failed to listen on {addr}: {e}"))?;
+    let app = Router::new()
+        .route("/health", get(health))
+        .route("/status", get(status_json))
+        .route("/metrics", get(metrics))
+        .with_state(status);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("Status endpoint stopped: {e}");
+        }
+    });
+    Ok(())
+}
+
+async fn health(State(status): State<SharedStatus>) -> Response {
+    let status = status.lock().unwrap();
+    let age = crate::now_secs().saturating_sub(status.updated_at);
+    // A stuck daemon loop is worth a restart, a disconnected LSP is not
+    if age > STALE_SECS {
+        let body = json!({ "ok": false, "error": format!("status not refreshed for {age}s") });
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response();
+    }
+    Json(json!({ "ok": true, "lsp_connected": status.lsp_connected })).into_response()
+}
+
+async fn status_json(State(status): State<SharedStatus>) -> Json<serde_json::Value> {
+    Json(status.lock().unwrap().to_json())
+}
+
+/// Prometheus text exposition of the status
+async fn metrics(State(status): State<SharedStatus>) -> Response {
+    let status = status.lock().unwrap();
+    let mut out = String::new();
+
+    let up = crate::now_secs().saturating_sub(status.updated_at) <= STALE_SECS;
+    gauge(
+        &mut out,
+        "orange_up",
+        "Whether the daemon loop is running",
+        &[("", up as u64)],
+    );
+    gauge(
+        &mut out,
+        "orange_lsp_connected",
+        "Whether the wallet is connected to its LSP",
+        &[("", status.lsp_connected as u64)],
+    );
+    if let Some((trusted, lightning, pending)) = status.balance_sats {
+        gauge(
+            &mut out,
+            "orange_balance_sats",
+            "Wallet balance by kind",
+            &[
+                ("kind=\"trusted\"", trusted),
+                ("kind=\"lightning\"", lightning),
+                ("kind=\"pending\"", pending),
+            ],
+        );
+    }
+    gauge(
+        &mut out,
+        "orange_channels",
+        "Lightning channels",
+        &[
+            ("state=\"open\"", status.channels),
+            ("state=\"usable\"", status.usable_channels),
+        ],
+    );
+    gauge(
+        &mut out,
+        "orange_capacity_sats",
+        "Lightning capacity summed over channels",
+        &[
+            ("direction=\"inbound\"", status.inbound_capacity_sats),
+            ("direction=\"outbound\"", status.outbound_capacity_sats),
+        ],
+    );
+
+    counter(
+        &mut out,
+        "orange_payments_received_total",
+        "Incoming payments",
+        &[("", status.received_payments)],
+    );
+    counter(
+        &mut out,
+        "orange_received_sats_total",
+        "Sats received",
+        &[("", status.received_sats)],
+    );
+    let sends: Vec<(String, u64)> = status
+        .sends
+        .iter()
+        .map(|s| (format!("status=\"{}\"", s.status), s.payment_count))
+        .collect();
+    counter(
+        &mut out,
+        "orange_payments_sent_total",
+        "Outgoing payments by status",
+        &labeled(&sends),
+    );
+    let sent_sats: Vec<(String, u64)> = status
+        .sends
+        .iter()
+        .map(|s| (format!("status=\"{}\"", s.status), s.amount_sats))
+        .collect();
+    counter(
+        &mut out,
+        "orange_sent_sats_total",
+        "Sats sent by payment status",
+        &labeled(&sent_sats),
+    );
+
+    let deliveries: Vec<(String, u64)> = status
+        .webhooks
+        .iter()
+        .flat_map(|w| {
+            let url = escape(&w.url);
+            [
+                (format!("url=\"{url}\",result=\"delivered\""), w.delivered),
+                (format!("url=\"{url}\",result=\"failed\""), w.failed),
+            ]
+        })
+        .collect();
+    counter(
+        &mut out,
+        "orange_webhook_deliveries_total",
+        "Webhook delivery attempts by result",
+        &labeled(&deliveries),
+    );
+    gauge(
+        &mut out,
+        "orange_webhook_backlog",
+        "Webhook deliveries queued or waiting for a retry",
+        &[("", status.webhook_backlog)],
+    );
+    gauge(
+        &mut out,
+        "orange_webhook_dead_letters",
+        "Webhook deliveries that ran out of attempts",
+        &[("", status.dead_letters)],
+    );
+
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], out).into_response()
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, samples: &[(&str, u64)]) {
+    write_metric(out, name, "gauge", help, samples);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, samples: &[(&str, u64)]) {
+    write_metric(out, name, "counter", help, samples);
+}
+
+fn write_metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, u64)]) {
+    out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n"));
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            out.push_str(&format!("{name} {value}\n"));
+        } else {
+            out.push_str(&format!("{name}{{{labels}}} {value}\n"));
+        }
+    }
+}
+
+fn labeled(samples: &[(String, u64)]) -> Vec<(&str, u64)> {
+    samples.iter().map(|(l, v)| (l.as_str(), *v)).collect()
+}
+
+/// Escapes a label value for the text format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,319 @@
+use crate::config::LnurlConfig;
+use crate::control;
+use crate::store::Store;
+use axum::Router;
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::get;
+use orange_sdk::bitcoin::bech32::{self, Bech32, Hrp};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Keys a limiter tracks before it forgets those whose bucket has refilled
+const LIMITER_MAX_KEYS: usize = 10_000;
+
+struct Server {
+    config: LnurlConfig,
+    /// Wallet directory holding the daemon's socket
+    dir: PathBuf,
+    /// Invoice requests by client address
+    ip_limiter: Limiter,
+    /// Invoice requests by name
+    name_limiter: Limiter,
+}
+
+/// Token buckets allowing `per_minute` requests per key, in bursts of up to a minute's worth.
+/// Every callback makes an invoice, a `receives` row and an `invoice_created` event, so
+/// anyone on the internet could otherwise flood the wallet and the webhooks.
+struct Limiter {
+    per_minute: u32,
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+impl Limiter {
+    fn new(per_minute: u32) -> Self {
+        Limiter {
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn take(&self, key: &str) -> bool {
+        if self.per_minute == 0 {
+            return true;
+        }
+        let burst = self.per_minute as f64;
+        let rate = burst / 60.0;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= LIMITER_MAX_KEYS {
+            buckets.retain(|_, (tokens, refilled)| {
+                *tokens + now.duration_since(*refilled).as_secs_f64() * rate < burst
+            });
+        }
+        let (tokens, refilled) = buckets.entry(key.to_string()).or_insert((burst, now));
+        *tokens = (*tokens + now.duration_since(*refilled).as_secs_f64() * rate).min(burst);
+        *refilled = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+/// URL of the withdraw link with secret `k1`
+pub fn withdraw_url(domain: &str, k1: &str) -> String {
+    format!("https://{domain}/lnurlw/{k1}")
+}
+
+/// `url` as an uppercase bech32 `LNURL1...` string, what wallets scan
+pub fn encode(url: &str) -> Result<String, String> {
+    let hrp = Hrp::parse("lnurl").expect("valid hrp");
+    bech32::encode::<Bech32>(hrp, url.as_bytes())
+        .map(|lnurl| lnurl.to_uppercase())
+        .map_err(|e| format!("Failed to encode LNURL: {e}"))
+}
+
+/// Serves LNURL-pay (LUD-06, LUD-16) for `name@{domain}` and LNURL-withdraw (LUD-03) for the
+/// links made by `withdraw-link` on `addr` until Ctrl+C. Invoices and payouts are made by the
+/// daemon through its control socket.
+pub async fn run(addr: &str, config: LnurlConfig, dir: PathBuf) -> Result<(), String> {
+    if config.min_sats == 0 || config.min_sats > config.max_sats {
+        return Err("[lnurl] min_sats must be at least 1 and at most max_sats".to_string());
+    }
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Failed to listen on {addr}: {e}"))?;
+    let domain = config.domain.clone();
+    let server = Arc::new(Server {
+        ip_limiter: Limiter::new(config.ip_invoices_per_minute),
+        name_limiter: Limiter::new(config.name_invoices_per_minute),
+        config,
+        dir,
+    });
+    let app = Router::new()
+        .route("/.well-known/lnurlp/:name", get(pay_request))
+        .route("/lnurlp/:name/callback", get(callback))
+        .route("/lnurlw/callback", get(withdraw_callback))
+        .route("/lnurlw/:k1", get(withdraw_request))
+        .with_state(server);
+    info!("Serving lightning addresses @{domain} on http://{addr}");
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+    .await
+    .map_err(|e| format!("LNURL server stopped: {e}"))
+}
+
+impl Server {
+    /// Whether `name` is one of the configured names, or any valid one when none are
+    fn serves(&self, name: &str) -> bool {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "-_.".contains(c));
+        valid && (self.config.names.is_empty() || self.config.names.iter().any(|n| n == name))
+    }
+
+    /// Address of the client, the last `X-Forwarded-For` entry when behind a proxy since that
+    /// is the one the proxy added
+    fn client(&self, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+        let forwarded = self
+            .config
+            .behind_proxy
+            .then(|| headers.get("x-forwarded-for")?.to_str().ok())
+            .flatten()
+            .and_then(|v| v.rsplit(',').next()?.trim().parse().ok());
+        forwarded.unwrap_or(peer.ip())
+    }
+
+    fn identifier(&self, name: &str) -> String {
+        format!("{name}@{}", self.config.domain)
+    }
+
+    /// The metadata string of `name`, whose hash a LUD-06 invoice should commit to
+    fn metadata(&self, name: &str) -> String {
+        let identifier = self.identifier(name);
+        let description = match &self.config.description {
+            Some(description) => description.clone(),
+            None => format!("Payment to {identifier}"),
+        };
+        json!([["text/plain", description], ["text/identifier", identifier]]).to_string()
+    }
+}
+
+async fn pay_request(State(server): State<Arc<Server>>, Path(name): Path<String>) -> Response {
+    if !server.serves(&name) {
+        return error(StatusCode::NOT_FOUND, &format!("No such user {name}"));
+    }
+    let config = &server.config;
+    let body = json!({
+        "tag": "payRequest",
+        "callback": format!("https://{}/lnurlp/{name}/callback", config.domain),
+        "minSendable": config.min_sats * 1000,
+        "maxSendable": config.max_sats * 1000,
+        "metadata": server.metadata(&name),
+        "commentAllowed": config.comment_allowed,
+    });
+    Json(body).into_response()
+}
+
+/// `?amount=<msat>[&comment=...]`, answered with a fresh invoice
+async fn callback(
+    State(server): State<Arc<Server>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    if !server.serves(&name) {
+        return error(StatusCode::NOT_FOUND, &format!("No such user {name}"));
+    }
+    let client = server.client(peer, &headers);
+    if !server.ip_limiter.take(&client.to_string()) || !server.name_limiter.take(&name) {
+        warn!(
+            "LNURL invoice for {} from {client}: rate limited",
+            server.identifier(&name)
+        );
+        return error(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many invoices requested, try again later",
+        );
+    }
+    let config = &server.config;
+    let Some(amount_msat) = query.get("amount").and_then(|a| a.parse::<u64>().ok()) else {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "amount is required, in millisatoshis",
+        );
+    };
+    // The wallet makes invoices for whole satoshis
+    if amount_msat % 1000 != 0 {
+        return error(StatusCode::BAD_REQUEST, "amount must be whole satoshis");
+    }
+    let amount_sats = amount_msat / 1000;
+    if amount_sats < config.min_sats || amount_sats > config.max_sats {
+        return error(
+            StatusCode::BAD_REQUEST,
+            &format!(
+                "amount must be between {} and {} sats",
+                config.min_sats, config.max_sats
+            ),
+        );
+    }
+
+    // A zap receipt needs an invoice committing to the zap request, which `receive` can't make,
+    // so `allowsNostr` isn't advertised
+    if query.contains_key("nostr") {
+        return error(StatusCode::BAD_REQUEST, "Zaps are not accepted");
+    }
+
+    let mut args = vec![
+        "receive".to_string(),
+        "--amount".to_string(),
+        amount_sats.to_string(),
+        "--purpose".to_string(),
+        server.identifier(&name),
+    ];
+    if let Some(comment) = query.get("comment").filter(|c| !c.is_empty()) {
+        if comment.chars().count() > config.comment_allowed as usize {
+            return error(
+                StatusCode::BAD_REQUEST,
+                &format!(
+                    "comment is longer than {} characters",
+                    config.comment_allowed
+                ),
+            );
+        }
+        args.push("--meta".to_string());
+        args.push(format!("comment={comment}"));
+    }
+
+    match control::proxy(&server.dir, args).await {
+        Some(Ok(receive)) => Json(json!({
+            "pr": receive["invoice"],
+            "routes": [],
+        }))
+        .into_response(),
+        Some(Err(e)) => {
+            warn!("LNURL invoice for {}: {e}", server.identifier(&name));
+            error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to create an invoice",
+            )
+        }
+        None => error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The wallet is offline, try again later",
+        ),
+    }
+}
+
+async fn withdraw_request(State(server): State<Arc<Server>>, Path(k1): Path<String>) -> Response {
+    let link = match Store::open(&server.dir).and_then(|store| store.withdraw_link(&k1)) {
+        Ok(Some(link)) => link,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "No such voucher"),
+        Err(e) => {
+            warn!("LNURL withdraw: {e}");
+            return error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to look up the voucher",
+            );
+        }
+    };
+    if link.revoked_at.is_some() || link.used >= link.uses {
+        return error(StatusCode::GONE, "This voucher was already redeemed");
+    }
+    let amount_msat = link.amount_sats * 1000;
+    Json(json!({
+        "tag": "withdrawRequest",
+        "callback": format!("https://{}/lnurlw/callback", server.config.domain),
+        "k1": k1,
+        "defaultDescription": link
+            .description
+            .unwrap_or_else(|| format!("{} sats voucher", link.amount_sats)),
+        "minWithdrawable": amount_msat,
+        "maxWithdrawable": amount_msat,
+    }))
+    .into_response()
+}
+
+/// `?k1=<secret>&pr=<invoice>`, paid by the daemon out of the link
+async fn withdraw_callback(
+    State(server): State<Arc<Server>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let (Some(k1), Some(invoice)) = (query.get("k1"), query.get("pr")) else {
+        return error(StatusCode::BAD_REQUEST, "k1 and pr are required");
+    };
+    let args = vec![
+        "withdraw-links".to_string(),
+        "redeem".to_string(),
+        "--".to_string(),
+        k1.clone(),
+        invoice.clone(),
+    ];
+    match control::proxy(&server.dir, args).await {
+        Some(Ok(_)) => Json(json!({ "status": "OK" })).into_response(),
+        Some(Err(e)) => error(StatusCode::BAD_REQUEST, &e),
+        None => error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The wallet is offline, try again later",
+        ),
+    }
+}
+
+/// An LNURL error response
+fn error(status: StatusCode, reason: &str) -> Response {
+    (status, Json(json!({ "status": "ERROR", "reason": reason }))).into_response()
+}
@@ -0,0 +1,95 @@
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+/// Lines below this level are skipped
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// The message alone, as a person would read it
+    #[default]
+    Text,
+    /// One JSON object per line with `timestamp`, `level`, `message` and the line's fields
+    Json,
+}
+
+static SETTINGS: OnceLock<(Format, Level)> = OnceLock::new();
+
+/// Sets the format and level for the rest of the process. Until then lines are text at info.
+pub fn init(format: Format, level: Level) {
+    let _ = SETTINGS.set((format, level));
+}
+
+/// Writes `message` to stderr if `level` is enabled. `fields` (an object or null) only show up
+/// in JSON lines, so a message should make sense without them.
+pub fn write(level: Level, message: &str, fields: serde_json::Value) {
+    let (format, max_level) = SETTINGS.get().copied().unwrap_or_default();
+    if level > max_level {
+        return;
+    }
+    match format {
+        Format::Text => eprintln!("{message}"),
+        Format::Json => {
+            let mut line = serde_json::json!({
+                "timestamp": crate::now_secs(),
+                "level": level_name(level),
+                "message": message,
+            });
+            if let (Some(line), serde_json::Value::Object(fields)) = (line.as_object_mut(), fields)
+            {
+                line.extend(fields);
+            }
+            eprintln!("{line}");
+        }
+    }
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warn => "warn",
+        Level::Info => "info",
+        Level::Debug => "debug",
+    }
+}
+
+/// `log_at!(Info, { "url": url }, "Webhook {url} ...")`, the fields object being optional
+macro_rules! log_at {
+    ($level:ident, { $($fields:tt)* }, $($arg:tt)+) => {
+        $crate::log::write(
+            $crate::log::Level::$level,
+            &format!($($arg)+),
+            serde_json::json!({ $($fields)* }),
+        )
+    };
+    ($level:ident, $($arg:tt)+) => {
+        $crate::log::write($crate::log::Level::$level, &format!($($arg)+), serde_json::Value::Null)
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => { log_at!(Error, $($arg)+) };
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => { log_at!(Warn, $($arg)+) };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => { log_at!(Info, $($arg)+) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => { log_at!(Debug, $($arg)+) };
+}
@@ -1,11 +1,62 @@
+// First, so its logging macros can be used by the modules below
+#[macro_use]
+mod log;
+
+mod chain_cache;
+mod codegen;
 mod config;
+mod control;
+mod email;
+mod exec;
+mod fiat;
+mod grpc;
+mod health;
+mod lnurl;
+mod mcp;
+mod mqtt;
+mod nwc;
+mod openapi;
+mod payroll;
+mod plugin;
+mod privacy;
+mod rpc;
+mod schema;
+mod serve;
+mod signing;
+mod sink;
+mod store;
+mod systemd;
+mod tz;
+mod webhook;
+mod zaps;
 
-use clap::{Parser, Subcommand};
-use config::Config;
-use orange_sdk::bitcoin::hex::DisplayHex;
+use clap::{ArgGroup, CommandFactory, Parser, Subcommand};
+use config::{
+    AckPolicy, Config, DeadManSwitchConfig, DustAction, EventsConfig, FiatConfig, Mode, Overflow,
+    PluginConfig, ReceiveFilterConfig, WebhooksConfig,
+};
+use email::Mailer;
+use exec::ExecHooks;
+use futures_util::StreamExt;
+use orange_sdk::bitcoin::hex::{DisplayHex, FromHex};
 use orange_sdk::bitcoin_payment_instructions::amount::Amount;
-use orange_sdk::{Event, PaymentInfo, Wallet};
+use orange_sdk::{Event, PaymentId, PaymentInfo, TxStatus, Wallet};
+use plugin::Plugins;
 use serde_json::json;
+use signing::Signer;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use store::{CommandStat, SendRecord, SendTimings, Store, StoredEvent, WebhookAttempt};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{Signal, SignalKind, signal};
+use tokio::task::JoinHandle;
+use webhook::{Dispatcher, Posted, Webhook};
 
 #[derive(Parser)]
 #[command(name = "orange", about = "Orange SDK Lightning wallet CLI")]
@@ -14,6 +65,18 @@ struct Cli {
     #[arg(long, default_value = "config.toml")]
     config: String,
 
+    /// Use the `[profiles.<network>]` config section instead of the top-level network
+    #[arg(long, global = true)]
+    network: Option<String>,
+
+    /// Answer read-only commands from the daemon's latest snapshot instead of opening the wallet
+    #[arg(long, global = true)]
+    replica: bool,
+
+    /// Time zone for the `*_iso` timestamps in the output, overrides `timezone` in the config
+    #[arg(long, global = true)]
+    tz: Option<String>,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -27,6 +90,22 @@ enum Command {
         /// Amount in satoshis (optional)
         #[arg(long)]
         amount: Option<u64>,
+        /// Amount in the configured fiat currency, converted at the current BTC price
+        #[arg(long, conflicts_with = "amount")]
+        amount_fiat: Option<f64>,
+        /// Label recorded with the issued address and invoice (e.g. "order-42")
+        #[arg(long)]
+        purpose: Option<String>,
+        /// Hand out the URI even if its on-chain address was issued before
+        #[arg(long)]
+        allow_reuse: bool,
+        /// Payer's email address, the daemon mails them a receipt once paid (needs [smtp])
+        #[arg(long)]
+        email: Option<String>,
+        /// "key=value" recorded with the invoice and added to its events, repeatable. An
+        /// `order_id` key gets at most one open invoice (see `[receive] unique_order_id`).
+        #[arg(long)]
+        meta: Vec<String>,
     },
     /// Get reusable BOLT12 offer
     ReceiveOffer,
@@ -37,6 +116,31 @@ enum Command {
         /// Amount in satoshis (required for addresses and amountless offers)
         #[arg(long)]
         amount: Option<u64>,
+        /// Cost center to bill this payment to (see `report --by-cost-center`)
+        #[arg(long)]
+        cost_center: Option<String>,
+    },
+    /// Sign a payment authorization for `execute`, e.g. on an offline machine
+    Authorize {
+        /// Lightning invoice, lightning address, on-chain address, BOLT12 offer, or BIP21 URI
+        destination: String,
+        /// Most the payment may be for, in satoshis
+        #[arg(long)]
+        max_amount: u64,
+        /// Expiry as a unix timestamp or a delay such as 30m, 24h or 7d
+        #[arg(long, default_value = "24h")]
+        expires: String,
+        /// File to write the signed authorization to
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Pay a signed authorization from `authorize`, if its key is in [authorizations]
+    Execute {
+        /// Authorization file written by `authorize`
+        file: PathBuf,
+        /// Amount in satoshis (required for addresses and amountless offers), within the maximum
+        #[arg(long)]
+        amount: Option<u64>,
     },
     /// Parse a payment string
     Parse {
@@ -45,10 +149,33 @@ enum Command {
     },
     /// List transaction history
     Transactions,
+    /// List payments still in flight, outgoing and incoming, with their ages
+    Pending,
+    /// Cross-check recorded outgoing payments against the wallet's transactions
+    Reconcile {
+        /// Only check payments made at or after this unix timestamp
+        #[arg(long, default_value_t = 0)]
+        since: u64,
+    },
     /// List lightning channels
     Channels,
     /// Get wallet/node information
     Info,
+    /// Sign a timestamped statement of balances, channel outputs and pending payments
+    AuditSnapshot {
+        /// Also write the signed statement to this file
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Pay a small invoice from this wallet to itself and time each step
+    Selftest {
+        /// Amount of the canary payment in satoshis
+        #[arg(long, default_value_t = 10)]
+        amount: u64,
+        /// Give up waiting for the payment to settle after this long
+        #[arg(long, default_value_t = 60)]
+        timeout_secs: u64,
+    },
     /// Estimate fee for a payment
     EstimateFee {
         /// Payment string to estimate fee for
@@ -63,21 +190,441 @@ enum Command {
     },
     /// Run as a long-lived daemon, listening for wallet events
     Daemon {
-        /// Webhook URL, optionally with a Bearer token: "url" or "url|token"
+        /// Webhook URL, optionally with a Bearer token and HMAC secret: "url", "url|token" or "url|token|secret"
         #[arg(long)]
         webhook: Vec<String>,
+        /// Start without a wallet and wait for `orange unlock` to supply the passphrase
+        #[arg(long)]
+        locked: bool,
+        /// Serve /health and /status on this address (e.g. 127.0.0.1:9735)
+        #[arg(long)]
+        listen: Option<String>,
+        /// Plugin program to stream events to as JSON lines on its stdin, can be repeated
+        #[arg(long)]
+        plugin: Vec<String>,
+        /// Write the daemon's process id to this file, removed again on shutdown
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
+        /// Log lines as plain text or JSON objects, overrides `[daemon] log_format`
+        #[arg(long, value_enum)]
+        log_format: Option<log::Format>,
+        /// Skip log lines below this level, overrides `[daemon] log_level`
+        #[arg(long, value_enum)]
+        log_level: Option<log::Level>,
+    },
+    /// Unlock a daemon started with --locked (reads ORANGE_PASSPHRASE or stdin)
+    Unlock,
+    /// List on-chain addresses handed out by `receive`
+    Addresses {
+        /// Only show addresses issued for this purpose
+        #[arg(long)]
+        purpose: Option<String>,
+    },
+    /// Aggregate payments from local records
+    #[command(group(ArgGroup::new("by").required(true)))]
+    Report {
+        /// Attribute incoming payments to the lightning address name registered at the time
+        #[arg(long, group = "by")]
+        by_address_name: bool,
+        /// Total outgoing payments per `send --cost-center` tag
+        #[arg(long, group = "by")]
+        by_cost_center: bool,
+    },
+    /// Compare fiat amounts promised via `receive --amount-fiat` with their current value
+    FiatExposure,
+    /// Check in, postponing the dead man's switch sweep
+    Heartbeat,
+    /// Time-locked sends, executed by the daemon once due
+    Vault {
+        #[command(subcommand)]
+        command: VaultCommand,
+    },
+    /// Nostr Wallet Connect (NIP-47) clients, served by the daemon on the `[nwc]` relays
+    Nwc {
+        #[command(subcommand)]
+        command: NwcCommand,
+    },
+    /// Make an LNURL-withdraw voucher paying `--amount` sats, redeemed through `lnurl-server`
+    WithdrawLink {
+        /// Paid out per redemption, in satoshis
+        #[arg(long)]
+        amount: u64,
+        /// How many times the voucher can be redeemed
+        #[arg(long, default_value_t = 1)]
+        uses: u32,
+        /// Shown by the redeeming wallet, "<amount> sats voucher" by default
+        #[arg(long)]
+        description: Option<String>,
+    },
+    /// Vouchers made by `withdraw-link`
+    WithdrawLinks {
+        #[command(subcommand)]
+        command: WithdrawLinksCommand,
+    },
+    /// Inspect and clean up webhook deliveries that haven't succeeded
+    Outbox {
+        #[command(subcommand)]
+        command: OutboxCommand,
+    },
+    /// Inspect and re-enable webhook endpoints
+    Webhooks {
+        #[command(subcommand)]
+        command: WebhooksCommand,
+    },
+    /// Archive of the events posted by the daemon or acknowledged with `event-handled`
+    Events {
+        #[command(subcommand)]
+        command: EventsCommand,
+    },
+    /// Statistics computed from local payment records
+    Analytics {
+        #[command(subcommand)]
+        command: AnalyticsCommand,
+    },
+    /// Pay contributors from a CSV file
+    Payroll {
+        #[command(subcommand)]
+        command: PayrollCommand,
     },
     /// Get the next pending event from the wallet event queue
     GetEvent,
     /// Mark the current event as handled, removing it from the queue
     EventHandled,
+    /// Take up to --max events off the queue at once, numbered with `seq`
+    GetEvents {
+        #[arg(long, default_value_t = 50)]
+        max: u32,
+    },
+    /// Acknowledge the events returned by `get-events` up to and including --up-to
+    EventsHandled {
+        #[arg(long)]
+        up_to: i64,
+    },
+    /// Where wallet commands spend their time, recorded when `[stats] enabled = true`
+    Stats {
+        /// Only include commands run at or after this unix timestamp
+        #[arg(long, default_value_t = 0)]
+        since: u64,
+    },
+    /// Print the JSON Schema of a command's output (e.g. "webhooks list"), or list the commands
+    Schema {
+        /// Command as typed after `orange`, or "error" for the error object
+        command: Vec<String>,
+    },
+    /// Helpers for signet and other test networks
+    Dev {
+        #[command(subcommand)]
+        command: DevCommand,
+    },
+    /// Serve the commands as an HTTP API, run by the daemon (e.g. `GET /balance`, `POST /send`)
+    Serve {
+        /// Address to listen on, every request must carry `[serve] token`
+        #[arg(long, default_value = "127.0.0.1:3000")]
+        listen: String,
+    },
+    /// Serve lightning addresses `name@domain` from `[lnurl]` over LNURL-pay, with invoices
+    /// made by the daemon
+    LnurlServer {
+        /// Address to listen on, behind a reverse proxy serving `https://{domain}`
+        #[arg(long, default_value = "127.0.0.1:3001")]
+        listen: String,
+    },
+    /// Serve the commands as a gRPC API (proto/orange.proto), run by the daemon
+    Grpc {
+        /// Address to listen on, every call must carry `[serve] token`
+        #[arg(long, default_value = "127.0.0.1:50051")]
+        listen: String,
+    },
+    /// Answer JSON-RPC 2.0 requests on stdin, one per line, with the wallet kept open
+    Rpc {
+        /// Take wallet events off the queue and write them as `event` notifications
+        #[arg(long)]
+        events: bool,
+    },
+    /// Serve wallet tools to AI agents over the Model Context Protocol on stdio, `pay` capped
+    /// by `[mcp] max_spend_sats`
+    Mcp,
+    /// Print an OpenAPI 3.1 document of the `serve` endpoints and the webhook events
+    Openapi,
+    /// Generate a typed client that runs these commands, from the same definitions and schemas
+    Codegen {
+        language: codegen::Language,
+        /// Directory to write the client into, created if missing
+        #[arg(long)]
+        out: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum VaultCommand {
+    /// Queue a payment that the daemon sends no earlier than --not-before
+    Send {
+        /// Lightning invoice, on-chain address, BOLT12 offer, or BIP21 URI
+        payment: String,
+        /// Amount in satoshis (required for addresses and amountless offers)
+        #[arg(long)]
+        amount: Option<u64>,
+        /// Unix timestamp, or a delay from now such as "30m", "24h" or "7d"
+        #[arg(long)]
+        not_before: String,
+        /// Cost center to bill this payment to
+        #[arg(long)]
+        cost_center: Option<String>,
+    },
+    /// List vault sends, newest first
+    List,
+    /// Cancel a vault send that has not been executed yet
+    Cancel {
+        /// Id returned by `vault send`
+        id: i64,
+    },
+}
+
+#[derive(Subcommand)]
+enum NwcCommand {
+    /// Make a connection string for an NWC client such as Alby or Damus
+    Create {
+        /// Name to tell the connection apart, e.g. the app it is for
+        name: String,
+        /// Most the client may spend per --renewal period, in satoshis. Unlimited if unset.
+        #[arg(long)]
+        budget: Option<u64>,
+        #[arg(long, value_enum, default_value = "monthly")]
+        renewal: nwc::BudgetRenewal,
+    },
+    /// List connections with what each spent in its current budget period
+    List,
+    /// Revoke a connection, its client's requests are refused from then on
+    Revoke {
+        /// Id printed by `nwc create`
+        id: i64,
+    },
+}
+
+#[derive(Subcommand)]
+enum WithdrawLinksCommand {
+    /// List vouchers with how often each was redeemed
+    List,
+    /// Revoke a voucher, it can't be redeemed from then on
+    Revoke {
+        /// Id printed by `withdraw-link`
+        id: i64,
+    },
+    /// Pay an invoice out of a voucher, what `lnurl-server` runs on the daemon
+    Redeem {
+        /// The voucher's secret, the last part of its URL
+        k1: String,
+        /// Invoice of the redeeming wallet, for at most the voucher's amount
+        invoice: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum WebhooksCommand {
+    /// Delivery counts and failure streaks of every webhook URL the daemon has posted to
+    List,
+    /// Log of individual webhook POSTs, newest first
+    Deliveries {
+        /// Only show attempts to this URL
+        #[arg(long)]
+        url: Option<String>,
+        /// Only show attempts for this event type (e.g. "payment_received")
+        #[arg(long)]
+        event_type: Option<String>,
+        /// Only show failed attempts
+        #[arg(long)]
+        failed: bool,
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+    },
+    /// Deliver a made-up event through the daemon's signing and retry path
+    Test {
+        /// Webhook in the daemon's --webhook format: "url", "url|token" or "url|token|secret"
+        #[arg(long, required = true)]
+        webhook: Vec<String>,
+        /// Event type to fake (e.g. "payment_received", "invoice_expired")
+        #[arg(long = "type", default_value = "payment_received")]
+        event_type: String,
+    },
+    /// Resume posting to a webhook the daemon disabled after repeated failures
+    Enable {
+        /// Webhook URL, without the "|token" or "|secret" parts
+        url: String,
+    },
+    /// Deliveries that gave up after `[webhooks] max_attempts`
+    DeadLetter {
+        #[command(subcommand)]
+        command: DeadLetterCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum OutboxCommand {
+    /// Deliveries waiting, retrying or dead-lettered, newest first
+    List {
+        /// Only show deliveries in this state
+        #[arg(long, value_enum)]
+        state: Option<OutboxState>,
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+    },
+    /// Delete deliveries created before a date, dead-lettered ones unless --state says otherwise
+    Purge {
+        /// Date (midnight in `timezone`), RFC 3339 time or unix timestamp
+        #[arg(long)]
+        before: String,
+        #[arg(long, value_enum, default_value = "failed")]
+        state: OutboxState,
+    },
+}
+
+/// Where a webhook delivery is: queued and not tried yet, waiting for a retry, or given up on
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum OutboxState {
+    Pending,
+    Retrying,
+    Failed,
+}
+
+impl OutboxState {
+    fn as_str(self) -> &'static str {
+        match self {
+            OutboxState::Pending => "pending",
+            OutboxState::Retrying => "retrying",
+            OutboxState::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum DeadLetterCommand {
+    /// List dead-lettered deliveries with their events, newest first
+    List,
+    /// Queue a dead-lettered delivery again, the daemon posts it within seconds
+    Redeliver {
+        /// Id from `webhooks dead-letter list`
+        id: i64,
+    },
+}
+
+#[derive(Subcommand)]
+enum EventsCommand {
+    /// Archived events, newest first
+    List {
+        /// Only show events of this type (e.g. "payment_received")
+        #[arg(long = "type")]
+        event_type: Option<String>,
+        /// Only show events recorded at or after this unix timestamp
+        #[arg(long)]
+        since: Option<u64>,
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+    },
+    /// Print or re-deliver posted events in `seq` order, starting at --from-seq
+    Replay {
+        #[arg(long)]
+        from_seq: i64,
+        /// Deliver the events to this webhook ("url", "url|token" or "url|token|secret")
+        /// one at a time instead of printing them, stopping at the first failure
+        #[arg(long)]
+        to_webhook: Option<String>,
+    },
+    /// List events waiting to be acknowledged, with indices, without consuming any
+    Peek,
+}
+
+#[derive(Subcommand)]
+enum AnalyticsCommand {
+    /// p50/p95 latency of each phase of succeeded sends (parse, initiate, settle)
+    Latency {
+        /// Only include sends created at or after this unix timestamp
+        #[arg(long, default_value_t = 0)]
+        since: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum DevCommand {
+    /// Request test coins from the `[faucet]` and wait for them to arrive
+    Faucet {
+        /// Amount in satoshis
+        #[arg(long, default_value_t = 100_000)]
+        amount: u64,
+        /// Ask for an on-chain payment to a new address instead of a lightning invoice
+        #[arg(long)]
+        onchain: bool,
+        /// Give up waiting for the coins after this long
+        #[arg(long, default_value_t = 300)]
+        timeout_secs: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum PayrollCommand {
+    /// Pay every row of a CSV with columns name,lightning_address,amount_sats,memo
+    Run {
+        /// Path to the payroll CSV
+        file: PathBuf,
+        /// Batch id used to pay each recipient only once (defaults to the file name)
+        #[arg(long)]
+        batch: Option<String>,
+        /// Directory for signed receipts (defaults to {storage_path}/payroll/{batch})
+        #[arg(long)]
+        receipts_dir: Option<PathBuf>,
+        /// Cost center to bill the payments to
+        #[arg(long)]
+        cost_center: Option<String>,
+    },
 }
 
+/// Set at startup with `mode = "receive-only"` and checked before every payment, so nothing in
+/// this process can move funds, whichever path it takes
+static RECEIVE_ONLY: AtomicBool = AtomicBool::new(false);
+
+const RECEIVE_ONLY_ERROR: &str =
+    "Payments are disabled: this wallet runs with mode = \"receive-only\"";
+
 #[tokio::main]
 async fn main() {
+    let started = Instant::now();
     let cli = Cli::parse();
 
-    let config = match Config::load(&cli.config) {
+    // Schemas describe the CLI itself, so they don't need a config
+    if let Command::Schema { command } = &cli.command {
+        match cmd_schema(command) {
+            Ok(value) => println!("{}", serde_json::to_string_pretty(&value).unwrap()),
+            Err(e) => {
+                print_error(&e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Command::Openapi = &cli.command {
+        match openapi::spec(&Cli::command()) {
+            Ok(value) => println!("{}", serde_json::to_string_pretty(&value).unwrap()),
+            Err(e) => {
+                print_error(&e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Clients are generated from the CLI definition and schemas, no config needed either
+    if let Command::Codegen { language, out } = &cli.command {
+        match codegen::write(*language, &Cli::command(), out) {
+            Ok(value) => println!("{}", serde_json::to_string_pretty(&value).unwrap()),
+            Err(e) => {
+                print_error(&e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut config = match Config::load(&cli.config, cli.network.as_deref()) {
         Ok(c) => c,
         Err(e) => {
             print_error(&e);
@@ -85,7 +632,199 @@ async fn main() {
         }
     };
 
-    let wallet_config = match config.into_wallet_config() {
+    if let Command::Daemon {
+        log_format,
+        log_level,
+        ..
+    } = &cli.command
+    {
+        log::init(
+            log_format.unwrap_or(config.daemon.log_format),
+            log_level.unwrap_or(config.daemon.log_level),
+        );
+    }
+
+    if config.mode == Mode::ReceiveOnly {
+        RECEIVE_ONLY.store(true, Ordering::SeqCst);
+        if spends(&cli.command) {
+            print_error(RECEIVE_ONLY_ERROR);
+            std::process::exit(1);
+        }
+    }
+
+    let tz = match tz::parse(cli.tz.as_deref().unwrap_or(&config.timezone)) {
+        Ok(tz) => tz,
+        Err(e) => {
+            print_error(&e);
+            std::process::exit(1);
+        }
+    };
+
+    // The API servers hand every request to the running daemon, they never open the wallet
+    if let Command::Serve { listen } = &cli.command {
+        let token = config.serve.token.clone();
+        let served = match config.storage_dir() {
+            Ok(dir) => serve::run(listen, token, Cli::command(), dir, tz).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = served {
+            print_error(&e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Command::Grpc { listen } = &cli.command {
+        let token = config.serve.token.clone();
+        let served = match config.storage_dir() {
+            Ok(dir) => grpc::run(listen, token, Cli::command(), dir, tz).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = served {
+            print_error(&e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Command::LnurlServer { listen } = &cli.command {
+        if let Err(e) = cmd_lnurl_server(&config, listen).await {
+            print_error(&e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Command::Unlock = &cli.command {
+        match cmd_unlock(&config).await {
+            Ok(value) => print_value(value, tz),
+            Err(e) => {
+                print_error(&e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // A locked daemon doesn't know which wallet to open until it is unlocked
+    if let Command::Daemon { locked: true, .. } = &cli.command {
+        systemd::notify("STATUS=Waiting for `orange unlock`");
+        let unlocked = tokio::select! {
+            result = wait_for_unlock(&config) => result,
+            _ = tokio::signal::ctrl_c() => return,
+        };
+        match unlocked {
+            Ok(passphrase) => config.unlocked_passphrase = Some(passphrase),
+            Err(e) => {
+                print_error(&e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let store = match config.storage_dir().and_then(|dir| Store::open(&dir)) {
+        Ok(s) => s,
+        Err(e) => {
+            print_error(&e);
+            std::process::exit(1);
+        }
+    };
+
+    // Two daemons on one wallet would both consume its events, and so would `rpc --events`. The
+    // stdio servers keep the wallet open, so they can't share it with a daemon either.
+    let _daemon_lock = match &cli.command {
+        Command::Rpc { .. } | Command::Mcp => {
+            match config.storage_dir().and_then(|dir| lock_daemon(&dir, None)) {
+                Ok(lock) => Some(lock),
+                Err(e) => {
+                    print_error(&e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Daemon { pid_file, .. } => {
+            match config
+                .storage_dir()
+                .and_then(|dir| lock_daemon(&dir, pid_file.as_deref()))
+            {
+                Ok(lock) => Some(lock),
+                Err(e) => {
+                    print_error(&e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => None,
+    };
+
+    // Commands that only read local bookkeeping don't need to start the wallet
+    if let Some(result) = run_local_command(&config, &store, &cli.command).await {
+        match result {
+            Ok(value) => print_value(value, tz),
+            Err(e) => {
+                print_error(&e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if cli.replica {
+        match cmd_replica(&store, &cli.command) {
+            Ok(value) => print_value(value, tz),
+            Err(e) => {
+                print_error(&e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // A running daemon already has the wallet open. Payroll reads files relative to the current
+    // directory, so it always opens the wallet here.
+    if !matches!(
+        cli.command,
+        Command::Payroll { .. } | Command::Daemon { .. } | Command::Rpc { .. } | Command::Mcp
+    ) && let Ok(dir) = config.storage_dir()
+        && let Some(result) = control::proxy(&dir, proxy_args(&cli.command)).await
+    {
+        let ok = result.is_ok();
+        match result {
+            Ok(value) => print_value(value, tz),
+            Err(e) => print_error(&e),
+        }
+        if config.stats.enabled {
+            let total_ms = started.elapsed().as_millis() as u64;
+            let stat = CommandStat {
+                command: format!("{} via daemon", wallet_command_name(&cli.command)),
+                ok,
+                wallet_init_ms: 0,
+                call_ms: total_ms,
+                stop_ms: 0,
+                total_ms,
+                recorded_at: now_secs(),
+            };
+            if let Err(e) = store.record_command_stat(&stat) {
+                error!("{e}");
+            }
+        }
+        if !ok {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if config.chain_cache.enabled {
+        match chain_cache::start(&config.chain_source, &config.chain_cache).await {
+            Ok(url) => config.chain_source.url = Some(url),
+            Err(e) => {
+                print_error(&e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let wallet_config = match config.wallet_config() {
         Ok(c) => c,
         Err(e) => {
             print_error(&e);
@@ -93,6 +832,7 @@ async fn main() {
         }
     };
 
+    let init_started = Instant::now();
     let wallet = match Wallet::new(wallet_config).await {
         Ok(w) => w,
         Err(e) => {
@@ -101,55 +841,501 @@ async fn main() {
         }
     };
 
+    let wallet_init_ms = init_started.elapsed().as_millis() as u64;
+
     // Daemon runs its own loop and never returns a Result value
-    if let Command::Daemon { webhook } = &cli.command {
-        cmd_daemon(&wallet, webhook).await;
+    if let Command::Daemon {
+        webhook,
+        listen,
+        plugin,
+        pid_file,
+        ..
+    } = &cli.command
+    {
+        let reload_from = (cli.config.as_str(), cli.network.as_deref());
+        cmd_daemon(
+            &wallet,
+            &config,
+            &store,
+            webhook,
+            plugin,
+            listen.as_deref(),
+            reload_from,
+        )
+        .await;
+        if let Some(path) = pid_file {
+            let _ = std::fs::remove_file(path);
+        }
         return;
     }
 
-    let result = match cli.command {
-        Command::Balance => cmd_balance(&wallet).await,
-        Command::Receive { amount } => cmd_receive(&wallet, amount).await,
-        Command::ReceiveOffer => cmd_receive_offer(&wallet).await,
-        Command::Send { payment, amount } => cmd_send(&wallet, &payment, amount).await,
-        Command::Parse { payment } => cmd_parse(&wallet, &payment).await,
-        Command::Transactions => cmd_transactions(&wallet).await,
-        Command::Channels => cmd_channels(&wallet),
-        Command::Info => cmd_info(&wallet),
-        Command::EstimateFee { payment } => cmd_estimate_fee(&wallet, &payment).await,
-        Command::LightningAddress => cmd_lightning_address(&wallet).await,
-        Command::RegisterLightningAddress { name } => {
-            cmd_register_lightning_address(&wallet, &name).await
-        }
-        Command::GetEvent => cmd_get_event(&wallet),
-        Command::EventHandled => cmd_event_handled(&wallet),
-        Command::Daemon { .. } => unreachable!(),
-    };
+    if let Command::Rpc { events } = &cli.command {
+        cmd_rpc(&wallet, &config, &store, *events, tz).await;
+        wallet.stop().await;
+        return;
+    }
+
+    if let Command::Mcp = &cli.command {
+        cmd_mcp(&wallet, &config, &store, tz).await;
+        wallet.stop().await;
+        return;
+    }
 
+    let command = wallet_command_name(&cli.command);
+    let call_started = Instant::now();
+    let result = run_wallet_command(&wallet, &config, &store, cli.command).await;
+
+    let call_ms = call_started.elapsed().as_millis() as u64;
+
+    let ok = result.is_ok();
     match result {
-        Ok(value) => {
-            println!("{}", serde_json::to_string_pretty(&value).unwrap());
-            wallet.stop().await;
+        Ok(value) => print_value(value, tz),
+        Err(e) => print_error(&e),
+    }
+    let stop_started = Instant::now();
+    wallet.stop().await;
+
+    if config.stats.enabled {
+        let stat = CommandStat {
+            command: command.to_string(),
+            ok,
+            wallet_init_ms,
+            call_ms,
+            stop_ms: stop_started.elapsed().as_millis() as u64,
+            total_ms: started.elapsed().as_millis() as u64,
+            recorded_at: now_secs(),
+        };
+        if let Err(e) = store.record_command_stat(&stat) {
+            error!("{e}");
         }
-        Err(e) => {
-            print_error(&e);
-            wallet.stop().await;
-            std::process::exit(1);
+    }
+    if !ok {
+        std::process::exit(1);
+    }
+}
+
+/// The command line to hand to the daemon. Files are resolved here, since the daemon's working
+/// directory is not ours.
+fn proxy_args(command: &Command) -> Vec<String> {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // The daemon runs in its own working directory, resolve paths against ours
+    let path = match command {
+        Command::Execute { file, .. } => std::fs::canonicalize(file).ok().map(|p| (file, p)),
+        Command::AuditSnapshot { out: Some(out) } => {
+            std::path::absolute(out).ok().map(|p| (out, p))
+        }
+        _ => None,
+    };
+    if let Some((given, path)) = path {
+        for arg in args.iter_mut() {
+            if Path::new(arg.as_str()) == given {
+                *arg = path.display().to_string();
+            } else if let Some(value) = arg.strip_prefix("--out=")
+                && Path::new(value) == given
+            {
+                *arg = format!("--out={}", path.display());
+            }
         }
     }
+    args
 }
 
-fn print_error(msg: &str) {
-    println!(
-        "{}",
-        serde_json::to_string_pretty(&json!({"error": msg})).unwrap()
-    );
+/// Commands that pay, or queue a payment the daemon makes later
+fn spends(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Send { .. }
+            | Command::Execute { .. }
+            | Command::Selftest { .. }
+            | Command::Payroll { .. }
+            | Command::Vault {
+                command: VaultCommand::Send { .. },
+            }
+            | Command::WithdrawLink { .. }
+            | Command::WithdrawLinks {
+                command: WithdrawLinksCommand::Redeem { .. },
+            }
+    )
 }
 
-async fn cmd_balance(wallet: &Wallet) -> Result<serde_json::Value, String> {
-    let balance = wallet
-        .get_balance()
-        .await
+/// Runs a command that needs the wallet, either in its own process or in the daemon on behalf
+/// of a `control` client
+async fn run_wallet_command(
+    wallet: &Wallet,
+    config: &Config,
+    store: &Store,
+    command: Command,
+) -> Result<serde_json::Value, String> {
+    match command {
+        Command::Balance => cmd_balance(wallet).await,
+        Command::Receive {
+            amount,
+            amount_fiat,
+            purpose,
+            allow_reuse,
+            email,
+            meta,
+        } => {
+            cmd_receive(
+                wallet,
+                store,
+                config,
+                amount,
+                amount_fiat,
+                purpose.as_deref(),
+                allow_reuse,
+                email.as_deref(),
+                &meta,
+            )
+            .await
+        }
+        Command::ReceiveOffer => cmd_receive_offer(wallet).await,
+        Command::Send {
+            payment,
+            amount,
+            cost_center,
+        } => cmd_send(wallet, store, &payment, amount, cost_center.as_deref()).await,
+        Command::Execute { file, amount } => {
+            cmd_execute(wallet, config, store, &file, amount).await
+        }
+        Command::WithdrawLinks {
+            command: WithdrawLinksCommand::Redeem { k1, invoice },
+        } => cmd_withdraw_links_redeem(wallet, store, &k1, &invoice).await,
+        Command::Parse { payment } => cmd_parse(wallet, &payment).await,
+        Command::Transactions => cmd_transactions(wallet).await,
+        Command::Pending => cmd_pending(wallet, store).await,
+        Command::Reconcile { since } => cmd_reconcile(wallet, store, since).await,
+        Command::Channels => cmd_channels(wallet),
+        Command::Info => cmd_info(wallet),
+        Command::AuditSnapshot { out } => cmd_audit_snapshot(wallet, config, out.as_deref()).await,
+        Command::Selftest {
+            amount,
+            timeout_secs,
+        } => cmd_selftest(wallet, store, amount, timeout_secs).await,
+        Command::Dev {
+            command:
+                DevCommand::Faucet {
+                    amount,
+                    onchain,
+                    timeout_secs,
+                },
+        } => cmd_dev_faucet(wallet, config, amount, onchain, timeout_secs).await,
+        Command::EstimateFee { payment } => cmd_estimate_fee(wallet, &payment).await,
+        Command::LightningAddress => cmd_lightning_address(wallet).await,
+        Command::RegisterLightningAddress { name } => {
+            cmd_register_lightning_address(wallet, store, &name).await
+        }
+        Command::Payroll {
+            command:
+                PayrollCommand::Run {
+                    file,
+                    batch,
+                    receipts_dir,
+                    cost_center,
+                },
+        } => {
+            cmd_payroll_run(
+                wallet,
+                config,
+                store,
+                &file,
+                batch,
+                receipts_dir,
+                cost_center.as_deref(),
+            )
+            .await
+        }
+        Command::GetEvent => cmd_get_event(wallet, store, &config.receive_filter),
+        Command::EventHandled => cmd_event_handled(wallet, store, &config.receive_filter),
+        Command::GetEvents { max } => cmd_get_events(wallet, store, &config.receive_filter, max),
+        Command::Events {
+            command: EventsCommand::Peek,
+        } => cmd_events_peek(wallet, store, &config.receive_filter),
+        Command::Daemon { .. }
+        | Command::Unlock
+        | Command::Authorize { .. }
+        | Command::Addresses { .. }
+        | Command::Report { .. }
+        | Command::FiatExposure
+        | Command::Analytics { .. }
+        | Command::Webhooks { .. }
+        | Command::Events { .. }
+        | Command::Vault { .. }
+        | Command::Nwc { .. }
+        | Command::WithdrawLink { .. }
+        | Command::WithdrawLinks { .. }
+        | Command::Outbox { .. }
+        | Command::Heartbeat
+        | Command::Stats { .. }
+        | Command::EventsHandled { .. }
+        | Command::Schema { .. }
+        | Command::Serve { .. }
+        | Command::Rpc { .. }
+        | Command::Mcp
+        | Command::Grpc { .. }
+        | Command::LnurlServer { .. }
+        | Command::Openapi
+        | Command::Codegen { .. } => Err("Not a wallet command".to_string()),
+    }
+}
+
+/// Name `orange stats` groups a wallet command's timings under
+fn wallet_command_name(command: &Command) -> &'static str {
+    match command {
+        Command::Balance => "balance",
+        Command::Receive { .. } => "receive",
+        Command::ReceiveOffer => "receive-offer",
+        Command::Send { .. } => "send",
+        Command::Execute { .. } => "execute",
+        Command::Parse { .. } => "parse",
+        Command::Transactions => "transactions",
+        Command::Pending => "pending",
+        Command::Reconcile { .. } => "reconcile",
+        Command::Channels => "channels",
+        Command::Info => "info",
+        Command::AuditSnapshot { .. } => "audit-snapshot",
+        Command::Selftest { .. } => "selftest",
+        Command::Dev { .. } => "dev faucet",
+        Command::EstimateFee { .. } => "estimate-fee",
+        Command::LightningAddress => "lightning-address",
+        Command::RegisterLightningAddress { .. } => "register-lightning-address",
+        Command::Payroll { .. } => "payroll run",
+        Command::GetEvent => "get-event",
+        Command::EventHandled => "event-handled",
+        Command::GetEvents { .. } => "get-events",
+        Command::Events {
+            command: EventsCommand::Peek,
+        } => "events peek",
+        Command::WithdrawLinks {
+            command: WithdrawLinksCommand::Redeem { .. },
+        } => "withdraw-links redeem",
+        _ => "other",
+    }
+}
+
+/// Prints a command's result, with an ISO 8601 rendering next to each timestamp
+fn print_value(mut value: serde_json::Value, tz: chrono_tz::Tz) {
+    tz::annotate(&mut value, tz);
+    println!("{}", serde_json::to_string_pretty(&value).unwrap());
+}
+
+fn print_error(msg: &str) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&json!({"error": msg})).unwrap()
+    );
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+async fn run_local_command(
+    config: &Config,
+    store: &Store,
+    command: &Command,
+) -> Option<Result<serde_json::Value, String>> {
+    match command {
+        Command::Authorize {
+            destination,
+            max_amount,
+            expires,
+            out,
+        } => Some(cmd_authorize(
+            config,
+            destination,
+            *max_amount,
+            expires,
+            out,
+        )),
+        Command::Addresses { purpose } => Some(cmd_addresses(store, purpose.as_deref())),
+        Command::Report {
+            by_cost_center: true,
+            ..
+        } => Some(cmd_report_by_cost_center(store)),
+        Command::Report { .. } => Some(cmd_report_by_address_name(store)),
+        Command::FiatExposure => Some(cmd_fiat_exposure(store, &config.fiat).await),
+        Command::Analytics {
+            command: AnalyticsCommand::Latency { since },
+        } => Some(cmd_analytics_latency(store, *since)),
+        Command::Stats { since } => Some(cmd_stats(config, store, *since)),
+        Command::EventsHandled { up_to } => Some(cmd_events_handled(store, *up_to)),
+        Command::Vault {
+            command:
+                VaultCommand::Send {
+                    payment,
+                    amount,
+                    not_before,
+                    cost_center,
+                },
+        } => Some(cmd_vault_send(
+            config,
+            store,
+            payment,
+            *amount,
+            not_before,
+            cost_center.as_deref(),
+        )),
+        Command::Vault {
+            command: VaultCommand::List,
+        } => Some(cmd_vault_list(store)),
+        Command::Heartbeat => Some(cmd_heartbeat(config, store)),
+        Command::Vault {
+            command: VaultCommand::Cancel { id },
+        } => Some(cmd_vault_cancel(store, *id)),
+        Command::Nwc {
+            command:
+                NwcCommand::Create {
+                    name,
+                    budget,
+                    renewal,
+                },
+        } => Some(cmd_nwc_create(config, store, name, *budget, *renewal)),
+        Command::Nwc {
+            command: NwcCommand::List,
+        } => Some(cmd_nwc_list(store)),
+        Command::Nwc {
+            command: NwcCommand::Revoke { id },
+        } => Some(cmd_nwc_revoke(store, *id)),
+        Command::WithdrawLink {
+            amount,
+            uses,
+            description,
+        } => Some(cmd_withdraw_link(
+            config,
+            store,
+            *amount,
+            *uses,
+            description.as_deref(),
+        )),
+        Command::WithdrawLinks {
+            command: WithdrawLinksCommand::List,
+        } => Some(cmd_withdraw_links_list(config, store)),
+        Command::WithdrawLinks {
+            command: WithdrawLinksCommand::Revoke { id },
+        } => Some(cmd_withdraw_links_revoke(store, *id)),
+        Command::Webhooks {
+            command: WebhooksCommand::List,
+        } => Some(cmd_webhooks_list(store)),
+        Command::Events {
+            command:
+                EventsCommand::List {
+                    event_type,
+                    since,
+                    limit,
+                },
+        } => Some(cmd_events_list(
+            store,
+            event_type.as_deref(),
+            *since,
+            *limit,
+        )),
+        Command::Events {
+            command:
+                EventsCommand::Replay {
+                    from_seq,
+                    to_webhook,
+                },
+        } => Some(cmd_events_replay(config, store, *from_seq, to_webhook.as_deref()).await),
+        Command::Webhooks {
+            command: WebhooksCommand::Enable { url },
+        } => Some(cmd_webhooks_enable(store, url)),
+        Command::Webhooks {
+            command:
+                WebhooksCommand::Deliveries {
+                    url,
+                    event_type,
+                    failed,
+                    limit,
+                },
+        } => Some(cmd_webhooks_deliveries(
+            store,
+            url.as_deref(),
+            event_type.as_deref(),
+            *failed,
+            *limit,
+        )),
+        Command::Webhooks {
+            command:
+                WebhooksCommand::Test {
+                    webhook,
+                    event_type,
+                },
+        } => Some(cmd_webhooks_test(config, store, webhook, event_type).await),
+        Command::Webhooks {
+            command:
+                WebhooksCommand::DeadLetter {
+                    command: DeadLetterCommand::List,
+                },
+        } => Some(cmd_dead_letter_list(store)),
+        Command::Webhooks {
+            command:
+                WebhooksCommand::DeadLetter {
+                    command: DeadLetterCommand::Redeliver { id },
+                },
+        } => Some(cmd_dead_letter_redeliver(store, *id)),
+        Command::Outbox {
+            command: OutboxCommand::List { state, limit },
+        } => Some(cmd_outbox_list(store, *state, *limit)),
+        Command::Outbox {
+            command: OutboxCommand::Purge { before, state },
+        } => Some(cmd_outbox_purge(config, store, before, *state)),
+        _ => None,
+    }
+}
+
+/// Commands whose output the daemon publishes for `--replica` readers
+const REPLICA_COMMANDS: &[&str] = &["balance", "transactions", "channels"];
+
+fn cmd_replica(store: &Store, command: &Command) -> Result<serde_json::Value, String> {
+    let name = match command {
+        Command::Balance => "balance",
+        Command::Transactions => "transactions",
+        Command::Channels => "channels",
+        _ => {
+            return Err(format!(
+                "Command not available with --replica, supported: {}",
+                REPLICA_COMMANDS.join(", ")
+            ));
+        }
+    };
+    let (mut value, updated_at) = store
+        .snapshot(name)?
+        .ok_or("No replica snapshot yet, is the daemon running?")?;
+    value["snapshot_at"] = json!(updated_at);
+    Ok(value)
+}
+
+/// Saves the output of `REPLICA_COMMANDS` so `--replica` readers see fresh data
+async fn publish_snapshots(wallet: &Wallet, store: &Store) {
+    let now = now_secs();
+    let snapshots = [
+        ("balance", cmd_balance(wallet).await),
+        ("transactions", cmd_transactions(wallet).await),
+        ("channels", cmd_channels(wallet)),
+    ];
+    for (name, result) in snapshots {
+        match result.and_then(|value| store.save_snapshot(name, &value, now)) {
+            Ok(()) => {}
+            Err(e) => warn!("Failed to publish {name} snapshot: {e}"),
+        }
+    }
+}
+
+async fn cmd_balance(wallet: &Wallet) -> Result<serde_json::Value, String> {
+    let balance = wallet
+        .get_balance()
+        .await
         .map_err(|e| format!("Failed to get balance: {e:?}"))?;
     Ok(json!({
         "trusted_sats": balance.trusted.sats_rounding_up(),
@@ -159,10 +1345,49 @@ async fn cmd_balance(wallet: &Wallet) -> Result<serde_json::Value, String> {
     }))
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn cmd_receive(
     wallet: &Wallet,
+    store: &Store,
+    config: &Config,
     amount_sats: Option<u64>,
+    amount_fiat: Option<f64>,
+    purpose: Option<&str>,
+    allow_reuse: bool,
+    payer_email: Option<&str>,
+    meta: &[String],
 ) -> Result<serde_json::Value, String> {
+    if let Some(address) = payer_email {
+        if config.smtp.is_none() {
+            return Err("--email needs an [smtp] section in the config".to_string());
+        }
+        email::validate_address(address)?;
+    }
+    // NaN and negative amounts would saturate to 0 sats
+    if amount_fiat.is_some_and(|fiat| !(fiat.is_finite() && fiat > 0.0)) {
+        return Err("--amount-fiat must be a positive number".to_string());
+    }
+    let meta = parse_meta(meta)?;
+    let order_id = meta.get("order_id").and_then(|v| v.as_str());
+    if config.receive.unique_order_id
+        && let Some(order_id) = order_id
+        && let Some(existing) = store.open_receive_for_order(order_id, now_secs())?
+    {
+        return existing_receive(existing, order_id, amount_sats, amount_fiat);
+    }
+    let fiat_config = &config.fiat;
+    let btc_price = match amount_fiat {
+        Some(_) => Some(fiat::fetch_btc_price(fiat_config).await?),
+        None => None,
+    };
+    let amount_sats = match (amount_fiat, btc_price) {
+        (Some(fiat_amount), Some(price)) => match fiat::fiat_to_sats(fiat_amount, price) {
+            0 => return Err("--amount-fiat is less than 1 sat".to_string()),
+            sats => Some(sats),
+        },
+        _ => amount_sats,
+    };
+
     let amount = match amount_sats {
         Some(sats) => Some(Amount::from_sats(sats).map_err(|_| "Invalid amount".to_string())?),
         None => None,
@@ -173,13 +1398,119 @@ async fn cmd_receive(
         .await
         .map_err(|e| format!("Failed to generate receive URI: {e:?}"))?;
 
-    Ok(json!({
-        "invoice": uri.invoice.to_string(),
-        "address": uri.address.as_ref().map(|a| a.to_string()),
+    let address = uri.address.as_ref().map(|a| a.to_string());
+    let reused = match &address {
+        Some(a) => store.address_issue_count(a)? > 0,
+        None => false,
+    };
+    if reused && !allow_reuse {
+        return Err(format!(
+            "Address {} was already issued, pass --allow-reuse to hand it out again",
+            address.unwrap_or_default()
+        ));
+    }
+
+    let invoice = uri.invoice.to_string();
+    let payment_hash = uri.invoice.payment_hash().to_string();
+    let created_at = now_secs();
+    let expires_at = (uri.invoice.duration_since_epoch() + uri.invoice.expiry_time()).as_secs();
+    store.record_receive(
+        purpose,
+        address.as_deref(),
+        &invoice,
+        &payment_hash,
+        amount_sats,
+        created_at,
+        expires_at,
+        payer_email,
+        (!meta.is_empty())
+            .then(|| serde_json::Value::Object(meta.clone()).to_string())
+            .as_deref(),
+        order_id,
+        &uri.to_string(),
+        uri.from_trusted,
+    )?;
+
+    let mut value = json!({
+        "invoice": invoice,
+        "address": address,
         "amount_sats": uri.amount.map(|a| a.sats_rounding_up()),
         "full_uri": uri.to_string(),
         "from_trusted": uri.from_trusted,
-    }))
+    });
+    if !meta.is_empty() {
+        value["meta"] = serde_json::Value::Object(meta);
+    }
+    if reused {
+        value["warning"] = json!("address reused");
+    }
+    if let (Some(fiat_amount), Some(price), Some(sats)) = (amount_fiat, btc_price, amount_sats) {
+        store.record_fiat_obligation(
+            &payment_hash,
+            fiat_amount,
+            &fiat_config.currency,
+            price,
+            sats,
+            created_at,
+        )?;
+        value["fiat"] = json!({
+            "amount": fiat_amount,
+            "currency": fiat_config.currency,
+            "btc_price": price,
+        });
+    }
+    Ok(value)
+}
+
+/// Parses `--meta key=value` pairs into a JSON object, later keys winning
+fn parse_meta(pairs: &[String]) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+    pairs
+        .iter()
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_string(), json!(value))),
+            _ => Err(format!("Invalid --meta {pair}, expected key=value")),
+        })
+        .collect()
+}
+
+/// `receive` output for an order that already has an open invoice, as long as the amount
+/// asked for is the same
+fn existing_receive(
+    existing: store::Receive,
+    order_id: &str,
+    amount_sats: Option<u64>,
+    amount_fiat: Option<f64>,
+) -> Result<serde_json::Value, String> {
+    let same_amount = match amount_fiat {
+        Some(fiat_amount) => existing.fiat_amount == Some(fiat_amount),
+        None => existing.fiat_amount.is_none() && existing.amount_sats == amount_sats,
+    };
+    if !same_amount {
+        return Err(format!(
+            "Order {order_id} already has an open invoice for a different amount, it expires at {}",
+            existing.expires_at.unwrap_or_default()
+        ));
+    }
+    let meta = receive_meta(&existing);
+    let mut value = json!({
+        "invoice": existing.invoice,
+        "address": existing.address,
+        "amount_sats": existing.amount_sats,
+        "full_uri": existing.full_uri,
+        "from_trusted": existing.from_trusted,
+        "meta": meta,
+        "existing": true,
+    });
+    if let (Some(amount), Some(currency), Some(price)) =
+        (existing.fiat_amount, existing.currency, existing.btc_price)
+    {
+        value["fiat"] = json!({
+            "amount": amount,
+            "currency": currency,
+            "btc_price": price,
+        });
+    }
+    Ok(value)
 }
 
 async fn cmd_receive_offer(wallet: &Wallet) -> Result<serde_json::Value, String> {
@@ -194,249 +1525,3836 @@ async fn cmd_receive_offer(wallet: &Wallet) -> Result<serde_json::Value, String>
 
 async fn cmd_send(
     wallet: &Wallet,
+    store: &Store,
     payment: &str,
     amount_sats: Option<u64>,
+    cost_center: Option<&str>,
 ) -> Result<serde_json::Value, String> {
+    let (payment_id, amount_sats) =
+        send_payment(wallet, store, payment, amount_sats, cost_center).await?;
+    Ok(json!({
+        "payment_id": payment_id,
+        "amount_sats": amount_sats,
+        "status": "initiated",
+    }))
+}
+
+/// Initiates a payment and records it locally, returning its payment id and amount
+async fn send_payment(
+    wallet: &Wallet,
+    store: &Store,
+    payment: &str,
+    amount_sats: Option<u64>,
+    cost_center: Option<&str>,
+) -> Result<(String, u64), String> {
+    send_payment_capped(wallet, store, payment, amount_sats, None, cost_center).await
+}
+
+/// Like `send_payment`, but refuses payments for more than `max_sats`
+async fn send_payment_capped(
+    wallet: &Wallet,
+    store: &Store,
+    payment: &str,
+    amount_sats: Option<u64>,
+    max_sats: Option<u64>,
+    cost_center: Option<&str>,
+) -> Result<(String, u64), String> {
+    if RECEIVE_ONLY.load(Ordering::SeqCst) {
+        return Err(RECEIVE_ONLY_ERROR.to_string());
+    }
     let amount = match amount_sats {
         Some(sats) => Some(Amount::from_sats(sats).map_err(|_| "Invalid amount".to_string())?),
         None => None,
     };
 
+    if let Some(kind) = asset_transfer_kind(payment) {
+        return Err(format!(
+            "Assets not supported: {kind} transfers cannot be sent from this wallet"
+        ));
+    }
+
+    let started_at_ms = now_millis();
     let instructions = wallet
         .parse_payment_instructions(payment)
         .await
         .map_err(|e| format!("Failed to parse payment: {e:?}"))?;
+    let parsed_at_ms = now_millis();
 
     let payment_info = PaymentInfo::build(instructions, amount)
         .map_err(|e| format!("Failed to build payment info: {e:?}"))?;
+    if let Some(max_sats) = max_sats
+        && payment_info.amount().sats_rounding_up() > max_sats
+    {
+        return Err(format!(
+            "Payment of {} sats is more than the {max_sats} sats allowed",
+            payment_info.amount().sats_rounding_up()
+        ));
+    }
 
-    let payment_id = wallet
-        .pay(&payment_info)
-        .await
-        .map_err(|e| format!("Failed to send payment: {e:?}"))?;
+    // Recorded before paying, so a payment that can't be recorded isn't made, and a budget
+    // checked by a concurrent send already counts this one
+    let amount_sats = payment_info.amount().sats_rounding_up();
+    let timings = SendTimings {
+        started_at_ms,
+        parsed_at_ms,
+        initiated_at_ms: 0,
+        settled_at_ms: None,
+    };
+    let reserved = store.reserve_send(payment, amount_sats, cost_center, &timings)?;
+
+    let payment_id = match wallet.pay(&payment_info).await {
+        Ok(payment_id) => payment_id.to_string(),
+        Err(e) => {
+            if let Err(e) = store.release_send(&reserved) {
+                error!("{e}");
+            }
+            return Err(format!("Failed to send payment: {e:?}"));
+        }
+    };
+    if let Err(e) = store.assign_send_id(&reserved, &payment_id, now_millis()) {
+        // The payment is already in flight, so don't report it as failed. The reserved row
+        // keeps counting against budgets.
+        error!("{e}");
+    }
+    Ok((payment_id, amount_sats))
+}
+
+fn cmd_authorize(
+    config: &Config,
+    destination: &str,
+    max_amount_sats: u64,
+    expires: &str,
+    out: &Path,
+) -> Result<serde_json::Value, String> {
+    if let Some(kind) = asset_transfer_kind(destination) {
+        return Err(format!(
+            "Assets not supported: {kind} transfers cannot be sent from this wallet"
+        ));
+    }
+    let now = now_secs();
+    let expires_at = parse_not_before(expires, now)?;
+    if expires_at <= now {
+        return Err("The authorization would already be expired".to_string());
+    }
+    let payload = json!({
+        "type": "payment_authorization",
+        "network": config.network,
+        "destination": destination,
+        "max_amount_sats": max_amount_sats,
+        "created_at": now,
+        "expires_at": expires_at,
+    });
+    let id = signing::payload_id(&payload);
+    let signer = Signer::from_mnemonic(&config.mnemonic()?, config.passphrase().as_deref())?;
+    let signed = signer.sign_json(payload);
 
+    // Never overwrite an authorization that may already have been handed over
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(out)
+        .map_err(|e| format!("Failed to create {}: {e}", out.display()))?;
+    file.write_all(serde_json::to_string_pretty(&signed).unwrap().as_bytes())
+        .map_err(|e| format!("Failed to write {}: {e}", out.display()))?;
     Ok(json!({
-        "payment_id": payment_id.to_string(),
-        "amount_sats": payment_info.amount().sats_rounding_up(),
-        "status": "initiated",
+        "id": id,
+        "file": out.display().to_string(),
+        "pubkey": signed["pubkey"],
+        "destination": destination,
+        "max_amount_sats": max_amount_sats,
+        "expires_at": expires_at,
     }))
 }
 
-async fn cmd_parse(wallet: &Wallet, payment: &str) -> Result<serde_json::Value, String> {
+/// Pays an `authorize` document signed by one of `[authorizations] trusted_keys`, once
+async fn cmd_execute(
+    wallet: &Wallet,
+    config: &Config,
+    store: &Store,
+    file: &Path,
+    amount_sats: Option<u64>,
+) -> Result<serde_json::Value, String> {
+    let content = std::fs::read_to_string(file)
+        .map_err(|e| format!("Failed to read {}: {e}", file.display()))?;
+    let signed: serde_json::Value =
+        serde_json::from_str(&content).map_err(|e| format!("Invalid authorization: {e}"))?;
+    let (pubkey, payload) = signing::verify_json(&signed)?;
+    let pubkey = pubkey.to_string();
+    if !config.authorizations.trusted_keys.contains(&pubkey) {
+        return Err(format!(
+            "Authorization signed by {pubkey}, which is not in [authorizations] trusted_keys"
+        ));
+    }
+    if payload["type"] != "payment_authorization" {
+        return Err("Not a payment authorization".to_string());
+    }
+    if payload["network"] != config.network.as_str() {
+        return Err(format!(
+            "Authorization is for {}, this wallet is on {}",
+            payload["network"], config.network
+        ));
+    }
+    let invalid = |field: &str| format!("Invalid authorization: missing {field}");
+    let destination = payload["destination"]
+        .as_str()
+        .ok_or_else(|| invalid("destination"))?;
+    let max_amount_sats = payload["max_amount_sats"]
+        .as_u64()
+        .ok_or_else(|| invalid("max_amount_sats"))?;
+    let expires_at = payload["expires_at"]
+        .as_u64()
+        .ok_or_else(|| invalid("expires_at"))?;
+
+    let now = now_secs();
+    if now >= expires_at {
+        return Err(format!("Authorization expired at {expires_at}"));
+    }
+    // The fee comes out of the same allowance, so the payment may only be what it leaves
     let instructions = wallet
-        .parse_payment_instructions(payment)
+        .parse_payment_instructions(destination)
         .await
         .map_err(|e| format!("Failed to parse payment: {e:?}"))?;
+    let fee_sats = wallet.estimate_fee(&instructions).await.sats_rounding_up();
+    let Some(max_payment_sats) = max_amount_sats.checked_sub(fee_sats) else {
+        return Err(format!(
+            "The estimated fee of {fee_sats} sats is more than the {max_amount_sats} sats allowed"
+        ));
+    };
+    if amount_sats.is_some_and(|sats| sats > max_payment_sats) {
+        return Err(format!(
+            "Amount plus the estimated fee of {fee_sats} sats is more than the {max_amount_sats} \
+             sats allowed"
+        ));
+    }
+    let id = signing::payload_id(payload);
+    if !store.claim_authorization(&id, &pubkey, now)? {
+        return Err(format!("Authorization {id} was already executed"));
+    }
+
+    let sent = send_payment_capped(
+        wallet,
+        store,
+        destination,
+        amount_sats,
+        Some(max_payment_sats),
+        None,
+    )
+    .await;
+    let (payment_id, amount_sats) = match sent {
+        Ok(sent) => sent,
+        Err(e) => {
+            // Nothing was paid, so the authorization can be tried again
+            if let Err(e) = store.release_authorization(&id) {
+                error!("{e}");
+            }
+            return Err(e);
+        }
+    };
+    if let Err(e) = store.finish_authorization(&id, &payment_id, amount_sats) {
+        error!("{e}");
+    }
     Ok(json!({
-        "parsed": format!("{instructions:?}"),
+        "id": id,
+        "payment_id": payment_id,
+        "destination": destination,
+        "amount_sats": amount_sats,
+        "estimated_fee_sats": fee_sats,
+        "max_amount_sats": max_amount_sats,
+        "status": "initiated",
     }))
 }
 
-async fn cmd_transactions(wallet: &Wallet) -> Result<serde_json::Value, String> {
-    let transactions = wallet
-        .list_transactions()
-        .await
-        .map_err(|e| format!("Failed to list transactions: {e:?}"))?;
-
-    let txs: Vec<serde_json::Value> = transactions
-        .iter()
-        .map(|tx| {
-            json!({
-                "id": tx.id.to_string(),
-                "status": format!("{:?}", tx.status),
-                "outbound": tx.outbound,
-                "amount_sats": tx.amount.map(|a| a.sats_rounding_up()),
-                "fee_sats": tx.fee.map(|a| a.sats_rounding_up()),
-                "payment_type": format!("{:?}", tx.payment_type),
-                "timestamp": tx.time_since_epoch.as_secs(),
-            })
-        })
-        .collect();
-
+fn cmd_vault_send(
+    config: &Config,
+    store: &Store,
+    payment: &str,
+    amount_sats: Option<u64>,
+    not_before: &str,
+    cost_center: Option<&str>,
+) -> Result<serde_json::Value, String> {
+    if let Some(kind) = asset_transfer_kind(payment) {
+        return Err(format!(
+            "Assets not supported: {kind} transfers cannot be sent from this wallet"
+        ));
+    }
+    let now = now_secs();
+    // Scheduled payouts don't all go out on the minute they were set for
+    let not_before = parse_not_before(not_before, now)? + privacy::delay(&config.privacy).as_secs();
+    let id = store.queue_vault_send(payment, amount_sats, cost_center, not_before, now)?;
     Ok(json!({
-        "count": txs.len(),
-        "transactions": txs,
+        "id": id,
+        "payment": payment,
+        "amount_sats": amount_sats,
+        "not_before": not_before,
+        "status": "pending",
     }))
 }
 
-fn cmd_channels(wallet: &Wallet) -> Result<serde_json::Value, String> {
-    let channels = wallet.channels();
-    let chans: Vec<serde_json::Value> = channels
+/// Parses a unix timestamp, or a delay from `now` with an s/m/h/d suffix
+fn parse_not_before(value: &str, now: u64) -> Result<u64, String> {
+    let invalid = || format!("Invalid time: {value} (use a unix timestamp or e.g. 30m, 24h, 7d)");
+    let unit = match value.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86400,
+        _ => return value.parse().map_err(|_| invalid()),
+    };
+    let count: u64 = value[..value.len() - 1].parse().map_err(|_| invalid())?;
+    count
+        .checked_mul(unit)
+        .and_then(|delay| now.checked_add(delay))
+        .ok_or_else(invalid)
+}
+
+fn cmd_vault_list(store: &Store) -> Result<serde_json::Value, String> {
+    let sends: Vec<serde_json::Value> = store
+        .vault_sends(None)?
         .iter()
-        .map(|ch| {
+        .map(|v| {
             json!({
-                "channel_id": ch.channel_id.to_string(),
-                "counterparty_node_id": ch.counterparty_node_id.to_string(),
-                "funding_txo": ch.funding_txo.map(|t| t.to_string()),
-                "is_channel_ready": ch.is_channel_ready,
-                "is_usable": ch.is_usable,
-                "inbound_capacity_sats": ch.inbound_capacity_msat / 1_000,
-                "outbound_capacity_sats": ch.outbound_capacity_msat / 1_000,
-                "channel_value_sats": ch.channel_value_sats,
+                "id": v.id,
+                "payment": v.payment,
+                "amount_sats": v.amount_sats,
+                "cost_center": v.cost_center,
+                "not_before": v.not_before,
+                "status": v.status,
+                "payment_id": v.payment_id,
+                "error": v.error,
+                "created_at": v.created_at,
+                "executed_at": v.executed_at,
             })
         })
         .collect();
-
     Ok(json!({
-        "count": chans.len(),
-        "channels": chans,
+        "count": sends.len(),
+        "vault_sends": sends,
     }))
 }
 
-fn cmd_info(wallet: &Wallet) -> Result<serde_json::Value, String> {
-    let tunables = wallet.get_tunables();
-    Ok(json!({
-        "node_id": wallet.node_id().to_string(),
-        "lsp_connected": wallet.is_connected_to_lsp(),
-        "tunables": {
-            "trusted_balance_limit_sats": tunables.trusted_balance_limit.sats_rounding_up(),
-            "rebalance_min_sats": tunables.rebalance_min.sats_rounding_up(),
-            "onchain_receive_threshold_sats": tunables.onchain_receive_threshold.sats_rounding_up(),
-            "enable_amountless_receive_on_chain": tunables.enable_amountless_receive_on_chain,
-        },
-    }))
+fn cmd_vault_cancel(store: &Store, id: i64) -> Result<serde_json::Value, String> {
+    if !store.transition_vault_send(id, "pending", "cancelled")? {
+        return Err(format!("Vault send {id} is not pending"));
+    }
+    Ok(json!({ "id": id, "status": "cancelled" }))
 }
 
-async fn cmd_estimate_fee(wallet: &Wallet, payment: &str) -> Result<serde_json::Value, String> {
-    let instructions = wallet
-        .parse_payment_instructions(payment)
-        .await
-        .map_err(|e| format!("Failed to parse payment for fee estimation: {e:?}"))?;
-
-    let fee = wallet.estimate_fee(&instructions).await;
+fn cmd_nwc_create(
+    config: &Config,
+    store: &Store,
+    name: &str,
+    budget_sats: Option<u64>,
+    renewal: nwc::BudgetRenewal,
+) -> Result<serde_json::Value, String> {
+    let Some(nwc_config) = &config.nwc else {
+        return Err("nwc create needs an [nwc] section with relays in the config".to_string());
+    };
+    if nwc_config.relays.is_empty() {
+        return Err("[nwc] relays is empty".to_string());
+    }
+    let service_key =
+        signing::nwc_service_key(&config.mnemonic()?, config.passphrase().as_deref())?;
+    let (service, _) = nwc::Service::new(&service_key.secret_bytes())?;
+    let (secret, client_pubkey) = nwc::generate_client();
+    let id = store.create_nwc_connection(
+        name,
+        &client_pubkey,
+        budget_sats,
+        renewal.as_str(),
+        now_secs(),
+    )?;
     Ok(json!({
-        "estimated_fee_sats": fee.sats_rounding_up(),
+        "id": id,
+        "name": name,
+        "client_pubkey": client_pubkey,
+        "budget_sats": budget_sats,
+        "budget_renewal": renewal.as_str(),
+        "connection_uri": nwc::connection_uri(&service.pubkey(), &nwc_config.relays, &secret),
     }))
 }
 
-async fn cmd_lightning_address(wallet: &Wallet) -> Result<serde_json::Value, String> {
-    let address = wallet
-        .get_lightning_address()
-        .await
-        .map_err(|e| format!("Failed to get lightning address: {e:?}"))?;
+fn cmd_nwc_list(store: &Store) -> Result<serde_json::Value, String> {
+    let now = now_secs();
+    let connections = store
+        .nwc_connections()?
+        .into_iter()
+        .map(|c| {
+            let since = nwc::budget_since(&c.budget_renewal, now);
+            let spent_sats = store.cost_center_spent(&format!("nwc:{}", c.id), since)?;
+            Ok(json!({
+                "id": c.id,
+                "name": c.name,
+                "client_pubkey": c.client_pubkey,
+                "budget_sats": c.budget_sats,
+                "budget_renewal": c.budget_renewal,
+                "spent_sats": spent_sats,
+                "created_at": c.created_at,
+                "revoked_at": c.revoked_at,
+            }))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
     Ok(json!({
-        "lightning_address": address,
+        "count": connections.len(),
+        "connections": connections,
     }))
 }
 
-async fn cmd_register_lightning_address(
-    wallet: &Wallet,
-    name: &str,
-) -> Result<serde_json::Value, String> {
-    wallet
-        .register_lightning_address(name.to_string())
-        .await
-        .map_err(|e| format!("Failed to register lightning address: {e:?}"))?;
-
-    let address = wallet
-        .get_lightning_address()
-        .await
-        .map_err(|e| format!("Failed to get lightning address: {e:?}"))?;
+fn cmd_nwc_revoke(store: &Store, id: i64) -> Result<serde_json::Value, String> {
+    if !store.revoke_nwc_connection(id, now_secs())? {
+        return Err(format!("No active NWC connection {id}"));
+    }
+    Ok(json!({ "id": id, "status": "revoked" }))
+}
 
+fn cmd_withdraw_link(
+    config: &Config,
+    store: &Store,
+    amount_sats: u64,
+    uses: u32,
+    description: Option<&str>,
+) -> Result<serde_json::Value, String> {
+    let Some(lnurl_config) = &config.lnurl else {
+        return Err(
+            "withdraw-link needs an [lnurl] section with a domain in the config".to_string(),
+        );
+    };
+    if amount_sats == 0 || uses == 0 {
+        return Err("--amount and --uses must be at least 1".to_string());
+    }
+    let Some(total_sats) = amount_sats.checked_mul(uses as u64) else {
+        return Err("--amount times --uses is too large".to_string());
+    };
+    let k1 = rand::random::<[u8; 32]>().to_lower_hex_string();
+    let id = store.create_withdraw_link(&k1, amount_sats, uses, description, now_secs())?;
+    let url = lnurl::withdraw_url(&lnurl_config.domain, &k1);
     Ok(json!({
-        "registered": true,
-        "lightning_address": address,
+        "id": id,
+        "amount_sats": amount_sats,
+        "uses": uses,
+        "total_sats": total_sats,
+        "description": description,
+        "url": url,
+        "lnurl": lnurl::encode(&url)?,
     }))
 }
 
-async fn cmd_daemon(wallet: &Wallet, webhooks: &[String]) {
-    let client = reqwest::Client::new();
-
-    // Parse "url|token" format
-    let hooks: Vec<(String, Option<String>)> = webhooks
-        .iter()
-        .map(|w| match w.split_once('|') {
-            Some((url, token)) => (url.to_string(), Some(token.to_string())),
-            None => (w.clone(), None),
+fn cmd_withdraw_links_list(config: &Config, store: &Store) -> Result<serde_json::Value, String> {
+    let links = store
+        .withdraw_links()?
+        .into_iter()
+        .map(|link| {
+            // Only vouchers that can still be redeemed are printed again
+            let active = link.revoked_at.is_none() && link.used < link.uses;
+            let lnurl = match &config.lnurl {
+                Some(lnurl_config) if active => {
+                    lnurl::encode(&lnurl::withdraw_url(&lnurl_config.domain, &link.k1)).ok()
+                }
+                _ => None,
+            };
+            json!({
+                "id": link.id,
+                "amount_sats": link.amount_sats,
+                "uses": link.uses,
+                "used": link.used,
+                "description": link.description,
+                "created_at": link.created_at,
+                "revoked_at": link.revoked_at,
+                "lnurl": lnurl,
+            })
         })
-        .collect();
-    let has_webhooks = !hooks.is_empty();
+        .collect::<Vec<_>>();
+    Ok(json!({
+        "count": links.len(),
+        "withdraw_links": links,
+    }))
+}
 
-    eprintln!("Daemon started");
-    if has_webhooks {
-        for (url, token) in &hooks {
-            if token.is_some() {
-                eprintln!("Webhook: {url} (auth: Bearer token)");
-            } else {
-                eprintln!("Webhook: {url}");
-            }
-        }
-    } else {
-        eprintln!("No webhooks configured, events will queue until consumed via get-event/event-handled");
+fn cmd_withdraw_links_revoke(store: &Store, id: i64) -> Result<serde_json::Value, String> {
+    if !store.revoke_withdraw_link(id, now_secs())? {
+        return Err(format!("No active withdraw link {id}"));
     }
-    eprintln!("Press Ctrl+C to stop");
-
-    loop {
-        tokio::select! {
-            event = wallet.next_event_async() => {
-                let timestamp = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs();
-
-                let value = serialize_event(&event, timestamp);
-
-                // POST to all webhooks in parallel, fire-and-forget
-                for (url, token) in &hooks {
-                    let client = client.clone();
-                    let url = url.clone();
-                    let body = value.clone();
-                    let token = token.clone();
-                    tokio::spawn(async move {
-                        let mut req = client.post(&url).json(&body);
-                        if let Some(ref t) = token {
-                            req = req.bearer_auth(t);
-                        }
-                        match req.send().await {
-                            Ok(resp) if !resp.status().is_success() => {
-                                eprintln!("Webhook {url} returned {}", resp.status());
-                            }
-                            Err(e) => {
-                                eprintln!("Webhook {url} failed: {e}");
-                            }
-                            _ => {}
-                        }
-                    });
-                }
-
-                eprintln!("[{timestamp}] {}", value["type"]);
+    Ok(json!({ "id": id, "status": "revoked" }))
+}
 
-                // Only auto-ack when webhooks are configured
-                if has_webhooks {
-                    let _ = wallet.event_handled();
-                }
-            }
-            _ = tokio::signal::ctrl_c() => {
-                eprintln!("Shutting down...");
-                break;
+/// Pays `invoice` out of the voucher with secret `k1`. The use is taken before paying, so two
+/// wallets racing for the last one can't both be paid, and given back if the payment fails.
+async fn cmd_withdraw_links_redeem(
+    wallet: &Wallet,
+    store: &Store,
+    k1: &str,
+    invoice: &str,
+) -> Result<serde_json::Value, String> {
+    // An address or offer would be paid with on-chain or unbounded fees on top of the voucher
+    if !is_bolt11(invoice) {
+        return Err("The voucher only pays BOLT11 invoices".to_string());
+    }
+    let Some(link) = store.withdraw_link(k1)? else {
+        return Err("No such voucher".to_string());
+    };
+    let instructions = wallet
+        .parse_payment_instructions(invoice)
+        .await
+        .map_err(|e| format!("Failed to parse payment: {e:?}"))?;
+    let invoice_sats = PaymentInfo::build(instructions, None)
+        .map_err(|_| "The invoice must have an amount".to_string())?
+        .amount()
+        .sats_rounding_up();
+    // A smaller invoice would still take a whole use
+    if invoice_sats != link.amount_sats {
+        return Err(format!(
+            "The invoice is for {invoice_sats} sats, the voucher pays exactly {}",
+            link.amount_sats
+        ));
+    }
+    let Some(link) = store.claim_withdraw_use(k1)? else {
+        return Err("This voucher was already redeemed".to_string());
+    };
+    let sent = send_payment_capped(
+        wallet,
+        store,
+        invoice,
+        None,
+        Some(link.amount_sats),
+        Some(&format!("withdraw:{}", link.id)),
+    )
+    .await;
+    match sent {
+        Ok((payment_id, amount_sats)) => Ok(json!({
+            "id": link.id,
+            "payment_id": payment_id,
+            "amount_sats": amount_sats,
+            "uses_left": link.uses - link.used,
+        })),
+        Err(e) => {
+            if let Err(e) = store.release_withdraw_use(link.id) {
+                error!("{e}");
             }
+            Err(e)
         }
     }
+}
 
+async fn start_nwc(
+    config: &Config,
+    relays: &[String],
+) -> Result<
+    (
+        nwc::Service,
+        tokio::sync::mpsc::UnboundedReceiver<nwc::Request>,
+    ),
+    String,
+> {
+    let key = signing::nwc_service_key(&config.mnemonic()?, config.passphrase().as_deref())?;
+    let (mut service, requests) = nwc::Service::new(&key.secret_bytes())?;
+    service.connect(relays).await?;
+    info!(
+        "Serving Nostr Wallet Connect as {} on {}",
+        service.pubkey(),
+        relays.join(", ")
+    );
+    Ok((service, requests))
+}
+
+/// Answers one NWC request. `pay_invoice` is answered once the payment settles or fails, so
+/// its request waits in `pending` under the payment id.
+async fn handle_nwc_request(
+    wallet: &Wallet,
+    config: &Config,
+    store: &Store,
+    service: &nwc::Service,
+    pending: &mut HashMap<String, nwc::Request>,
+    request: nwc::Request,
+) {
+    let connection = match store.active_nwc_connection(&request.client.to_hex()) {
+        Ok(Some(connection)) => connection,
+        Ok(None) => {
+            let message = "No active connection for this key".to_string();
+            service
+                .respond(&request, Err((nwc::UNAUTHORIZED, message)))
+                .await;
+            return;
+        }
+        Err(e) => {
+            error!("{e}");
+            service.respond(&request, Err((nwc::INTERNAL, e))).await;
+            return;
+        }
+    };
+    debug!(
+        { "connection": connection.id },
+        "NWC {} from {}", request.method, connection.name
+    );
+    let result = match request.method.as_str() {
+        "pay_invoice" => match nwc_pay(wallet, store, &connection, &request.params).await {
+            Ok(payment_id) => {
+                pending.insert(payment_id, request);
+                return;
+            }
+            Err(e) => Err(e),
+        },
+        "make_invoice" => nwc_make_invoice(wallet, config, store, &request.params).await,
+        "get_balance" => wallet
+            .get_balance()
+            .await
+            .map(|balance| {
+                let balance_msat = balance.available_balance().sats_rounding_up() * 1000;
+                json!({ "balance": balance_msat })
+            })
+            .map_err(|e| (nwc::INTERNAL, format!("Failed to get balance: {e:?}"))),
+        "lookup_invoice" => nwc_lookup_invoice(store, &request.params),
+        "get_info" => Ok(json!({
+            "alias": "orange",
+            "pubkey": wallet.node_id().to_string(),
+            "network": match config.network.as_str() {
+                "bitcoin" => "mainnet",
+                network => network,
+            },
+            "methods": nwc::METHODS,
+            "notifications": [],
+        })),
+        method => Err((nwc::NOT_IMPLEMENTED, format!("No method {method}"))),
+    };
+    service.respond(&request, result).await;
+}
+
+/// Starts an NWC payment within what is left of the connection's budget
+async fn nwc_pay(
+    wallet: &Wallet,
+    store: &Store,
+    connection: &store::NwcConnection,
+    params: &serde_json::Map<String, serde_json::Value>,
+) -> Result<String, nwc::Error> {
+    if RECEIVE_ONLY.load(Ordering::SeqCst) {
+        return Err((nwc::RESTRICTED, RECEIVE_ONLY_ERROR.to_string()));
+    }
+    let Some(invoice) = params.get("invoice").and_then(|v| v.as_str()) else {
+        return Err((nwc::OTHER, "invoice is required".to_string()));
+    };
+    // `send` would also pay addresses and offers, which NIP-47 `pay_invoice` doesn't cover
+    if !is_bolt11(invoice) {
+        return Err((nwc::OTHER, "invoice must be a BOLT11 invoice".to_string()));
+    }
+    // Only used for amountless invoices, in msat like every NWC amount
+    let amount_sats = params
+        .get("amount")
+        .and_then(|v| v.as_u64())
+        .map(|msat| msat.div_ceil(1000));
+    let cost_center = format!("nwc:{}", connection.id);
+    let max_sats = match connection.budget_sats {
+        Some(budget) => {
+            let since = nwc::budget_since(&connection.budget_renewal, now_secs());
+            let spent = store
+                .cost_center_spent(&cost_center, since)
+                .map_err(|e| (nwc::INTERNAL, e))?;
+            if spent >= budget {
+                return Err((
+                    nwc::QUOTA_EXCEEDED,
+                    format!("The {budget} sat budget is used up"),
+                ));
+            }
+            Some(budget - spent)
+        }
+        None => None,
+    };
+    let over_budget = |e: &str| max_sats.is_some() && e.starts_with("Payment of ");
+    send_payment_capped(
+        wallet,
+        store,
+        invoice,
+        amount_sats,
+        max_sats,
+        Some(&cost_center),
+    )
+    .await
+    .map(|(payment_id, _)| payment_id)
+    .map_err(|e| match over_budget(&e) {
+        true => (nwc::QUOTA_EXCEEDED, e),
+        false => (nwc::PAYMENT_FAILED, e),
+    })
+}
+
+async fn nwc_make_invoice(
+    wallet: &Wallet,
+    config: &Config,
+    store: &Store,
+    params: &serde_json::Map<String, serde_json::Value>,
+) -> Result<serde_json::Value, nwc::Error> {
+    let Some(amount_msat) = params.get("amount").and_then(|v| v.as_u64()) else {
+        return Err((nwc::OTHER, "amount is required".to_string()));
+    };
+    let description = params.get("description").and_then(|v| v.as_str());
+    let amount_sats = amount_msat.div_ceil(1000);
+    let created = cmd_receive(
+        wallet,
+        store,
+        config,
+        Some(amount_sats),
+        None,
+        description,
+        false,
+        None,
+        &[],
+    )
+    .await
+    .map_err(|e| (nwc::INTERNAL, e))?;
+    let invoice = created["invoice"].as_str().unwrap_or_default();
+    match store.receive_by_invoice(invoice) {
+        Ok(Some(receive)) => Ok(nwc_incoming(store, &receive)),
+        Ok(None) => Err((nwc::INTERNAL, "The invoice was not recorded".to_string())),
+        Err(e) => Err((nwc::INTERNAL, e)),
+    }
+}
+
+fn nwc_lookup_invoice(
+    store: &Store,
+    params: &serde_json::Map<String, serde_json::Value>,
+) -> Result<serde_json::Value, nwc::Error> {
+    let payment_hash = params.get("payment_hash").and_then(|v| v.as_str());
+    let invoice = params.get("invoice").and_then(|v| v.as_str());
+    let receive = match (payment_hash, invoice) {
+        (Some(hash), _) => store.receive_by_payment_hash(hash),
+        (None, Some(invoice)) => store.receive_by_invoice(invoice),
+        (None, None) => return Err((nwc::OTHER, "payment_hash or invoice is required".into())),
+    }
+    .map_err(|e| (nwc::INTERNAL, e))?;
+    if let Some(receive) = receive {
+        return Ok(nwc_incoming(store, &receive));
+    }
+    // Payments are only found by invoice, sends don't record their payment hash
+    let send = match invoice {
+        Some(invoice) => store
+            .send_by_payment(invoice)
+            .map_err(|e| (nwc::INTERNAL, e))?,
+        None => None,
+    };
+    match send {
+        Some(send) => Ok(json!({
+            "type": "outgoing",
+            "state": match send.status.as_str() {
+                "succeeded" => "settled",
+                "failed" => "failed",
+                _ => "pending",
+            },
+            "invoice": send.payment,
+            "amount": send.amount_sats * 1000,
+            "fees_paid": send.fee_msat.unwrap_or_default(),
+            "created_at": send.created_at,
+        })),
+        None => Err((nwc::NOT_FOUND, "No such invoice".to_string())),
+    }
+}
+
+/// An invoice from `receive` as an NWC transaction
+fn nwc_incoming(store: &Store, receive: &store::Receive) -> serde_json::Value {
+    let receipt = store
+        .receipt_by_payment_hash(&receive.payment_hash)
+        .unwrap_or_else(|e| {
+            error!("{e}");
+            None
+        });
+    let state = match (receipt, receive.expires_at) {
+        (Some(_), _) => "settled",
+        (None, Some(expires_at)) if expires_at <= now_secs() => "expired",
+        (None, _) => "pending",
+    };
+    json!({
+        "type": "incoming",
+        "state": state,
+        "invoice": receive.invoice,
+        "description": receive.purpose,
+        "payment_hash": receive.payment_hash,
+        "amount": receipt
+            .map(|(amount_msat, _)| amount_msat)
+            .or(receive.amount_sats.map(|sats| sats * 1000)),
+        "fees_paid": 0,
+        "created_at": receive.created_at,
+        "expires_at": receive.expires_at,
+        "settled_at": receipt.map(|(_, received_at)| received_at),
+    })
+}
+
+/// Answers the `pay_invoice` request that started the payment `event` settles or fails
+async fn settle_nwc_payment(
+    service: &nwc::Service,
+    pending: &mut HashMap<String, nwc::Request>,
+    event: &Event,
+) {
+    let (payment_id, result) = match event {
+        Event::PaymentSuccessful {
+            payment_id,
+            payment_preimage,
+            fee_paid_msat,
+            ..
+        } => (
+            payment_id.to_string(),
+            Ok(json!({
+                "preimage": payment_preimage.0.to_lower_hex_string(),
+                "fees_paid": fee_paid_msat,
+            })),
+        ),
+        Event::PaymentFailed {
+            payment_id, reason, ..
+        } => (
+            payment_id.to_string(),
+            Err((nwc::PAYMENT_FAILED, format!("Payment failed: {reason:?}"))),
+        ),
+        _ => return,
+    };
+    if let Some(request) = pending.remove(&payment_id) {
+        service.respond(&request, result).await;
+    }
+}
+
+async fn cmd_lnurl_server(config: &Config, listen: &str) -> Result<(), String> {
+    let Some(lnurl) = &config.lnurl else {
+        return Err(
+            "lnurl-server needs an [lnurl] section with a domain in the config".to_string(),
+        );
+    };
+    lnurl::run(listen, lnurl.clone(), config.storage_dir()?).await
+}
+
+/// Connects to the `[zaps]` relays, `None` without a `[zaps]` section
+async fn start_zaps(config: &Config) -> Result<Option<zaps::Zaps>, String> {
+    let Some(zaps_config) = &config.zaps else {
+        return Ok(None);
+    };
+    let Some(nostr) = &config.nostr else {
+        return Err("[zaps] needs a [nostr] secret_key to sign receipts with".to_string());
+    };
+    let zaps = zaps::Zaps::connect(&nostr.secret_key, &zaps_config.relays).await?;
+    info!("Publishing zap receipts as {}", zaps.pubkey());
+    Ok(Some(zaps))
+}
+
+/// Publishes the receipt of the keysend zap `event` pays, when its custom records carry a zap
+/// request whose receipt wasn't published yet
+fn publish_zap_receipt(store: &Store, zaps: &zaps::Zaps, event: &Event) {
+    let Event::PaymentReceived {
+        payment_hash,
+        amount_msat,
+        custom_records,
+        lsp_fee_msats,
+        ..
+    } = event
+    else {
+        return;
+    };
+    let Some(request) = zaps::find_request(custom_records.iter().map(|r| r.value.as_slice()))
+    else {
+        return;
+    };
+    let request_id = request.id.to_hex();
+    let payment_hash = payment_hash.0.to_lower_hex_string();
+    // NIP-57 clients discard a receipt whose invoice doesn't commit to the request, which
+    // invoices from `receive` can't
+    match store.receive_by_payment_hash(&payment_hash) {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            warn!(
+                { "zap_request": request_id },
+                "Not publishing a receipt for zap request {request_id}: it paid an invoice"
+            );
+            return;
+        }
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    }
+    // The wallet hands the event back until it is acked, so claim the receipt before publishing
+    match store.claim_zap_receipt(&request_id, &payment_hash, now_secs()) {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    }
+    // The request's amount is what the sender paid, before the LSP took its fee
+    let paid_msat = amount_msat + lsp_fee_msats.unwrap_or(0);
+    if let Err(e) = zaps.publish_receipt(&request, "", paid_msat) {
+        warn!(
+            { "zap_request": request_id },
+            "Not publishing a receipt for zap request {request_id}: {e}"
+        );
+    }
+}
+
+/// Posts an `invoice_created` event for each receive URI issued since the last check, by this
+/// or any other orange process sharing the wallet storage
+fn announce_receives(store: &Store, dispatcher: &Dispatcher) {
+    let receives = match store.unannounced_receives() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    };
+    for receive in receives {
+        let value = json!({
+            "type": "invoice_created",
+            "timestamp": receive.created_at,
+            "receive_id": receive.id,
+            "payment_hash": receive.payment_hash,
+            "invoice": receive.invoice,
+            "address": receive.address,
+            "amount_sats": receive.amount_sats,
+            "purpose": receive.purpose,
+            "meta": receive_meta(&receive),
+            "fiat_amount": receive.fiat_amount,
+            "currency": receive.currency,
+            "expires_at": receive.expires_at,
+        });
+        dispatcher.post(store, &value);
+        info!(
+            { "event_type": "invoice_created", "payment_hash": receive.payment_hash },
+            "[{}] \"invoice_created\"",
+            receive.created_at
+        );
+        if let Err(e) = store.mark_receive_announced(receive.id, now_secs()) {
+            error!("{e}");
+        }
+    }
+}
+
+fn receive_meta(receive: &store::Receive) -> Option<serde_json::Value> {
+    receive
+        .meta
+        .as_deref()
+        .and_then(|m| serde_json::from_str(m).ok())
+}
+
+/// Posts an `invoice_expired` event for each issued invoice that expired without being paid,
+/// over lightning or on its address
+async fn announce_expired_receives(config: &Config, store: &Store, dispatcher: &Dispatcher) {
+    let now = now_secs();
+    let receives = match store.expired_receives(now) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    };
+    for receive in receives {
+        match paid_onchain(config, store, &receive).await {
+            Ok(false) => {}
+            Ok(true) => {
+                debug!(
+                    "Receive {} was paid on-chain, not announcing it expired",
+                    receive.id
+                );
+                if let Err(e) = store.mark_receive_expired(receive.id, now) {
+                    error!("{e}");
+                }
+                continue;
+            }
+            // Checked again on the next round
+            Err(e) => {
+                warn!("Failed to check receive {} on-chain: {e}", receive.id);
+                continue;
+            }
+        }
+        let value = json!({
+            "type": "invoice_expired",
+            "timestamp": now,
+            "receive_id": receive.id,
+            "payment_hash": receive.payment_hash,
+            "address": receive.address,
+            "amount_sats": receive.amount_sats,
+            "purpose": receive.purpose,
+            "meta": receive_meta(&receive),
+            "expires_at": receive.expires_at,
+        });
+        dispatcher.post(store, &value);
+        info!(
+            { "event_type": "invoice_expired", "payment_hash": receive.payment_hash },
+            "[{now}] \"invoice_expired\""
+        );
+        if let Err(e) = store.mark_receive_expired(receive.id, now) {
+            error!("{e}");
+        }
+    }
+}
+
+/// Whether the address of a receive URI was paid. An esplora chain source is asked about the
+/// address itself. Other chain sources can't be, so an on-chain payment of the URI's amount (of
+/// any amount for amountless URIs) received since the URI was issued counts.
+async fn paid_onchain(
+    config: &Config,
+    store: &Store,
+    receive: &store::Receive,
+) -> Result<bool, String> {
+    let Some(address) = &receive.address else {
+        return Ok(false);
+    };
+    let chain_source = &config.chain_source;
+    let Some(url) = chain_source
+        .url
+        .as_deref()
+        .filter(|_| chain_source.source_type == "esplora")
+    else {
+        return store.onchain_receipt_since(receive.created_at, receive.amount_sats);
+    };
+    let url = format!("{}/address/{address}/txs", url.trim_end_matches('/'));
+    let mut request = reqwest::Client::new()
+        .get(&url)
+        .timeout(std::time::Duration::from_secs(30));
+    if let Some(username) = &chain_source.username {
+        request = request.basic_auth(username, chain_source.password.as_ref());
+    }
+    let txs: Vec<serde_json::Value> = request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("Failed to look up {address}: {e}"))?
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse transactions of {address}: {e}"))?;
+    Ok(!txs.is_empty())
+}
+
+/// Sends every vault send that has become due, reporting each outcome to the webhooks
+async fn execute_vault_sends(wallet: &Wallet, store: &Store, dispatcher: &Dispatcher) {
+    let due = match store.vault_sends(Some(now_secs())) {
+        Ok(d) => d,
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    };
+    for send in due {
+        // A cancel may have landed since the list was read
+        match store.transition_vault_send(send.id, "pending", "executing") {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                error!("{e}");
+                continue;
+            }
+        }
+        let result = send_payment(
+            wallet,
+            store,
+            &send.payment,
+            send.amount_sats,
+            send.cost_center.as_deref(),
+        )
+        .await;
+
+        let timestamp = now_secs();
+        let finished = match &result {
+            Ok((payment_id, _)) => store.finish_vault_send(send.id, Ok(payment_id), timestamp),
+            Err(e) => store.finish_vault_send(send.id, Err(e), timestamp),
+        };
+        if let Err(e) = finished {
+            error!("{e}");
+        }
+
+        let value = match result {
+            Ok((payment_id, amount_sats)) => json!({
+                "type": "vault_send_executed",
+                "timestamp": timestamp,
+                "id": send.id,
+                "payment_id": payment_id,
+                "amount_sats": amount_sats,
+            }),
+            Err(e) => json!({
+                "type": "vault_send_failed",
+                "timestamp": timestamp,
+                "id": send.id,
+                "error": e,
+            }),
+        };
+        info!(
+            { "event_type": value["type"], "payment_id": value["payment_id"] },
+            "[{timestamp}] {} {}",
+            value["type"],
+            send.id
+        );
+        dispatcher.post(store, &value);
+    }
+}
+
+fn cmd_heartbeat(config: &Config, store: &Store) -> Result<serde_json::Value, String> {
+    let now = now_secs();
+    store.record_heartbeat(now)?;
+    let sweep_after = config
+        .dead_man_switch
+        .as_ref()
+        .map(|d| now + d.after_days * 86400);
+    Ok(json!({
+        "heartbeat_at": now,
+        "sweep_after": sweep_after,
+    }))
+}
+
+/// Sweeps the wallet to the recovery destination once the last heartbeat is too old
+async fn check_dead_man_switch(
+    wallet: &Wallet,
+    store: &Store,
+    switch: &DeadManSwitchConfig,
+    dispatcher: &Dispatcher,
+) {
+    let now = now_secs();
+    let heartbeat_at = match store.last_heartbeat() {
+        Ok(Some(at)) => at,
+        // The clock starts the first time the daemon runs with the switch configured
+        Ok(None) => {
+            if let Err(e) = store.record_heartbeat(now) {
+                error!("{e}");
+            }
+            return;
+        }
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    };
+    if now < heartbeat_at + switch.after_days * 86400 {
+        return;
+    }
+    match store.dead_man_sweep(heartbeat_at) {
+        Ok(None) => {}
+        Ok(Some(_)) => return,
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    }
+
+    let cost_center = format!("dead_man_switch:{heartbeat_at}");
+    let result = sweep(wallet, store, &switch.sweep_to, &cost_center).await;
+    let mut value = json!({
+        "type": "dead_man_switch_triggered",
+        "timestamp": now,
+        "last_heartbeat": heartbeat_at,
+        "sweep_to": switch.sweep_to,
+    });
+    match result {
+        Ok(None) => {
+            warn!("[{now}] Dead man's switch due but there is nothing to sweep");
+            return;
+        }
+        Ok(Some((payment_id, amount_sats))) => {
+            // The send row already keeps the next check from sweeping again
+            if let Err(e) = store.record_dead_man_sweep(heartbeat_at, &payment_id, amount_sats, now)
+            {
+                error!("{e}");
+            }
+            value["payment_id"] = json!(payment_id);
+            value["amount_sats"] = json!(amount_sats);
+        }
+        // Retried on the next check, as is a sweep that fails after it started
+        Err(e) => value["error"] = json!(e),
+    }
+    warn!(
+        { "event_type": "dead_man_switch_triggered", "payment_id": value["payment_id"] },
+        "[{now}] \"dead_man_switch_triggered\""
+    );
+    dispatcher.post(store, &value);
+    dispatcher.post_contacts(store, &value);
+}
+
+/// Sends the whole available balance, less the estimated fee, to `destination`. Returns `None`
+/// when there is nothing to send.
+async fn sweep(
+    wallet: &Wallet,
+    store: &Store,
+    destination: &str,
+    cost_center: &str,
+) -> Result<Option<(String, u64)>, String> {
+    let balance = wallet
+        .get_balance()
+        .await
+        .map_err(|e| format!("Failed to get balance: {e:?}"))?;
+    let instructions = wallet
+        .parse_payment_instructions(destination)
+        .await
+        .map_err(|e| format!("Failed to parse sweep destination: {e:?}"))?;
+    let fee = wallet.estimate_fee(&instructions).await;
+    let amount_sats = balance
+        .available_balance()
+        .sats_rounding_up()
+        .saturating_sub(fee.sats_rounding_up());
+    if amount_sats == 0 {
+        return Ok(None);
+    }
+    send_payment(
+        wallet,
+        store,
+        destination,
+        Some(amount_sats),
+        Some(cost_center),
+    )
+    .await
+    .map(Some)
+}
+
+async fn cmd_payroll_run(
+    wallet: &Wallet,
+    config: &Config,
+    store: &Store,
+    file: &std::path::Path,
+    batch: Option<String>,
+    receipts_dir: Option<PathBuf>,
+    cost_center: Option<&str>,
+) -> Result<serde_json::Value, String> {
+    let batch = match batch {
+        Some(b) => b,
+        None => file
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .ok_or("Cannot derive a batch id from the file name, pass --batch")?,
+    };
+    let receipts_dir = match receipts_dir {
+        Some(dir) => dir,
+        None => config.storage_dir()?.join("payroll").join(&batch),
+    };
+    let signer = Signer::from_mnemonic(&config.mnemonic()?, config.passphrase().as_deref())?;
+    payroll::run(
+        wallet,
+        store,
+        &signer,
+        &config.privacy,
+        file,
+        &batch,
+        &receipts_dir,
+        cost_center,
+    )
+    .await
+}
+
+async fn cmd_parse(wallet: &Wallet, payment: &str) -> Result<serde_json::Value, String> {
+    if let Some(kind) = asset_transfer_kind(payment) {
+        return Ok(json!({
+            "asset_transfer": kind,
+            "supported": false,
+        }));
+    }
+
+    let instructions = wallet
+        .parse_payment_instructions(payment)
+        .await
+        .map_err(|e| format!("Failed to parse payment: {e:?}"))?;
+    Ok(json!({
+        "parsed": format!("{instructions:?}"),
+    }))
+}
+
+/// Detects payment strings that move an asset other than bitcoin, which the SDK can't pay
+fn asset_transfer_kind(payment: &str) -> Option<&'static str> {
+    let payment = payment.trim().to_ascii_lowercase();
+    // Taproot Assets address HRPs for mainnet, testnet/signet, regtest and simnet
+    if ["tapbc1", "taptb1", "taprt1", "tapsb1"]
+        .iter()
+        .any(|hrp| payment.starts_with(hrp))
+    {
+        return Some("taproot_assets");
+    }
+    if payment.starts_with("rgb:") {
+        return Some("rgb");
+    }
+    None
+}
+
+async fn cmd_transactions(wallet: &Wallet) -> Result<serde_json::Value, String> {
+    let transactions = wallet
+        .list_transactions()
+        .await
+        .map_err(|e| format!("Failed to list transactions: {e:?}"))?;
+
+    let txs: Vec<serde_json::Value> = transactions
+        .iter()
+        .map(|tx| {
+            json!({
+                "id": tx.id.to_string(),
+                "status": format!("{:?}", tx.status),
+                "outbound": tx.outbound,
+                "amount_sats": tx.amount.map(|a| a.sats_rounding_up()),
+                "fee_sats": tx.fee.map(|a| a.sats_rounding_up()),
+                "payment_type": format!("{:?}", tx.payment_type),
+                "timestamp": tx.time_since_epoch.as_secs(),
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "count": txs.len(),
+        "transactions": txs,
+    }))
+}
+
+async fn cmd_pending(wallet: &Wallet, store: &Store) -> Result<serde_json::Value, String> {
+    let transactions = wallet
+        .list_transactions()
+        .await
+        .map_err(|e| format!("Failed to list transactions: {e:?}"))?;
+    let sends: HashMap<String, SendRecord> = store
+        .sends(0)?
+        .into_iter()
+        .map(|send| (send.payment_id.clone(), send))
+        .collect();
+    let now = now_secs();
+
+    let mut pending: Vec<_> = transactions
+        .iter()
+        .filter(|tx| format!("{:?}", tx.status) == "Pending")
+        .collect();
+    // Longest stuck first
+    pending.sort_by_key(|tx| tx.time_since_epoch);
+    let (outbound, inbound): (Vec<_>, Vec<_>) = pending.into_iter().partition(|tx| tx.outbound);
+
+    let outbound: Vec<serde_json::Value> = outbound
+        .iter()
+        .map(|tx| {
+            let id = tx.id.to_string();
+            let send = sends.get(&id);
+            json!({
+                "payment_id": id,
+                "amount_sats": tx.amount.map(|a| a.sats_rounding_up()),
+                "fee_sats": tx.fee.map(|a| a.sats_rounding_up()),
+                "payment_type": format!("{:?}", tx.payment_type),
+                "timestamp": tx.time_since_epoch.as_secs(),
+                "age_secs": now.saturating_sub(tx.time_since_epoch.as_secs()),
+                "payment": send.map(|s| &s.payment),
+                "cost_center": send.and_then(|s| s.cost_center.as_ref()),
+            })
+        })
+        .collect();
+    let inbound: Vec<serde_json::Value> = inbound
+        .iter()
+        .map(|tx| {
+            json!({
+                "payment_id": tx.id.to_string(),
+                "amount_sats": tx.amount.map(|a| a.sats_rounding_up()),
+                "payment_type": format!("{:?}", tx.payment_type),
+                "timestamp": tx.time_since_epoch.as_secs(),
+                "age_secs": now.saturating_sub(tx.time_since_epoch.as_secs()),
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "count": outbound.len() + inbound.len(),
+        "outbound": outbound,
+        "inbound": inbound,
+    }))
+}
+
+/// Compares the `sends` table with the wallet's outbound transactions, listing sends the wallet
+/// doesn't know, outbound transactions orange didn't record, disagreeing status or amount, and
+/// invoices paid more than once
+async fn cmd_reconcile(
+    wallet: &Wallet,
+    store: &Store,
+    since: u64,
+) -> Result<serde_json::Value, String> {
+    let sends = store.sends(since)?;
+    let transactions = wallet
+        .list_transactions()
+        .await
+        .map_err(|e| format!("Failed to list transactions: {e:?}"))?;
+    let outbound: HashMap<String, _> = transactions
+        .iter()
+        .filter(|tx| tx.outbound && tx.time_since_epoch.as_secs() >= since)
+        .map(|tx| (tx.id.to_string(), tx))
+        .collect();
+
+    let mut orphans = Vec::new();
+    let mut mismatches = Vec::new();
+    for send in &sends {
+        let Some(tx) = outbound.get(&send.payment_id) else {
+            // A failed send moved no money, whether or not the wallet kept it
+            if send.status != "failed" {
+                orphans.push(json!({
+                    "payment_id": send.payment_id,
+                    "payment": send.payment,
+                    "amount_sats": send.amount_sats,
+                    "status": send.status,
+                    "created_at": send.created_at,
+                }));
+            }
+            continue;
+        };
+        let wallet_status = format!("{:?}", tx.status);
+        let expected = match send.status.as_str() {
+            "succeeded" => "Completed",
+            "failed" => "Failed",
+            _ => "Pending",
+        };
+        if wallet_status != expected {
+            mismatches.push(json!({
+                "payment_id": send.payment_id,
+                "field": "status",
+                "local": send.status,
+                "wallet": wallet_status,
+            }));
+        }
+        if let Some(amount) = tx.amount
+            && amount.sats_rounding_up() != send.amount_sats
+        {
+            mismatches.push(json!({
+                "payment_id": send.payment_id,
+                "field": "amount_sats",
+                "local": send.amount_sats,
+                "wallet": amount.sats_rounding_up(),
+            }));
+        }
+    }
+
+    let recorded: HashSet<&str> = sends.iter().map(|s| s.payment_id.as_str()).collect();
+    let mut unknown: Vec<serde_json::Value> = outbound
+        .iter()
+        .filter(|(id, _)| !recorded.contains(id.as_str()))
+        .map(|(id, tx)| {
+            json!({
+                "payment_id": id,
+                "status": format!("{:?}", tx.status),
+                "amount_sats": tx.amount.map(|a| a.sats_rounding_up()),
+                "fee_sats": tx.fee.map(|a| a.sats_rounding_up()),
+                "payment_type": format!("{:?}", tx.payment_type),
+                "timestamp": tx.time_since_epoch.as_secs(),
+            })
+        })
+        .collect();
+    unknown.sort_by_key(|tx| tx["timestamp"].as_u64());
+
+    // Addresses and offers are reusable, but an invoice should only ever be paid once
+    let mut by_invoice: HashMap<&str, Vec<&SendRecord>> = HashMap::new();
+    for send in sends
+        .iter()
+        .filter(|s| s.status != "failed" && is_bolt11(&s.payment))
+    {
+        by_invoice.entry(&send.payment).or_default().push(send);
+    }
+    let mut duplicates: Vec<serde_json::Value> = by_invoice
+        .into_iter()
+        .filter(|(_, sends)| sends.len() > 1)
+        .map(|(payment, sends)| {
+            json!({
+                "payment": payment,
+                "payment_ids": sends.iter().map(|s| &s.payment_id).collect::<Vec<_>>(),
+                "amount_sats": sends.iter().map(|s| s.amount_sats).sum::<u64>(),
+                "first_created_at": sends[0].created_at,
+            })
+        })
+        .collect();
+    duplicates.sort_by_key(|d| d["first_created_at"].as_u64());
+
+    let discrepancies = orphans.len() + unknown.len() + mismatches.len() + duplicates.len();
+    Ok(json!({
+        "since": since,
+        "sends_checked": sends.len(),
+        "transactions_checked": outbound.len(),
+        "discrepancies": discrepancies,
+        "orphans": orphans,
+        "unknown": unknown,
+        "mismatches": mismatches,
+        "duplicates": duplicates,
+    }))
+}
+
+/// Whether a payment string is a BOLT11 invoice, optionally with a `lightning:` prefix
+fn is_bolt11(payment: &str) -> bool {
+    let payment = payment.trim().to_ascii_lowercase();
+    let payment = payment.strip_prefix("lightning:").unwrap_or(&payment);
+    // A lightning address can start with "ln" too
+    payment.starts_with("ln")
+        && !payment.starts_with("lnurl")
+        && !payment.starts_with("lno")
+        && !payment.contains(['@', '?'])
+}
+
+fn cmd_addresses(store: &Store, purpose: Option<&str>) -> Result<serde_json::Value, String> {
+    let addresses: Vec<serde_json::Value> = store
+        .issued_addresses(purpose)?
+        .iter()
+        .map(|a| {
+            json!({
+                "address": a.address,
+                "purposes": a.purposes,
+                "times_issued": a.times_issued,
+                "first_issued": a.first_issued,
+                "last_issued": a.last_issued,
+                "invoice_received_sats": a.invoice_received_msat / 1000,
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "count": addresses.len(),
+        "addresses": addresses,
+    }))
+}
+
+fn cmd_report_by_cost_center(store: &Store) -> Result<serde_json::Value, String> {
+    let totals: Vec<serde_json::Value> = store
+        .cost_center_totals()?
+        .iter()
+        .map(|t| {
+            json!({
+                "cost_center": t.cost_center,
+                "payment_count": t.payment_count,
+                "amount_sats": t.amount_sats,
+                "fee_sats": t.fee_msat.div_ceil(1000),
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "by_cost_center": totals,
+    }))
+}
+
+fn cmd_report_by_address_name(store: &Store) -> Result<serde_json::Value, String> {
+    let names = store.address_names()?;
+    let mut totals = vec![(0u64, 0u64); names.len()];
+    let mut unattributed = (0u64, 0u64);
+
+    // A payment belongs to a name only through an invoice `receive` issued for that address, e.g.
+    // by `lnurl-server`. Payments without a recorded invoice (BOLT12 offers, invoices the SDK
+    // made for its own lightning address) can't be told apart, so they stay unattributed.
+    for (purpose, amount_msat, received_at) in store.receipts_with_purpose()? {
+        let bucket = match purpose {
+            Some(purpose) => {
+                let matching = names.iter().rposition(|n| {
+                    n.lightning_address.as_deref() == Some(purpose.as_str())
+                        && n.registered_at <= received_at
+                });
+                match matching {
+                    Some(i) => &mut totals[i],
+                    None => continue,
+                }
+            }
+            None => &mut unattributed,
+        };
+        bucket.0 += 1;
+        bucket.1 += amount_msat;
+    }
+
+    let by_name: Vec<serde_json::Value> = names
+        .iter()
+        .zip(&totals)
+        .map(|(n, (count, msat))| {
+            json!({
+                "name": n.name,
+                "lightning_address": n.lightning_address,
+                "registered_at": n.registered_at,
+                "payment_count": count,
+                "received_sats": msat / 1000,
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "by_address_name": by_name,
+        "unattributed": {
+            "payment_count": unattributed.0,
+            "received_sats": unattributed.1 / 1000,
+        },
+    }))
+}
+
+fn cmd_analytics_latency(store: &Store, since: u64) -> Result<serde_json::Value, String> {
+    let timings = store.send_timings(since)?;
+    let phase = |f: &dyn Fn(&SendTimings) -> u64| {
+        let mut ms: Vec<u64> = timings.iter().map(f).collect();
+        ms.sort_unstable();
+        json!({
+            "p50_ms": percentile(&ms, 50),
+            "p95_ms": percentile(&ms, 95),
+            "max_ms": ms.last(),
+        })
+    };
+    let settled = |t: &SendTimings| t.settled_at_ms.unwrap_or(t.initiated_at_ms);
+
+    Ok(json!({
+        "count": timings.len(),
+        "since": since,
+        "parse": phase(&|t| t.parsed_at_ms.saturating_sub(t.started_at_ms)),
+        "initiate": phase(&|t| t.initiated_at_ms.saturating_sub(t.parsed_at_ms)),
+        "settle": phase(&|t| settled(t).saturating_sub(t.initiated_at_ms)),
+        "total": phase(&|t| settled(t).saturating_sub(t.started_at_ms)),
+    }))
+}
+
+fn cmd_stats(config: &Config, store: &Store, since: u64) -> Result<serde_json::Value, String> {
+    let phase = |runs: &[CommandStat], f: fn(&CommandStat) -> u64| {
+        let mut ms: Vec<u64> = runs.iter().map(f).collect();
+        ms.sort_unstable();
+        json!({
+            "p50_ms": percentile(&ms, 50),
+            "p95_ms": percentile(&ms, 95),
+            "max_ms": ms.last(),
+        })
+    };
+
+    // Rows come sorted by command
+    let commands: Vec<serde_json::Value> = store
+        .command_stats(since)?
+        .chunk_by(|a, b| a.command == b.command)
+        .map(|runs| {
+            json!({
+                "command": runs[0].command,
+                "count": runs.len(),
+                "failed": runs.iter().filter(|s| !s.ok).count(),
+                "wallet_init": phase(runs, |s| s.wallet_init_ms),
+                "call": phase(runs, |s| s.call_ms),
+                "stop": phase(runs, |s| s.stop_ms),
+                "total": phase(runs, |s| s.total_ms),
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "enabled": config.stats.enabled,
+        "since": since,
+        "commands": commands,
+    }))
+}
+
+/// Nearest-rank percentile of an ascending slice
+fn percentile(sorted: &[u64], p: usize) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+async fn cmd_fiat_exposure(
+    store: &Store,
+    fiat_config: &FiatConfig,
+) -> Result<serde_json::Value, String> {
+    let price = fiat::fetch_btc_price(fiat_config).await?;
+    let exposure = fiat::Exposure::load(store, &fiat_config.currency, price)?;
+    Ok(exposure.to_json())
+}
+
+fn cmd_channels(wallet: &Wallet) -> Result<serde_json::Value, String> {
+    let channels = wallet.channels();
+    let chans: Vec<serde_json::Value> = channels
+        .iter()
+        .map(|ch| {
+            json!({
+                "channel_id": ch.channel_id.to_string(),
+                "counterparty_node_id": ch.counterparty_node_id.to_string(),
+                "funding_txo": ch.funding_txo.map(|t| t.to_string()),
+                "is_channel_ready": ch.is_channel_ready,
+                "is_usable": ch.is_usable,
+                "inbound_capacity_sats": ch.inbound_capacity_msat / 1_000,
+                "outbound_capacity_sats": ch.outbound_capacity_msat / 1_000,
+                "channel_value_sats": ch.channel_value_sats,
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "count": chans.len(),
+        "channels": chans,
+    }))
+}
+
+/// Canary for synthetic monitoring: issues an invoice, pays it from the same wallet and waits
+/// for both sides to complete. Fails with the step that broke.
+async fn cmd_selftest(
+    wallet: &Wallet,
+    store: &Store,
+    amount_sats: u64,
+    timeout_secs: u64,
+) -> Result<serde_json::Value, String> {
+    let started = Instant::now();
+    let amount = Amount::from_sats(amount_sats).map_err(|_| "Invalid amount".to_string())?;
+    let uri = wallet
+        .get_single_use_receive_uri(Some(amount))
+        .await
+        .map_err(|e| format!("Self-test failed at invoice: {e:?}"))?;
+    let invoice_ms = started.elapsed().as_millis() as u64;
+    let payment_hash = uri.invoice.payment_hash().to_string();
+    // Lightning payments are received under an id made of their payment hash
+    let incoming_id = <[u8; 32]>::from_hex(&payment_hash)
+        .map(PaymentId::Lightning)
+        .map_err(|e| format!("Invalid payment hash {payment_hash}: {e}"))?;
+
+    let (payment_id, _) = send_payment(
+        wallet,
+        store,
+        &uri.invoice.to_string(),
+        None,
+        Some("selftest"),
+    )
+    .await
+    .map_err(|e| format!("Self-test failed at send: {e}"))?;
+    let send_ms = started.elapsed().as_millis() as u64 - invoice_ms;
+
+    // Polls the wallet rather than its event queue, which belongs to the daemon or get-event
+    let deadline = started + std::time::Duration::from_secs(timeout_secs);
+    let (mut settle_ms, mut receive_ms, mut fee_sats) = (None, None, None);
+    while settle_ms.is_none() || receive_ms.is_none() {
+        if Instant::now() >= deadline {
+            let step = if settle_ms.is_none() {
+                "settle"
+            } else {
+                "receive"
+            };
+            return Err(format!(
+                "Self-test failed at {step}: payment {payment_id} not done after {timeout_secs}s"
+            ));
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        let transactions = wallet
+            .list_transactions()
+            .await
+            .map_err(|e| format!("Failed to list transactions: {e:?}"))?;
+        let elapsed = started.elapsed().as_millis() as u64;
+        for tx in &transactions {
+            if tx.outbound && tx.id.to_string() == payment_id {
+                match tx.status {
+                    TxStatus::Failed => {
+                        return Err(format!(
+                            "Self-test failed at settle: payment {payment_id} failed"
+                        ));
+                    }
+                    TxStatus::Completed if settle_ms.is_none() => {
+                        settle_ms = Some(elapsed - invoice_ms - send_ms);
+                        fee_sats = tx.fee.map(|f| f.sats_rounding_up());
+                    }
+                    _ => {}
+                }
+            }
+            if !tx.outbound
+                && tx.id == incoming_id
+                && matches!(tx.status, TxStatus::Completed)
+                && receive_ms.is_none()
+            {
+                receive_ms = Some(elapsed - invoice_ms - send_ms);
+            }
+        }
+        // A payment received by a trusted backend has an id of its own. If the daemon or
+        // get-event already saw it arrive, its receipt is on record under the invoice's hash.
+        if receive_ms.is_none() && store.receipt_by_payment_hash(&payment_hash)?.is_some() {
+            receive_ms = Some(elapsed - invoice_ms - send_ms);
+        }
+    }
+
+    Ok(json!({
+        "payment_id": payment_id,
+        "amount_sats": amount_sats,
+        "fee_sats": fee_sats,
+        "invoice_ms": invoice_ms,
+        "send_ms": send_ms,
+        "settle_ms": settle_ms,
+        "receive_ms": receive_ms,
+        "total_ms": started.elapsed().as_millis() as u64,
+    }))
+}
+
+/// Requests test coins from `[faucet]` and waits until the wallet sees them: settled for
+/// lightning, in the mempool or confirmed for on-chain
+async fn cmd_dev_faucet(
+    wallet: &Wallet,
+    config: &Config,
+    amount_sats: u64,
+    onchain: bool,
+    timeout_secs: u64,
+) -> Result<serde_json::Value, String> {
+    if config.network == "bitcoin" {
+        return Err("The faucet is for test networks, this wallet is on bitcoin".to_string());
+    }
+    let started = Instant::now();
+    let started_at = now_secs();
+    let amount = Amount::from_sats(amount_sats).map_err(|_| "Invalid amount".to_string())?;
+    let uri = wallet
+        .get_single_use_receive_uri(Some(amount))
+        .await
+        .map_err(|e| format!("Failed to create receive URI: {e:?}"))?;
+    let (method, body) = if onchain {
+        let address = uri
+            .address
+            .as_ref()
+            .ok_or("The wallet has no on-chain address for this amount")?;
+        (
+            "onchain",
+            json!({ "address": address.to_string(), "sats": amount_sats }),
+        )
+    } else {
+        ("lightning", json!({ "bolt11": uri.invoice.to_string() }))
+    };
+
+    let url = format!("{}/api/{method}", config.faucet.url.trim_end_matches('/'));
+    let mut request = reqwest::Client::new()
+        .post(&url)
+        .json(&body)
+        .timeout(std::time::Duration::from_secs(60));
+    if let Some(token) = &config.faucet.token {
+        request = request.bearer_auth(token);
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach the faucet at {url}: {e}"))?;
+    let status = response.status();
+    let text = response.text().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(format!("The faucet returned {status}: {}", text.trim()));
+    }
+    let faucet = serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text));
+
+    // Matched by time and amount, since the faucet's payment id isn't ours
+    let deadline = started + std::time::Duration::from_secs(timeout_secs);
+    loop {
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "Faucet coins not seen after {timeout_secs}s, they may still arrive"
+            ));
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        let transactions = wallet
+            .list_transactions()
+            .await
+            .map_err(|e| format!("Failed to list transactions: {e:?}"))?;
+        let arrived = transactions.iter().find(|tx| {
+            let status = format!("{:?}", tx.status);
+            !tx.outbound
+                && tx.time_since_epoch.as_secs() >= started_at
+                && tx
+                    .amount
+                    .is_some_and(|a| a.sats_rounding_up() <= amount_sats)
+                && (status == "Completed" || (onchain && status == "Pending"))
+        });
+        if let Some(tx) = arrived {
+            return Ok(json!({
+                "network": config.network,
+                "method": method,
+                "amount_sats": amount_sats,
+                "payment_id": tx.id.to_string(),
+                "status": format!("{:?}", tx.status),
+                "received_sats": tx.amount.map(|a| a.sats_rounding_up()),
+                "faucet": faucet,
+                "wait_ms": started.elapsed().as_millis() as u64,
+            }));
+        }
+    }
+}
+
+fn cmd_info(wallet: &Wallet) -> Result<serde_json::Value, String> {
+    let tunables = wallet.get_tunables();
+    Ok(json!({
+        "node_id": wallet.node_id().to_string(),
+        "lsp_connected": wallet.is_connected_to_lsp(),
+        "tunables": {
+            "trusted_balance_limit_sats": tunables.trusted_balance_limit.sats_rounding_up(),
+            "rebalance_min_sats": tunables.rebalance_min.sats_rounding_up(),
+            "onchain_receive_threshold_sats": tunables.onchain_receive_threshold.sats_rounding_up(),
+            "enable_amountless_receive_on_chain": tunables.enable_amountless_receive_on_chain,
+        },
+    }))
+}
+
+/// Statement of what the wallet holds, signed with the wallet-derived key for auditors. The
+/// SDK keeps on-chain funds in channels, so their funding outputs are the UTXOs listed.
+async fn cmd_audit_snapshot(
+    wallet: &Wallet,
+    config: &Config,
+    out: Option<&Path>,
+) -> Result<serde_json::Value, String> {
+    let balance = wallet
+        .get_balance()
+        .await
+        .map_err(|e| format!("Failed to get balance: {e:?}"))?;
+    let transactions = wallet
+        .list_transactions()
+        .await
+        .map_err(|e| format!("Failed to list transactions: {e:?}"))?;
+    let channels = wallet.channels();
+
+    let utxos: Vec<serde_json::Value> = channels
+        .iter()
+        .filter_map(|ch| {
+            ch.funding_txo.map(|txo| {
+                json!({
+                    "outpoint": txo.to_string(),
+                    "channel_value_sats": ch.channel_value_sats,
+                    "balance_sats": ch.outbound_capacity_msat / 1_000,
+                    "channel_id": ch.channel_id.to_string(),
+                })
+            })
+        })
+        .collect();
+    let channel_balances: Vec<serde_json::Value> = channels
+        .iter()
+        .map(|ch| {
+            json!({
+                "channel_id": ch.channel_id.to_string(),
+                "counterparty_node_id": ch.counterparty_node_id.to_string(),
+                "funding_txo": ch.funding_txo.map(|t| t.to_string()),
+                "is_usable": ch.is_usable,
+                "channel_value_sats": ch.channel_value_sats,
+                "outbound_capacity_sats": ch.outbound_capacity_msat / 1_000,
+                "inbound_capacity_sats": ch.inbound_capacity_msat / 1_000,
+            })
+        })
+        .collect();
+    let (mut pending_outbound, mut pending_inbound) = (0u64, 0u64);
+    for tx in transactions
+        .iter()
+        .filter(|tx| format!("{:?}", tx.status) == "Pending")
+    {
+        let amount = tx.amount.map_or(0, |a| a.sats_rounding_up());
+        if tx.outbound {
+            pending_outbound += amount;
+        } else {
+            pending_inbound += amount;
+        }
+    }
+
+    let payload = json!({
+        "type": "audit_snapshot",
+        "network": config.network,
+        "node_id": wallet.node_id().to_string(),
+        "timestamp": now_secs(),
+        "balances": {
+            "trusted_sats": balance.trusted.sats_rounding_up(),
+            "lightning_sats": balance.lightning.sats_rounding_up(),
+            "pending_sats": balance.pending_balance.sats_rounding_up(),
+            "available_sats": balance.available_balance().sats_rounding_up(),
+        },
+        "utxos": utxos,
+        "channels": channel_balances,
+        "pending": {
+            "outbound_sats": pending_outbound,
+            "inbound_sats": pending_inbound,
+        },
+    });
+    let id = signing::payload_id(&payload);
+    let signer = Signer::from_mnemonic(&config.mnemonic()?, config.passphrase().as_deref())?;
+    let signed = signer.sign_json(payload);
+
+    if let Some(out) = out {
+        // A statement may already have been handed to an auditor, never overwrite it
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(out)
+            .map_err(|e| format!("Failed to create {}: {e}", out.display()))?;
+        file.write_all(serde_json::to_string_pretty(&signed).unwrap().as_bytes())
+            .map_err(|e| format!("Failed to write {}: {e}", out.display()))?;
+    }
+    let mut value = signed;
+    value["id"] = json!(id);
+    value["file"] = json!(out.map(|out| out.display().to_string()));
+    Ok(value)
+}
+
+async fn cmd_estimate_fee(wallet: &Wallet, payment: &str) -> Result<serde_json::Value, String> {
+    if let Some(kind) = asset_transfer_kind(payment) {
+        return Err(format!(
+            "Assets not supported: cannot estimate fees for {kind} transfers"
+        ));
+    }
+
+    let instructions = wallet
+        .parse_payment_instructions(payment)
+        .await
+        .map_err(|e| format!("Failed to parse payment for fee estimation: {e:?}"))?;
+
+    let fee = wallet.estimate_fee(&instructions).await;
+    Ok(json!({
+        "estimated_fee_sats": fee.sats_rounding_up(),
+    }))
+}
+
+async fn cmd_lightning_address(wallet: &Wallet) -> Result<serde_json::Value, String> {
+    let address = wallet
+        .get_lightning_address()
+        .await
+        .map_err(|e| format!("Failed to get lightning address: {e:?}"))?;
+    Ok(json!({
+        "lightning_address": address,
+    }))
+}
+
+async fn cmd_register_lightning_address(
+    wallet: &Wallet,
+    store: &Store,
+    name: &str,
+) -> Result<serde_json::Value, String> {
+    wallet
+        .register_lightning_address(name.to_string())
+        .await
+        .map_err(|e| format!("Failed to register lightning address: {e:?}"))?;
+
+    let address = wallet
+        .get_lightning_address()
+        .await
+        .map_err(|e| format!("Failed to get lightning address: {e:?}"))?;
+    store.record_address_name(name, address.as_deref(), now_secs())?;
+
+    Ok(json!({
+        "registered": true,
+        "lightning_address": address,
+    }))
+}
+
+/// Listens on `{storage_path}/unlock.sock` until `orange unlock` sends the passphrase of an
+/// existing wallet, and returns it
+async fn wait_for_unlock(config: &Config) -> Result<String, String> {
+    let path = config.storage_root()?.join("unlock.sock");
+    let _ = std::fs::remove_file(&path);
+    let listener =
+        UnixListener::bind(&path).map_err(|e| format!("Failed to bind {}: {e}", path.display()))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("Failed to restrict {}: {e}", path.display()))?;
+    info!("Daemon locked, waiting for `orange unlock`");
+
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .map_err(|e| format!("Failed to accept unlock connection: {e}"))?;
+        let (read, mut write) = stream.into_split();
+        let mut line = String::new();
+        if BufReader::new(read).read_line(&mut line).await.is_err() {
+            continue;
+        }
+        let passphrase = serde_json::from_str::<serde_json::Value>(&line)
+            .ok()
+            .and_then(|v| v["passphrase"].as_str().map(str::to_string));
+
+        // Any passphrase derives a valid wallet, so only accept ones that were used before
+        // to keep a typo from starting a fresh empty wallet
+        let reply = match &passphrase {
+            None => Err("Malformed unlock request".to_string()),
+            Some(p) => match config.wallet_dir(Some(p)) {
+                Ok(dir) if dir.exists() => Ok(()),
+                Ok(_) => Err("No wallet exists for that passphrase".to_string()),
+                Err(e) => Err(e),
+            },
+        };
+        let response = match &reply {
+            Ok(()) => json!({ "ok": true }),
+            Err(e) => json!({ "error": e }),
+        };
+        let _ = write.write_all(format!("{response}\n").as_bytes()).await;
+
+        if let (Ok(()), Some(passphrase)) = (reply, passphrase) {
+            let _ = std::fs::remove_file(&path);
+            info!("Daemon unlocked");
+            return Ok(passphrase);
+        }
+    }
+}
+
+async fn cmd_unlock(config: &Config) -> Result<serde_json::Value, String> {
+    let passphrase = match std::env::var("ORANGE_PASSPHRASE") {
+        Ok(p) => p,
+        Err(_) => {
+            eprint!("Passphrase: ");
+            let mut line = String::new();
+            std::io::stdin()
+                .read_line(&mut line)
+                .map_err(|e| format!("Failed to read passphrase: {e}"))?;
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+
+    let path = config.storage_root()?.join("unlock.sock");
+    let stream = UnixStream::connect(&path)
+        .await
+        .map_err(|e| format!("No locked daemon at {}: {e}", path.display()))?;
+    let (read, mut write) = stream.into_split();
+    write
+        .write_all(format!("{}\n", json!({ "passphrase": passphrase })).as_bytes())
+        .await
+        .map_err(|e| format!("Failed to send passphrase: {e}"))?;
+
+    let mut line = String::new();
+    BufReader::new(read)
+        .read_line(&mut line)
+        .await
+        .map_err(|e| format!("Failed to read daemon response: {e}"))?;
+    let response: serde_json::Value =
+        serde_json::from_str(&line).map_err(|e| format!("Invalid daemon response: {e}"))?;
+    match response["error"].as_str() {
+        Some(e) => Err(e.to_string()),
+        None => Ok(json!({ "unlocked": true })),
+    }
+}
+
+/// Takes an exclusive lock on `{dir}/daemon.lock`, held until the returned file is dropped,
+/// and records the process id there and in `pid_file`
+fn lock_daemon(
+    dir: &std::path::Path,
+    pid_file: Option<&std::path::Path>,
+) -> Result<std::fs::File, String> {
+    let path = dir.join("daemon.lock");
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(std::fs::TryLockError::WouldBlock) => {
+            let pid = std::fs::read_to_string(&path).unwrap_or_default();
+            return Err(format!(
+                "Another daemon (pid {}) is already running on {}",
+                pid.trim(),
+                dir.display()
+            ));
+        }
+        Err(std::fs::TryLockError::Error(e)) => {
+            return Err(format!("Failed to lock {}: {e}", path.display()));
+        }
+    }
+
+    let pid = std::process::id().to_string();
+    file.set_len(0)
+        .and_then(|_| file.write_all(pid.as_bytes()))
+        .map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+    if let Some(pid_file) = pid_file {
+        std::fs::write(pid_file, format!("{pid}\n"))
+            .map_err(|e| format!("Failed to write {}: {e}", pid_file.display()))?;
+    }
+    Ok(file)
+}
+
+async fn cmd_daemon(
+    wallet: &Wallet,
+    config: &Config,
+    store: &Store,
+    webhooks: &[String],
+    plugins: &[String],
+    listen: Option<&str>,
+    (config_path, network): (&str, Option<&str>),
+) {
+    let hooks = match daemon_webhooks(webhooks, &config.webhooks) {
+        Ok(hooks) => hooks,
+        Err(e) => {
+            print_error(&e);
+            wallet.stop().await;
+            return;
+        }
+    };
+    let contacts = config
+        .dead_man_switch
+        .iter()
+        .flat_map(|d| &d.notify)
+        .map(|w| Webhook::parse(w))
+        .collect();
+    let (mut dispatcher, mut deliveries) = Dispatcher::new(hooks, contacts, &config.webhooks);
+    if dispatcher.is_batching() && config.webhooks.ack_policy != AckPolicy::Outbox {
+        print_error(
+            "[webhooks] ack_policy can't wait for single events when batch_window_ms is set",
+        );
+        wallet.stop().await;
+        return;
+    }
+    if config.mode == Mode::ReceiveOnly && config.dead_man_switch.is_some() {
+        print_error("[dead_man_switch] can't sweep the wallet with mode = \"receive-only\"");
+        wallet.stop().await;
+        return;
+    }
+    dispatcher.set_exec_hooks(ExecHooks::new(&config.exec_hooks));
+    dispatcher.set_plugins(Plugins::start(&daemon_plugins(plugins, &config.plugins)));
+    if let Some(mqtt) = &config.mqtt {
+        match mqtt::Mqtt::connect(mqtt) {
+            Ok(mqtt) => dispatcher.set_mqtt(mqtt),
+            Err(e) => {
+                print_error(&e);
+                wallet.stop().await;
+                return;
+            }
+        }
+    }
+    // Webhooks, exec hooks, plugins and MQTT all take events off the wallet's queue
+    let mut has_hooks = dispatcher.has_hooks();
+    let mailer = match config.smtp.as_ref().map(|smtp| {
+        let signer = Signer::from_mnemonic(&config.mnemonic()?, config.passphrase().as_deref())?;
+        Mailer::new(smtp, signer, config.fiat.clone(), config.storage_dir()?)
+    }) {
+        Some(Ok(m)) => Some(Arc::new(m)),
+        Some(Err(e)) => {
+            warn!("Receipt emails disabled: {e}");
+            None
+        }
+        None => None,
+    };
+
+    let status = Arc::new(std::sync::Mutex::new(health::Status {
+        node_id: wallet.node_id().to_string(),
+        started_at: now_secs(),
+        ..Default::default()
+    }));
+    if let Some(addr) = listen {
+        refresh_status(wallet, store, &status).await;
+        if let Err(e) = health::start(addr, Arc::clone(&status)).await {
+            print_error(&e);
+            wallet.stop().await;
+            std::process::exit(1);
+        }
+    }
+
+    info!("Daemon started");
+    if let Some(addr) = listen {
+        info!("Status: http://{addr}/status");
+    }
+    log_webhooks(&dispatcher);
+    if !has_hooks && config.events.limits_queue() {
+        info!("Holding events for get-events/events-handled, within the [events] limits");
+    }
+    info!("Press Ctrl+C to stop");
+
+    let mut snapshot_refresh = tokio::time::interval(std::time::Duration::from_secs(60));
+    let mut vault_check = tokio::time::interval(std::time::Duration::from_secs(30));
+    let mut receive_check = tokio::time::interval(std::time::Duration::from_secs(5));
+    let mut expiry_check = tokio::time::interval(std::time::Duration::from_secs(60));
+    let mut dead_man_check = tokio::time::interval(std::time::Duration::from_secs(3600));
+    let mut webhook_retry = tokio::time::interval(std::time::Duration::from_secs(5));
+    // Deliveries held back by `max_per_second` go out as soon as their endpoint has a token
+    let mut throttle_retry = tokio::time::interval(std::time::Duration::from_millis(200));
+    let mut batch_flush = tokio::time::interval(std::time::Duration::from_millis(
+        config.webhooks.batch_window_ms.max(1),
+    ));
+    let mut delivery_log_prune = tokio::time::interval(std::time::Duration::from_secs(3600));
+    let mut status_refresh = tokio::time::interval(std::time::Duration::from_secs(5));
+    let mut drift_check = tokio::time::interval(std::time::Duration::from_secs(
+        config.fiat.drift_check_interval_secs,
+    ));
+    // Readiness isn't reported through wallet events, so channels are compared to the last look
+    let mut channel_check = tokio::time::interval(std::time::Duration::from_secs(10));
+    let mut channel_states = channel_states(wallet);
+    // Under systemd, READY=1 waits for the LSP connection and the loop answers the watchdog
+    let mut ready = !systemd::is_enabled();
+    let mut ready_check = tokio::time::interval(std::time::Duration::from_secs(1));
+    // On Ctrl+C or SIGTERM, only events and webhook deliveries are handled until they settle
+    let started_at = now_secs();
+    let mut drain_deadline: Option<u64> = None;
+    let mut drain_check = tokio::time::interval(std::time::Duration::from_secs(1));
+    let mut sighup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            print_error(&format!("Failed to listen for SIGHUP: {e}"));
+            wallet.stop().await;
+            return;
+        }
+    };
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(s) => s,
+        Err(e) => {
+            print_error(&format!("Failed to listen for SIGTERM: {e}"));
+            wallet.stop().await;
+            return;
+        }
+    };
+    // Other CLI invocations run their wallet commands through this instead of opening the wallet
+    let control_dir = match config.storage_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            print_error(&e);
+            wallet.stop().await;
+            return;
+        }
+    };
+    let control = match control::bind(&control_dir) {
+        Ok(listener) => listener,
+        Err(e) => {
+            print_error(&e);
+            wallet.stop().await;
+            return;
+        }
+    };
+    // Answers Nostr Wallet Connect requests on the `[nwc]` relays, payments settle through events
+    let (nwc_service, mut nwc_requests) = match &config.nwc {
+        Some(nwc_config) => match start_nwc(config, &nwc_config.relays).await {
+            Ok(service) => service,
+            Err(e) => {
+                print_error(&e);
+                wallet.stop().await;
+                return;
+            }
+        },
+        None => nwc::Service::disabled(),
+    };
+    let mut nwc_pending: HashMap<String, nwc::Request> = HashMap::new();
+    let zaps = match start_zaps(config).await {
+        Ok(zaps) => zaps,
+        Err(e) => {
+            print_error(&e);
+            wallet.stop().await;
+            return;
+        }
+    };
+    // Control requests run alongside the loop, so a long `send` or `selftest` doesn't hold up
+    // events, timers or the watchdog
+    let mut control_requests = futures_util::stream::FuturesUnordered::new();
+    let watchdog = systemd::watchdog_interval();
+    let mut watchdog_ping =
+        tokio::time::interval(watchdog.unwrap_or(std::time::Duration::from_secs(3600)));
+
+    // Under `ack_policy` other than outbox, the posted event waiting for its deliveries
+    let ack_policy = config.webhooks.ack_policy;
+    let mut pending_ack: Option<PendingAck> = None;
+    let mut dropped = DroppedEvents::default();
+
+    loop {
+        let draining = drain_deadline.is_some();
+        // Without hooks, `[events]` limits make the daemon hold events for `get-events`
+        let holding = !has_hooks && config.events.limits_queue();
+        let blocked = holding
+            && config.events.overflow == Overflow::Block
+            && is_queue_full(store, &config.events);
+        tokio::select! {
+            event = wallet.next_event_async(), if pending_ack.is_none() && !blocked => {
+                let timestamp = now_secs();
+                record_event(store, &event, timestamp);
+                if let Some(mailer) = &mailer {
+                    email_receipt(store, mailer, &event, timestamp);
+                }
+                if !nwc_pending.is_empty() {
+                    settle_nwc_payment(&nwc_service, &mut nwc_pending, &event).await;
+                }
+                if let Some(zaps) = &zaps {
+                    publish_zap_receipt(store, zaps, &event);
+                }
+
+                let mut value = serialize_event(store, &event, timestamp);
+
+                if is_dust(&event, &config.receive_filter) {
+                    match config.receive_filter.dust_action {
+                        DustAction::Flag => value["dust"] = json!(true),
+                        DustAction::Ignore => {
+                            debug!("[{timestamp}] {} (dust, ignored)", value["type"]);
+                            let _ = wallet.event_handled();
+                            continue;
+                        }
+                    }
+                }
+
+                // Without hooks the event stays queued until `event-handled` archives it
+                let posted = if has_hooks {
+                    dispatcher.post(store, &value)
+                } else {
+                    Posted::default()
+                };
+
+                info!(
+                    {
+                        "event_type": value["type"],
+                        "payment_id": value["payment_id"],
+                        "payment_hash": value["payment_hash"],
+                    },
+                    "[{timestamp}] {}",
+                    value["type"]
+                );
+
+                // Only auto-ack when hooks are configured or the event is held in the store.
+                // Exec hooks have no outbox, so the event waits for them in any case.
+                if has_hooks {
+                    let outbox_ids = match ack_policy {
+                        AckPolicy::Outbox => Vec::new(),
+                        _ => posted.outbox_ids,
+                    };
+                    if outbox_ids.is_empty() && posted.exec.is_none() {
+                        ack_event(wallet, store).await;
+                    } else {
+                        pending_ack = Some(PendingAck::new(outbox_ids, posted.exec));
+                    }
+                } else if holding {
+                    hold_event(store, &config.events, &value, timestamp, &mut dropped);
+                    ack_event(wallet, store).await;
+                }
+
+                // Goes where the event it comes from went
+                if let Some(alert) =
+                    lsp_fee_alert(store, &config.receive_filter, &event, timestamp)
+                {
+                    if has_hooks {
+                        dispatcher.post(store, &alert);
+                    } else if holding {
+                        hold_event(store, &config.events, &alert, timestamp, &mut dropped);
+                    }
+                }
+            }
+            Some(delivery) = deliveries.recv() => {
+                let last_attempt = dispatcher.is_last_attempt(delivery.attempt);
+                let ack = pending_ack
+                    .as_mut()
+                    .is_some_and(|p| p.record(&delivery, ack_policy, last_attempt));
+                dispatcher.record(store, delivery, now_secs());
+                if ack {
+                    pending_ack = None;
+                    ack_event(wallet, store).await;
+                }
+            }
+            _ = exec_hooks_done(&mut pending_ack) => {
+                let ack = pending_ack.as_mut().is_some_and(|p| {
+                    p.exec = None;
+                    p.is_finished(ack_policy)
+                });
+                if ack {
+                    pending_ack = None;
+                    ack_event(wallet, store).await;
+                }
+            }
+            _ = webhook_retry.tick() => {
+                dispatcher.retry_due(store, now_secs());
+            }
+            _ = throttle_retry.tick(), if dispatcher.is_throttled() => {
+                dispatcher.retry_due(store, now_secs());
+            }
+            _ = batch_flush.tick(), if dispatcher.is_batching() => {
+                dispatcher.flush(store);
+            }
+            _ = delivery_log_prune.tick() => {
+                let cutoff = now_secs().saturating_sub(config.webhooks.delivery_log_days * 86400);
+                if let Err(e) = store.prune_webhook_attempts(cutoff) {
+                    error!("{e}");
+                }
+                if config.events.history_days > 0 {
+                    let cutoff = now_secs().saturating_sub(config.events.history_days * 86400);
+                    if let Err(e) = store.prune_events(cutoff) {
+                        error!("{e}");
+                    }
+                }
+                if config.events.max_age_hours > 0 {
+                    let cutoff = now_secs().saturating_sub(config.events.max_age_hours * 3600);
+                    match store.drop_stale_pulled_events(cutoff) {
+                        Ok(count) => dropped.add(count as u64, now_secs()),
+                        Err(e) => error!("{e}"),
+                    }
+                }
+                report_dropped_events(store, &config.events, &mut dropped);
+            }
+            _ = status_refresh.tick(), if listen.is_some() => {
+                refresh_status(wallet, store, &status).await;
+            }
+            _ = receive_check.tick(), if !draining => {
+                announce_receives(store, &dispatcher);
+            }
+            _ = expiry_check.tick(), if !draining => {
+                announce_expired_receives(config, store, &dispatcher).await;
+                if let Some(mailer) = &mailer {
+                    retry_receipt_emails(store, mailer);
+                }
+            }
+            _ = vault_check.tick(), if config.mode == Mode::Full && !draining => {
+                execute_vault_sends(wallet, store, &dispatcher).await;
+            }
+            _ = dead_man_check.tick(), if config.dead_man_switch.is_some() && !draining => {
+                if let Some(switch) = &config.dead_man_switch {
+                    check_dead_man_switch(wallet, store, switch, &dispatcher).await;
+                }
+            }
+            _ = snapshot_refresh.tick(), if !draining => {
+                publish_snapshots(wallet, store).await;
+            }
+            _ = drift_check.tick(), if config.fiat.drift_alert_percent.is_some() && !draining => {
+                let threshold = config.fiat.drift_alert_percent.unwrap_or_default();
+                let currency = &config.fiat.currency;
+                let checked = fiat::fetch_btc_price(&config.fiat).await.and_then(|price| {
+                    let exposure = fiat::Exposure::load(store, currency, price)?;
+                    let drifted = fiat::drifted_obligations(store, currency, price, threshold)?;
+                    Ok((exposure, drifted))
+                });
+                let (exposure, drifted) = match checked {
+                    Ok(checked) => checked,
+                    Err(e) => {
+                        warn!("Fiat drift check failed: {e}");
+                        continue;
+                    }
+                };
+                // Each receive is alerted on once, not again on every check while it drifts
+                if !drifted.is_empty() {
+                    let timestamp = now_secs();
+                    let mut value = json!({ "type": "fiat_drift", "timestamp": timestamp });
+                    if let (Some(obj), serde_json::Value::Object(fields)) =
+                        (value.as_object_mut(), exposure.to_json())
+                    {
+                        obj.extend(fields);
+                    }
+                    value["payment_hashes"] = json!(drifted);
+                    if let Err(e) = store.mark_fiat_drift_alerted(&drifted, timestamp) {
+                        error!("{e}");
+                    }
+                    dispatcher.post(store, &value);
+                    let drift = exposure.drift_percent();
+                    info!(
+                        { "event_type": "fiat_drift" },
+                        "[{timestamp}] \"fiat_drift\" {drift:.2}%"
+                    );
+                }
+            }
+            _ = channel_check.tick(), if !draining => {
+                for value in channel_changes(wallet, &mut channel_states) {
+                    let (event_type, channel_id) = (&value["type"], &value["channel_id"]);
+                    info!(
+                        { "event_type": event_type, "channel_id": channel_id },
+                        "[{}] {event_type} {channel_id}",
+                        value["timestamp"]
+                    );
+                    dispatcher.post(store, &value);
+                }
+            }
+            _ = ready_check.tick(), if !ready => {
+                if wallet.is_connected_to_lsp() {
+                    systemd::notify("READY=1\nSTATUS=Connected to LSP");
+                    ready = true;
+                }
+            }
+            _ = watchdog_ping.tick(), if watchdog.is_some() => {
+                systemd::notify("WATCHDOG=1");
+            }
+            _ = shutdown_signal(&mut sigterm) => {
+                if draining {
+                    warn!("Stopping without waiting for the drain");
+                    break;
+                }
+                let timeout = config.daemon.drain_timeout_secs;
+                info!("Draining for up to {timeout}s, signal again to stop now");
+                systemd::notify("STOPPING=1");
+                dispatcher.flush(store);
+                dispatcher.retry_due(store, now_secs());
+                drain_deadline = Some(now_secs() + timeout);
+            }
+            _ = sighup.recv() => {
+                // Only webhooks, exec hooks and plugins are reloaded, other settings need a
+                // restart. Plugins are restarted even when unchanged.
+                let reloaded = Config::load(config_path, network).and_then(|reloaded| {
+                    let hooks = daemon_webhooks(webhooks, &reloaded.webhooks)?;
+                    let plugins = daemon_plugins(plugins, &reloaded.plugins);
+                    Ok((hooks, ExecHooks::new(&reloaded.exec_hooks), plugins))
+                });
+                match reloaded {
+                    Ok((hooks, exec_hooks, plugins)) => {
+                        dispatcher.set_hooks(hooks);
+                        dispatcher.set_exec_hooks(exec_hooks);
+                        dispatcher.set_plugins(Plugins::start(&plugins));
+                        has_hooks = dispatcher.has_hooks();
+                        info!("Reloaded webhooks, exec hooks and plugins from {config_path}");
+                        log_webhooks(&dispatcher);
+                    }
+                    Err(e) => error!("Failed to reload {config_path}, keeping hooks: {e}"),
+                }
+            }
+            Some(request) = nwc_requests.recv(), if !draining => {
+                handle_nwc_request(
+                    wallet,
+                    config,
+                    store,
+                    &nwc_service,
+                    &mut nwc_pending,
+                    request,
+                )
+                .await;
+            }
+            accepted = control.accept(), if control_requests.len() < MAX_CONTROL_REQUESTS => {
+                match accepted {
+                    Ok((stream, _)) => {
+                        let request = serve_control(wallet, config, store, stream, draining);
+                        control_requests.push(request);
+                    }
+                    Err(e) => warn!("Failed to accept control connection: {e}"),
+                }
+            }
+            Some(()) = control_requests.next(), if !control_requests.is_empty() => {}
+            _ = drain_check.tick(), if draining => {
+                let backlog = store.webhook_backlog().unwrap_or_else(|e| {
+                    error!("{e}");
+                    0
+                });
+                let sends = store.in_flight_sends(started_at).unwrap_or_else(|e| {
+                    error!("{e}");
+                    0
+                });
+                let commands = control_requests.len();
+                if backlog == 0 && sends == 0 && commands == 0 {
+                    info!("Drained");
+                    break;
+                }
+                if drain_deadline.is_some_and(|deadline| now_secs() >= deadline) {
+                    warn!(
+                        "Drain timed out, {backlog} deliveries, {sends} payments and {commands} \
+                        commands pending"
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    // Batched events not posted yet are kept in the outbox for the next start
+    dispatcher.flush(store);
+    let _ = std::fs::remove_file(control::socket_path(&control_dir));
     wallet.stop().await;
 }
 
-fn cmd_get_event(wallet: &Wallet) -> Result<serde_json::Value, String> {
-    match wallet.next_event() {
-        Some(event) => {
-            let timestamp = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs();
-            Ok(serialize_event(&event, timestamp))
+/// Events dropped from the `get-events` queue since the last `events_dropped` report
+#[derive(Default)]
+struct DroppedEvents {
+    count: u64,
+    first_dropped_at: Option<u64>,
+}
+
+impl DroppedEvents {
+    fn add(&mut self, count: u64, at: u64) {
+        if count == 0 {
+            return;
+        }
+        warn!("[{at}] Dropped {count} queued event(s), see [events] max_queued/max_age_hours");
+        self.count += count;
+        self.first_dropped_at.get_or_insert(at);
+    }
+}
+
+fn is_queue_full(store: &Store, config: &EventsConfig) -> bool {
+    config.max_queued > 0
+        && store
+            .pulled_event_count()
+            .is_ok_and(|count| count >= config.max_queued)
+}
+
+/// Adds an event to the queue `get-events` reads, dropping the oldest or this one when the
+/// queue is full. Dropped events stay in the event history for `events list`.
+fn hold_event(
+    store: &Store,
+    config: &EventsConfig,
+    value: &serde_json::Value,
+    timestamp: u64,
+    dropped: &mut DroppedEvents,
+) {
+    let event_type = value["type"].as_str().unwrap_or_default();
+    let payload = value.to_string();
+    if config.overflow == Overflow::DropNewest && is_queue_full(store, config) {
+        if let Err(e) = store.append_event(event_type, &payload, timestamp) {
+            error!("{e}");
+        }
+        dropped.add(1, timestamp);
+        return;
+    }
+    if let Err(e) = store.pull_event(event_type, &payload, timestamp) {
+        error!("{e}");
+    }
+    if config.overflow == Overflow::DropOldest && config.max_queued > 0 {
+        match store.drop_oldest_pulled_events(config.max_queued) {
+            Ok(count) => dropped.add(count as u64, timestamp),
+            Err(e) => error!("{e}"),
+        }
+    }
+}
+
+/// Queues one `events_dropped` event for the drops since the last report. It is added past
+/// the limits, so the consumer learns about lost events even when the queue stays full.
+fn report_dropped_events(store: &Store, config: &EventsConfig, dropped: &mut DroppedEvents) {
+    let Some(first_dropped_at) = dropped.first_dropped_at else {
+        return;
+    };
+    let now = now_secs();
+    let value = json!({
+        "type": "events_dropped",
+        "timestamp": now,
+        "count": dropped.count,
+        "first_dropped_at": first_dropped_at,
+        "overflow": config.overflow.as_str(),
+        "max_queued": config.max_queued,
+        "max_age_hours": config.max_age_hours,
+    });
+    match store.pull_event("events_dropped", &value.to_string(), now) {
+        Ok(_) => *dropped = DroppedEvents::default(),
+        Err(e) => error!("{e}"),
+    }
+}
+
+/// Takes the current event off the wallet's queue
+async fn ack_event(wallet: &Wallet, store: &Store) {
+    let _ = wallet.event_handled();
+    publish_snapshots(wallet, store).await;
+}
+
+/// A posted wallet event held back from `event_handled` until its deliveries satisfy
+/// `[webhooks] ack_policy` and its exec hooks are done
+struct PendingAck {
+    outbox_ids: Vec<i64>,
+    delivered: usize,
+    dead_lettered: usize,
+    exec: Option<JoinHandle<()>>,
+}
+
+impl PendingAck {
+    fn new(outbox_ids: Vec<i64>, exec: Option<JoinHandle<()>>) -> Self {
+        PendingAck {
+            outbox_ids,
+            delivered: 0,
+            dead_lettered: 0,
+            exec,
+        }
+    }
+
+    /// Counts a finished delivery of the event, returning true once the event can be
+    /// acknowledged. Failed attempts that will be retried don't count. An event whose
+    /// deliveries were all dead-lettered is acknowledged too, the dead letters keep it.
+    fn record(&mut self, delivery: &WebhookAttempt, policy: AckPolicy, last_attempt: bool) -> bool {
+        if !self.outbox_ids.contains(&delivery.outbox_id) {
+            return false;
+        }
+        match delivery.error {
+            None => self.delivered += 1,
+            Some(_) if last_attempt => self.dead_lettered += 1,
+            Some(_) => return false,
+        }
+        self.is_finished(policy)
+    }
+
+    fn is_finished(&self, policy: AckPolicy) -> bool {
+        if self.exec.is_some() {
+            return false;
+        }
+        let finished = self.delivered + self.dead_lettered == self.outbox_ids.len();
+        match policy {
+            AckPolicy::AtLeastOnce => self.delivered > 0 || finished,
+            AckPolicy::All | AckPolicy::Outbox => finished,
+        }
+    }
+}
+
+/// Resolves once the exec hooks of the pending event are done, never when there are none
+async fn exec_hooks_done(pending_ack: &mut Option<PendingAck>) {
+    match pending_ack.as_mut().and_then(|p| p.exec.as_mut()) {
+        Some(exec) => {
+            let _ = exec.await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Control requests the daemon runs at once, further connections wait to be accepted
+const MAX_CONTROL_REQUESTS: usize = 32;
+
+/// Runs one command for a CLI invocation connected to the control socket. Requests run
+/// concurrently with each other and the daemon's own work.
+async fn serve_control(
+    wallet: &Wallet,
+    config: &Config,
+    store: &Store,
+    mut stream: tokio::net::UnixStream,
+    draining: bool,
+) {
+    let request = match control::read_request(&mut stream).await {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("Control socket: {e}");
+            return;
+        }
+    };
+    let args = std::iter::once("orange".to_string()).chain(request.args);
+    let result = match Cli::try_parse_from(args) {
+        _ if draining => Err("The daemon is shutting down".to_string()),
+        Ok(Cli {
+            command: Command::Payroll { .. },
+            ..
+        }) => Err("payroll run reads local files, run it without the daemon".to_string()),
+        Ok(cli) if config.mode == Mode::ReceiveOnly && spends(&cli.command) => {
+            Err(RECEIVE_ONLY_ERROR.to_string())
+        }
+        // Only `orange serve` sends local commands, the CLI runs them itself
+        Ok(cli) => match run_local_command(config, store, &cli.command).await {
+            Some(result) => result,
+            None => run_wallet_command(wallet, config, store, cli.command).await,
+        },
+        Err(e) => Err(format!("Invalid command: {e}")),
+    };
+    let response = match result {
+        Ok(value) => control::Response::Result(value),
+        Err(e) => control::Response::Error(e),
+    };
+    if let Err(e) = control::respond(&mut stream, &response).await {
+        warn!("Control socket: {e}");
+    }
+}
+
+/// Answers JSON-RPC requests on stdin until it closes, running each like the command line
+/// would. With `events`, wallet events are recorded and acknowledged like the daemon does
+/// and written to stdout as `event` notifications between responses.
+async fn cmd_rpc(wallet: &Wallet, config: &Config, store: &Store, events: bool, tz: chrono_tz::Tz) {
+    let cli = Cli::command();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => line,
+            _ = tokio::signal::ctrl_c() => return,
+            event = wallet.next_event_async(), if events => {
+                let timestamp = now_secs();
+                record_event(store, &event, timestamp);
+                let mut value = serialize_event(store, &event, timestamp);
+                if is_dust(&event, &config.receive_filter) {
+                    match config.receive_filter.dust_action {
+                        DustAction::Flag => value["dust"] = json!(true),
+                        DustAction::Ignore => {
+                            let _ = wallet.event_handled();
+                            continue;
+                        }
+                    }
+                }
+                let event_type = value["type"].as_str().unwrap_or_default();
+                match store.append_event(event_type, &value.to_string(), timestamp) {
+                    Ok(seq) => value["seq"] = json!(seq),
+                    Err(e) => error!("{e}"),
+                }
+                tz::annotate(&mut value, tz);
+                write_rpc(&mut stdout, &rpc::notification("event", value)).await;
+                ack_event(wallet, store).await;
+                continue;
+            }
+        };
+        let line = match line {
+            Ok(Some(line)) => line,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Failed to read stdin: {e}");
+                return;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match rpc::parse(&line) {
+            Ok(request) => request,
+            Err(response) => {
+                write_rpc(&mut stdout, &response).await;
+                continue;
+            }
+        };
+
+        let args = rpc::command_args(&cli, &request);
+        let result = match args
+            .map(|args| Cli::try_parse_from(std::iter::once("orange".to_string()).chain(args)))
+        {
+            Ok(Ok(parsed)) if config.mode == Mode::ReceiveOnly && spends(&parsed.command) => {
+                Err((rpc::COMMAND_FAILED, RECEIVE_ONLY_ERROR.to_string()))
+            }
+            Ok(Ok(parsed)) => match run_local_command(config, store, &parsed.command).await {
+                Some(result) => result,
+                None => run_wallet_command(wallet, config, store, parsed.command).await,
+            }
+            .map_err(|e| (rpc::COMMAND_FAILED, e)),
+            Ok(Err(e)) => Err((rpc::INVALID_PARAMS, format!("Invalid command: {e}"))),
+            Err(e) => Err(e),
+        };
+        let Some(id) = request.id else {
+            continue;
+        };
+        let response = match result {
+            Ok(mut value) => {
+                tz::annotate(&mut value, tz);
+                rpc::result(id, value)
+            }
+            Err((code, message)) => rpc::error(id, code, &message),
+        };
+        write_rpc(&mut stdout, &response).await;
+    }
+}
+
+/// Serves the MCP tools on stdin and stdout until stdin closes. Tool calls run one at a time,
+/// so `wait_for_payment` holds up the next call until it returns.
+async fn cmd_mcp(wallet: &Wallet, config: &Config, store: &Store, tz: chrono_tz::Tz) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    // A receive-only wallet doesn't offer `pay` at all
+    let max_spend_sats = match config.mode {
+        Mode::ReceiveOnly => 0,
+        _ => config.mcp.max_spend_sats,
+    };
+    // Where `wait_for_payment` starts looking by default, moved by every `create_invoice`
+    let mut invoiced_at = now_secs();
+    loop {
+        let line = tokio::select! {
+            line = lines.next_line() => line,
+            _ = tokio::signal::ctrl_c() => return,
+        };
+        let line = match line {
+            Ok(Some(line)) => line,
+            Ok(None) => return,
+            Err(e) => {
+                error!("Failed to read stdin: {e}");
+                return;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match rpc::parse(&line) {
+            Ok(request) => request,
+            Err(response) => {
+                write_rpc(&mut stdout, &response).await;
+                continue;
+            }
+        };
+
+        let result = match request.method.as_str() {
+            "initialize" => Ok(mcp::initialize(&request.params)),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(mcp::tools(max_spend_sats)),
+            "tools/call" => {
+                let name = request
+                    .params
+                    .get("name")
+                    .and_then(|name| name.as_str())
+                    .unwrap_or_default();
+                let args = match request.params.get("arguments") {
+                    None | Some(serde_json::Value::Null) => Some(serde_json::Map::new()),
+                    Some(serde_json::Value::Object(args)) => Some(args.clone()),
+                    Some(_) => None,
+                };
+                if name == "create_invoice" {
+                    invoiced_at = now_secs();
+                }
+                let called = match args {
+                    Some(args) => {
+                        call_mcp_tool(
+                            wallet,
+                            config,
+                            store,
+                            max_spend_sats,
+                            name,
+                            &args,
+                            invoiced_at,
+                        )
+                        .await
+                    }
+                    None => Some(Err("arguments must be an object".to_string())),
+                };
+                match called {
+                    Some(result) => Ok(mcp::tool_result(result.map(|mut value| {
+                        tz::annotate(&mut value, tz);
+                        value
+                    }))),
+                    None => Err((rpc::INVALID_PARAMS, format!("No tool {name}"))),
+                }
+            }
+            method => Err((rpc::METHOD_NOT_FOUND, format!("No method {method}"))),
+        };
+        // `notifications/initialized` and the other notifications need no response
+        let Some(id) = request.id else {
+            continue;
+        };
+        let response = match result {
+            Ok(value) => rpc::result(id, value),
+            Err((code, message)) => rpc::error(id, code, &message),
+        };
+        write_rpc(&mut stdout, &response).await;
+    }
+}
+
+/// Runs one MCP tool, `None` if there is no tool by that name
+async fn call_mcp_tool(
+    wallet: &Wallet,
+    config: &Config,
+    store: &Store,
+    max_spend_sats: u64,
+    name: &str,
+    args: &serde_json::Map<String, serde_json::Value>,
+    invoiced_at: u64,
+) -> Option<Result<serde_json::Value, String>> {
+    Some(match name {
+        "get_balance" => cmd_balance(wallet).await,
+        "create_invoice" => match (
+            mcp::u64_arg(args, "amount_sats"),
+            mcp::str_arg(args, "description"),
+        ) {
+            (Ok(amount_sats), Ok(purpose)) => {
+                cmd_receive(
+                    wallet,
+                    store,
+                    config,
+                    amount_sats,
+                    None,
+                    purpose,
+                    false,
+                    None,
+                    &[],
+                )
+                .await
+            }
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        },
+        "pay" if max_spend_sats > 0 => match (
+            mcp::str_arg(args, "payment"),
+            mcp::u64_arg(args, "amount_sats"),
+        ) {
+            (Ok(Some(payment)), Ok(amount_sats)) => send_payment_capped(
+                wallet,
+                store,
+                payment,
+                amount_sats,
+                Some(max_spend_sats),
+                Some("mcp"),
+            )
+            .await
+            .map(|(payment_id, amount_sats)| {
+                json!({
+                    "payment_id": payment_id,
+                    "amount_sats": amount_sats,
+                    "status": "initiated",
+                })
+            }),
+            (Ok(None), _) => Err("payment is required".to_string()),
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        },
+        "list_transactions" => match mcp::u64_arg(args, "limit") {
+            Ok(limit) => cmd_transactions(wallet).await.map(|mut value| {
+                if let Some(txs) = value["transactions"].as_array_mut() {
+                    txs.sort_by_key(|tx| std::cmp::Reverse(tx["timestamp"].as_u64()));
+                    txs.truncate(limit.unwrap_or(20) as usize);
+                    value["count"] = json!(txs.len());
+                }
+                value
+            }),
+            Err(e) => Err(e),
+        },
+        "wait_for_payment" => match (
+            mcp::u64_arg(args, "since"),
+            mcp::u64_arg(args, "min_amount_sats"),
+            mcp::u64_arg(args, "timeout_secs"),
+        ) {
+            (Ok(since), Ok(min_amount_sats), Ok(timeout_secs)) => {
+                let timeout_secs = timeout_secs.unwrap_or(60).min(mcp::MAX_WAIT_SECS);
+                let since = since.unwrap_or(invoiced_at);
+                wait_for_payment(wallet, since, min_amount_sats.unwrap_or(0), timeout_secs).await
+            }
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => Err(e),
+        },
+        _ => return None,
+    })
+}
+
+/// Waits for an incoming payment that completed at or after `since`. Like selftest it polls the
+/// wallet, since the event queue belongs to the daemon.
+async fn wait_for_payment(
+    wallet: &Wallet,
+    since: u64,
+    min_amount_sats: u64,
+    timeout_secs: u64,
+) -> Result<serde_json::Value, String> {
+    let deadline = Instant::now() + std::time::Duration::from_secs(timeout_secs);
+    loop {
+        let transactions = wallet
+            .list_transactions()
+            .await
+            .map_err(|e| format!("Failed to list transactions: {e:?}"))?;
+        let received = transactions.iter().find(|tx| {
+            !tx.outbound
+                && format!("{:?}", tx.status) == "Completed"
+                && tx.time_since_epoch.as_secs() >= since
+                && tx
+                    .amount
+                    .is_some_and(|a| a.sats_rounding_up() >= min_amount_sats)
+        });
+        if let Some(tx) = received {
+            return Ok(json!({
+                "id": tx.id.to_string(),
+                "status": format!("{:?}", tx.status),
+                "amount_sats": tx.amount.map(|a| a.sats_rounding_up()),
+                "payment_type": format!("{:?}", tx.payment_type),
+                "timestamp": tx.time_since_epoch.as_secs(),
+            }));
+        }
+        if Instant::now() >= deadline {
+            return Err(format!("No payment received after {timeout_secs}s"));
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+}
+
+async fn write_rpc(stdout: &mut tokio::io::Stdout, message: &serde_json::Value) {
+    let mut line = message.to_string();
+    line.push('\n');
+    if let Err(e) = stdout.write_all(line.as_bytes()).await {
+        error!("Failed to write to stdout: {e}");
+        return;
+    }
+    let _ = stdout.flush().await;
+}
+
+/// Webhooks from `--webhook` flags followed by those in `[webhooks] endpoints`
+fn daemon_webhooks(flags: &[String], config: &WebhooksConfig) -> Result<Vec<Webhook>, String> {
+    let endpoints = config
+        .endpoints
+        .iter()
+        .map(|endpoint| Webhook::from_config(endpoint, config));
+    flags
+        .iter()
+        .map(|w| Ok(Webhook::parse(w)))
+        .chain(endpoints)
+        .collect()
+}
+
+/// Plugins from `--plugin` flags followed by those in `[[plugins]]`
+fn daemon_plugins(flags: &[String], config: &[PluginConfig]) -> Vec<PluginConfig> {
+    flags
+        .iter()
+        .map(|command| PluginConfig {
+            command: command.clone(),
+            args: Vec::new(),
+            events: Vec::new(),
+        })
+        .chain(config.iter().cloned())
+        .collect()
+}
+
+fn log_webhooks(dispatcher: &Dispatcher) {
+    for hook in dispatcher.hooks() {
+        let auth: Vec<&str> = [
+            hook.token.as_ref().map(|_| "Bearer token"),
+            hook.basic_auth.as_ref().map(|_| "basic auth"),
+            hook.secret.as_ref().map(|_| "HMAC signature"),
+            (!hook.headers.is_empty()).then_some("custom headers"),
+            hook.client.as_ref().map(|_| "client certificate"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if auth.is_empty() {
+            info!({ "url": hook.url }, "Webhook: {}", hook.url);
+        } else {
+            info!({ "url": hook.url }, "Webhook: {} (auth: {})", hook.url, auth.join(", "));
+        }
+    }
+    for hook in dispatcher.exec_hooks().hooks() {
+        let events = if hook.events.is_empty() {
+            "all events".to_string()
+        } else {
+            hook.events.join(", ")
+        };
+        info!({ "command": hook.command }, "Exec hook: {} ({events})", hook.command);
+    }
+    if let Some(mqtt) = dispatcher.mqtt() {
+        info!(
+            { "broker": mqtt.broker() },
+            "MQTT: {} ({}/<event type>)",
+            mqtt.broker(),
+            mqtt.topic_prefix()
+        );
+    }
+    for plugin in dispatcher.plugins().configs() {
+        let events = if plugin.events.is_empty() {
+            "all events".to_string()
+        } else {
+            plugin.events.join(", ")
+        };
+        info!({ "command": plugin.command }, "Plugin: {} ({events})", plugin.command);
+    }
+    if !dispatcher.has_hooks() {
+        info!(
+            "No webhooks configured, events will queue until consumed via get-event/event-handled"
+        );
+    }
+}
+
+/// `(is_channel_ready, is_usable)` of each open or pending channel, by channel id
+fn channel_states(wallet: &Wallet) -> HashMap<String, (bool, bool)> {
+    wallet
+        .channels()
+        .iter()
+        .map(|ch| {
+            (
+                ch.channel_id.to_string(),
+                (ch.is_channel_ready, ch.is_usable),
+            )
+        })
+        .collect()
+}
+
+/// Events for channels that appeared, became ready or changed usability since `known`, which
+/// is updated to the current states. Closed channels are reported by `channel_closed`.
+fn channel_changes(
+    wallet: &Wallet,
+    known: &mut HashMap<String, (bool, bool)>,
+) -> Vec<serde_json::Value> {
+    let timestamp = now_secs();
+    let mut events = Vec::new();
+    let mut current = HashMap::new();
+    for ch in wallet.channels() {
+        let channel_id = ch.channel_id.to_string();
+        let event = |event_type: &str| {
+            json!({
+                "type": event_type,
+                "timestamp": timestamp,
+                "channel_id": channel_id,
+                "counterparty_node_id": ch.counterparty_node_id.to_string(),
+                "funding_txo": ch.funding_txo.map(|t| t.to_string()),
+                "is_channel_ready": ch.is_channel_ready,
+                "is_usable": ch.is_usable,
+                "inbound_capacity_sats": ch.inbound_capacity_msat / 1_000,
+                "outbound_capacity_sats": ch.outbound_capacity_msat / 1_000,
+                "channel_value_sats": ch.channel_value_sats,
+            })
+        };
+        let (was_ready, was_usable) = match known.get(&channel_id) {
+            Some(&state) => state,
+            None if ch.is_channel_ready => (false, false),
+            None => {
+                events.push(event("channel_pending"));
+                (false, false)
+            }
+        };
+        if ch.is_channel_ready && !was_ready {
+            events.push(event("channel_ready"));
+        }
+        // A channel that just became ready is covered by `channel_ready`
+        if was_ready && ch.is_usable != was_usable {
+            events.push(event("channel_usable_changed"));
+        }
+        current.insert(channel_id, (ch.is_channel_ready, ch.is_usable));
+    }
+    *known = current;
+    events
+}
+
+/// Resolves on Ctrl+C or SIGTERM
+async fn shutdown_signal(sigterm: &mut Signal) {
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+}
+
+/// Updates what the `--listen` endpoints serve, keeping the previous values of anything that
+/// fails to load
+async fn refresh_status(wallet: &Wallet, store: &Store, status: &health::SharedStatus) {
+    let balance = wallet.get_balance().await;
+    let channels = wallet.channels();
+
+    let mut status = status.lock().unwrap();
+    status.lsp_connected = wallet.is_connected_to_lsp();
+    match balance {
+        Ok(b) => {
+            status.balance_sats = Some((
+                b.trusted.sats_rounding_up(),
+                b.lightning.sats_rounding_up(),
+                b.pending_balance.sats_rounding_up(),
+            ))
+        }
+        Err(e) => warn!("Failed to get balance: {e:?}"),
+    }
+    status.channels = channels.len() as u64;
+    status.usable_channels = channels.iter().filter(|c| c.is_usable).count() as u64;
+    status.inbound_capacity_sats = channels
+        .iter()
+        .map(|c| c.inbound_capacity_msat)
+        .sum::<u64>()
+        / 1000;
+    status.outbound_capacity_sats = channels
+        .iter()
+        .map(|c| c.outbound_capacity_msat)
+        .sum::<u64>()
+        / 1000;
+    match store.webhook_backlog() {
+        Ok(count) => status.webhook_backlog = count,
+        Err(e) => error!("{e}"),
+    }
+    match store.dead_letter_count() {
+        Ok(count) => status.dead_letters = count,
+        Err(e) => error!("{e}"),
+    }
+    match store.events(None, None, 1) {
+        Ok(events) => status.last_event = events.first().map(|e| (e.seq, e.created_at)),
+        Err(e) => error!("{e}"),
+    }
+    match store.receipt_totals() {
+        Ok((count, msat)) => {
+            status.received_payments = count;
+            status.received_sats = msat / 1000;
+        }
+        Err(e) => error!("{e}"),
+    }
+    match store.send_totals() {
+        Ok(sends) => status.sends = sends,
+        Err(e) => error!("{e}"),
+    }
+    match store.webhook_endpoints() {
+        Ok(webhooks) => status.webhooks = webhooks,
+        Err(e) => error!("{e}"),
+    }
+    status.updated_at = now_secs();
+}
+
+fn cmd_webhooks_list(store: &Store) -> Result<serde_json::Value, String> {
+    let webhooks: Vec<serde_json::Value> = store
+        .webhook_endpoints()?
+        .iter()
+        .map(|w| {
+            json!({
+                "url": w.url,
+                "enabled": w.disabled_at.is_none(),
+                "delivered": w.delivered,
+                "failed": w.failed,
+                "consecutive_failures": w.consecutive_failures,
+                "failing_since": w.failing_since,
+                "last_error": w.last_error,
+                "disabled_at": w.disabled_at,
+            })
+        })
+        .collect();
+    Ok(json!({ "webhooks": webhooks }))
+}
+
+fn cmd_webhooks_deliveries(
+    store: &Store,
+    url: Option<&str>,
+    event_type: Option<&str>,
+    failed_only: bool,
+    limit: u32,
+) -> Result<serde_json::Value, String> {
+    let deliveries: Vec<serde_json::Value> = store
+        .webhook_attempts(url, event_type, failed_only, limit)?
+        .iter()
+        .map(|a| {
+            json!({
+                "delivery_id": a.outbox_id,
+                "url": a.url,
+                "event_type": a.event_type,
+                "attempt": a.attempt,
+                "status": a.status,
+                "error": a.error,
+                "latency_ms": a.latency_ms,
+                "attempted_at": a.attempted_at,
+            })
+        })
+        .collect();
+    Ok(json!({
+        "count": deliveries.len(),
+        "deliveries": deliveries,
+    }))
+}
+
+/// Posts a sample event through the outbox and waits for the first attempt at each webhook.
+/// Failed deliveries stay queued, so a daemon running with the same webhook retries them.
+async fn cmd_webhooks_test(
+    config: &Config,
+    store: &Store,
+    webhooks: &[String],
+    event_type: &str,
+) -> Result<serde_json::Value, String> {
+    let value = webhook::sample_event(event_type, now_secs())?;
+    let hooks = webhooks.iter().map(|w| Webhook::parse(w)).collect();
+    let (dispatcher, mut deliveries) = Dispatcher::new(hooks, Vec::new(), &config.webhooks);
+    let started = dispatcher.deliver(store, &value);
+    if started == 0 {
+        return Err("No enabled webhooks to test, see `orange webhooks list`".to_string());
+    }
+
+    let mut results = Vec::with_capacity(started);
+    for _ in 0..started {
+        let Some(delivery) = deliveries.recv().await else {
+            break;
+        };
+        results.push(json!({
+            "delivery_id": delivery.outbox_id,
+            "url": delivery.url,
+            "status": delivery.status,
+            "error": delivery.error,
+            "latency_ms": delivery.latency_ms,
+        }));
+        dispatcher.record(store, delivery, now_secs());
+    }
+    Ok(json!({
+        "event": value,
+        "deliveries": results,
+    }))
+}
+
+/// A delivery's payload as JSON, with its event type or "batch"
+fn delivery_event(payload: &str) -> (serde_json::Value, serde_json::Value) {
+    let event =
+        serde_json::from_str::<serde_json::Value>(payload).unwrap_or_else(|_| json!(payload));
+    let event_type = match &event {
+        serde_json::Value::Array(_) => json!("batch"),
+        event => event["type"].clone(),
+    };
+    (event, event_type)
+}
+
+fn cmd_outbox_list(
+    store: &Store,
+    state: Option<OutboxState>,
+    limit: u32,
+) -> Result<serde_json::Value, String> {
+    let deliveries: Vec<serde_json::Value> = store
+        .outbox_entries(state.map(OutboxState::as_str), limit)?
+        .iter()
+        .map(|d| {
+            let (event, event_type) = delivery_event(&d.payload);
+            json!({
+                "id": d.id,
+                "url": d.url,
+                "state": d.state,
+                "event_type": event_type,
+                "attempts": d.attempts,
+                "last_error": d.last_error,
+                "next_attempt_at": d.next_attempt_at,
+                "created_at": d.created_at,
+                "dead_at": d.dead_at,
+                "event": event,
+            })
+        })
+        .collect();
+    Ok(json!({
+        "count": deliveries.len(),
+        "deliveries": deliveries,
+    }))
+}
+
+fn cmd_outbox_purge(
+    config: &Config,
+    store: &Store,
+    before: &str,
+    state: OutboxState,
+) -> Result<serde_json::Value, String> {
+    let before = tz::parse_time(before, tz::parse(&config.timezone)?)?;
+    let purged = store.purge_outbox(state.as_str(), before)?;
+    Ok(json!({
+        "state": state.as_str(),
+        "before": before,
+        "purged": purged,
+    }))
+}
+
+fn cmd_dead_letter_list(store: &Store) -> Result<serde_json::Value, String> {
+    let dead_letters: Vec<serde_json::Value> = store
+        .webhook_dead_letters()?
+        .iter()
+        .map(|d| {
+            let (event, event_type) = delivery_event(&d.payload);
+            json!({
+                "id": d.id,
+                "url": d.url,
+                "event_type": event_type,
+                "attempts": d.attempts,
+                "last_error": d.last_error,
+                "created_at": d.created_at,
+                "dead_at": d.dead_at,
+                "event": event,
+            })
+        })
+        .collect();
+    Ok(json!({
+        "count": dead_letters.len(),
+        "dead_letters": dead_letters,
+    }))
+}
+
+fn cmd_dead_letter_redeliver(store: &Store, id: i64) -> Result<serde_json::Value, String> {
+    let url = store
+        .redeliver_dead_letter(id, now_secs())?
+        .ok_or_else(|| format!("Unknown dead letter: {id}"))?;
+    Ok(json!({ "id": id, "url": url, "status": "queued" }))
+}
+
+/// A history event as it was posted, `seq` included
+fn stored_event_json(event: &StoredEvent) -> serde_json::Value {
+    let mut value = serde_json::from_str(&event.payload)
+        .unwrap_or_else(|_| json!({ "type": event.event_type, "timestamp": event.created_at }));
+    value["seq"] = json!(event.seq);
+    value
+}
+
+fn cmd_events_list(
+    store: &Store,
+    event_type: Option<&str>,
+    since: Option<u64>,
+    limit: u32,
+) -> Result<serde_json::Value, String> {
+    let events: Vec<serde_json::Value> = store
+        .events(event_type, since, limit)?
+        .iter()
+        .map(stored_event_json)
+        .collect();
+    Ok(json!({
+        "count": events.len(),
+        "events": events,
+    }))
+}
+
+async fn cmd_events_replay(
+    config: &Config,
+    store: &Store,
+    from_seq: i64,
+    to_webhook: Option<&str>,
+) -> Result<serde_json::Value, String> {
+    let events: Vec<serde_json::Value> = store
+        .events_from(from_seq)?
+        .iter()
+        .map(stored_event_json)
+        .collect();
+    let Some(spec) = to_webhook else {
+        return Ok(json!({
+            "count": events.len(),
+            "events": events,
+        }));
+    };
+
+    let hook = Webhook::parse(spec);
+    let url = hook.url.clone();
+    let (dispatcher, mut deliveries) = Dispatcher::new(vec![hook], Vec::new(), &config.webhooks);
+    // One at a time, so the receiver sees the events in order and a failure leaves no gaps
+    let mut delivered = 0;
+    for event in &events {
+        if dispatcher.deliver(store, event) == 0 {
+            return Err(format!(
+                "Webhook {url} is disabled, re-enable it with `orange webhooks enable {url}`"
+            ));
+        }
+        let Some(delivery) = deliveries.recv().await else {
+            break;
+        };
+        let (outbox_id, error) = (delivery.outbox_id, delivery.error.clone());
+        dispatcher.record(store, delivery, now_secs());
+        if let Some(error) = error {
+            // Left queued, the daemon would retry it out of order
+            if let Err(e) = store.discard_webhook_delivery(outbox_id) {
+                error!("{e}");
+            }
+            return Ok(json!({
+                "url": url,
+                "delivered": delivered,
+                "failed_seq": event["seq"],
+                "error": error,
+            }));
+        }
+        delivered += 1;
+    }
+    Ok(json!({
+        "url": url,
+        "delivered": delivered,
+        "failed_seq": null,
+        "error": null,
+    }))
+}
+
+fn cmd_webhooks_enable(store: &Store, url: &str) -> Result<serde_json::Value, String> {
+    if !store.enable_webhook(url)? {
+        return Err(format!("Unknown webhook: {url}"));
+    }
+    Ok(json!({ "url": url, "enabled": true }))
+}
+
+fn cmd_get_event(
+    wallet: &Wallet,
+    store: &Store,
+    filter: &ReceiveFilterConfig,
+) -> Result<serde_json::Value, String> {
+    loop {
+        let Some(event) = wallet.next_event() else {
+            return Ok(json!({ "event": null }));
+        };
+        let timestamp = now_secs();
+        record_event(store, &event, timestamp);
+
+        // Ignored dust is acknowledged here so it never reaches the caller
+        let dust = is_dust(&event, filter);
+        if dust && filter.dust_action == DustAction::Ignore {
+            wallet
+                .event_handled()
+                .map_err(|_| "Failed to mark event as handled".to_string())?;
+            continue;
+        }
+
+        let mut value = serialize_event(store, &event, timestamp);
+        if dust {
+            value["dust"] = json!(true);
+        }
+        return Ok(value);
+    }
+}
+
+fn cmd_schema(command: &[String]) -> Result<serde_json::Value, String> {
+    if command.is_empty() {
+        return Ok(json!({ "commands": schema::COMMANDS }));
+    }
+    schema::output_schema(&command.join(" "))
+}
+
+/// Returns the events a previous call handed out but nobody acknowledged, then moves more off
+/// the wallet queue into the event history. They are acknowledged in the wallet right away and
+/// held in the store until `events-handled`.
+fn cmd_get_events(
+    wallet: &Wallet,
+    store: &Store,
+    filter: &ReceiveFilterConfig,
+    max: u32,
+) -> Result<serde_json::Value, String> {
+    let mut events: Vec<serde_json::Value> = store
+        .pulled_events(max)?
+        .iter()
+        .map(stored_event_json)
+        .collect();
+    while events.len() < max as usize {
+        let Some(event) = wallet.next_event() else {
+            break;
+        };
+        let timestamp = now_secs();
+        record_event(store, &event, timestamp);
+
+        let dust = is_dust(&event, filter);
+        if !(dust && filter.dust_action == DustAction::Ignore) {
+            let mut value = serialize_event(store, &event, timestamp);
+            if dust {
+                value["dust"] = json!(true);
+            }
+            let event_type = value["type"].as_str().unwrap_or_default();
+            let seq = store.pull_event(event_type, &value.to_string(), timestamp)?;
+            value["seq"] = json!(seq);
+            events.push(value);
+        }
+        wallet
+            .event_handled()
+            .map_err(|_| "Failed to mark event as handled".to_string())?;
+    }
+    Ok(json!({
+        "count": events.len(),
+        "events": events,
+    }))
+}
+
+/// Everything `get-event` or `get-events` would hand out next: events pulled by `get-events`
+/// and not yet acked, then the head of the wallet queue. The SDK only exposes the head, so at
+/// most one event that hasn't been pulled is listed.
+fn cmd_events_peek(
+    wallet: &Wallet,
+    store: &Store,
+    filter: &ReceiveFilterConfig,
+) -> Result<serde_json::Value, String> {
+    let mut events: Vec<serde_json::Value> = store
+        .pulled_events(u32::MAX)?
+        .iter()
+        .map(|event| {
+            let mut value = stored_event_json(event);
+            value["state"] = json!("pulled");
+            value
+        })
+        .collect();
+    if let Some(event) = wallet.next_event() {
+        let mut value = serialize_event(store, &event, now_secs());
+        if is_dust(&event, filter) {
+            value["dust"] = json!(true);
         }
-        None => Ok(json!({ "event": null })),
+        value["state"] = json!("queued");
+        events.push(value);
+    }
+    for (index, event) in events.iter_mut().enumerate() {
+        event["index"] = json!(index);
     }
+    Ok(json!({
+        "count": events.len(),
+        "events": events,
+    }))
 }
 
-fn cmd_event_handled(wallet: &Wallet) -> Result<serde_json::Value, String> {
+fn cmd_events_handled(store: &Store, up_to: i64) -> Result<serde_json::Value, String> {
+    let handled = store.ack_pulled_events(up_to)?;
+    Ok(json!({ "ok": true, "handled": handled }))
+}
+
+fn cmd_event_handled(
+    wallet: &Wallet,
+    store: &Store,
+    filter: &ReceiveFilterConfig,
+) -> Result<serde_json::Value, String> {
+    // Archived here rather than in get-event, which returns the same event until it is handled
+    if let Some(event) = wallet.next_event() {
+        let timestamp = now_secs();
+        let mut value = serialize_event(store, &event, timestamp);
+        if is_dust(&event, filter) {
+            value["dust"] = json!(true);
+        }
+        let event_type = value["type"].as_str().unwrap_or_default();
+        if let Err(e) = store.append_event(event_type, &value.to_string(), timestamp) {
+            error!("{e}");
+        }
+    }
     wallet
         .event_handled()
         .map_err(|_| "Failed to mark event as handled".to_string())?;
     Ok(json!({ "ok": true }))
 }
 
-fn serialize_event(event: &Event, timestamp: u64) -> serde_json::Value {
+/// Keeps local bookkeeping in sync with an event as it is consumed
+fn record_event(store: &Store, event: &Event, timestamp: u64) {
+    let result = match event {
+        Event::PaymentReceived {
+            payment_id,
+            payment_hash,
+            amount_msat,
+            ..
+        } => store.record_receipt(
+            &payment_id.to_string(),
+            &payment_hash.0.to_lower_hex_string(),
+            *amount_msat,
+            timestamp,
+        ),
+        Event::PaymentSuccessful {
+            payment_id,
+            fee_paid_msat,
+            ..
+        } => store.update_send_status(
+            &payment_id.to_string(),
+            "succeeded",
+            *fee_paid_msat,
+            now_millis(),
+        ),
+        Event::PaymentFailed { payment_id, .. } => {
+            store.update_send_status(&payment_id.to_string(), "failed", None, now_millis())
+        }
+        Event::OnchainPaymentReceived {
+            txid, amount_sat, ..
+        } => store.record_onchain_receipt(&txid.to_string(), *amount_sat, timestamp),
+        _ => Ok(()),
+    };
+    if let Err(e) = result {
+        error!("{e}");
+    }
+}
+
+/// Mails the payer's receipt when `event` settles a receive created with `--email`
+fn email_receipt(store: &Store, mailer: &Arc<Mailer>, event: &Event, timestamp: u64) {
+    let Event::PaymentReceived {
+        payment_id,
+        payment_hash,
+        amount_msat,
+        ..
+    } = event
+    else {
+        return;
+    };
+    let receive = match store.receipt_email_due(&payment_hash.0.to_lower_hex_string()) {
+        Ok(Some(r)) => r,
+        Ok(None) => return,
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    };
+    // Claimed before sending, so a redelivered event never mails the payer twice. A send that
+    // fails gives the claim back for `retry_receipt_emails`.
+    match store.mark_receipt_emailed(receive.id, timestamp) {
+        Ok(true) => mailer.send_receipt(receive, payment_id.to_string(), *amount_msat, timestamp),
+        Ok(false) => {}
+        Err(e) => error!("{e}"),
+    }
+}
+
+/// Sends again the receipt emails of the last day that failed, or were due while the daemon
+/// couldn't send them
+fn retry_receipt_emails(store: &Store, mailer: &Arc<Mailer>) {
+    let now = now_secs();
+    let due = match store.receipt_emails_due(now.saturating_sub(86400)) {
+        Ok(due) => due,
+        Err(e) => {
+            error!("{e}");
+            return;
+        }
+    };
+    for (receive, payment_id, amount_msat, received_at) in due {
+        match store.mark_receipt_emailed(receive.id, now) {
+            Ok(true) => mailer.send_receipt(receive, payment_id, amount_msat, received_at),
+            Ok(false) => {}
+            Err(e) => error!("{e}"),
+        }
+    }
+}
+
+/// The `lsp_fee_exceeded` event for `event` when the LSP took more than `max_lsp_fee_msats`
+/// out of the payment, once per payment however often the wallet hands the event back. The
+/// SDK claims the payment before the daemon hears of it, so it is only reported, not refused.
+fn lsp_fee_alert(
+    store: &Store,
+    filter: &ReceiveFilterConfig,
+    event: &Event,
+    timestamp: u64,
+) -> Option<serde_json::Value> {
+    let (
+        Some(max_lsp_fee_msats),
+        Event::PaymentReceived {
+            payment_id,
+            payment_hash,
+            amount_msat,
+            lsp_fee_msats: Some(lsp_fee_msats),
+            ..
+        },
+    ) = (filter.max_lsp_fee_msats, event)
+    else {
+        return None;
+    };
+    if *lsp_fee_msats <= max_lsp_fee_msats {
+        return None;
+    }
+    let payment_id = payment_id.to_string();
+    match store.mark_lsp_fee_alerted(&payment_id, timestamp) {
+        Ok(true) => {}
+        Ok(false) => return None,
+        Err(e) => {
+            error!("{e}");
+            return None;
+        }
+    }
+    let payment_hash = payment_hash.0.to_lower_hex_string();
+    let value = json!({
+        "type": "lsp_fee_exceeded",
+        "timestamp": timestamp,
+        "payment_id": payment_id,
+        "payment_hash": payment_hash,
+        "amount_msat": amount_msat,
+        "lsp_fee_msats": lsp_fee_msats,
+        "max_lsp_fee_msats": max_lsp_fee_msats,
+        "invoice": paid_invoice(store, &payment_hash),
+    });
+    warn!(
+        { "event_type": "lsp_fee_exceeded", "payment_id": value["payment_id"] },
+        "[{timestamp}] \"lsp_fee_exceeded\" the LSP took {lsp_fee_msats} msat, over the \
+        {max_lsp_fee_msats} msat limit"
+    );
+    Some(value)
+}
+
+/// Whether `event` is an incoming payment below the configured dust threshold
+fn is_dust(event: &Event, filter: &ReceiveFilterConfig) -> bool {
+    let amount_msat = match event {
+        Event::PaymentReceived { amount_msat, .. } => *amount_msat,
+        Event::OnchainPaymentReceived { amount_sat, .. } => amount_sat * 1000,
+        _ => return false,
+    };
+    amount_msat < filter.min_amount_sats * 1000
+}
+
+/// What `receive` recorded about the invoice paid by `payment_hash`, null for payments to
+/// invoices it didn't issue (lightning address, offers)
+fn paid_invoice(store: &Store, payment_hash: &str) -> serde_json::Value {
+    let receive = match store.receive_by_payment_hash(payment_hash) {
+        Ok(Some(receive)) => receive,
+        Ok(None) => return serde_json::Value::Null,
+        Err(e) => {
+            error!("{e}");
+            return serde_json::Value::Null;
+        }
+    };
+    json!({
+        "receive_id": receive.id,
+        "invoice": receive.invoice,
+        "purpose": receive.purpose,
+        "meta": receive_meta(&receive),
+        "amount_sats": receive.amount_sats,
+        "fiat_amount": receive.fiat_amount,
+        "currency": receive.currency,
+        "created_at": receive.created_at,
+        "expires_at": receive.expires_at,
+    })
+}
+
+fn serialize_event(store: &Store, event: &Event, timestamp: u64) -> serde_json::Value {
     match event {
         Event::PaymentSuccessful {
             payment_id,
@@ -476,7 +5394,20 @@ fn serialize_event(event: &Event, timestamp: u64) -> serde_json::Value {
             "amount_msat": amount_msat,
             "amount_sats": amount_msat / 1000,
             "custom_records_count": custom_records.len(),
+            "custom_records": custom_records
+                .iter()
+                .map(|record| {
+                    json!({
+                        "type": record.type_num,
+                        "hex": record.value.to_lower_hex_string(),
+                        "utf8": std::str::from_utf8(&record.value).ok(),
+                    })
+                })
+                .collect::<Vec<_>>(),
             "lsp_fee_msats": lsp_fee_msats,
+            "invoice": paid_invoice(store, &payment_hash.0.to_lower_hex_string()),
+            "zap": zaps::find_request(custom_records.iter().map(|r| r.value.as_slice()))
+                .map(|request| zaps::metadata(&request)),
         }),
         Event::OnchainPaymentReceived {
             payment_id,
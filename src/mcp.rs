@@ -0,0 +1,158 @@
+use serde_json::{Value, json};
+
+/// MCP revisions this server speaks, newest first. A client asking for another gets the newest.
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Longest `wait_for_payment` blocks, clients tend to time out tool calls after a few minutes
+pub const MAX_WAIT_SECS: u64 = 300;
+
+/// The response to `initialize`
+pub fn initialize(params: &serde_json::Map<String, Value>) -> Value {
+    // Indexing a map panics on a missing key, unlike indexing a value
+    let requested = params
+        .get("protocolVersion")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let version = PROTOCOL_VERSIONS
+        .iter()
+        .find(|v| **v == requested)
+        .unwrap_or(&PROTOCOL_VERSIONS[0]);
+    json!({
+        "protocolVersion": version,
+        "capabilities": { "tools": {} },
+        "serverInfo": { "name": "orange", "version": env!("CARGO_PKG_VERSION") },
+        "instructions": "Tools of a bitcoin and lightning wallet. Amounts are in satoshis.",
+    })
+}
+
+/// The tools with their input schemas. `pay` is only offered when `max_spend_sats` lets it pay
+/// anything.
+pub fn tools(max_spend_sats: u64) -> Value {
+    let mut tools = vec![
+        json!({
+            "name": "get_balance",
+            "description": "Balances of the wallet in satoshis. available_sats is what can be \
+                spent now, pending_sats is still confirming.",
+            "inputSchema": { "type": "object", "properties": {} },
+        }),
+        json!({
+            "name": "create_invoice",
+            "description": "Create a single-use BIP21 URI with a lightning invoice and an \
+                on-chain address to get paid. Without amount_sats the payer chooses the amount.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "amount_sats": { "type": "integer", "minimum": 1 },
+                    "description": {
+                        "type": "string",
+                        "description": "What the payment is for, kept with the invoice",
+                    },
+                },
+            },
+        }),
+        json!({
+            "name": "list_transactions",
+            "description": "The wallet's payments, newest first",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "limit": { "type": "integer", "minimum": 1, "default": 20 },
+                },
+            },
+        }),
+        json!({
+            "name": "wait_for_payment",
+            "description": "Wait until an incoming payment completes and return it. Matches \
+                the first one since `since`, by default since the last create_invoice call, \
+                and fails after timeout_secs.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "since": {
+                        "type": "integer",
+                        "description": "Unix timestamp, payments completed before are ignored",
+                    },
+                    "min_amount_sats": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": "Ignore smaller payments. LSP fees may be taken off \
+                            what was invoiced.",
+                    },
+                    "timeout_secs": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "maximum": MAX_WAIT_SECS,
+                        "default": 60,
+                    },
+                },
+            },
+        }),
+    ];
+    if max_spend_sats > 0 {
+        tools.insert(
+            2,
+            json!({
+                "name": "pay",
+                "description": format!(
+                    "Pay a lightning invoice, BOLT12 offer, on-chain address or BIP21 URI. \
+                    Payments of more than {max_spend_sats} sats are refused."
+                ),
+                "inputSchema": {
+                    "type": "object",
+                    "properties": {
+                        "payment": { "type": "string" },
+                        "amount_sats": {
+                            "type": "integer",
+                            "minimum": 1,
+                            "maximum": max_spend_sats,
+                            "description": "Required for addresses and amountless invoices",
+                        },
+                    },
+                    "required": ["payment"],
+                },
+            }),
+        );
+    }
+    json!({ "tools": tools })
+}
+
+/// The result of `tools/call`. A failed tool is reported in the result, so the model sees why.
+pub fn tool_result(result: Result<Value, String>) -> Value {
+    match result {
+        Ok(value) => json!({
+            "content": [{
+                "type": "text",
+                "text": serde_json::to_string_pretty(&value).unwrap(),
+            }],
+            "structuredContent": value,
+            "isError": false,
+        }),
+        Err(e) => json!({
+            "content": [{ "type": "text", "text": e }],
+            "isError": true,
+        }),
+    }
+}
+
+pub fn u64_arg(args: &serde_json::Map<String, Value>, name: &str) -> Result<Option<u64>, String> {
+    match args.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| format!("{name} must be a positive integer")),
+    }
+}
+
+pub fn str_arg<'a>(
+    args: &'a serde_json::Map<String, Value>,
+    name: &str,
+) -> Result<Option<&'a str>, String> {
+    match args.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value
+            .as_str()
+            .map(Some)
+            .ok_or_else(|| format!("{name} must be a string")),
+    }
+}
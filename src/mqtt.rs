@@ -0,0 +1,116 @@
+use crate::config::MqttConfig;
+use rumqttc::{AsyncClient, MqttOptions, QoS, Transport};
+use std::time::Duration;
+
+/// Messages waiting for the broker while it is unreachable, newer events are dropped beyond
+const QUEUE_CAPACITY: usize = 1000;
+
+/// Wait before reconnecting after the connection to the broker failed
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Publishes every posted event to `{topic_prefix}/{event type}` on the `[mqtt]` broker, e.g.
+/// for Home Assistant automations. The connection is kept up in the background.
+pub struct Mqtt {
+    client: AsyncClient,
+    topic_prefix: String,
+    qos: QoS,
+    retain: bool,
+    broker: String,
+}
+
+impl Mqtt {
+    /// Sets up the client and starts connecting. Only the settings are checked here, an
+    /// unreachable broker is retried in the background.
+    pub fn connect(config: &MqttConfig) -> Result<Self, String> {
+        let qos = match config.qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            qos => return Err(format!("[mqtt] qos must be 0, 1 or 2, not {qos}")),
+        };
+        // Without a scheme the broker is `host` or `host:port` over plain TCP
+        let url = if config.broker.contains("://") {
+            config.broker.clone()
+        } else {
+            format!("mqtt://{}", config.broker)
+        };
+        let url = reqwest::Url::parse(&url)
+            .map_err(|e| format!("Invalid [mqtt] broker {}: {e}", config.broker))?;
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("Invalid [mqtt] broker {}: no host", config.broker))?;
+        let tls = match url.scheme() {
+            "mqtt" | "tcp" => false,
+            "mqtts" | "ssl" => true,
+            scheme => {
+                return Err(format!(
+                    "[mqtt] broker scheme must be mqtt or mqtts, not {scheme}"
+                ));
+            }
+        };
+        let port = url.port().unwrap_or(if tls { 8883 } else { 1883 });
+
+        let mut options = MqttOptions::new(&config.client_id, host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.clone().unwrap_or_default());
+        }
+        if tls {
+            options.set_transport(Transport::tls_with_default_config());
+        }
+        let (client, mut eventloop) = AsyncClient::new(options, QUEUE_CAPACITY);
+
+        let broker = config.broker.clone();
+        tokio::spawn(async move {
+            let mut failing = false;
+            loop {
+                match eventloop.poll().await {
+                    Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => {
+                        failing = false;
+                        info!({ "broker": broker }, "Connected to MQTT broker {broker}");
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        // Logged once per outage, not on every reconnect attempt
+                        if failing {
+                            debug!({ "broker": broker }, "MQTT broker {broker}: {e}");
+                        } else {
+                            warn!({ "broker": broker }, "MQTT broker {broker} unreachable: {e}");
+                        }
+                        failing = true;
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                    }
+                }
+            }
+        });
+
+        Ok(Mqtt {
+            client,
+            topic_prefix: config.topic_prefix.trim_end_matches('/').to_string(),
+            qos,
+            retain: config.retain,
+            broker: config.broker.clone(),
+        })
+    }
+
+    pub fn broker(&self) -> &str {
+        &self.broker
+    }
+
+    pub fn topic_prefix(&self) -> &str {
+        &self.topic_prefix
+    }
+
+    /// Queues the event for publishing without waiting for the broker
+    pub fn publish(&self, event: &serde_json::Value) {
+        let event_type = event["type"].as_str().unwrap_or("unknown");
+        let topic = format!("{}/{event_type}", self.topic_prefix);
+        let payload = event.to_string();
+        if let Err(e) = self
+            .client
+            .try_publish(&topic, self.qos, self.retain, payload)
+        {
+            warn!({ "topic": topic }, "Failed to publish to MQTT topic {topic}: {e}");
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use nostr_sdk::nips::{nip04, nip44};
+use nostr_sdk::{
+    Client, Event, EventBuilder, EventId, Filter, Keys, Kind, PublicKey, RelayPoolNotification,
+    SecretKey, Tag, TagKind, Timestamp,
+};
+use serde_json::{Value, json};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+
+/// Methods the service answers, advertised in its info event
+pub const METHODS: &[&str] = &[
+    "pay_invoice",
+    "make_invoice",
+    "get_balance",
+    "lookup_invoice",
+    "get_info",
+];
+
+/// NIP-47 error codes
+pub const QUOTA_EXCEEDED: &str = "QUOTA_EXCEEDED";
+pub const RESTRICTED: &str = "RESTRICTED";
+pub const UNAUTHORIZED: &str = "UNAUTHORIZED";
+pub const NOT_IMPLEMENTED: &str = "NOT_IMPLEMENTED";
+pub const PAYMENT_FAILED: &str = "PAYMENT_FAILED";
+pub const NOT_FOUND: &str = "NOT_FOUND";
+pub const INTERNAL: &str = "INTERNAL";
+pub const OTHER: &str = "OTHER";
+
+/// An error code and message for the client
+pub type Error = (&'static str, String);
+
+/// How often a connection's budget starts over. Periods are rolling windows ending now, e.g.
+/// monthly is the last 30 days.
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum BudgetRenewal {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Never,
+}
+
+impl BudgetRenewal {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BudgetRenewal::Daily => "daily",
+            BudgetRenewal::Weekly => "weekly",
+            BudgetRenewal::Monthly => "monthly",
+            BudgetRenewal::Yearly => "yearly",
+            BudgetRenewal::Never => "never",
+        }
+    }
+}
+
+/// Start of the current budget period of a connection renewed `renewal`, as stored
+pub fn budget_since(renewal: &str, now: u64) -> u64 {
+    let days = match renewal {
+        "daily" => 1,
+        "weekly" => 7,
+        "monthly" => 30,
+        "yearly" => 365,
+        _ => return 0,
+    };
+    now.saturating_sub(days * 86400)
+}
+
+/// A new client key pair, as (secret, public key) hex. The secret only goes into the
+/// connection string, the store keeps the public key.
+pub fn generate_client() -> (String, String) {
+    let keys = Keys::generate();
+    (
+        keys.secret_key().to_secret_hex(),
+        keys.public_key().to_hex(),
+    )
+}
+
+/// The `nostr+walletconnect://` URI a client is set up with
+pub fn connection_uri(service_pubkey: &str, relays: &[String], secret: &str) -> String {
+    let mut url = reqwest::Url::parse(&format!("nostr+walletconnect://{service_pubkey}"))
+        .expect("a hex pubkey is a valid host");
+    {
+        let mut query = url.query_pairs_mut();
+        for relay in relays {
+            query.append_pair("relay", relay);
+        }
+        query.append_pair("secret", secret);
+    }
+    url.to_string()
+}
+
+/// A decrypted request from a client. The connection it belongs to is looked up by `client`.
+pub struct Request {
+    pub event_id: EventId,
+    pub client: PublicKey,
+    pub method: String,
+    pub params: serde_json::Map<String, Value>,
+    /// Answered with the encryption it was sent with
+    nip44: bool,
+}
+
+/// The wallet service side of NIP-47. Requests come out of the channel `new` returns, to be
+/// answered with `respond`.
+pub struct Service {
+    keys: Keys,
+    client: Option<Client>,
+    requests: mpsc::UnboundedSender<Request>,
+}
+
+impl Service {
+    pub fn new(secret: &[u8; 32]) -> Result<(Self, mpsc::UnboundedReceiver<Request>), String> {
+        let secret =
+            SecretKey::from_slice(secret).map_err(|e| format!("Invalid NWC service key: {e}"))?;
+        let (requests, receiver) = mpsc::unbounded_channel();
+        let service = Service {
+            keys: Keys::new(secret),
+            client: None,
+            requests,
+        };
+        Ok((service, receiver))
+    }
+
+    /// A service that never gets requests, for a daemon without `[nwc]`
+    pub fn disabled() -> (Self, mpsc::UnboundedReceiver<Request>) {
+        let (requests, receiver) = mpsc::unbounded_channel();
+        let service = Service {
+            keys: Keys::generate(),
+            client: None,
+            requests,
+        };
+        (service, receiver)
+    }
+
+    pub fn pubkey(&self) -> String {
+        self.keys.public_key().to_hex()
+    }
+
+    /// Connects to the relays and starts listening for requests. Relays that are down are
+    /// retried in the background.
+    pub async fn connect(&mut self, relays: &[String]) -> Result<(), String> {
+        let client = Client::new(self.keys.clone());
+        for relay in relays {
+            client
+                .add_relay(relay.as_str())
+                .await
+                .map_err(|e| format!("Invalid [nwc] relay {relay}: {e}"))?;
+        }
+        client.connect().await;
+
+        // Tells clients what this service can do and that it speaks NIP-44 as well as NIP-04
+        let info = EventBuilder::new(Kind::WalletConnectInfo, METHODS.join(" ")).tag(Tag::custom(
+            TagKind::custom("encryption"),
+            ["nip44_v2 nip04"],
+        ));
+        if let Err(e) = client.send_event_builder(info).await {
+            warn!("Failed to publish the NWC info event: {e}");
+        }
+
+        // Requests sent while the daemon was down are not answered, clients give up on them
+        let filter = Filter::new()
+            .kind(Kind::WalletConnectRequest)
+            .pubkey(self.keys.public_key())
+            .since(Timestamp::now());
+        client
+            .subscribe(filter, None)
+            .await
+            .map_err(|e| format!("Failed to subscribe to NWC requests: {e}"))?;
+
+        let mut notifications = client.notifications();
+        let keys = self.keys.clone();
+        let requests = self.requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let event = match notifications.recv().await {
+                    Ok(RelayPoolNotification::Event { event, .. }) => event,
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Missed {skipped} NWC relay notifications");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                if event.kind != Kind::WalletConnectRequest {
+                    continue;
+                }
+                match decrypt(&keys, &event) {
+                    Ok(request) => {
+                        if requests.send(request).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => warn!("Ignoring NWC request {}: {e}", event.id),
+                }
+            }
+        });
+        self.client = Some(client);
+        Ok(())
+    }
+
+    /// Publishes the response to `request`, encrypted to its client
+    pub async fn respond(&self, request: &Request, result: Result<Value, Error>) {
+        let Some(client) = &self.client else {
+            return;
+        };
+        let body = match result {
+            Ok(result) => json!({ "result_type": request.method, "result": result }),
+            Err((code, message)) => json!({
+                "result_type": request.method,
+                "error": { "code": code, "message": message },
+            }),
+        };
+        let secret = self.keys.secret_key();
+        let content = if request.nip44 {
+            nip44::encrypt(
+                secret,
+                &request.client,
+                body.to_string(),
+                nip44::Version::V2,
+            )
+            .map_err(|e| e.to_string())
+        } else {
+            nip04::encrypt(secret, &request.client, body.to_string()).map_err(|e| e.to_string())
+        };
+        let content = match content {
+            Ok(content) => content,
+            Err(e) => {
+                error!(
+                    "Failed to encrypt NWC response to {}: {e}",
+                    request.event_id
+                );
+                return;
+            }
+        };
+        let mut response = EventBuilder::new(Kind::WalletConnectResponse, content)
+            .tag(Tag::public_key(request.client))
+            .tag(Tag::event(request.event_id));
+        if request.nip44 {
+            response = response.tag(Tag::custom(TagKind::custom("encryption"), ["nip44_v2"]));
+        }
+        if let Err(e) = client.send_event_builder(response).await {
+            warn!(
+                "Failed to publish NWC response to {}: {e}",
+                request.event_id
+            );
+        }
+    }
+}
+
+fn decrypt(keys: &Keys, event: &Event) -> Result<Request, String> {
+    let nip44 = event.tags.iter().any(|tag| {
+        let tag = tag.as_slice();
+        tag.first().is_some_and(|name| name == "encryption")
+            && tag.get(1).is_some_and(|value| value.starts_with("nip44"))
+    });
+    let content = if nip44 {
+        nip44::decrypt(keys.secret_key(), &event.pubkey, &event.content).map_err(|e| e.to_string())
+    } else {
+        nip04::decrypt(keys.secret_key(), &event.pubkey, &event.content).map_err(|e| e.to_string())
+    };
+    let content: Value = content
+        .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()))
+        .map_err(|e| format!("undecryptable: {e}"))?;
+    let method = content["method"]
+        .as_str()
+        .ok_or("the request has no method")?;
+    Ok(Request {
+        event_id: event.id,
+        client: event.pubkey,
+        method: method.to_string(),
+        params: content["params"].as_object().cloned().unwrap_or_default(),
+        nip44,
+    })
+}
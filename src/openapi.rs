@@ -0,0 +1,188 @@
+use crate::schema;
+use crate::serve;
+use clap::ArgAction;
+use serde_json::{Value, json};
+
+/// OpenAPI 3.1 description of the endpoints `orange serve` offers, built from the CLI
+/// definition and the output schemas, with the events posted to webhooks under `webhooks`
+pub fn spec(cli: &clap::Command) -> Result<Value, String> {
+    let mut paths = serde_json::Map::new();
+    let mut schemas = serde_json::Map::new();
+    schemas.insert(
+        "Error".to_string(),
+        component(schema::output_schema("error")?),
+    );
+    schemas.insert("Event".to_string(), component(schema::event_schema()));
+
+    for command in schema::COMMANDS {
+        let words: Vec<&str> = command.split(' ').collect();
+        if *command == "error" || serve::EXCLUDED.contains(&words[0]) {
+            continue;
+        }
+        let mut subcommand = cli;
+        for word in &words {
+            subcommand = subcommand
+                .find_subcommand(word)
+                .ok_or_else(|| format!("No command {command} for its schema"))?;
+        }
+        let name: String = command
+            .split([' ', '-'])
+            .map(|word| word[..1].to_uppercase() + &word[1..])
+            .collect();
+        schemas.insert(name.clone(), component(schema::output_schema(command)?));
+
+        let arguments: Vec<&clap::Arg> = subcommand
+            .get_arguments()
+            .filter(|arg| {
+                !arg.is_global_set()
+                    && !matches!(arg.get_id().as_str(), "help" | "version")
+                    && !serve::is_local_argument(command, arg)
+            })
+            .collect();
+        let responses = json!({
+            "200": {
+                "description": "The command's output",
+                "content": { "application/json": { "schema": reference(&name) } },
+            },
+            "default": {
+                "description": "The command failed or the request was invalid",
+                "content": { "application/json": { "schema": reference("Error") } },
+            },
+        });
+        let operation_id = command.replace([' ', '-'], "_");
+        let mut operation = json!({ "responses": responses });
+        if let Some(about) = subcommand.get_about() {
+            operation["summary"] = json!(about.to_string());
+        }
+
+        let mut item = serde_json::Map::new();
+        if serve::READ_ONLY.contains(command) {
+            let parameters: Vec<Value> = arguments
+                .iter()
+                .map(|arg| {
+                    let mut parameter = json!({
+                        "name": arg.get_id().as_str(),
+                        "in": "query",
+                        "required": is_required(arg),
+                        "schema": argument_schema(arg),
+                    });
+                    if let Some(help) = arg.get_help() {
+                        parameter["description"] = json!(help.to_string());
+                    }
+                    parameter
+                })
+                .collect();
+            let mut get = operation.clone();
+            get["operationId"] = json!(format!("get_{operation_id}"));
+            get["parameters"] = json!(parameters);
+            item.insert("get".to_string(), get);
+        }
+
+        let mut properties = serde_json::Map::new();
+        let mut required = Vec::new();
+        for arg in &arguments {
+            let mut property = argument_schema(arg);
+            if let Some(help) = arg.get_help() {
+                property["description"] = json!(help.to_string());
+            }
+            properties.insert(arg.get_id().as_str().to_string(), property);
+            if is_required(arg) {
+                required.push(arg.get_id().as_str().to_string());
+            }
+        }
+        let mut post = operation;
+        post["operationId"] = json!(operation_id);
+        post["requestBody"] = json!({
+            "required": !required.is_empty(),
+            "content": {
+                "application/json": {
+                    "schema": {
+                        "type": "object",
+                        "properties": properties,
+                        "required": required,
+                        "additionalProperties": false,
+                    },
+                },
+            },
+        });
+        item.insert("post".to_string(), post);
+        paths.insert(format!("/{}", words.join("/")), Value::Object(item));
+    }
+
+    Ok(json!({
+        "openapi": "3.1.0",
+        "jsonSchemaDialect": "https://json-schema.org/draft/2020-12/schema",
+        "info": {
+            "title": "orange",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Lightning wallet commands served by `orange serve`. Each path is a \
+                command, its arguments are query parameters (GET) or a JSON object (POST).",
+        },
+        "security": [{ "bearerAuth": [] }],
+        "paths": paths,
+        "webhooks": {
+            "event": {
+                "post": {
+                    "summary": "A wallet or daemon event, or an array of them when \
+                        [webhooks] batch_window_ms is set",
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "oneOf": [
+                                        reference("Event"),
+                                        { "type": "array", "items": reference("Event") },
+                                    ],
+                                },
+                            },
+                        },
+                    },
+                    "responses": { "2XX": { "description": "Delivered" } },
+                },
+            },
+        },
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "bearerAuth": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "[serve] token",
+                },
+            },
+        },
+    }))
+}
+
+/// A command's schema without the `$schema` dialect, which the document sets for all of them
+fn component(mut schema: Value) -> Value {
+    if let Some(fields) = schema.as_object_mut() {
+        fields.remove("$schema");
+    }
+    schema
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+fn is_required(arg: &clap::Arg) -> bool {
+    arg.is_required_set() && arg.get_default_values().is_empty()
+}
+
+/// Values are passed on as command line arguments, so numbers and strings are both accepted
+fn argument_schema(arg: &clap::Arg) -> Value {
+    let value = json!({ "type": ["string", "number"] });
+    let mut schema = match arg.get_action() {
+        ArgAction::SetTrue => json!({ "type": "boolean" }),
+        ArgAction::Append => json!({ "type": "array", "items": value }),
+        _ => value,
+    };
+    if let Some(default) = arg.get_default_values().first()
+        && !matches!(arg.get_action(), ArgAction::SetTrue)
+    {
+        schema["default"] = json!(default.to_string_lossy());
+    }
+    schema
+}
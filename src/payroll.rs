@@ -0,0 +1,235 @@
+use crate::config::PrivacyConfig;
+use crate::privacy;
+use crate::signing::Signer;
+use crate::store::{PayrollRecipient, Store};
+use crate::{now_secs, send_payment};
+use orange_sdk::Wallet;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Deserialize)]
+struct PayrollRow {
+    name: String,
+    lightning_address: String,
+    #[serde(alias = "amount")]
+    amount_sats: u64,
+    #[serde(default)]
+    memo: String,
+}
+
+/// Pays every row of a payroll CSV once per batch, writing a signed receipt per row. A row that
+/// fails is reported in its result and the rest of the batch still runs.
+/// With `[privacy]` enabled, payments are spread out and round amounts paid in parts.
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    wallet: &Wallet,
+    store: &Store,
+    signer: &Signer,
+    privacy: &PrivacyConfig,
+    csv_path: &Path,
+    batch: &str,
+    receipts_dir: &Path,
+    cost_center: Option<&str>,
+) -> Result<serde_json::Value, String> {
+    let mut reader = csv::Reader::from_path(csv_path)
+        .map_err(|e| format!("Failed to open {}: {e}", csv_path.display()))?;
+    let rows: Vec<PayrollRow> = reader
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("Invalid payroll CSV: {e}"))?;
+
+    std::fs::create_dir_all(receipts_dir)
+        .map_err(|e| format!("Failed to create receipts directory: {e}"))?;
+
+    let mut results = Vec::with_capacity(rows.len());
+    let (mut paid, mut skipped, mut failed, mut paid_sats) = (0, 0, 0, 0);
+    let mut sent_any = false;
+    // A recipient listed twice is paid twice, each row keyed by its occurrence
+    let mut occurrences: HashMap<(String, String), u32> = HashMap::new();
+    for (index, row) in rows.into_iter().enumerate() {
+        let key = (row.name.clone(), row.lightning_address.clone());
+        let occurrence = occurrences.entry(key).or_default();
+        let recipient = PayrollRecipient {
+            batch,
+            name: &row.name,
+            lightning_address: &row.lightning_address,
+            occurrence: *occurrence,
+        };
+        *occurrence += 1;
+        let failure = |e: String| {
+            json!({
+                "name": row.name,
+                "lightning_address": row.lightning_address,
+                "amount_sats": row.amount_sats,
+                "status": "failed",
+                "error": e,
+            })
+        };
+
+        match store.payroll_payment(&recipient) {
+            Ok(Some(payment_id)) => {
+                skipped += 1;
+                results.push(json!({
+                    "name": row.name,
+                    "lightning_address": row.lightning_address,
+                    "amount_sats": row.amount_sats,
+                    "status": "already_paid",
+                    "payment_id": payment_id,
+                }));
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                failed += 1;
+                results.push(failure(e));
+                continue;
+            }
+        }
+
+        // Parts paid by an earlier run that stopped partway, only the rest is sent now
+        let earlier = match store.payroll_parts(&recipient) {
+            Ok(earlier) => earlier,
+            Err(e) => {
+                failed += 1;
+                results.push(failure(e));
+                continue;
+            }
+        };
+        let (mut payment_ids, mut parts): (Vec<String>, Vec<u64>) = earlier.into_iter().unzip();
+        let mut amount_sats: u64 = parts.iter().sum();
+        let due = row.amount_sats.saturating_sub(amount_sats);
+        let split = if parts.is_empty() || due > 0 {
+            privacy::split(privacy, &row.lightning_address, due)
+        } else {
+            Vec::new()
+        };
+        // Once money has moved, bookkeeping errors are reported with the row, not returned
+        let mut errors = Vec::new();
+        let mut send_error = None;
+        for part in split {
+            if sent_any {
+                tokio::time::sleep(privacy::delay(privacy)).await;
+            }
+            sent_any = true;
+            match send_payment(
+                wallet,
+                store,
+                &row.lightning_address,
+                Some(part),
+                cost_center,
+            )
+            .await
+            {
+                Ok((payment_id, sats)) => {
+                    // Recorded right away, so a run that stops here resumes after this part
+                    if let Err(e) =
+                        store.record_payroll_part(&recipient, &payment_id, sats, now_secs())
+                    {
+                        errors.push(e);
+                    }
+                    paid_sats += sats;
+                    payment_ids.push(payment_id);
+                    parts.push(sats);
+                    amount_sats += sats;
+                }
+                Err(e) => {
+                    send_error = Some(e);
+                    break;
+                }
+            }
+        }
+
+        let paid_at = now_secs();
+        if let Some(e) = send_error {
+            failed += 1;
+            errors.insert(0, e);
+            results.push(json!({
+                "name": row.name,
+                "lightning_address": row.lightning_address,
+                "amount_sats": row.amount_sats,
+                "status": if payment_ids.is_empty() { "failed" } else { "partial" },
+                "paid_sats": amount_sats,
+                "payment_ids": payment_ids,
+                "error": errors.join("; "),
+            }));
+            continue;
+        }
+        let payment_id = payment_ids[0].clone();
+        if let Err(e) =
+            store.record_payroll_payment(&recipient, amount_sats, &row.memo, &payment_id, paid_at)
+        {
+            errors.push(e);
+        }
+
+        let mut payload = json!({
+            "type": "payroll_receipt",
+            "batch": batch,
+            "name": row.name,
+            "lightning_address": row.lightning_address,
+            "amount_sats": amount_sats,
+            "memo": row.memo,
+            "payment_id": payment_id,
+            "paid_at": paid_at,
+        });
+        if parts.len() > 1 {
+            payload["payment_ids"] = json!(payment_ids);
+        }
+        let receipt = signer.sign_json(payload);
+        // Numbered by CSV row, so recipients with the same name or file stem keep their own
+        let receipt_path =
+            receipts_dir.join(format!("{}-{}.json", index + 1, file_stem(&row.name)));
+        let written = std::fs::write(
+            &receipt_path,
+            serde_json::to_string_pretty(&receipt).unwrap(),
+        );
+        if let Err(e) = &written {
+            errors.push(format!(
+                "Failed to write receipt {}: {e}",
+                receipt_path.display()
+            ));
+        }
+
+        paid += 1;
+        let mut result = json!({
+            "name": row.name,
+            "lightning_address": row.lightning_address,
+            "amount_sats": amount_sats,
+            "status": "paid",
+            "payment_id": payment_id,
+            "receipt": written.ok().map(|_| receipt_path.display().to_string()),
+        });
+        if parts.len() > 1 {
+            result["parts"] = json!(parts);
+            result["payment_ids"] = json!(payment_ids);
+        }
+        if !errors.is_empty() {
+            result["error"] = json!(errors.join("; "));
+        }
+        results.push(result);
+    }
+
+    Ok(json!({
+        "batch": batch,
+        "total": results.len(),
+        "paid": paid,
+        "already_paid": skipped,
+        "failed": failed,
+        "paid_sats": paid_sats,
+        "results": results,
+    }))
+}
+
+/// Makes a recipient name safe to use as a file name
+fn file_stem(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,141 @@
+use crate::config::PluginConfig;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::mpsc;
+
+/// Wait before restarting a plugin that exited, doubled after each quick exit
+const RESTART_MIN: Duration = Duration::from_secs(1);
+const RESTART_MAX: Duration = Duration::from_secs(300);
+
+/// A plugin that closes stdin isn't killed for this long, so it can finish what it was doing
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Long-running transport plugins from `[[plugins]]` and `--plugin`. Each is one child process
+/// that gets every event it subscribes to as a line of JSON on stdin. A plugin that exits is
+/// restarted, and events posted meanwhile wait for it.
+#[derive(Default)]
+pub struct Plugins {
+    plugins: Vec<Plugin>,
+}
+
+struct Plugin {
+    config: PluginConfig,
+    lines: mpsc::UnboundedSender<String>,
+}
+
+impl Plugins {
+    /// Starts every plugin. Dropping the returned value closes their stdin, which asks them to
+    /// exit.
+    pub fn start(configs: &[PluginConfig]) -> Self {
+        let plugins = configs
+            .iter()
+            .map(|config| {
+                let (lines, receiver) = mpsc::unbounded_channel();
+                tokio::spawn(supervise(config.clone(), receiver));
+                Plugin {
+                    config: config.clone(),
+                    lines,
+                }
+            })
+            .collect();
+        Plugins { plugins }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    pub fn configs(&self) -> impl Iterator<Item = &PluginConfig> {
+        self.plugins.iter().map(|plugin| &plugin.config)
+    }
+
+    /// Hands the event to every plugin subscribed to its type
+    pub fn send(&self, event: &serde_json::Value) {
+        let event_type = event["type"].as_str().unwrap_or_default();
+        for plugin in &self.plugins {
+            let events = &plugin.config.events;
+            if !events.is_empty() && !events.iter().any(|e| e == event_type) {
+                continue;
+            }
+            let _ = plugin.lines.send(format!("{event}\n"));
+        }
+    }
+}
+
+/// Keeps the plugin running and feeds it `lines` until they close
+async fn supervise(config: PluginConfig, mut lines: mpsc::UnboundedReceiver<String>) {
+    let command = config.command.as_str();
+    let mut delay = RESTART_MIN;
+    let mut unsent: Option<String> = None;
+    loop {
+        let started = Instant::now();
+        match spawn(&config) {
+            Ok((mut child, stdin)) => {
+                info!({ "command": command }, "Plugin {command} started");
+                if !feed(&config, &mut child, stdin, &mut lines, &mut unsent).await {
+                    let _ = tokio::time::timeout(SHUTDOWN_GRACE, child.wait()).await;
+                    return;
+                }
+            }
+            Err(e) => warn!({ "command": command }, "Plugin {command} failed to start: {e}"),
+        }
+
+        // A plugin that ran for a while gets restarted quickly again
+        if started.elapsed() > RESTART_MAX {
+            delay = RESTART_MIN;
+        }
+        info!({ "command": command }, "Restarting plugin {command} in {}s", delay.as_secs());
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(RESTART_MAX);
+    }
+}
+
+/// Writes lines to the plugin until it exits or stops reading, returning true, or until
+/// `lines` close, returning false with its stdin closed. A line that couldn't be written is
+/// left in `unsent` for the restarted plugin.
+async fn feed(
+    config: &PluginConfig,
+    child: &mut Child,
+    mut stdin: ChildStdin,
+    lines: &mut mpsc::UnboundedReceiver<String>,
+    unsent: &mut Option<String>,
+) -> bool {
+    let command = config.command.as_str();
+    loop {
+        let line = match unsent.take() {
+            Some(line) => line,
+            None => tokio::select! {
+                line = lines.recv() => match line {
+                    Some(line) => line,
+                    None => return false,
+                },
+                status = child.wait() => {
+                    let status = status.map_or_else(|e| e.to_string(), |s| s.to_string());
+                    warn!({ "command": command }, "Plugin {command} exited: {status}");
+                    return true;
+                }
+            },
+        };
+        if let Err(e) = stdin.write_all(line.as_bytes()).await {
+            warn!({ "command": command }, "Plugin {command} stopped reading: {e}");
+            *unsent = Some(line);
+            return true;
+        }
+    }
+}
+
+fn spawn(config: &PluginConfig) -> Result<(Child, ChildStdin), String> {
+    // stderr is the plugin's log and goes to the daemon's, stdout is unused
+    let mut child = Command::new(&config.command)
+        .args(&config.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let stdin = child.stdin.take().ok_or("no stdin")?;
+    Ok((child, stdin))
+}
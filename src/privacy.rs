@@ -0,0 +1,115 @@
+use crate::config::PrivacyConfig;
+use rand::Rng;
+use std::time::Duration;
+
+/// Amounts that are a multiple of this many sats count as round, e.g. 250000 or 1000000
+const ROUND_SATS: u64 = 1000;
+
+/// No part of a split amount is smaller than this share of it, in percent
+const MIN_PART_PERCENT: u64 = 15;
+
+/// Most parts an amount can be split into, so they can all get their minimum share
+pub const MAX_PARTS: u8 = (100 / MIN_PART_PERCENT) as u8;
+
+/// A random wait of up to `max_delay_secs`, before an automated payment
+pub fn delay(config: &PrivacyConfig) -> Duration {
+    if !config.enabled || config.max_delay_secs == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_secs(rand::thread_rng().gen_range(0..=config.max_delay_secs))
+}
+
+/// Splits a round amount into 2 to `max_parts` payments of uneven, non-round sizes that add up
+/// to it. Only destinations that hand out a fresh invoice per payment can be paid in parts, and
+/// on-chain addresses aren't, since that would reuse the address.
+pub fn split(config: &PrivacyConfig, payment: &str, amount_sats: u64) -> Vec<u64> {
+    let reusable = payment.contains('@') || payment.to_ascii_lowercase().starts_with("lno");
+    if !config.enabled
+        || !config.split_round_amounts
+        || !reusable
+        || config.max_parts < 2
+        || amount_sats % ROUND_SATS != 0
+        || amount_sats < 2 * ROUND_SATS
+    {
+        return vec![amount_sats];
+    }
+    let mut rng = rand::thread_rng();
+    let parts = rng.gen_range(2..=config.max_parts.min(MAX_PARTS) as u64);
+    let min_part = amount_sats * MIN_PART_PERCENT / 100;
+    let mut remaining = amount_sats;
+    let mut amounts = Vec::with_capacity(parts as usize);
+    for left in (2..=parts).rev() {
+        // Leave enough for the parts still to come
+        let max_part = remaining - min_part * (left - 1);
+        let mut part = rng.gen_range(min_part..=max_part);
+        if part % ROUND_SATS == 0 {
+            part -= rng.gen_range(1..min_part.clamp(2, ROUND_SATS));
+        }
+        amounts.push(part);
+        remaining -= part;
+    }
+    // The other parts are uneven, but their sum may have left this one round. It is at least
+    // `min_part`, so it can give a sat or two to the first part.
+    if remaining % ROUND_SATS == 0 {
+        let shift = if amounts[0] % ROUND_SATS == ROUND_SATS - 1 {
+            2
+        } else {
+            1
+        };
+        amounts[0] += shift;
+        remaining -= shift;
+    }
+    amounts.push(remaining);
+    amounts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_parts: u8) -> PrivacyConfig {
+        PrivacyConfig {
+            enabled: true,
+            max_parts,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn split_adds_up_to_uneven_parts() {
+        for amount_sats in [2_000, 7_000, 100_000, 250_000, 1_000_000, 21_000_000] {
+            for _ in 0..500 {
+                let parts = split(&config(MAX_PARTS), "alice@example.com", amount_sats);
+                assert!((2..=MAX_PARTS as usize).contains(&parts.len()), "{parts:?}");
+                assert_eq!(parts.iter().sum::<u64>(), amount_sats, "{parts:?}");
+                assert!(
+                    parts.iter().all(|p| *p > 0 && p % ROUND_SATS != 0),
+                    "{parts:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn split_caps_parts() {
+        for _ in 0..500 {
+            let parts = split(&config(u8::MAX), "lno1qcp4256ypq", 1_000_000);
+            assert!(parts.len() <= MAX_PARTS as usize);
+            assert_eq!(parts.iter().sum::<u64>(), 1_000_000);
+        }
+    }
+
+    #[test]
+    fn split_leaves_other_payments_whole() {
+        let cases = [
+            (config(4), "bc1qexampleaddress", 100_000),
+            (config(4), "alice@example.com", 100_001),
+            (config(4), "alice@example.com", 1_000),
+            (config(1), "alice@example.com", 100_000),
+            (PrivacyConfig::default(), "alice@example.com", 100_000),
+        ];
+        for (config, payment, amount_sats) in cases {
+            assert_eq!(split(&config, payment, amount_sats), vec![amount_sats]);
+        }
+    }
+}
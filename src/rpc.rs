@@ -0,0 +1,77 @@
+use crate::serve;
+use axum::http::StatusCode;
+use serde_json::{Value, json};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The command ran and failed, the message is its error
+pub const COMMAND_FAILED: i64 = -32000;
+
+/// A JSON-RPC 2.0 request. Without an `id` it is a notification, which gets no response.
+pub struct Request {
+    pub id: Option<Value>,
+    pub method: String,
+    pub params: serde_json::Map<String, Value>,
+}
+
+/// Reads one line of input, or returns the error response to write for it
+pub fn parse(line: &str) -> Result<Request, Value> {
+    let value: Value = serde_json::from_str(line)
+        .map_err(|e| error(Value::Null, PARSE_ERROR, &format!("Invalid JSON: {e}")))?;
+    let id = value.get("id").cloned();
+    let invalid = |message: &str| error(id.clone().unwrap_or_default(), INVALID_REQUEST, message);
+    if value["jsonrpc"] != "2.0" {
+        return Err(invalid("Not a JSON-RPC 2.0 request"));
+    }
+    let Some(method) = value["method"].as_str() else {
+        return Err(invalid("The request has no method"));
+    };
+    let params = match value.get("params") {
+        None | Some(Value::Null) => serde_json::Map::new(),
+        Some(Value::Object(params)) => params.clone(),
+        Some(_) => {
+            return Err(error(
+                id.unwrap_or_default(),
+                INVALID_PARAMS,
+                "params must be an object",
+            ));
+        }
+    };
+    Ok(Request {
+        id,
+        method: method.to_string(),
+        params,
+    })
+}
+
+/// The command line for a request. Methods are the commands as `orange schema` lists them,
+/// e.g. "balance" or "vault send", and params are named like their arguments.
+pub fn command_args(cli: &clap::Command, request: &Request) -> Result<Vec<String>, (i64, String)> {
+    let words: Vec<String> = request
+        .method
+        .split(' ')
+        .filter(|word| !word.is_empty())
+        .map(String::from)
+        .collect();
+    if words.is_empty() || serve::EXCLUDED.contains(&words[0].as_str()) {
+        return Err((METHOD_NOT_FOUND, format!("No method {}", request.method)));
+    }
+    serve::command_args(cli, &words, &request.params).map_err(|(status, e)| match status {
+        StatusCode::NOT_FOUND => (METHOD_NOT_FOUND, e),
+        _ => (INVALID_PARAMS, e),
+    })
+}
+
+pub fn result(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+pub fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+pub fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
@@ -0,0 +1,1096 @@
+use crate::tz;
+use serde_json::json;
+
+/// Commands with a documented output schema, as typed after `orange`
+pub const COMMANDS: &[&str] = &[
+    "balance",
+    "receive",
+    "receive-offer",
+    "send",
+    "authorize",
+    "execute",
+    "parse",
+    "transactions",
+    "pending",
+    "reconcile",
+    "channels",
+    "info",
+    "audit-snapshot",
+    "selftest",
+    "dev faucet",
+    "estimate-fee",
+    "lightning-address",
+    "register-lightning-address",
+    "unlock",
+    "addresses",
+    "report",
+    "fiat-exposure",
+    "heartbeat",
+    "vault send",
+    "vault list",
+    "vault cancel",
+    "nwc create",
+    "nwc list",
+    "nwc revoke",
+    "withdraw-link",
+    "withdraw-links list",
+    "withdraw-links revoke",
+    "withdraw-links redeem",
+    "webhooks list",
+    "webhooks deliveries",
+    "webhooks test",
+    "webhooks enable",
+    "webhooks dead-letter list",
+    "webhooks dead-letter redeliver",
+    "outbox list",
+    "outbox purge",
+    "events list",
+    "events replay",
+    "events peek",
+    "analytics latency",
+    "stats",
+    "payroll run",
+    "get-event",
+    "event-handled",
+    "get-events",
+    "events-handled",
+    "codegen",
+    "openapi",
+    "error",
+];
+
+/// JSON Schema (draft 2020-12) of what `command` prints on success, or of the error object
+/// for "error"
+pub fn output_schema(command: &str) -> Result<serde_json::Value, String> {
+    let body = match command {
+        "balance" => object(&[
+            ("trusted_sats", t("integer")),
+            ("lightning_sats", t("integer")),
+            ("pending_sats", t("integer")),
+            ("available_sats", t("integer")),
+            ("snapshot_at?", t("integer")),
+        ]),
+        "receive" => object(&[
+            ("invoice", t("string")),
+            ("address", tn("string")),
+            ("amount_sats", tn("integer")),
+            ("full_uri", t("string")),
+            ("from_trusted", t("boolean")),
+            ("warning?", t("string")),
+            (
+                "meta?",
+                json!({ "type": "object", "additionalProperties": { "type": "string" } }),
+            ),
+            ("existing?", t("boolean")),
+            (
+                "fiat?",
+                object(&[
+                    ("amount", t("number")),
+                    ("currency", t("string")),
+                    ("btc_price", t("number")),
+                ]),
+            ),
+        ]),
+        "receive-offer" => object(&[("offer", t("string"))]),
+        "send" => object(&[
+            ("payment_id", t("string")),
+            ("amount_sats", t("integer")),
+            ("status", t("string")),
+        ]),
+        "authorize" => object(&[
+            ("id", t("string")),
+            ("file", t("string")),
+            ("pubkey", t("string")),
+            ("destination", t("string")),
+            ("max_amount_sats", t("integer")),
+            ("expires_at", t("integer")),
+        ]),
+        "execute" => object(&[
+            ("id", t("string")),
+            ("payment_id", t("string")),
+            ("destination", t("string")),
+            ("amount_sats", t("integer")),
+            ("estimated_fee_sats", t("integer")),
+            ("max_amount_sats", t("integer")),
+            ("status", t("string")),
+        ]),
+        "parse" => json!({
+            "oneOf": [
+                object(&[("parsed", t("string"))]),
+                object(&[("asset_transfer", t("string")), ("supported", t("boolean"))]),
+            ]
+        }),
+        "transactions" => list(
+            "transactions",
+            object(&[
+                ("id", t("string")),
+                ("status", t("string")),
+                ("outbound", t("boolean")),
+                ("amount_sats", tn("integer")),
+                ("fee_sats", tn("integer")),
+                ("payment_type", t("string")),
+                ("timestamp", t("integer")),
+            ]),
+            &[("snapshot_at?", t("integer"))],
+        ),
+        "pending" => object(&[
+            ("count", t("integer")),
+            (
+                "outbound",
+                array(object(&[
+                    ("payment_id", t("string")),
+                    ("amount_sats", tn("integer")),
+                    ("fee_sats", tn("integer")),
+                    ("payment_type", t("string")),
+                    ("timestamp", t("integer")),
+                    ("age_secs", t("integer")),
+                    ("payment", tn("string")),
+                    ("cost_center", tn("string")),
+                ])),
+            ),
+            (
+                "inbound",
+                array(object(&[
+                    ("payment_id", t("string")),
+                    ("amount_sats", tn("integer")),
+                    ("payment_type", t("string")),
+                    ("timestamp", t("integer")),
+                    ("age_secs", t("integer")),
+                ])),
+            ),
+        ]),
+        "reconcile" => object(&[
+            ("since", t("integer")),
+            ("sends_checked", t("integer")),
+            ("transactions_checked", t("integer")),
+            ("discrepancies", t("integer")),
+            (
+                "orphans",
+                array(object(&[
+                    ("payment_id", t("string")),
+                    ("payment", t("string")),
+                    ("amount_sats", t("integer")),
+                    ("status", t("string")),
+                    ("created_at", t("integer")),
+                ])),
+            ),
+            (
+                "unknown",
+                array(object(&[
+                    ("payment_id", t("string")),
+                    ("status", t("string")),
+                    ("amount_sats", tn("integer")),
+                    ("fee_sats", tn("integer")),
+                    ("payment_type", t("string")),
+                    ("timestamp", t("integer")),
+                ])),
+            ),
+            (
+                "mismatches",
+                array(object(&[
+                    ("payment_id", t("string")),
+                    ("field", json!({ "enum": ["status", "amount_sats"] })),
+                    ("local", json!({ "type": ["string", "integer"] })),
+                    ("wallet", json!({ "type": ["string", "integer"] })),
+                ])),
+            ),
+            (
+                "duplicates",
+                array(object(&[
+                    ("payment", t("string")),
+                    ("payment_ids", array(t("string"))),
+                    ("amount_sats", t("integer")),
+                    ("first_created_at", t("integer")),
+                ])),
+            ),
+        ]),
+        "channels" => list(
+            "channels",
+            object(&[
+                ("channel_id", t("string")),
+                ("counterparty_node_id", t("string")),
+                ("funding_txo", tn("string")),
+                ("is_channel_ready", t("boolean")),
+                ("is_usable", t("boolean")),
+                ("inbound_capacity_sats", t("integer")),
+                ("outbound_capacity_sats", t("integer")),
+                ("channel_value_sats", t("integer")),
+            ]),
+            &[("snapshot_at?", t("integer"))],
+        ),
+        "info" => object(&[
+            ("node_id", t("string")),
+            ("lsp_connected", t("boolean")),
+            (
+                "tunables",
+                object(&[
+                    ("trusted_balance_limit_sats", t("integer")),
+                    ("rebalance_min_sats", t("integer")),
+                    ("onchain_receive_threshold_sats", t("integer")),
+                    ("enable_amountless_receive_on_chain", t("boolean")),
+                ]),
+            ),
+        ]),
+        "audit-snapshot" => object(&[
+            ("id", t("string")),
+            ("file", tn("string")),
+            ("pubkey", t("string")),
+            ("signature", t("string")),
+            (
+                "payload",
+                object(&[
+                    ("type", json!({ "enum": ["audit_snapshot"] })),
+                    ("network", t("string")),
+                    ("node_id", t("string")),
+                    ("timestamp", t("integer")),
+                    (
+                        "balances",
+                        object(&[
+                            ("trusted_sats", t("integer")),
+                            ("lightning_sats", t("integer")),
+                            ("pending_sats", t("integer")),
+                            ("available_sats", t("integer")),
+                        ]),
+                    ),
+                    (
+                        "utxos",
+                        array(object(&[
+                            ("outpoint", t("string")),
+                            ("amount_sats", t("integer")),
+                            ("channel_id", t("string")),
+                        ])),
+                    ),
+                    (
+                        "channels",
+                        array(object(&[
+                            ("channel_id", t("string")),
+                            ("counterparty_node_id", t("string")),
+                            ("funding_txo", tn("string")),
+                            ("is_usable", t("boolean")),
+                            ("channel_value_sats", t("integer")),
+                            ("outbound_capacity_sats", t("integer")),
+                            ("inbound_capacity_sats", t("integer")),
+                        ])),
+                    ),
+                    (
+                        "pending",
+                        object(&[
+                            ("outbound_sats", t("integer")),
+                            ("inbound_sats", t("integer")),
+                        ]),
+                    ),
+                ]),
+            ),
+        ]),
+        "selftest" => object(&[
+            ("payment_id", t("string")),
+            ("amount_sats", t("integer")),
+            ("fee_sats", tn("integer")),
+            ("invoice_ms", t("integer")),
+            ("send_ms", t("integer")),
+            ("settle_ms", t("integer")),
+            ("receive_ms", t("integer")),
+            ("total_ms", t("integer")),
+        ]),
+        "estimate-fee" => object(&[("estimated_fee_sats", t("integer"))]),
+        "lightning-address" => object(&[("lightning_address", tn("string"))]),
+        "register-lightning-address" => object(&[
+            ("registered", t("boolean")),
+            ("lightning_address", tn("string")),
+        ]),
+        "unlock" => object(&[("unlocked", t("boolean"))]),
+        "addresses" => list(
+            "addresses",
+            object(&[
+                ("address", t("string")),
+                ("purposes", array(t("string"))),
+                ("times_issued", t("integer")),
+                ("first_issued", t("integer")),
+                ("last_issued", t("integer")),
+                ("invoice_received_sats", t("integer")),
+            ]),
+            &[],
+        ),
+        "report" => json!({
+            "oneOf": [
+                object(&[(
+                    "by_cost_center",
+                    array(object(&[
+                        ("cost_center", tn("string")),
+                        ("payment_count", t("integer")),
+                        ("amount_sats", t("integer")),
+                        ("fee_sats", t("integer")),
+                    ])),
+                )]),
+                object(&[
+                    (
+                        "by_address_name",
+                        array(object(&[
+                            ("name", t("string")),
+                            ("lightning_address", tn("string")),
+                            ("registered_at", t("integer")),
+                            ("payment_count", t("integer")),
+                            ("received_sats", t("integer")),
+                        ])),
+                    ),
+                    (
+                        "unattributed",
+                        object(&[
+                            ("payment_count", t("integer")),
+                            ("received_sats", t("integer")),
+                        ]),
+                    ),
+                ]),
+            ]
+        }),
+        "fiat-exposure" => object(&[
+            ("currency", t("string")),
+            ("btc_price", t("number")),
+            ("obligations", t("integer")),
+            ("fiat_obligated", t("number")),
+            ("received_sats", t("integer")),
+            ("current_value", t("number")),
+            ("drift", t("number")),
+            ("drift_percent", t("number")),
+            (
+                "suggestion",
+                json!({
+                    "oneOf": [
+                        object(&[
+                            ("action", json!({ "enum": ["add_sats", "convert_to_fiat"] })),
+                            ("amount_sats", t("integer")),
+                        ]),
+                        t("null"),
+                    ]
+                }),
+            ),
+        ]),
+        "heartbeat" => object(&[
+            ("heartbeat_at", t("integer")),
+            ("sweep_after", tn("integer")),
+        ]),
+        "vault send" => object(&[
+            ("id", t("integer")),
+            ("payment", t("string")),
+            ("amount_sats", tn("integer")),
+            ("not_before", t("integer")),
+            ("status", t("string")),
+        ]),
+        "vault list" => list(
+            "vault_sends",
+            object(&[
+                ("id", t("integer")),
+                ("payment", t("string")),
+                ("amount_sats", tn("integer")),
+                ("cost_center", tn("string")),
+                ("not_before", t("integer")),
+                (
+                    "status",
+                    json!({ "enum": ["pending", "executing", "executed", "failed", "cancelled"] }),
+                ),
+                ("payment_id", tn("string")),
+                ("error", tn("string")),
+                ("created_at", t("integer")),
+                ("executed_at", tn("integer")),
+            ]),
+            &[],
+        ),
+        "vault cancel" => object(&[("id", t("integer")), ("status", t("string"))]),
+        "nwc create" => object(&[
+            ("id", t("integer")),
+            ("name", t("string")),
+            ("client_pubkey", t("string")),
+            ("budget_sats", tn("integer")),
+            (
+                "budget_renewal",
+                json!({ "enum": ["daily", "weekly", "monthly", "yearly", "never"] }),
+            ),
+            ("connection_uri", t("string")),
+        ]),
+        "nwc list" => list(
+            "connections",
+            object(&[
+                ("id", t("integer")),
+                ("name", t("string")),
+                ("client_pubkey", t("string")),
+                ("budget_sats", tn("integer")),
+                (
+                    "budget_renewal",
+                    json!({ "enum": ["daily", "weekly", "monthly", "yearly", "never"] }),
+                ),
+                ("spent_sats", t("integer")),
+                ("created_at", t("integer")),
+                ("revoked_at", tn("integer")),
+            ]),
+            &[],
+        ),
+        "nwc revoke" => object(&[("id", t("integer")), ("status", t("string"))]),
+        "withdraw-link" => object(&[
+            ("id", t("integer")),
+            ("amount_sats", t("integer")),
+            ("uses", t("integer")),
+            ("total_sats", t("integer")),
+            ("description", tn("string")),
+            ("url", t("string")),
+            ("lnurl", t("string")),
+        ]),
+        "withdraw-links list" => list(
+            "withdraw_links",
+            object(&[
+                ("id", t("integer")),
+                ("amount_sats", t("integer")),
+                ("uses", t("integer")),
+                ("used", t("integer")),
+                ("description", tn("string")),
+                ("created_at", t("integer")),
+                ("revoked_at", tn("integer")),
+                ("lnurl", tn("string")),
+            ]),
+            &[],
+        ),
+        "withdraw-links revoke" => object(&[("id", t("integer")), ("status", t("string"))]),
+        "withdraw-links redeem" => object(&[
+            ("id", t("integer")),
+            ("payment_id", t("string")),
+            ("amount_sats", t("integer")),
+            ("uses_left", t("integer")),
+        ]),
+        "webhooks list" => object(&[(
+            "webhooks",
+            array(object(&[
+                ("url", t("string")),
+                ("enabled", t("boolean")),
+                ("delivered", t("integer")),
+                ("failed", t("integer")),
+                ("consecutive_failures", t("integer")),
+                ("failing_since", tn("integer")),
+                ("last_error", tn("string")),
+                ("disabled_at", tn("integer")),
+            ])),
+        )]),
+        "webhooks deliveries" => list(
+            "deliveries",
+            object(&[
+                ("delivery_id", t("integer")),
+                ("url", t("string")),
+                ("event_type", t("string")),
+                ("attempt", t("integer")),
+                ("status", tn("integer")),
+                ("error", tn("string")),
+                ("latency_ms", t("integer")),
+                ("attempted_at", t("integer")),
+            ]),
+            &[],
+        ),
+        "webhooks test" => object(&[
+            ("event", event()),
+            (
+                "deliveries",
+                array(object(&[
+                    ("delivery_id", t("integer")),
+                    ("url", t("string")),
+                    ("status", tn("integer")),
+                    ("error", tn("string")),
+                    ("latency_ms", t("integer")),
+                ])),
+            ),
+        ]),
+        "webhooks enable" => object(&[("url", t("string")), ("enabled", t("boolean"))]),
+        "webhooks dead-letter list" => list(
+            "dead_letters",
+            object(&[
+                ("id", t("integer")),
+                ("url", t("string")),
+                ("event_type", tn("string")),
+                ("attempts", t("integer")),
+                ("last_error", tn("string")),
+                ("created_at", t("integer")),
+                ("dead_at", t("integer")),
+                // A JSON array of events for batched deliveries
+                ("event", json!({ "oneOf": [event(), array(event())] })),
+            ]),
+            &[],
+        ),
+        "webhooks dead-letter redeliver" => object(&[
+            ("id", t("integer")),
+            ("url", t("string")),
+            ("status", t("string")),
+        ]),
+        "outbox list" => list(
+            "deliveries",
+            object(&[
+                ("id", t("integer")),
+                ("url", t("string")),
+                (
+                    "state",
+                    json!({ "enum": ["pending", "retrying", "failed"] }),
+                ),
+                ("event_type", tn("string")),
+                ("attempts", t("integer")),
+                ("last_error", tn("string")),
+                ("next_attempt_at", tn("integer")),
+                ("created_at", t("integer")),
+                ("dead_at", tn("integer")),
+                ("event", json!({ "oneOf": [event(), array(event())] })),
+            ]),
+            &[],
+        ),
+        "outbox purge" => object(&[
+            (
+                "state",
+                json!({ "enum": ["pending", "retrying", "failed"] }),
+            ),
+            ("before", t("integer")),
+            ("purged", t("integer")),
+        ]),
+        "events list" => list("events", event(), &[]),
+        "events replay" => json!({
+            "oneOf": [
+                list("events", event(), &[]),
+                object(&[
+                    ("url", t("string")),
+                    ("delivered", t("integer")),
+                    ("failed_seq", tn("integer")),
+                    ("error", tn("string")),
+                ]),
+            ]
+        }),
+        "events peek" => {
+            let mut item = event();
+            item["properties"]["index"] = t("integer");
+            item["properties"]["state"] = json!({ "enum": ["pulled", "queued"] });
+            item["required"]
+                .as_array_mut()
+                .unwrap()
+                .extend([json!("index"), json!("state")]);
+            list("events", item, &[])
+        }
+        "analytics latency" => {
+            let phase = object(&[
+                ("p50_ms", tn("integer")),
+                ("p95_ms", tn("integer")),
+                ("max_ms", tn("integer")),
+            ]);
+            object(&[
+                ("count", t("integer")),
+                ("since", t("integer")),
+                ("parse", phase.clone()),
+                ("initiate", phase.clone()),
+                ("settle", phase.clone()),
+                ("total", phase),
+            ])
+        }
+        "stats" => {
+            let phase = object(&[
+                ("p50_ms", tn("integer")),
+                ("p95_ms", tn("integer")),
+                ("max_ms", tn("integer")),
+            ]);
+            object(&[
+                ("enabled", t("boolean")),
+                ("since", t("integer")),
+                (
+                    "commands",
+                    array(object(&[
+                        ("command", t("string")),
+                        ("count", t("integer")),
+                        ("failed", t("integer")),
+                        ("wallet_init", phase.clone()),
+                        ("call", phase.clone()),
+                        ("stop", phase.clone()),
+                        ("total", phase),
+                    ])),
+                ),
+            ])
+        }
+        "payroll run" => object(&[
+            ("batch", t("string")),
+            ("total", t("integer")),
+            ("paid", t("integer")),
+            ("already_paid", t("integer")),
+            ("failed", t("integer")),
+            ("paid_sats", t("integer")),
+            (
+                "results",
+                array(object(&[
+                    ("name", t("string")),
+                    ("lightning_address", t("string")),
+                    ("amount_sats", t("integer")),
+                    (
+                        "status",
+                        json!({ "enum": ["paid", "already_paid", "partial", "failed"] }),
+                    ),
+                    ("payment_id?", t("string")),
+                    ("receipt?", t("string")),
+                    // Amounts and payments of a recipient paid in parts under [privacy]
+                    ("parts?", array(t("integer"))),
+                    ("payment_ids?", array(t("string"))),
+                    ("paid_sats?", t("integer")),
+                    ("error?", t("string")),
+                ])),
+            ),
+        ]),
+        "get-event" => json!({
+            "oneOf": [event(), object(&[("event", t("null"))])]
+        }),
+        "event-handled" => object(&[("ok", t("boolean"))]),
+        "get-events" => list("events", event(), &[]),
+        "events-handled" => object(&[("ok", t("boolean")), ("handled", t("integer"))]),
+        "dev faucet" => object(&[
+            ("network", t("string")),
+            ("method", t("string")),
+            ("amount_sats", t("integer")),
+            ("payment_id", t("string")),
+            ("status", t("string")),
+            ("received_sats", tn("integer")),
+            ("faucet", json!({})),
+            ("wait_ms", t("integer")),
+        ]),
+        "codegen" => object(&[
+            ("language", t("string")),
+            ("files", array(t("string"))),
+            ("commands", t("integer")),
+        ]),
+        "openapi" => object(&[
+            ("openapi", t("string")),
+            ("jsonSchemaDialect", t("string")),
+            ("info", t("object")),
+            ("security", array(t("object"))),
+            ("paths", t("object")),
+            ("webhooks", t("object")),
+            ("components", t("object")),
+        ]),
+        "error" => object(&[("error", t("string"))]),
+        other => {
+            return Err(format!(
+                "No schema for {other}, available: {}",
+                COMMANDS.join(", ")
+            ));
+        }
+    };
+
+    let mut schema = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": format!("orange {command}"),
+    });
+    if let (Some(schema), serde_json::Value::Object(body)) = (schema.as_object_mut(), body) {
+        schema.extend(body);
+    }
+    Ok(schema)
+}
+
+fn t(type_name: &str) -> serde_json::Value {
+    json!({ "type": type_name })
+}
+
+/// A value of `type_name` or null
+fn tn(type_name: &str) -> serde_json::Value {
+    json!({ "type": [type_name, "null"] })
+}
+
+fn array(items: serde_json::Value) -> serde_json::Value {
+    json!({ "type": "array", "items": items })
+}
+
+/// An object with the given properties in output order. Names ending in `?` are optional,
+/// timestamp fields gain the `<name>_iso` string added to command output.
+fn object(fields: &[(&str, serde_json::Value)]) -> serde_json::Value {
+    let mut properties = serde_json::Map::new();
+    let mut required = Vec::new();
+    for (name, schema) in fields {
+        let (name, optional) = match name.strip_suffix('?') {
+            Some(name) => (name, true),
+            None => (*name, false),
+        };
+        properties.insert(name.to_string(), schema.clone());
+        if !optional {
+            required.push(name.to_string());
+        }
+        if tz::is_timestamp_key(name) {
+            let iso = format!("{name}_iso");
+            properties.insert(
+                iso.clone(),
+                json!({ "type": "string", "format": "date-time" }),
+            );
+            // Null timestamps are left without an _iso field
+            if !optional && schema["type"] == "integer" {
+                required.push(iso);
+            }
+        }
+    }
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+/// `{"count", <key>: [item]}` plus any extra fields
+fn list(
+    key: &str,
+    item: serde_json::Value,
+    extra: &[(&str, serde_json::Value)],
+) -> serde_json::Value {
+    let mut fields = vec![("count", t("integer")), (key, array(item))];
+    fields.extend(extra.iter().cloned());
+    object(&fields)
+}
+
+/// A wallet or daemon event as delivered to webhooks, for the OpenAPI document
+pub fn event_schema() -> serde_json::Value {
+    event()
+}
+
+/// A wallet or daemon event, see the event types table in SKILL.md
+fn event() -> serde_json::Value {
+    let mut event = object(&[
+        ("type", t("string")),
+        ("timestamp", t("integer")),
+        ("seq?", t("integer")),
+    ]);
+    event["additionalProperties"] = json!(true);
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::store::{CommandStat, SendTimings, Store, WebhookAttempt};
+    use serde_json::Value;
+    use std::path::PathBuf;
+
+    /// Where `value` breaks `schema`: a wrong type, a missing or unknown key, or keys out of
+    /// the documented order. Covers the parts of JSON Schema `output_schema` uses.
+    fn violation(schema: &Value, value: &Value, path: &str) -> Option<String> {
+        if let Some(branches) = schema["oneOf"].as_array() {
+            let errors: Vec<String> = branches
+                .iter()
+                .filter_map(|branch| violation(branch, value, path))
+                .collect();
+            return (errors.len() == branches.len())
+                .then(|| format!("{path} matches no branch: {}", errors.join("; ")));
+        }
+        if let Some(options) = schema["enum"].as_array() {
+            return (!options.contains(value)).then(|| format!("{path} is not one of {options:?}"));
+        }
+        let types: Vec<&str> = match &schema["type"] {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => return None,
+        };
+        let type_matches = |t: &str| match t {
+            "integer" => value.is_i64() || value.is_u64(),
+            "number" => value.is_number(),
+            "string" => value.is_string(),
+            "boolean" => value.is_boolean(),
+            "null" => value.is_null(),
+            "array" => value.is_array(),
+            "object" => value.is_object(),
+            _ => false,
+        };
+        if !types.iter().any(|t| type_matches(t)) {
+            return Some(format!("{path} is {value}, not {types:?}"));
+        }
+
+        if let (Some(properties), Some(fields)) =
+            (schema["properties"].as_object(), value.as_object())
+        {
+            let open = schema["additionalProperties"] == true;
+            let mut last = None;
+            for (key, field) in fields {
+                let Some(index) = properties.keys().position(|k| k == key) else {
+                    if open {
+                        continue;
+                    }
+                    return Some(format!("{path}.{key} is not in the schema"));
+                };
+                if last.is_some_and(|last| index < last) {
+                    return Some(format!("{path}.{key} is out of the schema's order"));
+                }
+                last = Some(index);
+                if let Some(e) = violation(&properties[key], field, &format!("{path}.{key}")) {
+                    return Some(e);
+                }
+            }
+            let missing = schema["required"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .find(|key| !fields.contains_key(*key));
+            if let Some(key) = missing {
+                return Some(format!("{path}.{key} is missing"));
+            }
+        }
+        if let (Some(items), Some(values)) = (schema.get("items"), value.as_array()) {
+            for (i, item) in values.iter().enumerate() {
+                if let Some(e) = violation(items, item, &format!("{path}[{i}]")) {
+                    return Some(e);
+                }
+            }
+        }
+        None
+    }
+
+    /// Checks `output`, annotated with `_iso` times like printed output, against the schema
+    /// of `command`
+    fn assert_conforms(command: &str, output: Result<Value, String>) {
+        let mut output = output.unwrap_or_else(|e| panic!("orange {command} failed: {e}"));
+        tz::annotate(&mut output, chrono_tz::UTC);
+        let schema = output_schema(command).unwrap();
+        if let Some(e) = violation(&schema, &output, "$") {
+            panic!("orange {command} output doesn't match its schema: {e}\n{output:#}");
+        }
+    }
+
+    /// A wallet directory with a config and a store holding a row of each kind, so lists
+    /// aren't empty
+    struct Fixture {
+        dir: PathBuf,
+        config: Config,
+        store: Store,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("orange-schema-{}-{name}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("config.toml");
+            std::fs::write(
+                &path,
+                format!(
+                    "network = \"regtest\"\n\
+                     storage_path = \"{}\"\n\
+                     [chain_source]\n\
+                     type = \"esplora\"\n\
+                     url = \"http://127.0.0.1:3002\"\n\
+                     [lsp]\n\
+                     address = \"127.0.0.1:9735\"\n\
+                     node_id = \"02{}\"\n\
+                     [lnurl]\n\
+                     domain = \"pay.example.com\"\n",
+                    dir.display(),
+                    "11".repeat(32),
+                ),
+            )
+            .unwrap();
+            let config = Config::load(path.to_str().unwrap(), None).unwrap();
+            let store = Store::open(&dir).unwrap();
+            let fixture = Fixture { dir, config, store };
+            fixture.seed();
+            fixture
+        }
+
+        fn seed(&self) {
+            let store = &self.store;
+            let now = crate::now_secs();
+            let hash = "aa".repeat(32);
+            let event = crate::webhook::sample_event("payment_received", now)
+                .unwrap()
+                .to_string();
+
+            store
+                .record_address_name("tips", Some("tips@pay.example.com"), now - 60)
+                .unwrap();
+            store
+                .record_receive(
+                    Some("tips@pay.example.com"),
+                    Some("bcrt1qexample"),
+                    "lnbcrt10u1p...",
+                    &hash,
+                    Some(1000),
+                    now - 30,
+                    now + 3600,
+                    None,
+                    None,
+                    None,
+                    "bitcoin:bcrt1qexample?lightning=lnbcrt10u1p...",
+                    false,
+                )
+                .unwrap();
+            store
+                .record_receipt("received", &hash, 1_000_000, now)
+                .unwrap();
+            store
+                .record_receipt("offer", &"bb".repeat(32), 2_000_000, now)
+                .unwrap();
+
+            let timings = SendTimings {
+                started_at_ms: now * 1000,
+                parsed_at_ms: now * 1000 + 10,
+                initiated_at_ms: now * 1000 + 50,
+                settled_at_ms: None,
+            };
+            let reserved = store
+                .reserve_send("lnbcrt5u1p...", 500, Some("ops"), &timings)
+                .unwrap();
+            store
+                .assign_send_id(&reserved, "sent", now * 1000 + 50)
+                .unwrap();
+            store
+                .update_send_status("sent", "succeeded", Some(2_000), now * 1000 + 900)
+                .unwrap();
+
+            store
+                .record_command_stat(&CommandStat {
+                    command: "balance".to_string(),
+                    ok: true,
+                    wallet_init_ms: 800,
+                    call_ms: 40,
+                    stop_ms: 20,
+                    total_ms: 900,
+                    recorded_at: now,
+                })
+                .unwrap();
+
+            let url = "https://hooks.example.com/orange";
+            store.webhook_failed(url, "HTTP 500", now).unwrap();
+            store.disable_webhook(url, now).unwrap();
+            store
+                .log_webhook_attempt(&WebhookAttempt {
+                    outbox_id: 1,
+                    url: url.to_string(),
+                    event_type: "payment_received".to_string(),
+                    attempt: 1,
+                    status: Some(500),
+                    error: Some("HTTP 500".to_string()),
+                    latency_ms: 120,
+                    attempted_at: now,
+                })
+                .unwrap();
+            let retrying = store.enqueue_webhook(url, &event, now, now).unwrap();
+            store
+                .webhook_delivery_failed(retrying, 1, "HTTP 500", now + 60)
+                .unwrap();
+            let dead = store.enqueue_webhook(url, &event, now, now).unwrap();
+            store
+                .dead_letter_webhook(dead, 10, "HTTP 500", now)
+                .unwrap();
+            store.append_event("payment_received", &event, now).unwrap();
+
+            store
+                .queue_vault_send("lnbcrt1u1p...", None, Some("ops"), now + 3600, now)
+                .unwrap();
+            store
+                .create_nwc_connection("agent", &"22".repeat(32), Some(10_000), "monthly", now)
+                .unwrap();
+            store
+                .create_withdraw_link(&"33".repeat(32), 100, 5, Some("meetup"), now)
+                .unwrap();
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn store_reports_match_schemas() {
+        let f = Fixture::new("reports");
+        assert_conforms("addresses", crate::cmd_addresses(&f.store, None));
+        assert_conforms("report", crate::cmd_report_by_cost_center(&f.store));
+        assert_conforms("report", crate::cmd_report_by_address_name(&f.store));
+        assert_conforms(
+            "analytics latency",
+            crate::cmd_analytics_latency(&f.store, 0),
+        );
+        assert_conforms("stats", crate::cmd_stats(&f.config, &f.store, 0));
+        assert_conforms("heartbeat", crate::cmd_heartbeat(&f.config, &f.store));
+    }
+
+    #[test]
+    fn queued_payments_match_schemas() {
+        let f = Fixture::new("payments");
+        let send = crate::cmd_vault_send(
+            &f.config,
+            &f.store,
+            "lnbcrt2u1p...",
+            Some(200),
+            "1h",
+            Some("ops"),
+        );
+        let id = send.as_ref().unwrap()["id"].as_i64().unwrap();
+        assert_conforms("vault send", send);
+        assert_conforms("vault list", crate::cmd_vault_list(&f.store));
+        assert_conforms("vault cancel", crate::cmd_vault_cancel(&f.store, id));
+
+        assert_conforms("nwc list", crate::cmd_nwc_list(&f.store));
+        let connection = f.store.nwc_connections().unwrap()[0].id;
+        assert_conforms("nwc revoke", crate::cmd_nwc_revoke(&f.store, connection));
+
+        let link = crate::cmd_withdraw_link(&f.config, &f.store, 21, 2, None);
+        let id = link.as_ref().unwrap()["id"].as_i64().unwrap();
+        assert_conforms("withdraw-link", link);
+        assert_conforms(
+            "withdraw-links list",
+            crate::cmd_withdraw_links_list(&f.config, &f.store),
+        );
+        assert_conforms(
+            "withdraw-links revoke",
+            crate::cmd_withdraw_links_revoke(&f.store, id),
+        );
+    }
+
+    #[test]
+    fn webhook_and_event_outputs_match_schemas() {
+        let f = Fixture::new("webhooks");
+        assert_conforms("webhooks list", crate::cmd_webhooks_list(&f.store));
+        assert_conforms(
+            "webhooks deliveries",
+            crate::cmd_webhooks_deliveries(&f.store, None, None, false, 50),
+        );
+        assert_conforms(
+            "webhooks enable",
+            crate::cmd_webhooks_enable(&f.store, "https://hooks.example.com/orange"),
+        );
+        assert_conforms("outbox list", crate::cmd_outbox_list(&f.store, None, 50));
+        assert_conforms(
+            "webhooks dead-letter list",
+            crate::cmd_dead_letter_list(&f.store),
+        );
+        let dead_letter = f.store.webhook_dead_letters().unwrap()[0].id;
+        assert_conforms(
+            "webhooks dead-letter redeliver",
+            crate::cmd_dead_letter_redeliver(&f.store, dead_letter),
+        );
+        assert_conforms(
+            "outbox purge",
+            crate::cmd_outbox_purge(&f.config, &f.store, "0", crate::OutboxState::Failed),
+        );
+        assert_conforms(
+            "events list",
+            crate::cmd_events_list(&f.store, None, None, 50),
+        );
+        assert_conforms("events-handled", crate::cmd_events_handled(&f.store, 0));
+    }
+
+    #[test]
+    fn sample_events_match_event_schema() {
+        for event_type in crate::webhook::SAMPLE_EVENT_TYPES {
+            // As `events list` prints them
+            let mut event = crate::webhook::sample_event(event_type, 1_700_000_000).unwrap();
+            tz::annotate(&mut event, chrono_tz::UTC);
+            if let Some(e) = violation(&event_schema(), &event, "$") {
+                panic!("{event_type}: {e}");
+            }
+        }
+    }
+
+    #[test]
+    fn openapi_matches_schema() {
+        use clap::CommandFactory;
+        assert_conforms("openapi", crate::openapi::spec(&crate::Cli::command()));
+    }
+
+    #[test]
+    fn every_command_has_a_schema() {
+        for command in COMMANDS {
+            let schema = output_schema(command).unwrap();
+            assert_eq!(schema["title"], format!("orange {command}"));
+        }
+    }
+}
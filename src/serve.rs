@@ -0,0 +1,468 @@
+use crate::control;
+use crate::store::Store;
+use crate::tz;
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Json, Response};
+use clap::ArgAction;
+use futures_util::{Stream, StreamExt};
+use orange_sdk::bitcoin::hashes::{Hash, sha256};
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often an event stream looks for new events in the history
+const EVENTS_POLL: Duration = Duration::from_secs(1);
+
+/// Comment lines sent on an idle `/events` stream, so proxies don't close it
+const EVENTS_HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// Commands answered over `GET` as well as `POST`, since they don't change anything
+pub const READ_ONLY: &[&str] = &[
+    "balance",
+    "parse",
+    "transactions",
+    "pending",
+    "channels",
+    "info",
+    "estimate-fee",
+    "lightning-address",
+    "addresses",
+    "report",
+    "fiat-exposure",
+    "analytics latency",
+    "stats",
+    "webhooks list",
+    "webhooks deliveries",
+    "webhooks dead-letter list",
+    "outbox list",
+    "events list",
+    "events peek",
+    "vault list",
+    "nwc list",
+    "withdraw-links list",
+];
+
+/// Commands that manage the process or read and write files where it runs, not served
+pub const EXCLUDED: &[&str] = &[
+    "daemon",
+    "serve",
+    "unlock",
+    "schema",
+    "codegen",
+    "openapi",
+    "authorize",
+    // Reads a file path on the daemon's host
+    "execute",
+    "payroll",
+    "rpc",
+    "grpc",
+    "mcp",
+    "lnurl-server",
+];
+
+/// Arguments of served commands that are left out, since they write a file path on the
+/// daemon's host: `(command, argument id)`
+pub const LOCAL_ARGUMENTS: &[(&str, &str)] = &[("audit-snapshot", "out")];
+
+/// Whether `arg` of `command` is only available from the CLI, see [`LOCAL_ARGUMENTS`]
+pub fn is_local_argument(command: &str, arg: &clap::Arg) -> bool {
+    LOCAL_ARGUMENTS
+        .iter()
+        .any(|(c, id)| *c == command && arg.get_id() == *id)
+}
+
+/// `Host` names a loopback listener answers to, anything else may be a DNS rebinding attack
+const LOOPBACK_HOSTS: &[&str] = &["localhost", "127.0.0.1", "[::1]"];
+
+struct Server {
+    cli: clap::Command,
+    /// Wallet directory holding the daemon's socket
+    dir: PathBuf,
+    token: String,
+    /// Whether the listen address is loopback, so `Host` is checked
+    loopback: bool,
+    tz: chrono_tz::Tz,
+}
+
+/// Serves the commands over HTTP on `addr` until Ctrl+C, running each on the daemon through
+/// its control socket, so every request shares the daemon's wallet. The token is required
+/// even on loopback, since any web page the operator opens can POST to localhost.
+pub async fn run(
+    addr: &str,
+    token: Option<String>,
+    cli: clap::Command,
+    dir: PathBuf,
+    tz: chrono_tz::Tz,
+) -> Result<(), String> {
+    let Some(token) = token.filter(|token| !token.is_empty()) else {
+        return Err("Set [serve] token, every request must carry it".to_string());
+    };
+    let loopback = addr
+        .parse::<SocketAddr>()
+        .is_ok_and(|addr| addr.ip().is_loopback());
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Failed to listen on {addr}: {e}"))?;
+    let server = Arc::new(Server {
+        cli,
+        dir,
+        token,
+        loopback,
+        tz,
+    });
+    let app = Router::new().fallback(handle).with_state(server);
+    info!("Serving the wallet API on http://{addr}");
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+        .map_err(|e| format!("API server stopped: {e}"))
+}
+
+/// `/<command words>` with the arguments as query parameters for `GET` or a JSON object for
+/// `POST`, e.g. `POST /vault/send {"payment": "...", "not_before": "24h"}`
+async fn handle(
+    State(server): State<Arc<Server>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    Query(query): Query<Vec<(String, String)>>,
+    body: Bytes,
+) -> Response {
+    if let Err(e) = check_origin(&server, &headers) {
+        return error(StatusCode::FORBIDDEN, e);
+    }
+    // Browsers' EventSource can't set headers, so `/events` also takes the token as `?token=`
+    let is_events = method == Method::GET && uri.path() == "/events";
+    let given = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let in_query = is_events
+        && query
+            .iter()
+            .any(|(k, v)| k == "token" && token_matches(v, &server.token));
+    if !given.is_some_and(|given| token_matches(given, &server.token)) && !in_query {
+        return error(StatusCode::UNAUTHORIZED, "Missing or wrong Bearer token");
+    }
+
+    if is_events {
+        return events(&server, &headers, &query);
+    }
+
+    if method == Method::GET && uri.path() == "/openapi.json" {
+        return match crate::openapi::spec(&server.cli) {
+            Ok(spec) => (StatusCode::OK, Json(spec)).into_response(),
+            Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e),
+        };
+    }
+
+    let words: Vec<String> = uri
+        .path()
+        .split('/')
+        .filter(|word| !word.is_empty())
+        .map(String::from)
+        .collect();
+    let command = words.join(" ");
+    if words.is_empty() || EXCLUDED.contains(&words[0].as_str()) {
+        return error(
+            StatusCode::NOT_FOUND,
+            &format!("No command at {}", uri.path()),
+        );
+    }
+    let params = match method {
+        Method::GET if READ_ONLY.contains(&command.as_str()) => {
+            let mut params = serde_json::Map::new();
+            for (key, value) in query {
+                match params.get_mut(&key) {
+                    Some(Value::Array(values)) => values.push(json!(value)),
+                    Some(first) => *first = json!([first.take(), value]),
+                    None => {
+                        params.insert(key, json!(value));
+                    }
+                }
+            }
+            params
+        }
+        Method::POST if body.is_empty() => serde_json::Map::new(),
+        // Anything else is a body a page can send cross-origin without a preflight
+        Method::POST if !is_json(&headers) => {
+            return error(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "The body must be sent as Content-Type: application/json",
+            );
+        }
+        Method::POST => match serde_json::from_slice(&body) {
+            Ok(Value::Object(params)) => params,
+            _ => return error(StatusCode::BAD_REQUEST, "The body must be a JSON object"),
+        },
+        _ => {
+            return error(
+                StatusCode::METHOD_NOT_ALLOWED,
+                &format!("{command} takes POST"),
+            );
+        }
+    };
+
+    let args = match command_args(&server.cli, &words, &params) {
+        Ok(args) => args,
+        Err((status, e)) => return error(status, &e),
+    };
+    match control::proxy(&server.dir, args).await {
+        Some(Ok(mut value)) => {
+            tz::annotate(&mut value, server.tz);
+            (StatusCode::OK, Json(value)).into_response()
+        }
+        Some(Err(e)) => error(StatusCode::UNPROCESSABLE_ENTITY, &e),
+        None => error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The daemon isn't running, start `orange daemon`",
+        ),
+    }
+}
+
+/// Server-Sent Events of the event history, each with its `seq` as id. A reconnecting client
+/// sends `Last-Event-ID` and resumes after it. A new one starts with the next event, or at
+/// `?from_seq=`. `?type=` (repeatable) only sends events of those types.
+fn events(server: &Server, headers: &HeaderMap, query: &[(String, String)]) -> Response {
+    let store = match Store::open(&server.dir) {
+        Ok(store) => store,
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    };
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<i64>().ok());
+    let from_seq = query
+        .iter()
+        .find(|(key, _)| key == "from_seq")
+        .and_then(|(_, v)| v.parse::<i64>().ok());
+    let next = match (last_event_id, from_seq) {
+        (Some(seq), _) => seq + 1,
+        (None, Some(seq)) => seq,
+        (None, None) => match store.last_event_seq() {
+            Ok(seq) => seq + 1,
+            Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, &e),
+        },
+    };
+    let types: Vec<String> = query
+        .iter()
+        .filter(|(key, _)| key == "type")
+        .map(|(_, v)| v.clone())
+        .collect();
+
+    let stream = follow_events(store, next, types).map(|(seq, event_type, value)| {
+        Ok::<_, Infallible>(
+            Event::default()
+                .id(seq.to_string())
+                .event(event_type)
+                .data(value.to_string()),
+        )
+    });
+    Sse::new(stream)
+        .keep_alive(
+            KeepAlive::new()
+                .interval(EVENTS_HEARTBEAT)
+                .text("heartbeat"),
+        )
+        .into_response()
+}
+
+/// Events of the history from `seq` `next` on as they are recorded, forever: `(seq, type,
+/// event JSON with its seq)`. Only events of `types` are yielded, unless it is empty.
+pub fn follow_events(
+    store: Store,
+    next: i64,
+    types: Vec<String>,
+) -> impl Stream<Item = (i64, String, Value)> + Send {
+    futures_util::stream::unfold(
+        (store, next, VecDeque::new()),
+        move |(store, mut next, mut pending)| {
+            let types = types.clone();
+            async move {
+                loop {
+                    if let Some(event) = pending.pop_front() {
+                        return Some((event, (store, next, pending)));
+                    }
+                    let found = store.events_from(next).unwrap_or_else(|e| {
+                        warn!("Event stream: {e}");
+                        Vec::new()
+                    });
+                    if found.is_empty() {
+                        tokio::time::sleep(EVENTS_POLL).await;
+                        continue;
+                    }
+                    for stored in found {
+                        next = stored.seq + 1;
+                        if !types.is_empty() && !types.contains(&stored.event_type) {
+                            continue;
+                        }
+                        let mut value: Value = serde_json::from_str(&stored.payload)
+                            .unwrap_or_else(|_| json!({ "type": stored.event_type }));
+                        value["seq"] = json!(stored.seq);
+                        pending.push_back((stored.seq, stored.event_type, value));
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// The command line for `words`, with each parameter given as the argument of the same name:
+/// a positional, `--name value` (repeated for arrays) or `--name` for a true switch
+pub fn command_args(
+    cli: &clap::Command,
+    words: &[String],
+    params: &serde_json::Map<String, Value>,
+) -> Result<Vec<String>, (StatusCode, String)> {
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            format!("No command {}", words.join(" ")),
+        )
+    };
+    let mut subcommand = cli;
+    for word in words {
+        subcommand = subcommand.find_subcommand(word).ok_or_else(not_found)?;
+    }
+    if subcommand.has_subcommands() {
+        return Err(not_found());
+    }
+
+    let command = words.join(" ");
+    let (local, arguments): (Vec<&clap::Arg>, Vec<&clap::Arg>) = subcommand
+        .get_arguments()
+        .filter(|arg| !arg.is_global_set())
+        .partition(|arg| is_local_argument(&command, arg));
+    if let Some(key) = params
+        .keys()
+        .find(|key| local.iter().any(|arg| is_named(arg, key)))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("{key} writes a file on the daemon's host, run {command} with the CLI there"),
+        ));
+    }
+    if let Some(unknown) = params
+        .keys()
+        .find(|key| !arguments.iter().any(|arg| is_named(arg, key)))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Unknown parameter {unknown}"),
+        ));
+    }
+
+    let mut args = words.to_vec();
+    let mut positionals = Vec::new();
+    for arg in arguments {
+        let id = arg.get_id().as_str();
+        let Some(value) = params
+            .iter()
+            .find_map(|(key, value)| is_named(arg, key).then_some(value))
+        else {
+            continue;
+        };
+        let values = match value {
+            Value::Array(items) => items.iter().map(|v| scalar(id, v)).collect(),
+            value => scalar(id, value).map(|v| vec![v]),
+        }
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        if arg.is_positional() {
+            positionals.extend(values);
+            continue;
+        }
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        if matches!(arg.get_action(), ArgAction::SetTrue) {
+            if values.iter().any(|v| v == "true") {
+                args.push(format!("--{long}"));
+            }
+            continue;
+        }
+        for value in values {
+            args.push(format!("--{long}"));
+            args.push(value);
+        }
+    }
+    // Positionals go after `--`, so a value starting with a dash isn't taken for a flag
+    if !positionals.is_empty() {
+        args.push("--".to_string());
+        args.extend(positionals);
+    }
+    Ok(args)
+}
+
+/// Whether `key` names the argument, by its id or its `--long` name, with `-` or `_`
+fn is_named(arg: &clap::Arg, key: &str) -> bool {
+    let key = key.replace('-', "_");
+    arg.get_id() == key.as_str()
+        || arg
+            .get_long()
+            .is_some_and(|long| long.replace('-', "_") == key)
+}
+
+fn scalar(name: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(format!("{name} must be a string, number or boolean")),
+    }
+}
+
+/// Refuses requests from web pages of other origins, and on loopback requests for a host name
+/// that isn't loopback, which is how DNS rebinding reaches a local server from a page
+fn check_origin(server: &Server, headers: &HeaderMap) -> Result<(), &'static str> {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    let host = header(axum::http::header::HOST).ok_or("The Host header is required")?;
+    if server.loopback {
+        let name = match host.rsplit_once(':') {
+            Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+            _ => host,
+        };
+        if !LOOPBACK_HOSTS.contains(&name) {
+            return Err("Only loopback host names are served");
+        }
+    }
+    match header(axum::http::header::ORIGIN) {
+        Some(origin) if origin.split_once("://").map(|(_, rest)| rest) != Some(host) => {
+            Err("Cross-origin requests are refused")
+        }
+        _ => Ok(()),
+    }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Compares `given` to the token in constant time, hashing both first so their lengths don't
+/// show either
+pub fn token_matches(given: &str, token: &str) -> bool {
+    let given = sha256::Hash::hash(given.as_bytes()).to_byte_array();
+    let token = sha256::Hash::hash(token.as_bytes()).to_byte_array();
+    given
+        .iter()
+        .zip(token.iter())
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
@@ -0,0 +1,96 @@
+use orange_sdk::Mnemonic;
+use orange_sdk::bitcoin::hashes::{Hash, HashEngine, sha256};
+use orange_sdk::bitcoin::secp256k1::schnorr::Signature;
+use orange_sdk::bitcoin::secp256k1::{Keypair, Message, Secp256k1, SecretKey, XOnlyPublicKey};
+use serde_json::json;
+use std::str::FromStr;
+
+/// Schnorr key derived from the wallet seed, used to sign receipts and statements this CLI
+/// produces. It is unrelated to the node key, so signatures don't link back to the node.
+pub struct Signer {
+    keypair: Keypair,
+}
+
+impl Signer {
+    pub fn from_mnemonic(mnemonic: &Mnemonic, passphrase: Option<&str>) -> Result<Self, String> {
+        let secret = derive_key(mnemonic, passphrase, b"orange-skill/signing-key")
+            .map_err(|e| format!("Failed to derive signing key: {e}"))?;
+        Ok(Signer {
+            keypair: Keypair::from_secret_key(&Secp256k1::new(), &secret),
+        })
+    }
+
+    pub fn pubkey(&self) -> XOnlyPublicKey {
+        self.keypair.x_only_public_key().0
+    }
+
+    /// Wraps `payload` as `{"payload", "pubkey", "signature"}`, where the BIP340 signature
+    /// commits to the SHA256 of the compact JSON encoding of `payload`
+    pub fn sign_json(&self, payload: serde_json::Value) -> serde_json::Value {
+        let msg = Message::from_digest(payload_digest(&payload));
+        let signature = Secp256k1::new().sign_schnorr_no_aux_rand(&msg, &self.keypair);
+        json!({
+            "payload": payload,
+            "pubkey": self.pubkey().to_string(),
+            "signature": signature.to_string(),
+        })
+    }
+}
+
+/// Secret key of the Nostr Wallet Connect service, derived from the seed like the signing key
+/// but unrelated to it, so every connection string made for the wallet stays valid
+pub fn nwc_service_key(mnemonic: &Mnemonic, passphrase: Option<&str>) -> Result<SecretKey, String> {
+    derive_key(mnemonic, passphrase, b"orange-skill/nwc-service-key")
+        .map_err(|e| format!("Failed to derive NWC service key: {e}"))
+}
+
+/// BIP39 passphrase of the decoy wallet opened with `duress_wallet`, derived from the seed so
+/// it is never written to the config
+pub fn duress_passphrase(mnemonic: &Mnemonic) -> String {
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"orange-skill/duress-passphrase");
+    engine.input(&mnemonic.to_seed(""));
+    sha256::Hash::from_engine(engine).to_string()
+}
+
+fn derive_key(
+    mnemonic: &Mnemonic,
+    passphrase: Option<&str>,
+    tag: &[u8],
+) -> Result<SecretKey, orange_sdk::bitcoin::secp256k1::Error> {
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag);
+    engine.input(&mnemonic.to_seed(passphrase.unwrap_or_default()));
+    SecretKey::from_slice(sha256::Hash::from_engine(engine).as_byte_array())
+}
+
+/// Checks a document made by [`Signer::sign_json`], returning its signer and payload
+pub fn verify_json(
+    signed: &serde_json::Value,
+) -> Result<(XOnlyPublicKey, &serde_json::Value), String> {
+    let field = |name: &str| {
+        signed[name]
+            .as_str()
+            .ok_or_else(|| format!("Signed document has no {name}"))
+    };
+    let pubkey = XOnlyPublicKey::from_str(field("pubkey")?)
+        .map_err(|e| format!("Invalid pubkey in signed document: {e}"))?;
+    let signature = Signature::from_str(field("signature")?)
+        .map_err(|e| format!("Invalid signature in signed document: {e}"))?;
+    let payload = &signed["payload"];
+    let msg = Message::from_digest(payload_digest(payload));
+    Secp256k1::verification_only()
+        .verify_schnorr(&signature, &msg, &pubkey)
+        .map_err(|_| "Signature does not match the payload".to_string())?;
+    Ok((pubkey, payload))
+}
+
+/// Hex SHA256 of `payload` as signed, identifying a signed document
+pub fn payload_id(payload: &serde_json::Value) -> String {
+    sha256::Hash::from_byte_array(payload_digest(payload)).to_string()
+}
+
+fn payload_digest(payload: &serde_json::Value) -> [u8; 32] {
+    let bytes = serde_json::to_vec(payload).expect("JSON values always serialize");
+    sha256::Hash::hash(&bytes).to_byte_array()
+}
@@ -0,0 +1,124 @@
+use rskafka::client::ClientBuilder;
+use rskafka::client::partition::{Compression, PartitionClient, UnknownTopicHandling};
+use rskafka::record::Record;
+use std::collections::BTreeMap;
+use tokio::sync::OnceCell;
+
+const DEFAULT_NATS_SUBJECT: &str = "orange.events";
+const DEFAULT_KAFKA_TOPIC: &str = "orange-events";
+
+/// Message broker a webhook URL with a `nats://` or `kafka://` scheme publishes to instead of
+/// POSTing. Deliveries go through the same outbox as HTTP webhooks, and one only succeeds
+/// once the broker acknowledged the message, so every event is published at least once.
+pub enum Sink {
+    /// `nats://[user:pass@]host[:port][,host...]/subject`, published to JetStream
+    Nats {
+        servers: String,
+        subject: String,
+        jetstream: OnceCell<async_nats::jetstream::Context>,
+    },
+    /// `kafka://host:port[,host:port...]/topic`, produced to partition 0
+    Kafka {
+        brokers: Vec<String>,
+        topic: String,
+        partition: OnceCell<PartitionClient>,
+    },
+}
+
+impl Sink {
+    /// The sink for a webhook URL, `None` for anything but the two broker schemes. Connecting
+    /// is left to the first delivery, so a broker that is down is retried like a webhook.
+    pub fn from_url(url: &str) -> Option<Self> {
+        let (scheme, rest) = url.split_once("://")?;
+        let (servers, name) = match rest.split_once('/') {
+            Some((servers, name)) if !name.is_empty() => (servers, Some(name)),
+            Some((servers, _)) => (servers, None),
+            None => (rest, None),
+        };
+        match scheme {
+            "nats" => Some(Sink::Nats {
+                servers: servers.to_string(),
+                subject: name.unwrap_or(DEFAULT_NATS_SUBJECT).to_string(),
+                jetstream: OnceCell::new(),
+            }),
+            "kafka" => Some(Sink::Kafka {
+                brokers: servers.split(',').map(str::to_string).collect(),
+                topic: name.unwrap_or(DEFAULT_KAFKA_TOPIC).to_string(),
+                partition: OnceCell::new(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Publishes `payload` and waits for the broker's acknowledgement. `headers` carry the
+    /// `X-Orange-*` values a webhook gets as HTTP headers. `delivery_id` is the same for every
+    /// attempt of a delivery, so JetStream drops a retry of a message it already stored.
+    pub async fn publish(
+        &self,
+        delivery_id: i64,
+        key: Option<String>,
+        headers: &[(&str, String)],
+        payload: Vec<u8>,
+    ) -> Result<(), String> {
+        match self {
+            Sink::Nats {
+                servers,
+                subject,
+                jetstream,
+            } => {
+                let jetstream = jetstream
+                    .get_or_try_init(|| async {
+                        let client = async_nats::connect(servers.as_str())
+                            .await
+                            .map_err(|e| format!("failed to connect: {e}"))?;
+                        Ok::<_, String>(async_nats::jetstream::new(client))
+                    })
+                    .await?;
+                let mut nats_headers = async_nats::HeaderMap::new();
+                nats_headers.insert("Nats-Msg-Id", format!("orange-{delivery_id}").as_str());
+                for (name, value) in headers {
+                    nats_headers.insert(*name, value.as_str());
+                }
+                jetstream
+                    .publish_with_headers(subject.clone(), nats_headers, payload.into())
+                    .await
+                    .map_err(|e| format!("failed: {e}"))?
+                    .await
+                    .map_err(|e| format!("not acknowledged: {e}"))?;
+                Ok(())
+            }
+            Sink::Kafka {
+                brokers,
+                topic,
+                partition,
+            } => {
+                let partition = partition
+                    .get_or_try_init(|| async {
+                        let client = ClientBuilder::new(brokers.clone())
+                            .build()
+                            .await
+                            .map_err(|e| format!("failed to connect: {e}"))?;
+                        client
+                            .partition_client(topic.as_str(), 0, UnknownTopicHandling::Error)
+                            .await
+                            .map_err(|e| format!("failed to open topic {topic}: {e}"))
+                    })
+                    .await?;
+                let record = Record {
+                    key: key.map(String::into_bytes),
+                    value: Some(payload),
+                    headers: headers
+                        .iter()
+                        .map(|(name, value)| (name.to_string(), value.clone().into_bytes()))
+                        .collect::<BTreeMap<_, _>>(),
+                    timestamp: chrono::Utc::now(),
+                };
+                partition
+                    .produce(vec![record], Compression::NoCompression)
+                    .await
+                    .map_err(|e| format!("failed: {e}"))?;
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,2355 @@
+use orange_sdk::bitcoin::hex::DisplayHex;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::path::Path;
+
+/// Local bookkeeping kept next to the wallet data, for things the SDK does not track itself
+pub struct Store {
+    conn: Connection,
+}
+
+/// Schema migrations, applied in order. Never edit an entry once released, append a new one.
+const MIGRATIONS: &[&str] = &[
+    // 1: issued receive URIs and observed incoming payments
+    "CREATE TABLE receives (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        purpose TEXT,
+        address TEXT,
+        invoice TEXT NOT NULL,
+        payment_hash TEXT NOT NULL,
+        amount_sats INTEGER,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX receives_address ON receives(address);
+    CREATE INDEX receives_payment_hash ON receives(payment_hash);
+    CREATE TABLE receipts (
+        payment_id TEXT PRIMARY KEY,
+        payment_hash TEXT NOT NULL,
+        amount_msat INTEGER NOT NULL,
+        received_at INTEGER NOT NULL
+    );
+    CREATE INDEX receipts_payment_hash ON receipts(payment_hash);",
+    // 2: lightning address registrations
+    "CREATE TABLE address_names (
+        name TEXT NOT NULL,
+        lightning_address TEXT,
+        registered_at INTEGER NOT NULL
+    );",
+    // 3: fiat amounts promised by `receive --amount-fiat`
+    "CREATE TABLE fiat_obligations (
+        payment_hash TEXT PRIMARY KEY,
+        fiat_amount REAL NOT NULL,
+        currency TEXT NOT NULL,
+        btc_price REAL NOT NULL,
+        amount_sats INTEGER NOT NULL,
+        created_at INTEGER NOT NULL
+    );",
+    // 4: payments initiated by `send`
+    "CREATE TABLE sends (
+        payment_id TEXT PRIMARY KEY,
+        payment TEXT NOT NULL,
+        amount_sats INTEGER NOT NULL,
+        cost_center TEXT,
+        status TEXT NOT NULL DEFAULT 'initiated',
+        fee_msat INTEGER,
+        created_at INTEGER NOT NULL
+    );",
+    // 5: payroll runs, one row per paid recipient per batch
+    "CREATE TABLE payroll (
+        batch TEXT NOT NULL,
+        name TEXT NOT NULL,
+        lightning_address TEXT NOT NULL,
+        amount_sats INTEGER NOT NULL,
+        memo TEXT NOT NULL,
+        payment_id TEXT NOT NULL,
+        paid_at INTEGER NOT NULL,
+        PRIMARY KEY (batch, name, lightning_address)
+    );",
+    // 6: command output published by the daemon for `--replica` readers
+    "CREATE TABLE snapshots (
+        name TEXT PRIMARY KEY,
+        value TEXT NOT NULL,
+        updated_at INTEGER NOT NULL
+    );",
+    // 7: per-phase timestamps of sends, in milliseconds
+    "ALTER TABLE sends ADD COLUMN started_at_ms INTEGER;
+    ALTER TABLE sends ADD COLUMN parsed_at_ms INTEGER;
+    ALTER TABLE sends ADD COLUMN initiated_at_ms INTEGER;
+    ALTER TABLE sends ADD COLUMN settled_at_ms INTEGER;",
+    // 8: delivery health of each webhook URL
+    "CREATE TABLE webhook_endpoints (
+        url TEXT PRIMARY KEY,
+        delivered INTEGER NOT NULL DEFAULT 0,
+        failed INTEGER NOT NULL DEFAULT 0,
+        consecutive_failures INTEGER NOT NULL DEFAULT 0,
+        failing_since INTEGER,
+        last_error TEXT,
+        disabled_at INTEGER
+    );",
+    // 9: time-locked sends queued by `vault send`
+    "CREATE TABLE vault_sends (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        payment TEXT NOT NULL,
+        amount_sats INTEGER,
+        cost_center TEXT,
+        not_before INTEGER NOT NULL,
+        status TEXT NOT NULL DEFAULT 'pending',
+        payment_id TEXT,
+        error TEXT,
+        created_at INTEGER NOT NULL,
+        executed_at INTEGER
+    );",
+    // 10: `heartbeat` check-ins and the dead man's switch sweeps they missed
+    "CREATE TABLE heartbeats (
+        at INTEGER NOT NULL
+    );
+    CREATE TABLE dead_man_sweeps (
+        heartbeat_at INTEGER PRIMARY KEY,
+        payment_id TEXT NOT NULL,
+        amount_sats INTEGER NOT NULL,
+        swept_at INTEGER NOT NULL
+    );",
+    // 11: which receives the daemon has announced as `invoice_created`, existing ones count as done
+    "ALTER TABLE receives ADD COLUMN announced_at INTEGER;
+    UPDATE receives SET announced_at = created_at;",
+    // 12: invoice expiry, and when the daemon announced an unpaid invoice as expired
+    "ALTER TABLE receives ADD COLUMN expires_at INTEGER;
+    ALTER TABLE receives ADD COLUMN expired_at INTEGER;",
+    // 13: webhook deliveries that haven't succeeded yet
+    "CREATE TABLE webhook_outbox (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        url TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        attempts INTEGER NOT NULL DEFAULT 0,
+        next_attempt_at INTEGER NOT NULL,
+        last_error TEXT,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX webhook_outbox_next_attempt ON webhook_outbox(next_attempt_at);",
+    // 14: log of every webhook POST for `webhooks deliveries`
+    "CREATE TABLE webhook_attempts (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        outbox_id INTEGER NOT NULL,
+        url TEXT NOT NULL,
+        event_type TEXT NOT NULL,
+        attempt INTEGER NOT NULL,
+        status INTEGER,
+        error TEXT,
+        latency_ms INTEGER NOT NULL,
+        attempted_at INTEGER NOT NULL
+    );
+    CREATE INDEX webhook_attempts_attempted_at ON webhook_attempts(attempted_at);",
+    // 15: payer email given to `receive --email`, and when the daemon mailed its receipt
+    "ALTER TABLE receives ADD COLUMN payer_email TEXT;
+    ALTER TABLE receives ADD COLUMN receipt_emailed_at INTEGER;",
+    // 16: deliveries that ran out of attempts, keyed by their former outbox id
+    "CREATE TABLE webhook_dead_letters (
+        id INTEGER PRIMARY KEY,
+        url TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        attempts INTEGER NOT NULL,
+        last_error TEXT,
+        created_at INTEGER NOT NULL,
+        dead_at INTEGER NOT NULL
+    );",
+    // 17: every event the daemon posted, numbered by `seq`
+    "CREATE TABLE events (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        event_type TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX events_created_at ON events(created_at);",
+    // 18: wall time of each wallet command by phase, recorded when `[stats] enabled`
+    "CREATE TABLE command_stats (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        command TEXT NOT NULL,
+        ok INTEGER NOT NULL,
+        wallet_init_ms INTEGER NOT NULL,
+        call_ms INTEGER NOT NULL,
+        stop_ms INTEGER NOT NULL,
+        total_ms INTEGER NOT NULL,
+        recorded_at INTEGER NOT NULL
+    );",
+    // 19: events taken off the wallet queue by `get-events`, until `events-handled` acks them
+    "CREATE TABLE pulled_events (
+        seq INTEGER PRIMARY KEY REFERENCES events(seq)
+    );",
+    // 20: `receive --meta` pairs, and what `receive` printed so a repeated order can get it again
+    "ALTER TABLE receives ADD COLUMN meta TEXT;
+    ALTER TABLE receives ADD COLUMN order_id TEXT;
+    ALTER TABLE receives ADD COLUMN full_uri TEXT;
+    ALTER TABLE receives ADD COLUMN from_trusted INTEGER;
+    CREATE INDEX receives_order_id ON receives(order_id);",
+    // 21: payment authorizations run by `execute`, so each one pays at most once
+    "CREATE TABLE authorizations (
+        id TEXT PRIMARY KEY,
+        pubkey TEXT NOT NULL,
+        payment_id TEXT,
+        amount_sats INTEGER,
+        executed_at INTEGER NOT NULL
+    );",
+    // 22: Nostr Wallet Connect clients, payments are billed to cost center `nwc:{id}`
+    "CREATE TABLE nwc_connections (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        client_pubkey TEXT NOT NULL UNIQUE,
+        budget_sats INTEGER,
+        budget_renewal TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        revoked_at INTEGER
+    );",
+    // 23: LNURL-withdraw vouchers, redeemed through `lnurl-server` and billed to `withdraw:{id}`
+    "CREATE TABLE withdraw_links (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        k1 TEXT NOT NULL UNIQUE,
+        amount_sats INTEGER NOT NULL,
+        uses INTEGER NOT NULL,
+        used INTEGER NOT NULL DEFAULT 0,
+        description TEXT,
+        created_at INTEGER NOT NULL,
+        revoked_at INTEGER
+    );",
+    // 24: each payment to a payroll recipient, so a recipient paid in part gets the rest later
+    "CREATE TABLE payroll_parts (
+        payment_id TEXT PRIMARY KEY,
+        batch TEXT NOT NULL,
+        name TEXT NOT NULL,
+        lightning_address TEXT NOT NULL,
+        amount_sats INTEGER NOT NULL,
+        paid_at INTEGER NOT NULL
+    );
+    CREATE INDEX payroll_parts_recipient ON payroll_parts(batch, name, lightning_address);",
+    // 25: outcomes of payments whose send row didn't have its payment id yet
+    "CREATE TABLE send_outcomes (
+        payment_id TEXT PRIMARY KEY,
+        status TEXT NOT NULL,
+        fee_msat INTEGER,
+        settled_at_ms INTEGER,
+        recorded_at INTEGER NOT NULL
+    );",
+    // 26: payroll rows keyed by which occurrence of the recipient in the CSV they are, so a
+    // recipient listed twice is paid twice
+    "ALTER TABLE payroll RENAME TO payroll_old;
+    CREATE TABLE payroll (
+        batch TEXT NOT NULL,
+        name TEXT NOT NULL,
+        lightning_address TEXT NOT NULL,
+        occurrence INTEGER NOT NULL DEFAULT 0,
+        amount_sats INTEGER NOT NULL,
+        memo TEXT NOT NULL,
+        payment_id TEXT NOT NULL,
+        paid_at INTEGER NOT NULL,
+        PRIMARY KEY (batch, name, lightning_address, occurrence)
+    );
+    INSERT INTO payroll (batch, name, lightning_address, amount_sats, memo, payment_id, paid_at)
+        SELECT batch, name, lightning_address, amount_sats, memo, payment_id, paid_at
+        FROM payroll_old;
+    DROP TABLE payroll_old;
+    ALTER TABLE payroll_parts ADD COLUMN occurrence INTEGER NOT NULL DEFAULT 0;
+    DROP INDEX payroll_parts_recipient;
+    CREATE INDEX payroll_parts_recipient
+        ON payroll_parts(batch, name, lightning_address, occurrence);",
+    // 27: when a paid fiat receive was named in a `fiat_drift` event, so it's alerted on once
+    "ALTER TABLE fiat_obligations ADD COLUMN drift_alerted_at INTEGER;",
+    // 28: on-chain payments received, to tell whether a receive URI was paid on its address
+    "CREATE TABLE onchain_receipts (
+        txid TEXT PRIMARY KEY,
+        amount_sats INTEGER NOT NULL,
+        received_at INTEGER NOT NULL
+    );",
+    // 29: zap requests whose receipt was published, so a redelivered event doesn't publish again
+    "CREATE TABLE zap_receipts (
+        request_id TEXT PRIMARY KEY,
+        payment_hash TEXT NOT NULL,
+        published_at INTEGER NOT NULL
+    );",
+    // 30: when a receipt was named in an `lsp_fee_exceeded` event, so it's alerted on once
+    "ALTER TABLE receipts ADD COLUMN lsp_fee_alerted_at INTEGER;",
+];
+
+/// A receive URI handed out by `receive`, with its fiat pricing if it had one
+pub struct Receive {
+    pub id: i64,
+    pub purpose: Option<String>,
+    pub address: Option<String>,
+    pub invoice: String,
+    pub payment_hash: String,
+    pub amount_sats: Option<u64>,
+    pub fiat_amount: Option<f64>,
+    pub currency: Option<String>,
+    pub btc_price: Option<f64>,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub payer_email: Option<String>,
+    /// JSON object of `--meta` pairs
+    pub meta: Option<String>,
+    /// Only recorded since `--meta` was added
+    pub full_uri: Option<String>,
+    pub from_trusted: Option<bool>,
+}
+
+pub struct IssuedAddress {
+    pub address: String,
+    pub purposes: Vec<String>,
+    pub times_issued: u64,
+    pub first_issued: u64,
+    pub last_issued: u64,
+    pub invoice_received_msat: u64,
+}
+
+pub struct AddressName {
+    pub name: String,
+    pub lightning_address: Option<String>,
+    pub registered_at: u64,
+}
+
+/// A row of a payroll batch: the recipient and which of their rows in the CSV it is, counting
+/// from 0
+pub struct PayrollRecipient<'a> {
+    pub batch: &'a str,
+    pub name: &'a str,
+    pub lightning_address: &'a str,
+    pub occurrence: u32,
+}
+
+/// A payment started by `send`, `vault send`, `payroll run` or the dead man's switch
+pub struct SendRecord {
+    pub payment_id: String,
+    pub payment: String,
+    pub amount_sats: u64,
+    pub cost_center: Option<String>,
+    /// initiated, succeeded or failed
+    pub status: String,
+    pub fee_msat: Option<u64>,
+    pub created_at: u64,
+}
+
+/// When each phase of a send finished, in milliseconds since the epoch
+pub struct SendTimings {
+    /// Before the payment string was parsed
+    pub started_at_ms: u64,
+    /// Payment instructions resolved (includes BIP353/LNURL lookups)
+    pub parsed_at_ms: u64,
+    /// The wallet accepted the payment and returned a payment id
+    pub initiated_at_ms: u64,
+    /// `PaymentSuccessful` was consumed, only set for succeeded sends
+    pub settled_at_ms: Option<u64>,
+}
+
+pub struct WebhookEndpoint {
+    pub url: String,
+    pub delivered: u64,
+    pub failed: u64,
+    pub consecutive_failures: u64,
+    pub failing_since: Option<u64>,
+    pub last_error: Option<String>,
+    pub disabled_at: Option<u64>,
+}
+
+pub struct VaultSend {
+    pub id: i64,
+    pub payment: String,
+    pub amount_sats: Option<u64>,
+    pub cost_center: Option<String>,
+    pub not_before: u64,
+    /// pending, executing, executed, failed or cancelled
+    pub status: String,
+    pub payment_id: Option<String>,
+    pub error: Option<String>,
+    pub created_at: u64,
+    pub executed_at: Option<u64>,
+}
+
+/// A Nostr Wallet Connect client made by `nwc create`
+pub struct NwcConnection {
+    pub id: i64,
+    pub name: String,
+    pub client_pubkey: String,
+    /// Most it may spend per renewal period, unlimited if unset
+    pub budget_sats: Option<u64>,
+    /// daily, weekly, monthly, yearly or never
+    pub budget_renewal: String,
+    pub created_at: u64,
+    pub revoked_at: Option<u64>,
+}
+
+/// An LNURL-withdraw voucher made by `withdraw-link`
+pub struct WithdrawLink {
+    pub id: i64,
+    /// Secret in the voucher's URL, whoever has it can redeem the voucher
+    pub k1: String,
+    /// Paid out per redemption
+    pub amount_sats: u64,
+    pub uses: u32,
+    pub used: u32,
+    pub description: Option<String>,
+    pub created_at: u64,
+    pub revoked_at: Option<u64>,
+}
+
+pub struct PendingDelivery {
+    pub id: i64,
+    pub url: String,
+    pub payload: String,
+    pub attempts: u32,
+}
+
+/// A webhook delivery that gave up after `[webhooks] max_attempts`
+pub struct DeadLetter {
+    /// The delivery's outbox id, shown as `delivery_id` in the delivery log
+    pub id: i64,
+    pub url: String,
+    pub payload: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: u64,
+    pub dead_at: u64,
+}
+
+/// A delivery in the outbox or the dead letters, for `outbox list`
+pub struct OutboxEntry {
+    pub id: i64,
+    pub url: String,
+    pub payload: String,
+    /// pending (not tried yet), retrying or failed (dead-lettered)
+    pub state: String,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: Option<u64>,
+    pub created_at: u64,
+    pub dead_at: Option<u64>,
+}
+
+/// An event from the history, `payload` as posted but without its `seq`
+pub struct StoredEvent {
+    pub seq: i64,
+    pub event_type: String,
+    pub payload: String,
+    pub created_at: u64,
+}
+
+/// Wall time of one wallet command, split by phase
+pub struct CommandStat {
+    pub command: String,
+    pub ok: bool,
+    /// Starting the wallet, including whatever chain sync the SDK does before returning
+    pub wallet_init_ms: u64,
+    /// The command itself, mostly SDK calls
+    pub call_ms: u64,
+    /// Stopping the wallet after printing the output
+    pub stop_ms: u64,
+    /// From process start, so config loading and the chain cache are included
+    pub total_ms: u64,
+    pub recorded_at: u64,
+}
+
+/// One POST of a queued webhook delivery
+pub struct WebhookAttempt {
+    pub outbox_id: i64,
+    pub url: String,
+    pub event_type: String,
+    /// 1 for the first attempt
+    pub attempt: u32,
+    /// HTTP status, `None` if no response was received
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub latency_ms: u64,
+    pub attempted_at: u64,
+}
+
+pub struct CostCenterTotal {
+    pub cost_center: Option<String>,
+    pub payment_count: u64,
+    pub amount_sats: u64,
+    pub fee_msat: u64,
+}
+
+/// Sends in one status, for the daemon's metrics
+pub struct SendTotal {
+    pub status: String,
+    pub payment_count: u64,
+    pub amount_sats: u64,
+}
+
+impl Store {
+    pub fn open(storage_dir: &Path) -> Result<Self, String> {
+        let mut conn = Connection::open(storage_dir.join("orange-cli.sqlite"))
+            .map_err(|e| format!("Failed to open local store: {e}"))?;
+        // WAL lets replica readers and one-shot commands work alongside the daemon
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .map_err(|e| format!("Failed to open local store: {e}"))?;
+        migrate(&mut conn).map_err(|e| format!("Failed to migrate local store: {e}"))?;
+        Ok(Store { conn })
+    }
+
+    /// Records a receive URI handed out by `receive`
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_receive(
+        &self,
+        purpose: Option<&str>,
+        address: Option<&str>,
+        invoice: &str,
+        payment_hash: &str,
+        amount_sats: Option<u64>,
+        created_at: u64,
+        expires_at: u64,
+        payer_email: Option<&str>,
+        meta: Option<&str>,
+        order_id: Option<&str>,
+        full_uri: &str,
+        from_trusted: bool,
+    ) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT INTO receives
+                 (purpose, address, invoice, payment_hash, amount_sats, created_at, expires_at,
+                  payer_email, meta, order_id, full_uri, from_trusted)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                params![
+                    purpose,
+                    address,
+                    invoice,
+                    payment_hash,
+                    amount_sats,
+                    created_at,
+                    expires_at,
+                    payer_email,
+                    meta,
+                    order_id,
+                    full_uri,
+                    from_trusted
+                ],
+            )
+            .map_err(|e| format!("Failed to record receive: {e}"))?;
+        Ok(())
+    }
+
+    /// Receives not yet announced by the daemon, oldest first
+    pub fn unannounced_receives(&self) -> Result<Vec<Receive>, String> {
+        self.query_receives("v.announced_at IS NULL", params![])
+    }
+
+    /// Unpaid receives whose invoice expired by `now` and that haven't been announced as expired
+    pub fn expired_receives(&self, now: u64) -> Result<Vec<Receive>, String> {
+        self.query_receives(
+            "v.expired_at IS NULL AND v.expires_at <= ?1
+             AND v.payment_hash NOT IN (SELECT payment_hash FROM receipts)",
+            params![now],
+        )
+    }
+
+    /// The newest receive for `order_id` that is neither paid nor expired at `now`
+    pub fn open_receive_for_order(
+        &self,
+        order_id: &str,
+        now: u64,
+    ) -> Result<Option<Receive>, String> {
+        let receives = self.query_receives(
+            "v.order_id = ?1 AND v.expires_at > ?2
+             AND v.payment_hash NOT IN (SELECT payment_hash FROM receipts)",
+            params![order_id, now],
+        )?;
+        Ok(receives.into_iter().last())
+    }
+
+    pub fn receive_by_invoice(&self, invoice: &str) -> Result<Option<Receive>, String> {
+        let receives = self.query_receives("v.invoice = ?1", params![invoice])?;
+        Ok(receives.into_iter().next())
+    }
+
+    /// When and for how much `payment_hash` was received, if it was
+    pub fn receipt_by_payment_hash(
+        &self,
+        payment_hash: &str,
+    ) -> Result<Option<(u64, u64)>, String> {
+        self.conn
+            .query_row(
+                "SELECT amount_msat, received_at FROM receipts WHERE payment_hash = ?1",
+                params![payment_hash],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| format!("Failed to look up receipt: {e}"))
+    }
+
+    pub fn receive_by_payment_hash(&self, payment_hash: &str) -> Result<Option<Receive>, String> {
+        let receives = self.query_receives("v.payment_hash = ?1", params![payment_hash])?;
+        Ok(receives.into_iter().next())
+    }
+
+    /// The receive paid by `payment_hash` if its payer asked for an emailed receipt that
+    /// hasn't been sent yet
+    pub fn receipt_email_due(&self, payment_hash: &str) -> Result<Option<Receive>, String> {
+        let receives = self.query_receives(
+            "v.payment_hash = ?1 AND v.payer_email IS NOT NULL AND v.receipt_emailed_at IS NULL",
+            params![payment_hash],
+        )?;
+        Ok(receives.into_iter().next())
+    }
+
+    /// Claims a receive's receipt email, returning false if it was already sent
+    pub fn mark_receipt_emailed(&self, id: i64, at: u64) -> Result<bool, String> {
+        let changed = self
+            .conn
+            .execute(
+                "UPDATE receives SET receipt_emailed_at = ?2
+                 WHERE id = ?1 AND receipt_emailed_at IS NULL",
+                params![id, at],
+            )
+            .map_err(|e| format!("Failed to update receive: {e}"))?;
+        Ok(changed > 0)
+    }
+
+    /// Gives back a receipt email claimed by `mark_receipt_emailed` that couldn't be sent
+    pub fn release_receipt_email(&self, id: i64) -> Result<(), String> {
+        self.conn
+            .execute(
+                "UPDATE receives SET receipt_emailed_at = NULL WHERE id = ?1",
+                params![id],
+            )
+            .map_err(|e| format!("Failed to update receive: {e}"))?;
+        Ok(())
+    }
+
+    /// Receives paid since `since` whose receipt email is unsent, with the `(payment_id,
+    /// amount_msat, received_at)` of the payment
+    pub fn receipt_emails_due(
+        &self,
+        since: u64,
+    ) -> Result<Vec<(Receive, String, u64, u64)>, String> {
+        let receives = self.query_receives(
+            "v.payer_email IS NOT NULL AND v.receipt_emailed_at IS NULL
+             AND v.payment_hash IN (SELECT payment_hash FROM receipts WHERE received_at >= ?1)",
+            params![since],
+        )?;
+        let mut due = Vec::with_capacity(receives.len());
+        for receive in receives {
+            let (payment_id, amount_msat, received_at) = self
+                .conn
+                .query_row(
+                    "SELECT payment_id, amount_msat, received_at FROM receipts
+                     WHERE payment_hash = ?1",
+                    params![receive.payment_hash],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .map_err(|e| format!("Failed to look up receipt: {e}"))?;
+            due.push((receive, payment_id, amount_msat, received_at));
+        }
+        Ok(due)
+    }
+
+    fn query_receives(
+        &self,
+        filter: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<Receive>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!(
+                "SELECT v.id, v.purpose, v.address, v.invoice, v.payment_hash, v.amount_sats,
+                        f.fiat_amount, f.currency, f.btc_price, v.created_at, v.expires_at,
+                        v.payer_email, v.meta, v.full_uri, v.from_trusted
+                 FROM receives v LEFT JOIN fiat_obligations f ON f.payment_hash = v.payment_hash
+                 WHERE {filter} ORDER BY v.id"
+            ))
+            .map_err(|e| format!("Failed to list receives: {e}"))?;
+        let rows = stmt
+            .query_map(params, |row| {
+                Ok(Receive {
+                    id: row.get(0)?,
+                    purpose: row.get(1)?,
+                    address: row.get(2)?,
+                    invoice: row.get(3)?,
+                    payment_hash: row.get(4)?,
+                    amount_sats: row.get(5)?,
+                    fiat_amount: row.get(6)?,
+                    currency: row.get(7)?,
+                    btc_price: row.get(8)?,
+                    created_at: row.get(9)?,
+                    expires_at: row.get(10)?,
+                    payer_email: row.get(11)?,
+                    meta: row.get(12)?,
+                    full_uri: row.get(13)?,
+                    from_trusted: row.get(14)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list receives: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list receives: {e}"))
+    }
+
+    pub fn mark_receive_announced(&self, id: i64, at: u64) -> Result<(), String> {
+        self.conn
+            .execute(
+                "UPDATE receives SET announced_at = ?2 WHERE id = ?1",
+                params![id, at],
+            )
+            .map_err(|e| format!("Failed to update receive: {e}"))?;
+        Ok(())
+    }
+
+    pub fn mark_receive_expired(&self, id: i64, at: u64) -> Result<(), String> {
+        self.conn
+            .execute(
+                "UPDATE receives SET expired_at = ?2 WHERE id = ?1",
+                params![id, at],
+            )
+            .map_err(|e| format!("Failed to update receive: {e}"))?;
+        Ok(())
+    }
+
+    /// Number of times `address` has been handed out before
+    pub fn address_issue_count(&self, address: &str) -> Result<u64, String> {
+        self.conn
+            .query_row(
+                "SELECT COUNT(*) FROM receives WHERE address = ?1",
+                params![address],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Failed to look up address: {e}"))
+    }
+
+    /// Records an incoming payment. Repeated calls for the same payment are ignored.
+    pub fn record_receipt(
+        &self,
+        payment_id: &str,
+        payment_hash: &str,
+        amount_msat: u64,
+        received_at: u64,
+    ) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT OR IGNORE INTO receipts (payment_id, payment_hash, amount_msat, received_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![payment_id, payment_hash, amount_msat, received_at],
+            )
+            .map_err(|e| format!("Failed to record receipt: {e}"))?;
+        Ok(())
+    }
+
+    /// Claims the `lsp_fee_exceeded` alert of a recorded receipt, returning false if it was
+    /// already raised
+    pub fn mark_lsp_fee_alerted(&self, payment_id: &str, at: u64) -> Result<bool, String> {
+        let changed = self
+            .conn
+            .execute(
+                "UPDATE receipts SET lsp_fee_alerted_at = ?2
+                 WHERE payment_id = ?1 AND lsp_fee_alerted_at IS NULL",
+                params![payment_id, at],
+            )
+            .map_err(|e| format!("Failed to update receipt: {e}"))?;
+        Ok(changed > 0)
+    }
+
+    pub fn record_onchain_receipt(
+        &self,
+        txid: &str,
+        amount_sats: u64,
+        received_at: u64,
+    ) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT OR IGNORE INTO onchain_receipts (txid, amount_sats, received_at)
+                 VALUES (?1, ?2, ?3)",
+                params![txid, amount_sats, received_at],
+            )
+            .map_err(|e| format!("Failed to record on-chain receipt: {e}"))?;
+        Ok(())
+    }
+
+    /// Whether an on-chain payment of `amount_sats`, or of any amount when `None`, was received
+    /// at or after `since`
+    pub fn onchain_receipt_since(
+        &self,
+        since: u64,
+        amount_sats: Option<u64>,
+    ) -> Result<bool, String> {
+        self.conn
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM onchain_receipts
+                 WHERE received_at >= ?1 AND (?2 IS NULL OR amount_sats = ?2))",
+                params![since, amount_sats],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Failed to look up on-chain receipts: {e}"))
+    }
+
+    /// Claims the receipt of zap request `request_id`, returning false if it was already
+    /// published
+    pub fn claim_zap_receipt(
+        &self,
+        request_id: &str,
+        payment_hash: &str,
+        at: u64,
+    ) -> Result<bool, String> {
+        let changed = self
+            .conn
+            .execute(
+                "INSERT OR IGNORE INTO zap_receipts (request_id, payment_hash, published_at)
+                 VALUES (?1, ?2, ?3)",
+                params![request_id, payment_hash, at],
+            )
+            .map_err(|e| format!("Failed to record zap receipt: {e}"))?;
+        Ok(changed > 0)
+    }
+
+    pub fn record_address_name(
+        &self,
+        name: &str,
+        lightning_address: Option<&str>,
+        registered_at: u64,
+    ) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT INTO address_names (name, lightning_address, registered_at) VALUES (?1, ?2, ?3)",
+                params![name, lightning_address, registered_at],
+            )
+            .map_err(|e| format!("Failed to record lightning address name: {e}"))?;
+        Ok(())
+    }
+
+    /// Registered lightning address names, oldest first
+    pub fn address_names(&self) -> Result<Vec<AddressName>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT name, lightning_address, registered_at FROM address_names
+                 ORDER BY registered_at, rowid",
+            )
+            .map_err(|e| format!("Failed to list lightning address names: {e}"))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(AddressName {
+                    name: row.get(0)?,
+                    lightning_address: row.get(1)?,
+                    registered_at: row.get(2)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list lightning address names: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list lightning address names: {e}"))
+    }
+
+    /// `(purpose, amount_msat, received_at)` of every receipt, where `purpose` is that of the
+    /// invoice issued by `receive`, or `None` for payments `receive` didn't ask for
+    pub fn receipts_with_purpose(&self) -> Result<Vec<(Option<String>, u64, u64)>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT v.purpose, r.amount_msat, r.received_at FROM receipts r
+                 LEFT JOIN receives v ON v.payment_hash = r.payment_hash
+                 ORDER BY r.received_at",
+            )
+            .map_err(|e| format!("Failed to list receipts: {e}"))?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(|e| format!("Failed to list receipts: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list receipts: {e}"))
+    }
+
+    pub fn record_fiat_obligation(
+        &self,
+        payment_hash: &str,
+        fiat_amount: f64,
+        currency: &str,
+        btc_price: f64,
+        amount_sats: u64,
+        created_at: u64,
+    ) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT INTO fiat_obligations (payment_hash, fiat_amount, currency, btc_price, amount_sats, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![payment_hash, fiat_amount, currency, btc_price, amount_sats, created_at],
+            )
+            .map_err(|e| format!("Failed to record fiat obligation: {e}"))?;
+        Ok(())
+    }
+
+    /// `(fiat_amount, received_msat)` for each paid obligation in `currency`
+    pub fn settled_fiat_obligations(&self, currency: &str) -> Result<Vec<(f64, u64)>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT f.fiat_amount, SUM(r.amount_msat)
+                 FROM fiat_obligations f JOIN receipts r ON r.payment_hash = f.payment_hash
+                 WHERE f.currency = ?1
+                 GROUP BY f.payment_hash",
+            )
+            .map_err(|e| format!("Failed to list fiat obligations: {e}"))?;
+        let rows = stmt
+            .query_map(params![currency], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| format!("Failed to list fiat obligations: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list fiat obligations: {e}"))
+    }
+
+    /// `(payment_hash, fiat_amount, amount_msat)` of the paid fiat receives in `currency` that
+    /// no `fiat_drift` event has named yet
+    pub fn fiat_obligations_to_alert(
+        &self,
+        currency: &str,
+    ) -> Result<Vec<(String, f64, u64)>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT f.payment_hash, f.fiat_amount, SUM(r.amount_msat)
+                 FROM fiat_obligations f JOIN receipts r ON r.payment_hash = f.payment_hash
+                 WHERE f.currency = ?1 AND f.drift_alerted_at IS NULL
+                 GROUP BY f.payment_hash",
+            )
+            .map_err(|e| format!("Failed to list fiat obligations: {e}"))?;
+        let rows = stmt
+            .query_map(params![currency], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .map_err(|e| format!("Failed to list fiat obligations: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list fiat obligations: {e}"))
+    }
+
+    pub fn mark_fiat_drift_alerted(
+        &self,
+        payment_hashes: &[String],
+        at: u64,
+    ) -> Result<(), String> {
+        let tx = self
+            .conn
+            .unchecked_transaction()
+            .map_err(|e| format!("Failed to mark fiat drift alerted: {e}"))?;
+        for payment_hash in payment_hashes {
+            tx.execute(
+                "UPDATE fiat_obligations SET drift_alerted_at = ?2 WHERE payment_hash = ?1",
+                params![payment_hash, at],
+            )
+            .map_err(|e| format!("Failed to mark fiat drift alerted: {e}"))?;
+        }
+        tx.commit()
+            .map_err(|e| format!("Failed to mark fiat drift alerted: {e}"))
+    }
+
+    /// Records a send before it is paid, under a placeholder id returned for `assign_send_id`,
+    /// so budgets summing `sends` see it while the payment is being made
+    pub fn reserve_send(
+        &self,
+        payment: &str,
+        amount_sats: u64,
+        cost_center: Option<&str>,
+        timings: &SendTimings,
+    ) -> Result<String, String> {
+        let reserved = format!(
+            "pending-{}",
+            rand::random::<[u8; 16]>().to_lower_hex_string()
+        );
+        self.conn
+            .execute(
+                "INSERT INTO sends
+                 (payment_id, payment, amount_sats, cost_center, created_at,
+                  started_at_ms, parsed_at_ms)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    reserved,
+                    payment,
+                    amount_sats,
+                    cost_center,
+                    timings.started_at_ms / 1000,
+                    timings.started_at_ms,
+                    timings.parsed_at_ms
+                ],
+            )
+            .map_err(|e| format!("Failed to record send: {e}"))?;
+        Ok(reserved)
+    }
+
+    /// Gives a reserved send the id the wallet paid it under, along with any outcome
+    /// `update_send_status` got for that id in the meantime
+    pub fn assign_send_id(
+        &self,
+        reserved: &str,
+        payment_id: &str,
+        initiated_at_ms: u64,
+    ) -> Result<(), String> {
+        let tx = self
+            .conn
+            .unchecked_transaction()
+            .map_err(|e| format!("Failed to record send: {e}"))?;
+        tx.execute(
+            "UPDATE sends SET payment_id = ?2, initiated_at_ms = ?3 WHERE payment_id = ?1",
+            params![reserved, payment_id, initiated_at_ms],
+        )
+        .map_err(|e| format!("Failed to record send: {e}"))?;
+        let failed: bool = tx
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM send_outcomes
+                 WHERE payment_id = ?1 AND status = 'failed')",
+                params![payment_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Failed to record send: {e}"))?;
+        if failed {
+            release_failed_withdraw(&tx, payment_id)?;
+        }
+        tx.execute(
+            "UPDATE sends SET
+               status = (SELECT status FROM send_outcomes WHERE payment_id = ?1),
+               fee_msat = (SELECT fee_msat FROM send_outcomes WHERE payment_id = ?1),
+               settled_at_ms = (SELECT settled_at_ms FROM send_outcomes WHERE payment_id = ?1)
+             WHERE payment_id = ?1
+               AND EXISTS (SELECT 1 FROM send_outcomes WHERE payment_id = ?1)",
+            params![payment_id],
+        )
+        .map_err(|e| format!("Failed to record send: {e}"))?;
+        // Outcomes no send claimed within a day were for payments made elsewhere
+        tx.execute(
+            "DELETE FROM send_outcomes WHERE payment_id = ?1 OR recorded_at < ?2",
+            params![payment_id, (initiated_at_ms / 1000).saturating_sub(86400)],
+        )
+        .map_err(|e| format!("Failed to record send: {e}"))?;
+        tx.commit()
+            .map_err(|e| format!("Failed to record send: {e}"))
+    }
+
+    /// Drops a reserved send the wallet refused to pay
+    pub fn release_send(&self, reserved: &str) -> Result<(), String> {
+        self.conn
+            .execute("DELETE FROM sends WHERE payment_id = ?1", params![reserved])
+            .map_err(|e| format!("Failed to release send: {e}"))?;
+        Ok(())
+    }
+
+    /// The latest send of `payment`, the invoice or other payment string it was made to
+    pub fn send_by_payment(&self, payment: &str) -> Result<Option<SendRecord>, String> {
+        self.conn
+            .query_row(
+                "SELECT payment_id, payment, amount_sats, cost_center, status, fee_msat, created_at
+                 FROM sends WHERE payment = ?1 ORDER BY created_at DESC LIMIT 1",
+                params![payment],
+                |row| {
+                    Ok(SendRecord {
+                        payment_id: row.get(0)?,
+                        payment: row.get(1)?,
+                        amount_sats: row.get(2)?,
+                        cost_center: row.get(3)?,
+                        status: row.get(4)?,
+                        fee_msat: row.get(5)?,
+                        created_at: row.get(6)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(|e| format!("Failed to look up send: {e}"))
+    }
+
+    /// Sats sent on `cost_center` since `since` including fees, counting payments still in
+    /// flight but not failed ones
+    pub fn cost_center_spent(&self, cost_center: &str, since: u64) -> Result<u64, String> {
+        self.conn
+            .query_row(
+                "SELECT COALESCE(SUM(amount_sats + (COALESCE(fee_msat, 0) + 999) / 1000), 0)
+                 FROM sends
+                 WHERE cost_center = ?1 AND created_at >= ?2 AND status != 'failed'",
+                params![cost_center, since],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Failed to sum sends: {e}"))
+    }
+
+    /// Records the outcome of a payment started by `send`. The outcome of an unknown payment
+    /// id is kept for `assign_send_id`, in case it settled before its send got the id.
+    pub fn update_send_status(
+        &self,
+        payment_id: &str,
+        status: &str,
+        fee_msat: Option<u64>,
+        at_ms: u64,
+    ) -> Result<(), String> {
+        let tx = self
+            .conn
+            .unchecked_transaction()
+            .map_err(|e| format!("Failed to update send: {e}"))?;
+        if status == "failed" {
+            release_failed_withdraw(&tx, payment_id)?;
+        }
+        let updated = tx
+            .execute(
+                "UPDATE sends SET status = ?2, fee_msat = COALESCE(?3, fee_msat),
+                   settled_at_ms = CASE WHEN ?2 = 'succeeded' THEN COALESCE(settled_at_ms, ?4) END
+                 WHERE payment_id = ?1",
+                params![payment_id, status, fee_msat, at_ms],
+            )
+            .map_err(|e| format!("Failed to update send: {e}"))?;
+        if updated == 0 {
+            tx.execute(
+                "INSERT OR REPLACE INTO send_outcomes
+                 (payment_id, status, fee_msat, settled_at_ms, recorded_at)
+                 VALUES (?1, ?2, ?3, CASE WHEN ?2 = 'succeeded' THEN ?4 END, ?5)",
+                params![payment_id, status, fee_msat, at_ms, at_ms / 1000],
+            )
+            .map_err(|e| format!("Failed to update send: {e}"))?;
+        }
+        tx.commit()
+            .map_err(|e| format!("Failed to update send: {e}"))
+    }
+
+    /// Totals of sends that have not failed, grouped by cost center
+    pub fn cost_center_totals(&self) -> Result<Vec<CostCenterTotal>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT cost_center, COUNT(*), SUM(amount_sats), COALESCE(SUM(fee_msat), 0)
+                 FROM sends WHERE status != 'failed'
+                 GROUP BY cost_center ORDER BY cost_center",
+            )
+            .map_err(|e| format!("Failed to aggregate sends: {e}"))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(CostCenterTotal {
+                    cost_center: row.get(0)?,
+                    payment_count: row.get(1)?,
+                    amount_sats: row.get(2)?,
+                    fee_msat: row.get(3)?,
+                })
+            })
+            .map_err(|e| format!("Failed to aggregate sends: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to aggregate sends: {e}"))
+    }
+
+    /// Sends created at or after `since`, oldest first
+    pub fn sends(&self, since: u64) -> Result<Vec<SendRecord>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT payment_id, payment, amount_sats, cost_center, status, fee_msat, created_at
+                 FROM sends WHERE created_at >= ?1 ORDER BY created_at, payment_id",
+            )
+            .map_err(|e| format!("Failed to list sends: {e}"))?;
+        let rows = stmt
+            .query_map(params![since], |row| {
+                Ok(SendRecord {
+                    payment_id: row.get(0)?,
+                    payment: row.get(1)?,
+                    amount_sats: row.get(2)?,
+                    cost_center: row.get(3)?,
+                    status: row.get(4)?,
+                    fee_msat: row.get(5)?,
+                    created_at: row.get(6)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list sends: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list sends: {e}"))
+    }
+
+    /// Sends created at or after `since` that haven't succeeded or failed yet
+    pub fn in_flight_sends(&self, since: u64) -> Result<u64, String> {
+        self.conn
+            .query_row(
+                "SELECT COUNT(*) FROM sends WHERE status = 'initiated' AND created_at >= ?1",
+                params![since],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Failed to count sends: {e}"))
+    }
+
+    /// Number and total amount of sends, grouped by status
+    pub fn send_totals(&self) -> Result<Vec<SendTotal>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT status, COUNT(*), SUM(amount_sats) FROM sends
+                 GROUP BY status ORDER BY status",
+            )
+            .map_err(|e| format!("Failed to aggregate sends: {e}"))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(SendTotal {
+                    status: row.get(0)?,
+                    payment_count: row.get(1)?,
+                    amount_sats: row.get(2)?,
+                })
+            })
+            .map_err(|e| format!("Failed to aggregate sends: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to aggregate sends: {e}"))
+    }
+
+    /// Number and total amount (msat) of incoming payments
+    pub fn receipt_totals(&self) -> Result<(u64, u64), String> {
+        self.conn
+            .query_row(
+                "SELECT COUNT(*), COALESCE(SUM(amount_msat), 0) FROM receipts",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(|e| format!("Failed to aggregate receipts: {e}"))
+    }
+
+    /// Phase timestamps of succeeded sends created at or after `since` (seconds)
+    pub fn send_timings(&self, since: u64) -> Result<Vec<SendTimings>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT started_at_ms, parsed_at_ms, initiated_at_ms, settled_at_ms FROM sends
+                 WHERE status = 'succeeded' AND started_at_ms IS NOT NULL
+                   AND settled_at_ms IS NOT NULL AND created_at >= ?1
+                 ORDER BY created_at",
+            )
+            .map_err(|e| format!("Failed to list send timings: {e}"))?;
+        let rows = stmt
+            .query_map(params![since], |row| {
+                Ok(SendTimings {
+                    started_at_ms: row.get(0)?,
+                    parsed_at_ms: row.get(1)?,
+                    initiated_at_ms: row.get(2)?,
+                    settled_at_ms: row.get(3)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list send timings: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list send timings: {e}"))
+    }
+
+    pub fn record_command_stat(&self, stat: &CommandStat) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT INTO command_stats
+                 (command, ok, wallet_init_ms, call_ms, stop_ms, total_ms, recorded_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    stat.command,
+                    stat.ok,
+                    stat.wallet_init_ms,
+                    stat.call_ms,
+                    stat.stop_ms,
+                    stat.total_ms,
+                    stat.recorded_at
+                ],
+            )
+            .map_err(|e| format!("Failed to record command stats: {e}"))?;
+        Ok(())
+    }
+
+    /// Command timings recorded at or after `since`, grouped by command
+    pub fn command_stats(&self, since: u64) -> Result<Vec<CommandStat>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT command, ok, wallet_init_ms, call_ms, stop_ms, total_ms, recorded_at
+                 FROM command_stats WHERE recorded_at >= ?1 ORDER BY command, id",
+            )
+            .map_err(|e| format!("Failed to list command stats: {e}"))?;
+        let rows = stmt
+            .query_map(params![since], |row| {
+                Ok(CommandStat {
+                    command: row.get(0)?,
+                    ok: row.get(1)?,
+                    wallet_init_ms: row.get(2)?,
+                    call_ms: row.get(3)?,
+                    stop_ms: row.get(4)?,
+                    total_ms: row.get(5)?,
+                    recorded_at: row.get(6)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list command stats: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list command stats: {e}"))
+    }
+
+    /// Payment id of a recipient already paid in `batch`, unless that payment failed
+    pub fn payroll_payment(&self, recipient: &PayrollRecipient) -> Result<Option<String>, String> {
+        self.conn
+            .query_row(
+                "SELECT p.payment_id FROM payroll p LEFT JOIN sends s ON s.payment_id = p.payment_id
+                 WHERE p.batch = ?1 AND p.name = ?2 AND p.lightning_address = ?3
+                   AND p.occurrence = ?4 AND COALESCE(s.status, '') != 'failed'",
+                params![
+                    recipient.batch,
+                    recipient.name,
+                    recipient.lightning_address,
+                    recipient.occurrence
+                ],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| format!("Failed to look up payroll payment: {e}"))
+    }
+
+    /// Records one payment to a payroll recipient, before the rest of their parts are sent
+    pub fn record_payroll_part(
+        &self,
+        recipient: &PayrollRecipient,
+        payment_id: &str,
+        amount_sats: u64,
+        paid_at: u64,
+    ) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT OR IGNORE INTO payroll_parts
+                 (payment_id, batch, name, lightning_address, occurrence, amount_sats, paid_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    payment_id,
+                    recipient.batch,
+                    recipient.name,
+                    recipient.lightning_address,
+                    recipient.occurrence,
+                    amount_sats,
+                    paid_at
+                ],
+            )
+            .map_err(|e| format!("Failed to record payroll payment: {e}"))?;
+        Ok(())
+    }
+
+    /// `(payment_id, amount_sats)` of the payments to a payroll recipient that haven't failed,
+    /// oldest first
+    pub fn payroll_parts(
+        &self,
+        recipient: &PayrollRecipient,
+    ) -> Result<Vec<(String, u64)>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT p.payment_id, p.amount_sats FROM payroll_parts p
+                 LEFT JOIN sends s ON s.payment_id = p.payment_id
+                 WHERE p.batch = ?1 AND p.name = ?2 AND p.lightning_address = ?3
+                   AND p.occurrence = ?4 AND COALESCE(s.status, '') != 'failed'
+                 ORDER BY p.paid_at, p.rowid",
+            )
+            .map_err(|e| format!("Failed to look up payroll payments: {e}"))?;
+        let rows = stmt
+            .query_map(
+                params![
+                    recipient.batch,
+                    recipient.name,
+                    recipient.lightning_address,
+                    recipient.occurrence
+                ],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(|e| format!("Failed to look up payroll payments: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to look up payroll payments: {e}"))
+    }
+
+    pub fn record_payroll_payment(
+        &self,
+        recipient: &PayrollRecipient,
+        amount_sats: u64,
+        memo: &str,
+        payment_id: &str,
+        paid_at: u64,
+    ) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO payroll
+                 (batch, name, lightning_address, occurrence, amount_sats, memo, payment_id,
+                  paid_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    recipient.batch,
+                    recipient.name,
+                    recipient.lightning_address,
+                    recipient.occurrence,
+                    amount_sats,
+                    memo,
+                    payment_id,
+                    paid_at
+                ],
+            )
+            .map_err(|e| format!("Failed to record payroll payment: {e}"))?;
+        Ok(())
+    }
+
+    /// Records a successful delivery, ending the endpoint's failure streak
+    pub fn webhook_delivered(&self, url: &str) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT INTO webhook_endpoints (url, delivered) VALUES (?1, 1)
+                 ON CONFLICT(url) DO UPDATE SET delivered = delivered + 1,
+                   consecutive_failures = 0, failing_since = NULL",
+                params![url],
+            )
+            .map_err(|e| format!("Failed to record webhook delivery: {e}"))?;
+        Ok(())
+    }
+
+    /// Records a failed delivery and returns when the current failure streak started
+    pub fn webhook_failed(&self, url: &str, error: &str, at: u64) -> Result<u64, String> {
+        self.conn
+            .query_row(
+                "INSERT INTO webhook_endpoints (url, failed, consecutive_failures, failing_since, last_error)
+                 VALUES (?1, 1, 1, ?3, ?2)
+                 ON CONFLICT(url) DO UPDATE SET failed = failed + 1,
+                   consecutive_failures = consecutive_failures + 1,
+                   failing_since = COALESCE(failing_since, ?3), last_error = ?2
+                 RETURNING failing_since",
+                params![url, error, at],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Failed to record webhook failure: {e}"))
+    }
+
+    /// Disables an endpoint, returning false if it already was
+    pub fn disable_webhook(&self, url: &str, at: u64) -> Result<bool, String> {
+        let changed = self
+            .conn
+            .execute(
+                "UPDATE webhook_endpoints SET disabled_at = ?2 WHERE url = ?1 AND disabled_at IS NULL",
+                params![url, at],
+            )
+            .map_err(|e| format!("Failed to disable webhook: {e}"))?;
+        Ok(changed > 0)
+    }
+
+    /// Re-enables an endpoint and clears its failure streak, returning false for unknown URLs
+    pub fn enable_webhook(&self, url: &str) -> Result<bool, String> {
+        let changed = self
+            .conn
+            .execute(
+                "UPDATE webhook_endpoints SET disabled_at = NULL, consecutive_failures = 0,
+                   failing_since = NULL WHERE url = ?1",
+                params![url],
+            )
+            .map_err(|e| format!("Failed to enable webhook: {e}"))?;
+        Ok(changed > 0)
+    }
+
+    /// Adds a delivery to the outbox, claimed by the caller's first attempt until `lease_until`
+    pub fn enqueue_webhook(
+        &self,
+        url: &str,
+        payload: &str,
+        created_at: u64,
+        lease_until: u64,
+    ) -> Result<i64, String> {
+        self.conn
+            .execute(
+                "INSERT INTO webhook_outbox (url, payload, next_attempt_at, created_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![url, payload, lease_until, created_at],
+            )
+            .map_err(|e| format!("Failed to queue webhook delivery: {e}"))?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn due_webhook_deliveries(&self, now: u64) -> Result<Vec<PendingDelivery>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT id, url, payload, attempts FROM webhook_outbox
+                 WHERE next_attempt_at <= ?1 ORDER BY id",
+            )
+            .map_err(|e| format!("Failed to list webhook deliveries: {e}"))?;
+        let rows = stmt
+            .query_map(params![now], |row| {
+                Ok(PendingDelivery {
+                    id: row.get(0)?,
+                    url: row.get(1)?,
+                    payload: row.get(2)?,
+                    attempts: row.get(3)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list webhook deliveries: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list webhook deliveries: {e}"))
+    }
+
+    /// Claims a queued delivery for a retry until `until`
+    pub fn lease_webhook_delivery(&self, id: i64, until: u64) -> Result<(), String> {
+        self.conn
+            .execute(
+                "UPDATE webhook_outbox SET next_attempt_at = ?2 WHERE id = ?1",
+                params![id, until],
+            )
+            .map_err(|e| format!("Failed to update webhook delivery: {e}"))?;
+        Ok(())
+    }
+
+    pub fn webhook_delivery_succeeded(&self, id: i64) -> Result<(), String> {
+        self.conn
+            .execute("DELETE FROM webhook_outbox WHERE id = ?1", params![id])
+            .map_err(|e| format!("Failed to update webhook delivery: {e}"))?;
+        Ok(())
+    }
+
+    pub fn webhook_delivery_failed(
+        &self,
+        id: i64,
+        attempts: u32,
+        error: &str,
+        next_attempt_at: u64,
+    ) -> Result<(), String> {
+        self.conn
+            .execute(
+                "UPDATE webhook_outbox SET attempts = ?2, last_error = ?3, next_attempt_at = ?4
+                 WHERE id = ?1",
+                params![id, attempts, error, next_attempt_at],
+            )
+            .map_err(|e| format!("Failed to update webhook delivery: {e}"))?;
+        Ok(())
+    }
+
+    /// Moves a delivery from the outbox to the dead-letter queue
+    pub fn dead_letter_webhook(
+        &self,
+        id: i64,
+        attempts: u32,
+        error: &str,
+        at: u64,
+    ) -> Result<(), String> {
+        let tx = self
+            .conn
+            .unchecked_transaction()
+            .map_err(|e| format!("Failed to dead-letter webhook delivery: {e}"))?;
+        tx.execute(
+            "INSERT OR REPLACE INTO webhook_dead_letters
+             (id, url, payload, attempts, last_error, created_at, dead_at)
+             SELECT id, url, payload, ?2, ?3, created_at, ?4 FROM webhook_outbox WHERE id = ?1",
+            params![id, attempts, error, at],
+        )
+        .and_then(|_| tx.execute("DELETE FROM webhook_outbox WHERE id = ?1", params![id]))
+        .and_then(|_| tx.commit())
+        .map_err(|e| format!("Failed to dead-letter webhook delivery: {e}"))
+    }
+
+    /// Number of deliveries in the outbox, in flight or waiting for a retry
+    pub fn webhook_backlog(&self) -> Result<u64, String> {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM webhook_outbox", [], |row| row.get(0))
+            .map_err(|e| format!("Failed to count webhook deliveries: {e}"))
+    }
+
+    /// Deliveries in the outbox and the dead letters, newest first, optionally in one state
+    pub fn outbox_entries(
+        &self,
+        state: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<OutboxEntry>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT id, url, payload, state, attempts, last_error, next_attempt_at,
+                        created_at, dead_at
+                 FROM (
+                     SELECT id, url, payload,
+                            CASE WHEN attempts = 0 THEN 'pending' ELSE 'retrying' END AS state,
+                            attempts, last_error, next_attempt_at, created_at, NULL AS dead_at
+                     FROM webhook_outbox
+                     UNION ALL
+                     SELECT id, url, payload, 'failed', attempts, last_error, NULL, created_at,
+                            dead_at
+                     FROM webhook_dead_letters
+                 )
+                 WHERE ?1 IS NULL OR state = ?1
+                 ORDER BY created_at DESC, id DESC LIMIT ?2",
+            )
+            .map_err(|e| format!("Failed to list the outbox: {e}"))?;
+        let rows = stmt
+            .query_map(params![state, limit], |row| {
+                Ok(OutboxEntry {
+                    id: row.get(0)?,
+                    url: row.get(1)?,
+                    payload: row.get(2)?,
+                    state: row.get(3)?,
+                    attempts: row.get(4)?,
+                    last_error: row.get(5)?,
+                    next_attempt_at: row.get(6)?,
+                    created_at: row.get(7)?,
+                    dead_at: row.get(8)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list the outbox: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list the outbox: {e}"))
+    }
+
+    /// Deletes deliveries in `state` created before `before`, returning how many
+    pub fn purge_outbox(&self, state: &str, before: u64) -> Result<u64, String> {
+        let sql = match state {
+            "pending" => "DELETE FROM webhook_outbox WHERE attempts = 0 AND created_at < ?1",
+            "retrying" => "DELETE FROM webhook_outbox WHERE attempts > 0 AND created_at < ?1",
+            "failed" => "DELETE FROM webhook_dead_letters WHERE created_at < ?1",
+            state => return Err(format!("Unknown outbox state: {state}")),
+        };
+        let purged = self
+            .conn
+            .execute(sql, params![before])
+            .map_err(|e| format!("Failed to purge the outbox: {e}"))?;
+        Ok(purged as u64)
+    }
+
+    pub fn dead_letter_count(&self) -> Result<u64, String> {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM webhook_dead_letters", [], |row| {
+                row.get(0)
+            })
+            .map_err(|e| format!("Failed to count dead letters: {e}"))
+    }
+
+    /// Dead-lettered deliveries, newest first
+    pub fn webhook_dead_letters(&self) -> Result<Vec<DeadLetter>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT id, url, payload, attempts, last_error, created_at, dead_at
+                 FROM webhook_dead_letters ORDER BY dead_at DESC, id DESC",
+            )
+            .map_err(|e| format!("Failed to list dead letters: {e}"))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(DeadLetter {
+                    id: row.get(0)?,
+                    url: row.get(1)?,
+                    payload: row.get(2)?,
+                    attempts: row.get(3)?,
+                    last_error: row.get(4)?,
+                    created_at: row.get(5)?,
+                    dead_at: row.get(6)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list dead letters: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list dead letters: {e}"))
+    }
+
+    /// Puts a dead letter back in the outbox, due at `now` with a fresh attempt count. Returns
+    /// the URL it will be posted to, or `None` for unknown ids.
+    pub fn redeliver_dead_letter(&self, id: i64, now: u64) -> Result<Option<String>, String> {
+        let tx = self
+            .conn
+            .unchecked_transaction()
+            .map_err(|e| format!("Failed to redeliver dead letter: {e}"))?;
+        let url: Option<String> = tx
+            .query_row(
+                "INSERT INTO webhook_outbox
+                 (id, url, payload, attempts, next_attempt_at, last_error, created_at)
+                 SELECT id, url, payload, 0, ?2, last_error, created_at
+                 FROM webhook_dead_letters WHERE id = ?1
+                 RETURNING url",
+                params![id, now],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| format!("Failed to redeliver dead letter: {e}"))?;
+        tx.execute(
+            "DELETE FROM webhook_dead_letters WHERE id = ?1",
+            params![id],
+        )
+        .and_then(|_| tx.commit())
+        .map_err(|e| format!("Failed to redeliver dead letter: {e}"))?;
+        Ok(url)
+    }
+
+    /// Removes a delivery from the outbox without retrying it
+    pub fn discard_webhook_delivery(&self, id: i64) -> Result<(), String> {
+        self.conn
+            .execute("DELETE FROM webhook_outbox WHERE id = ?1", params![id])
+            .map_err(|e| format!("Failed to discard webhook delivery: {e}"))?;
+        Ok(())
+    }
+
+    /// Appends an event to the history and returns its sequence number
+    pub fn append_event(&self, event_type: &str, payload: &str, at: u64) -> Result<i64, String> {
+        self.conn
+            .execute(
+                "INSERT INTO events (event_type, payload, created_at) VALUES (?1, ?2, ?3)",
+                params![event_type, payload, at],
+            )
+            .map_err(|e| format!("Failed to record event: {e}"))?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Events with `seq >= from_seq`, oldest first
+    pub fn events_from(&self, from_seq: i64) -> Result<Vec<StoredEvent>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT seq, event_type, payload, created_at FROM events
+                 WHERE seq >= ?1 ORDER BY seq",
+            )
+            .map_err(|e| format!("Failed to list events: {e}"))?;
+        let rows = stmt
+            .query_map(params![from_seq], |row| {
+                Ok(StoredEvent {
+                    seq: row.get(0)?,
+                    event_type: row.get(1)?,
+                    payload: row.get(2)?,
+                    created_at: row.get(3)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list events: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list events: {e}"))
+    }
+
+    /// `seq` of the newest event in the history, 0 when it is empty
+    pub fn last_event_seq(&self) -> Result<i64, String> {
+        self.conn
+            .query_row("SELECT COALESCE(MAX(seq), 0) FROM events", [], |row| {
+                row.get(0)
+            })
+            .map_err(|e| format!("Failed to read the last event: {e}"))
+    }
+
+    /// Events of `event_type` recorded at or after `since`, newest first
+    pub fn events(
+        &self,
+        event_type: Option<&str>,
+        since: Option<u64>,
+        limit: u32,
+    ) -> Result<Vec<StoredEvent>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT seq, event_type, payload, created_at FROM events
+                 WHERE (?1 IS NULL OR event_type = ?1) AND (?2 IS NULL OR created_at >= ?2)
+                 ORDER BY seq DESC LIMIT ?3",
+            )
+            .map_err(|e| format!("Failed to list events: {e}"))?;
+        let rows = stmt
+            .query_map(params![event_type, since, limit], |row| {
+                Ok(StoredEvent {
+                    seq: row.get(0)?,
+                    event_type: row.get(1)?,
+                    payload: row.get(2)?,
+                    created_at: row.get(3)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list events: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list events: {e}"))
+    }
+
+    /// Archives an event taken off the wallet queue and holds it for `events-handled`
+    pub fn pull_event(&self, event_type: &str, payload: &str, at: u64) -> Result<i64, String> {
+        let tx = self
+            .conn
+            .unchecked_transaction()
+            .map_err(|e| format!("Failed to record event: {e}"))?;
+        tx.execute(
+            "INSERT INTO events (event_type, payload, created_at) VALUES (?1, ?2, ?3)",
+            params![event_type, payload, at],
+        )
+        .map_err(|e| format!("Failed to record event: {e}"))?;
+        let seq = tx.last_insert_rowid();
+        tx.execute("INSERT INTO pulled_events (seq) VALUES (?1)", params![seq])
+            .and_then(|_| tx.commit())
+            .map_err(|e| format!("Failed to record event: {e}"))?;
+        Ok(seq)
+    }
+
+    /// Pulled events not acknowledged yet, oldest first
+    pub fn pulled_events(&self, limit: u32) -> Result<Vec<StoredEvent>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT e.seq, e.event_type, e.payload, e.created_at
+                 FROM pulled_events p JOIN events e ON e.seq = p.seq
+                 ORDER BY p.seq LIMIT ?1",
+            )
+            .map_err(|e| format!("Failed to list pulled events: {e}"))?;
+        let rows = stmt
+            .query_map(params![limit], |row| {
+                Ok(StoredEvent {
+                    seq: row.get(0)?,
+                    event_type: row.get(1)?,
+                    payload: row.get(2)?,
+                    created_at: row.get(3)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list pulled events: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list pulled events: {e}"))
+    }
+
+    pub fn pulled_event_count(&self) -> Result<u64, String> {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM pulled_events", [], |row| row.get(0))
+            .map_err(|e| format!("Failed to count pulled events: {e}"))
+    }
+
+    /// Drops all but the newest `keep` pulled events, returning how many were dropped. They
+    /// stay in the event history.
+    pub fn drop_oldest_pulled_events(&self, keep: u64) -> Result<usize, String> {
+        self.conn
+            .execute(
+                "DELETE FROM pulled_events
+                 WHERE seq NOT IN (SELECT seq FROM pulled_events ORDER BY seq DESC LIMIT ?1)",
+                params![keep],
+            )
+            .map_err(|e| format!("Failed to drop pulled events: {e}"))
+    }
+
+    /// Drops pulled events recorded before `before`, returning how many were dropped
+    pub fn drop_stale_pulled_events(&self, before: u64) -> Result<usize, String> {
+        self.conn
+            .execute(
+                "DELETE FROM pulled_events
+                 WHERE seq IN (SELECT seq FROM events WHERE created_at < ?1)",
+                params![before],
+            )
+            .map_err(|e| format!("Failed to drop pulled events: {e}"))
+    }
+
+    /// Acknowledges pulled events up to and including `seq`, returning how many there were
+    pub fn ack_pulled_events(&self, up_to: i64) -> Result<usize, String> {
+        self.conn
+            .execute("DELETE FROM pulled_events WHERE seq <= ?1", params![up_to])
+            .map_err(|e| format!("Failed to acknowledge events: {e}"))
+    }
+
+    /// Deletes events recorded before `before`, except pulled events still waiting for an ack
+    pub fn prune_events(&self, before: u64) -> Result<(), String> {
+        self.conn
+            .execute(
+                "DELETE FROM events
+                 WHERE created_at < ?1 AND seq NOT IN (SELECT seq FROM pulled_events)",
+                params![before],
+            )
+            .map_err(|e| format!("Failed to prune events: {e}"))?;
+        Ok(())
+    }
+
+    pub fn log_webhook_attempt(&self, attempt: &WebhookAttempt) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT INTO webhook_attempts
+                 (outbox_id, url, event_type, attempt, status, error, latency_ms, attempted_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    attempt.outbox_id,
+                    attempt.url,
+                    attempt.event_type,
+                    attempt.attempt,
+                    attempt.status,
+                    attempt.error,
+                    attempt.latency_ms,
+                    attempt.attempted_at
+                ],
+            )
+            .map_err(|e| format!("Failed to log webhook attempt: {e}"))?;
+        Ok(())
+    }
+
+    /// Logged webhook attempts, newest first
+    pub fn webhook_attempts(
+        &self,
+        url: Option<&str>,
+        event_type: Option<&str>,
+        failed_only: bool,
+        limit: u32,
+    ) -> Result<Vec<WebhookAttempt>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT outbox_id, url, event_type, attempt, status, error, latency_ms, attempted_at
+                 FROM webhook_attempts
+                 WHERE (?1 IS NULL OR url = ?1) AND (?2 IS NULL OR event_type = ?2)
+                   AND (NOT ?3 OR error IS NOT NULL)
+                 ORDER BY id DESC LIMIT ?4",
+            )
+            .map_err(|e| format!("Failed to list webhook attempts: {e}"))?;
+        let rows = stmt
+            .query_map(params![url, event_type, failed_only, limit], |row| {
+                Ok(WebhookAttempt {
+                    outbox_id: row.get(0)?,
+                    url: row.get(1)?,
+                    event_type: row.get(2)?,
+                    attempt: row.get(3)?,
+                    status: row.get(4)?,
+                    error: row.get(5)?,
+                    latency_ms: row.get(6)?,
+                    attempted_at: row.get(7)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list webhook attempts: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list webhook attempts: {e}"))
+    }
+
+    /// Deletes logged webhook attempts older than `before`
+    pub fn prune_webhook_attempts(&self, before: u64) -> Result<(), String> {
+        self.conn
+            .execute(
+                "DELETE FROM webhook_attempts WHERE attempted_at < ?1",
+                params![before],
+            )
+            .map_err(|e| format!("Failed to prune webhook attempts: {e}"))?;
+        Ok(())
+    }
+
+    pub fn disabled_webhooks(&self) -> Result<Vec<String>, String> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT url FROM webhook_endpoints WHERE disabled_at IS NOT NULL")
+            .map_err(|e| format!("Failed to list disabled webhooks: {e}"))?;
+        let rows = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(|e| format!("Failed to list disabled webhooks: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list disabled webhooks: {e}"))
+    }
+
+    pub fn webhook_endpoints(&self) -> Result<Vec<WebhookEndpoint>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT url, delivered, failed, consecutive_failures, failing_since, last_error,
+                        disabled_at
+                 FROM webhook_endpoints ORDER BY url",
+            )
+            .map_err(|e| format!("Failed to list webhooks: {e}"))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(WebhookEndpoint {
+                    url: row.get(0)?,
+                    delivered: row.get(1)?,
+                    failed: row.get(2)?,
+                    consecutive_failures: row.get(3)?,
+                    failing_since: row.get(4)?,
+                    last_error: row.get(5)?,
+                    disabled_at: row.get(6)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list webhooks: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list webhooks: {e}"))
+    }
+
+    pub fn queue_vault_send(
+        &self,
+        payment: &str,
+        amount_sats: Option<u64>,
+        cost_center: Option<&str>,
+        not_before: u64,
+        created_at: u64,
+    ) -> Result<i64, String> {
+        self.conn
+            .execute(
+                "INSERT INTO vault_sends (payment, amount_sats, cost_center, not_before, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![payment, amount_sats, cost_center, not_before, created_at],
+            )
+            .map_err(|e| format!("Failed to queue vault send: {e}"))?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Vault sends, newest first. `due_at` restricts the list to pending sends due by then.
+    pub fn vault_sends(&self, due_at: Option<u64>) -> Result<Vec<VaultSend>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT id, payment, amount_sats, cost_center, not_before, status, payment_id, error,
+                        created_at, executed_at
+                 FROM vault_sends
+                 WHERE ?1 IS NULL OR (status = 'pending' AND not_before <= ?1)
+                 ORDER BY id DESC",
+            )
+            .map_err(|e| format!("Failed to list vault sends: {e}"))?;
+        let rows = stmt
+            .query_map(params![due_at], |row| {
+                Ok(VaultSend {
+                    id: row.get(0)?,
+                    payment: row.get(1)?,
+                    amount_sats: row.get(2)?,
+                    cost_center: row.get(3)?,
+                    not_before: row.get(4)?,
+                    status: row.get(5)?,
+                    payment_id: row.get(6)?,
+                    error: row.get(7)?,
+                    created_at: row.get(8)?,
+                    executed_at: row.get(9)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list vault sends: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list vault sends: {e}"))
+    }
+
+    /// Moves a vault send from `from` to `to`, returning false if it was not in state `from`
+    pub fn transition_vault_send(&self, id: i64, from: &str, to: &str) -> Result<bool, String> {
+        let changed = self
+            .conn
+            .execute(
+                "UPDATE vault_sends SET status = ?3 WHERE id = ?1 AND status = ?2",
+                params![id, from, to],
+            )
+            .map_err(|e| format!("Failed to update vault send: {e}"))?;
+        Ok(changed > 0)
+    }
+
+    /// Records the result of executing a claimed vault send
+    pub fn finish_vault_send(
+        &self,
+        id: i64,
+        result: Result<&str, &str>,
+        executed_at: u64,
+    ) -> Result<(), String> {
+        let (status, payment_id, error) = match result {
+            Ok(payment_id) => ("executed", Some(payment_id), None),
+            Err(error) => ("failed", None, Some(error)),
+        };
+        self.conn
+            .execute(
+                "UPDATE vault_sends SET status = ?2, payment_id = ?3, error = ?4, executed_at = ?5
+                 WHERE id = ?1",
+                params![id, status, payment_id, error, executed_at],
+            )
+            .map_err(|e| format!("Failed to update vault send: {e}"))?;
+        Ok(())
+    }
+
+    /// Marks an authorization as used, returning false if it already was
+    pub fn claim_authorization(&self, id: &str, pubkey: &str, at: u64) -> Result<bool, String> {
+        let inserted = self
+            .conn
+            .execute(
+                "INSERT OR IGNORE INTO authorizations (id, pubkey, executed_at)
+                 VALUES (?1, ?2, ?3)",
+                params![id, pubkey, at],
+            )
+            .map_err(|e| format!("Failed to claim authorization: {e}"))?;
+        Ok(inserted > 0)
+    }
+
+    pub fn finish_authorization(
+        &self,
+        id: &str,
+        payment_id: &str,
+        amount_sats: u64,
+    ) -> Result<(), String> {
+        self.conn
+            .execute(
+                "UPDATE authorizations SET payment_id = ?2, amount_sats = ?3 WHERE id = ?1",
+                params![id, payment_id, amount_sats],
+            )
+            .map_err(|e| format!("Failed to record authorization payment: {e}"))?;
+        Ok(())
+    }
+
+    /// Frees a claimed authorization whose payment could not be initiated
+    pub fn release_authorization(&self, id: &str) -> Result<(), String> {
+        self.conn
+            .execute("DELETE FROM authorizations WHERE id = ?1", params![id])
+            .map_err(|e| format!("Failed to release authorization: {e}"))?;
+        Ok(())
+    }
+
+    pub fn create_nwc_connection(
+        &self,
+        name: &str,
+        client_pubkey: &str,
+        budget_sats: Option<u64>,
+        budget_renewal: &str,
+        created_at: u64,
+    ) -> Result<i64, String> {
+        self.conn
+            .execute(
+                "INSERT INTO nwc_connections
+                 (name, client_pubkey, budget_sats, budget_renewal, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![name, client_pubkey, budget_sats, budget_renewal, created_at],
+            )
+            .map_err(|e| format!("Failed to create NWC connection: {e}"))?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Every NWC connection, revoked ones included, oldest first
+    pub fn nwc_connections(&self) -> Result<Vec<NwcConnection>, String> {
+        self.query_nwc_connections("1", params![])
+    }
+
+    /// The connection of a client that hasn't been revoked
+    pub fn active_nwc_connection(
+        &self,
+        client_pubkey: &str,
+    ) -> Result<Option<NwcConnection>, String> {
+        let connections = self.query_nwc_connections(
+            "client_pubkey = ?1 AND revoked_at IS NULL",
+            params![client_pubkey],
+        )?;
+        Ok(connections.into_iter().next())
+    }
+
+    fn query_nwc_connections(
+        &self,
+        filter: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<NwcConnection>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!(
+                "SELECT id, name, client_pubkey, budget_sats, budget_renewal, created_at, revoked_at
+                 FROM nwc_connections WHERE {filter} ORDER BY id"
+            ))
+            .map_err(|e| format!("Failed to list NWC connections: {e}"))?;
+        let rows = stmt
+            .query_map(params, |row| {
+                Ok(NwcConnection {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    client_pubkey: row.get(2)?,
+                    budget_sats: row.get(3)?,
+                    budget_renewal: row.get(4)?,
+                    created_at: row.get(5)?,
+                    revoked_at: row.get(6)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list NWC connections: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list NWC connections: {e}"))
+    }
+
+    /// Revokes a connection, returning false if there is no active one with that id
+    pub fn revoke_nwc_connection(&self, id: i64, at: u64) -> Result<bool, String> {
+        let changed = self
+            .conn
+            .execute(
+                "UPDATE nwc_connections SET revoked_at = ?2 WHERE id = ?1 AND revoked_at IS NULL",
+                params![id, at],
+            )
+            .map_err(|e| format!("Failed to revoke NWC connection: {e}"))?;
+        Ok(changed > 0)
+    }
+
+    pub fn create_withdraw_link(
+        &self,
+        k1: &str,
+        amount_sats: u64,
+        uses: u32,
+        description: Option<&str>,
+        created_at: u64,
+    ) -> Result<i64, String> {
+        self.conn
+            .execute(
+                "INSERT INTO withdraw_links (k1, amount_sats, uses, description, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![k1, amount_sats, uses, description, created_at],
+            )
+            .map_err(|e| format!("Failed to create withdraw link: {e}"))?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Every withdraw link, revoked and used up ones included, oldest first
+    pub fn withdraw_links(&self) -> Result<Vec<WithdrawLink>, String> {
+        self.query_withdraw_links("1", params![])
+    }
+
+    pub fn withdraw_link(&self, k1: &str) -> Result<Option<WithdrawLink>, String> {
+        let links = self.query_withdraw_links("k1 = ?1", params![k1])?;
+        Ok(links.into_iter().next())
+    }
+
+    fn query_withdraw_links(
+        &self,
+        filter: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<WithdrawLink>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!(
+                "SELECT id, k1, amount_sats, uses, used, description, created_at, revoked_at
+                 FROM withdraw_links WHERE {filter} ORDER BY id"
+            ))
+            .map_err(|e| format!("Failed to list withdraw links: {e}"))?;
+        let rows = stmt
+            .query_map(params, |row| {
+                Ok(WithdrawLink {
+                    id: row.get(0)?,
+                    k1: row.get(1)?,
+                    amount_sats: row.get(2)?,
+                    uses: row.get(3)?,
+                    used: row.get(4)?,
+                    description: row.get(5)?,
+                    created_at: row.get(6)?,
+                    revoked_at: row.get(7)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list withdraw links: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list withdraw links: {e}"))
+    }
+
+    /// Takes one use of the link with secret `k1`, returning it with the use counted, or
+    /// `None` if it is revoked, used up or unknown
+    pub fn claim_withdraw_use(&self, k1: &str) -> Result<Option<WithdrawLink>, String> {
+        let claimed = self
+            .conn
+            .execute(
+                "UPDATE withdraw_links SET used = used + 1
+                 WHERE k1 = ?1 AND revoked_at IS NULL AND used < uses",
+                params![k1],
+            )
+            .map_err(|e| format!("Failed to claim withdraw link: {e}"))?;
+        if claimed == 0 {
+            return Ok(None);
+        }
+        self.withdraw_link(k1)
+    }
+
+    /// Gives back a use whose payment could not be initiated
+    pub fn release_withdraw_use(&self, id: i64) -> Result<(), String> {
+        self.conn
+            .execute(
+                "UPDATE withdraw_links SET used = used - 1 WHERE id = ?1 AND used > 0",
+                params![id],
+            )
+            .map_err(|e| format!("Failed to release withdraw link: {e}"))?;
+        Ok(())
+    }
+
+    /// Revokes a link, returning false if there is no active one with that id
+    pub fn revoke_withdraw_link(&self, id: i64, at: u64) -> Result<bool, String> {
+        let changed = self
+            .conn
+            .execute(
+                "UPDATE withdraw_links SET revoked_at = ?2 WHERE id = ?1 AND revoked_at IS NULL",
+                params![id, at],
+            )
+            .map_err(|e| format!("Failed to revoke withdraw link: {e}"))?;
+        Ok(changed > 0)
+    }
+
+    pub fn record_heartbeat(&self, at: u64) -> Result<(), String> {
+        self.conn
+            .execute("INSERT INTO heartbeats (at) VALUES (?1)", params![at])
+            .map_err(|e| format!("Failed to record heartbeat: {e}"))?;
+        Ok(())
+    }
+
+    pub fn last_heartbeat(&self) -> Result<Option<u64>, String> {
+        self.conn
+            .query_row("SELECT MAX(at) FROM heartbeats", [], |row| row.get(0))
+            .map_err(|e| format!("Failed to read heartbeat: {e}"))
+    }
+
+    /// Payment id of the sweep made after the heartbeat at `heartbeat_at` went stale, unless it
+    /// failed. The sweep's send is billed to `dead_man_switch:{heartbeat_at}` and recorded before
+    /// it is paid, so a sweep in flight counts even if recording the sweep itself failed.
+    pub fn dead_man_sweep(&self, heartbeat_at: u64) -> Result<Option<String>, String> {
+        self.conn
+            .query_row(
+                "SELECT payment_id FROM sends
+                 WHERE cost_center = 'dead_man_switch:' || ?1 AND status != 'failed'
+                 UNION ALL
+                 SELECT d.payment_id FROM dead_man_sweeps d
+                 LEFT JOIN sends s ON s.payment_id = d.payment_id
+                 WHERE d.heartbeat_at = ?1 AND COALESCE(s.status, '') != 'failed'
+                 LIMIT 1",
+                params![heartbeat_at],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| format!("Failed to read dead man's switch sweep: {e}"))
+    }
+
+    pub fn record_dead_man_sweep(
+        &self,
+        heartbeat_at: u64,
+        payment_id: &str,
+        amount_sats: u64,
+        swept_at: u64,
+    ) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO dead_man_sweeps
+                 (heartbeat_at, payment_id, amount_sats, swept_at)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![heartbeat_at, payment_id, amount_sats, swept_at],
+            )
+            .map_err(|e| format!("Failed to record dead man's switch sweep: {e}"))?;
+        Ok(())
+    }
+
+    pub fn save_snapshot(
+        &self,
+        name: &str,
+        value: &serde_json::Value,
+        updated_at: u64,
+    ) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO snapshots (name, value, updated_at) VALUES (?1, ?2, ?3)",
+                params![name, value.to_string(), updated_at],
+            )
+            .map_err(|e| format!("Failed to save {name} snapshot: {e}"))?;
+        Ok(())
+    }
+
+    /// The latest snapshot saved under `name` and when it was taken
+    pub fn snapshot(&self, name: &str) -> Result<Option<(serde_json::Value, u64)>, String> {
+        let row: Option<(String, u64)> = self
+            .conn
+            .query_row(
+                "SELECT value, updated_at FROM snapshots WHERE name = ?1",
+                params![name],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| format!("Failed to read {name} snapshot: {e}"))?;
+        row.map(|(value, updated_at)| {
+            serde_json::from_str(&value)
+                .map(|v| (v, updated_at))
+                .map_err(|e| format!("Corrupt {name} snapshot: {e}"))
+        })
+        .transpose()
+    }
+
+    pub fn issued_addresses(&self, purpose: Option<&str>) -> Result<Vec<IssuedAddress>, String> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT v.address, GROUP_CONCAT(DISTINCT v.purpose), COUNT(DISTINCT v.id),
+                        MIN(v.created_at), MAX(v.created_at), COALESCE(SUM(r.amount_msat), 0)
+                 FROM receives v LEFT JOIN receipts r ON r.payment_hash = v.payment_hash
+                 WHERE v.address IS NOT NULL AND (?1 IS NULL OR v.purpose = ?1)
+                 GROUP BY v.address
+                 ORDER BY MIN(v.created_at)",
+            )
+            .map_err(|e| format!("Failed to list addresses: {e}"))?;
+        let rows = stmt
+            .query_map(params![purpose], |row| {
+                let purposes: Option<String> = row.get(1)?;
+                Ok(IssuedAddress {
+                    address: row.get(0)?,
+                    purposes: purposes
+                        .map(|p| p.split(',').map(str::to_string).collect())
+                        .unwrap_or_default(),
+                    times_issued: row.get(2)?,
+                    first_issued: row.get(3)?,
+                    last_issued: row.get(4)?,
+                    invoice_received_msat: row.get(5)?,
+                })
+            })
+            .map_err(|e| format!("Failed to list addresses: {e}"))?;
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to list addresses: {e}"))
+    }
+}
+
+/// Gives back the withdraw link use a send paid out of, the first time it is marked failed
+fn release_failed_withdraw(conn: &Connection, payment_id: &str) -> Result<(), String> {
+    conn.execute(
+        "UPDATE withdraw_links SET used = used - 1
+         WHERE used > 0 AND 'withdraw:' || id = (
+           SELECT cost_center FROM sends WHERE payment_id = ?1 AND status != 'failed'
+         )",
+        params![payment_id],
+    )
+    .map_err(|e| format!("Failed to release withdraw link: {e}"))?;
+    Ok(())
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use serde_json::json;
+
+/// Parses an IANA time zone name such as "UTC" or "Europe/Berlin"
+pub fn parse(name: &str) -> Result<Tz, String> {
+    name.parse()
+        .map_err(|_| format!("Unknown time zone: {name} (use an IANA name like Europe/Berlin)"))
+}
+
+/// Parses a unix timestamp, an RFC 3339 time or a date, taken as midnight in `tz`
+pub fn parse_time(value: &str, tz: Tz) -> Result<u64, String> {
+    let invalid = || {
+        format!("Invalid time: {value} (use a date like 2026-01-31, RFC 3339 or a unix timestamp)")
+    };
+    if let Ok(secs) = value.parse() {
+        return Ok(secs);
+    }
+    let time = match DateTime::parse_from_rfc3339(value) {
+        Ok(time) => time.timestamp(),
+        Err(_) => NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0)?.and_local_timezone(tz).earliest())
+            .ok_or_else(invalid)?
+            .timestamp(),
+    };
+    u64::try_from(time).map_err(|_| invalid())
+}
+
+/// Adds `<key>_iso`, the ISO 8601 time in `tz`, after every epoch-seconds timestamp field
+pub fn annotate(value: &mut serde_json::Value, tz: Tz) {
+    match value {
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|v| annotate(v, tz)),
+        serde_json::Value::Object(fields) => {
+            for (key, mut field) in std::mem::take(fields) {
+                annotate(&mut field, tz);
+                let iso = field
+                    .as_u64()
+                    .filter(|_| is_timestamp_key(&key))
+                    .and_then(|secs| iso8601(secs, tz));
+                if let Some(iso) = iso {
+                    fields.insert(key.clone(), field);
+                    fields.insert(format!("{key}_iso"), json!(iso));
+                } else {
+                    fields.insert(key, field);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Field names that hold unix timestamps in seconds across the CLI's output
+pub fn is_timestamp_key(key: &str) -> bool {
+    key == "timestamp"
+        || key.ends_with("_at")
+        || matches!(
+            key,
+            "not_before"
+                | "first_issued"
+                | "last_issued"
+                | "failing_since"
+                | "sweep_after"
+                | "last_heartbeat"
+                | "before"
+        )
+}
+
+fn iso8601(secs: u64, tz: Tz) -> Option<String> {
+    let utc = DateTime::<Utc>::from_timestamp(i64::try_from(secs).ok()?, 0)?;
+    Some(
+        utc.with_timezone(&tz)
+            .to_rfc3339_opts(SecondsFormat::Secs, true),
+    )
+}
@@ -0,0 +1,847 @@
+use crate::config::{Severity, WebhookEndpointConfig, WebhooksConfig};
+use crate::exec::ExecHooks;
+use crate::mqtt::Mqtt;
+use crate::plugin::Plugins;
+use crate::sink::Sink;
+use crate::store::{Store, WebhookAttempt};
+use orange_sdk::bitcoin::hashes::{Hash, HashEngine, hmac, sha256};
+use orange_sdk::bitcoin::hex::DisplayHex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc};
+use tokio::task::JoinHandle;
+
+/// A webhook endpoint given on the command line as "url", "url|token" or "url|token|secret",
+/// or in `[webhooks] endpoints`
+pub struct Webhook {
+    pub url: String,
+    pub token: Option<String>,
+    /// Key for the `X-Orange-Signature` HMAC
+    pub secret: Option<String>,
+    pub headers: HeaderMap,
+    /// Username and password for `Authorization: Basic`
+    pub basic_auth: Option<(String, Option<String>)>,
+    /// Own client presenting a certificate for mutual TLS, instead of the shared one
+    pub client: Option<reqwest::Client>,
+    /// Own rate limit instead of `[webhooks] max_per_second`
+    pub max_per_second: Option<f64>,
+    /// Broker published to instead of POSTing, for `nats://` and `kafka://` URLs
+    pub sink: Option<Arc<Sink>>,
+    /// Posted events less urgent than this skip the endpoint
+    pub min_severity: Severity,
+}
+
+impl Webhook {
+    pub fn parse(spec: &str) -> Self {
+        let mut parts = spec.splitn(3, '|');
+        let url = parts.next().unwrap_or_default().to_string();
+        let mut next = || parts.next().filter(|s| !s.is_empty()).map(str::to_string);
+        let token = next();
+        let secret = next();
+        let sink = Sink::from_url(&url).map(Arc::new);
+        Webhook {
+            url,
+            token,
+            secret,
+            headers: HeaderMap::new(),
+            basic_auth: None,
+            client: None,
+            max_per_second: None,
+            sink,
+            min_severity: Severity::Info,
+        }
+    }
+
+    /// Whether a posted event goes to this endpoint, by its `severity`
+    fn accepts(&self, event: &serde_json::Value) -> bool {
+        let severity = match event["severity"].as_str() {
+            Some("critical") => Severity::Critical,
+            Some("warning") => Severity::Warning,
+            _ => Severity::Info,
+        };
+        severity >= self.min_severity
+    }
+
+    pub fn from_config(
+        endpoint: &WebhookEndpointConfig,
+        config: &WebhooksConfig,
+    ) -> Result<Self, String> {
+        let table = match endpoint {
+            WebhookEndpointConfig::Spec(spec) => return Ok(Webhook::parse(spec)),
+            WebhookEndpointConfig::Table(table) => table,
+        };
+        let url = &table.url;
+        let sink = Sink::from_url(url).map(Arc::new);
+        if sink.is_some()
+            && (table.token.is_some()
+                || table.basic_auth.is_some()
+                || !table.headers.is_empty()
+                || table.client_cert.is_some())
+        {
+            return Err(format!(
+                "Webhook {url}: token, headers, basic_auth and client_cert don't apply to a broker, put credentials in the URL"
+            ));
+        }
+        if table.token.is_some() && table.basic_auth.is_some() {
+            return Err(format!(
+                "Webhook {url}: token and basic_auth both set the Authorization header"
+            ));
+        }
+        let mut headers = HeaderMap::new();
+        for (name, value) in &table.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Webhook {url}: invalid header name {name}"))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| format!("Webhook {url}: invalid value for header {name}"))?;
+            headers.insert(name, value);
+        }
+        let client = match (&table.client_cert, &table.client_key) {
+            (Some(cert), Some(key)) => {
+                let read = |path: &std::path::Path| {
+                    std::fs::read(path).map_err(|e| {
+                        format!("Webhook {url}: failed to read {}: {e}", path.display())
+                    })
+                };
+                let mut pem = read(cert)?;
+                pem.push(b'\n');
+                pem.extend(read(key)?);
+                let identity = reqwest::Identity::from_pem(&pem)
+                    .map_err(|e| format!("Webhook {url}: invalid client certificate: {e}"))?;
+                Some(client(config, Some(identity))?)
+            }
+            (None, None) => None,
+            _ => {
+                return Err(format!(
+                    "Webhook {url}: client_cert and client_key must be set together"
+                ));
+            }
+        };
+        Ok(Webhook {
+            url: url.clone(),
+            token: table.token.clone(),
+            secret: table.secret.clone(),
+            headers,
+            basic_auth: table
+                .basic_auth
+                .as_ref()
+                .map(|b| (b.username.clone(), b.password.clone())),
+            client,
+            max_per_second: table.max_per_second,
+            sink,
+            min_severity: table.min_severity,
+        })
+    }
+}
+
+/// HTTP client for webhook POSTs. Connections are pooled per host, keeping as many idle as may
+/// be in flight.
+fn client(
+    config: &WebhooksConfig,
+    identity: Option<reqwest::Identity>,
+) -> Result<reqwest::Client, String> {
+    let timeout = Duration::from_secs(config.timeout_secs);
+    let mut builder = reqwest::Client::builder()
+        .timeout(timeout)
+        .connect_timeout(timeout.min(Duration::from_secs(10)))
+        .pool_max_idle_per_host(config.max_concurrent);
+    if let Some(identity) = identity {
+        builder = builder.identity(identity);
+    }
+    builder
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {e}"))
+}
+
+/// Hex HMAC-SHA256 of "{timestamp}.{body}", binding the signature to the delivery time
+fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(secret.as_bytes());
+    engine.input(timestamp.to_string().as_bytes());
+    engine.input(b".");
+    engine.input(body);
+    hmac::Hmac::<sha256::Hash>::from_engine(engine)
+        .to_byte_array()
+        .to_lower_hex_string()
+}
+
+/// How much longer than the request timeout a delivery stays claimed by an in-flight POST
+/// before it is retried
+const LEASE_MARGIN_SECS: u64 = 30;
+
+/// Token bucket allowing `rate` POSTs per second, in bursts of up to one second's worth
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64) -> Self {
+        Bucket {
+            tokens: rate.max(1.0),
+            refilled: Instant::now(),
+        }
+    }
+
+    fn take(&mut self, rate: f64) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate.max(1.0));
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// What `Dispatcher::post` started for an event
+#[derive(Default)]
+pub struct Posted {
+    pub outbox_ids: Vec<i64>,
+    /// Finishes once the exec hooks are done with the event
+    pub exec: Option<JoinHandle<()>>,
+}
+
+/// Posts events to the configured webhooks through a persistent outbox. Deliveries are retried
+/// with exponential backoff until they succeed, also across restarts.
+pub struct Dispatcher {
+    client: reqwest::Client,
+    hooks: Vec<Webhook>,
+    /// Only notified through `post_contacts`, e.g. the dead man's switch contacts
+    contacts: Vec<Webhook>,
+    disable_after_secs: u64,
+    retry_base_secs: u64,
+    retry_max_secs: u64,
+    max_attempts: u32,
+    lease_secs: u64,
+    /// Limit on a broker publish, the HTTP client has its own
+    timeout: Duration,
+    max_concurrent: usize,
+    /// Permits for in-flight POSTs, one semaphore per URL
+    in_flight: Mutex<HashMap<String, Arc<Semaphore>>>,
+    max_per_second: f64,
+    /// Token buckets of the rate-limited endpoints, by URL
+    rate: Mutex<HashMap<String, Bucket>>,
+    /// Set when a delivery was left in the outbox for lack of a token, so the daemon retries
+    /// sooner than its usual retry interval
+    throttled: AtomicBool,
+    /// Local commands run on every posted event, alongside the webhooks
+    exec_hooks: ExecHooks,
+    /// Plugin processes every posted event is streamed to
+    plugins: Plugins,
+    /// `[mqtt]` broker every posted event is published to
+    mqtt: Option<Mqtt>,
+    /// `[webhooks] severity` overrides of the default severities
+    severity: HashMap<String, Severity>,
+    /// Posted events waiting for `flush`, only used when batching
+    batch: Mutex<Vec<serde_json::Value>>,
+    batch_max_events: usize,
+    results: mpsc::UnboundedSender<WebhookAttempt>,
+}
+
+impl Dispatcher {
+    /// Returns the dispatcher and the receiver its delivery results arrive on
+    pub fn new(
+        hooks: Vec<Webhook>,
+        contacts: Vec<Webhook>,
+        config: &WebhooksConfig,
+    ) -> (Self, mpsc::UnboundedReceiver<WebhookAttempt>) {
+        let (results, receiver) = mpsc::unbounded_channel();
+        let client = client(config, None).unwrap_or_default();
+        let dispatcher = Dispatcher {
+            client,
+            hooks,
+            contacts,
+            disable_after_secs: config.disable_after_hours * 3600,
+            retry_base_secs: config.retry_base_secs,
+            retry_max_secs: config.retry_max_secs,
+            max_attempts: config.max_attempts,
+            lease_secs: config.timeout_secs + LEASE_MARGIN_SECS,
+            timeout: Duration::from_secs(config.timeout_secs),
+            max_concurrent: config.max_concurrent.max(1),
+            in_flight: Mutex::new(HashMap::new()),
+            max_per_second: config.max_per_second,
+            rate: Mutex::new(HashMap::new()),
+            throttled: AtomicBool::new(false),
+            exec_hooks: ExecHooks::default(),
+            plugins: Plugins::default(),
+            mqtt: None,
+            severity: config.severity.clone(),
+            batch: Mutex::new(Vec::new()),
+            batch_max_events: if config.batch_window_ms > 0 {
+                config.batch_max_events.max(1)
+            } else {
+                0
+            },
+            results,
+        };
+        (dispatcher, receiver)
+    }
+
+    pub fn hooks(&self) -> &[Webhook] {
+        &self.hooks
+    }
+
+    /// Replaces the webhooks new events are posted to. Deliveries already queued for a removed
+    /// endpoint stay in the outbox until it is added back.
+    pub fn set_hooks(&mut self, hooks: Vec<Webhook>) {
+        self.hooks = hooks;
+    }
+
+    /// Whether posted events go anywhere, to a webhook, an exec hook, a plugin or MQTT
+    pub fn has_hooks(&self) -> bool {
+        !self.hooks.is_empty()
+            || !self.exec_hooks.is_empty()
+            || !self.plugins.is_empty()
+            || self.mqtt.is_some()
+    }
+
+    pub fn exec_hooks(&self) -> &ExecHooks {
+        &self.exec_hooks
+    }
+
+    pub fn set_exec_hooks(&mut self, exec_hooks: ExecHooks) {
+        self.exec_hooks = exec_hooks;
+    }
+
+    pub fn plugins(&self) -> &Plugins {
+        &self.plugins
+    }
+
+    /// Replaces the plugins, the old ones get their stdin closed and are asked to exit
+    pub fn set_plugins(&mut self, plugins: Plugins) {
+        self.plugins = plugins;
+    }
+
+    pub fn mqtt(&self) -> Option<&Mqtt> {
+        self.mqtt.as_ref()
+    }
+
+    pub fn set_mqtt(&mut self, mqtt: Mqtt) {
+        self.mqtt = Some(mqtt);
+    }
+
+    /// Numbers `value` with the next `seq` in the event history, then queues it for every
+    /// enabled webhook and starts delivering it right away, unless the webhook already has
+    /// `max_concurrent` POSTs in flight. Returns the outbox ids of the queued deliveries and
+    /// the task running the matching exec hooks. Plugins and MQTT get the event too, neither
+    /// has an outbox.
+    ///
+    /// When batching, the event is held for the next `flush` instead and no ids are returned,
+    /// unless it fills the batch.
+    pub fn post(&self, store: &Store, value: &serde_json::Value) -> Posted {
+        let mut value = value.clone();
+        let event_type = value["type"].as_str().unwrap_or_default().to_string();
+        let severity = self
+            .severity
+            .get(&event_type)
+            .copied()
+            .unwrap_or_else(|| severity(&value));
+        value["severity"] = json!(severity.as_str());
+        match store.append_event(&event_type, &value.to_string(), crate::now_secs()) {
+            Ok(seq) => value["seq"] = json!(seq),
+            Err(e) => error!("{e}"),
+        }
+        let exec = self.exec_hooks.run(&value);
+        self.plugins.send(&value);
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.publish(&value);
+        }
+        let outbox_ids = if !self.is_batching() {
+            let hooks = self.hooks.iter().filter(|hook| hook.accepts(&value));
+            self.enqueue(store, hooks, &value).0
+        } else {
+            let full = {
+                let mut batch = self.batch.lock().unwrap();
+                batch.push(value);
+                batch.len() >= self.batch_max_events
+            };
+            if full { self.flush(store) } else { Vec::new() }
+        };
+        Posted { outbox_ids, exec }
+    }
+
+    /// Whether `[webhooks] batch_window_ms` coalesces posted events into one POST
+    pub fn is_batching(&self) -> bool {
+        self.batch_max_events > 0
+    }
+
+    /// Queues the events held for batching as one delivery per webhook, with a JSON array of
+    /// the events it accepts as its body
+    pub fn flush(&self, store: &Store) -> Vec<i64> {
+        let events = std::mem::take(&mut *self.batch.lock().unwrap());
+        let mut queued = Vec::new();
+        for hook in &self.hooks {
+            let accepted: Vec<_> = events
+                .iter()
+                .filter(|event| hook.accepts(event))
+                .cloned()
+                .collect();
+            if accepted.is_empty() {
+                continue;
+            }
+            let batch = serde_json::Value::Array(accepted);
+            queued.extend(self.enqueue(store, [hook], &batch).0);
+        }
+        queued
+    }
+
+    /// Like `post`, but for payloads outside the event history (tests and replays). Returns
+    /// the number of deliveries started.
+    pub fn deliver(&self, store: &Store, value: &serde_json::Value) -> usize {
+        self.enqueue(store, &self.hooks, value).1
+    }
+
+    pub fn post_contacts(&self, store: &Store, value: &serde_json::Value) -> usize {
+        self.enqueue(store, &self.contacts, value).1
+    }
+
+    /// Whether a failed `attempt` moves the delivery to the dead-letter queue
+    pub fn is_last_attempt(&self, attempt: u32) -> bool {
+        self.max_attempts > 0 && attempt >= self.max_attempts
+    }
+
+    /// Returns the outbox ids of the queued deliveries and how many of them were started
+    fn enqueue<'a>(
+        &self,
+        store: &Store,
+        hooks: impl IntoIterator<Item = &'a Webhook>,
+        value: &serde_json::Value,
+    ) -> (Vec<i64>, usize) {
+        let disabled = store.disabled_webhooks().unwrap_or_else(|e| {
+            error!("{e}");
+            Vec::new()
+        });
+        let payload = value.to_string();
+        let now = crate::now_secs();
+        let mut queued = Vec::new();
+        let mut started = 0;
+        for hook in hooks.into_iter().filter(|h| !disabled.contains(&h.url)) {
+            // Without a permit the delivery is left due, the next `retry_due` picks it up
+            let permit = self.permit(hook);
+            let lease_until = match permit {
+                Some(_) => now + self.lease_secs,
+                None => now,
+            };
+            match (
+                store.enqueue_webhook(&hook.url, &payload, now, lease_until),
+                permit,
+            ) {
+                (Ok(id), Some(permit)) => {
+                    self.attempt(hook, id, 1, payload.clone(), permit);
+                    queued.push(id);
+                    started += 1;
+                }
+                (Ok(id), None) => queued.push(id),
+                (Err(e), _) => error!("{e}"),
+            }
+        }
+        (queued, started)
+    }
+
+    /// Starts another attempt for every queued delivery whose backoff has elapsed
+    pub fn retry_due(&self, store: &Store, now: u64) {
+        self.throttled.store(false, Ordering::Relaxed);
+        let due = match store.due_webhook_deliveries(now) {
+            Ok(d) => d,
+            Err(e) => {
+                error!("{e}");
+                return;
+            }
+        };
+        let disabled = store.disabled_webhooks().unwrap_or_default();
+        for pending in due {
+            // Endpoints removed from the command line or config keep their queue until they come back
+            let Some(hook) = self
+                .hooks
+                .iter()
+                .chain(&self.contacts)
+                .find(|h| h.url == pending.url)
+            else {
+                continue;
+            };
+            if disabled.contains(&hook.url) {
+                continue;
+            }
+            let Some(permit) = self.permit(hook) else {
+                continue;
+            };
+            if let Err(e) = store.lease_webhook_delivery(pending.id, now + self.lease_secs) {
+                error!("{e}");
+                continue;
+            }
+            self.attempt(
+                hook,
+                pending.id,
+                pending.attempts + 1,
+                pending.payload,
+                permit,
+            );
+        }
+    }
+
+    /// Whether deliveries are waiting for a rate limit, see `retry_due`
+    pub fn is_throttled(&self) -> bool {
+        self.throttled.load(Ordering::Relaxed)
+    }
+
+    /// Takes one of the `max_concurrent` slots for POSTs to the webhook if one is free, and a
+    /// token if it is rate limited
+    fn permit(&self, hook: &Webhook) -> Option<OwnedSemaphorePermit> {
+        let permit = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            let semaphore = in_flight
+                .entry(hook.url.clone())
+                .or_insert_with(|| Arc::new(Semaphore::new(self.max_concurrent)));
+            semaphore.clone().try_acquire_owned().ok()?
+        };
+        let rate = hook.max_per_second.unwrap_or(self.max_per_second);
+        if rate <= 0.0 {
+            return Some(permit);
+        }
+        let mut buckets = self.rate.lock().unwrap();
+        let bucket = buckets
+            .entry(hook.url.clone())
+            .or_insert_with(|| Bucket::new(rate));
+        if bucket.take(rate) {
+            Some(permit)
+        } else {
+            self.throttled.store(true, Ordering::Relaxed);
+            None
+        }
+    }
+
+    fn attempt(
+        &self,
+        hook: &Webhook,
+        outbox_id: i64,
+        attempt: u32,
+        payload: String,
+        permit: OwnedSemaphorePermit,
+    ) {
+        let client = hook.client.as_ref().unwrap_or(&self.client).clone();
+        let url = hook.url.clone();
+        let token = hook.token.clone();
+        let secret = hook.secret.clone();
+        let headers = hook.headers.clone();
+        let basic_auth = hook.basic_auth.clone();
+        let sink = hook.sink.clone();
+        let timeout = self.timeout;
+        let parsed = serde_json::from_str::<serde_json::Value>(&payload).ok();
+        let event_type = match &parsed {
+            Some(serde_json::Value::Array(_)) => "batch".to_string(),
+            Some(v) => v["type"].as_str().unwrap_or_default().to_string(),
+            None => String::new(),
+        };
+        // Brokers key a message by its event's `seq`, a batch has none
+        let key = parsed
+            .and_then(|v| v["seq"].as_i64())
+            .map(|seq| seq.to_string());
+        let body = payload.into_bytes();
+        let results = self.results.clone();
+        tokio::spawn(async move {
+            let timestamp = crate::now_secs();
+            let started = Instant::now();
+            let (status, error) = match sink {
+                Some(sink) => {
+                    let mut headers = vec![("X-Orange-Timestamp", timestamp.to_string())];
+                    if let Some(ref s) = secret {
+                        headers.push((
+                            "X-Orange-Signature",
+                            format!("sha256={}", sign(s, timestamp, &body)),
+                        ));
+                    }
+                    let publish = sink.publish(outbox_id, key, &headers, body);
+                    match tokio::time::timeout(timeout, publish).await {
+                        Ok(Ok(())) => (None, None),
+                        Ok(Err(e)) => (None, Some(e)),
+                        Err(_) => (
+                            None,
+                            Some(format!("timed out after {}s", timeout.as_secs())),
+                        ),
+                    }
+                }
+                None => {
+                    let mut req = client
+                        .post(&url)
+                        .headers(headers)
+                        .header(reqwest::header::CONTENT_TYPE, "application/json")
+                        .header("X-Orange-Timestamp", timestamp);
+                    if let Some(ref s) = secret {
+                        req = req.header(
+                            "X-Orange-Signature",
+                            format!("sha256={}", sign(s, timestamp, &body)),
+                        );
+                    }
+                    if let Some(ref t) = token {
+                        req = req.bearer_auth(t);
+                    }
+                    if let Some((ref username, ref password)) = basic_auth {
+                        req = req.basic_auth(username, password.as_ref());
+                    }
+                    let req = req.body(body);
+                    match req.send().await {
+                        Ok(resp) if !resp.status().is_success() => (
+                            Some(resp.status().as_u16()),
+                            Some(format!("returned {}", resp.status())),
+                        ),
+                        Ok(resp) => (Some(resp.status().as_u16()), None),
+                        Err(e) => (None, Some(format!("failed: {e}"))),
+                    }
+                }
+            };
+            // Freed before the result is reported, so the next delivery can start right away
+            drop(permit);
+            match &error {
+                Some(e) => warn!(
+                    { "url": url, "event_type": event_type, "status": status, "attempt": attempt },
+                    "Webhook {url} {e} (attempt {attempt})"
+                ),
+                None => debug!(
+                    { "url": url, "event_type": event_type, "status": status, "attempt": attempt },
+                    "Webhook {url} delivered {event_type}"
+                ),
+            }
+            let _ = results.send(WebhookAttempt {
+                outbox_id,
+                url,
+                event_type,
+                attempt,
+                status,
+                latency_ms: started.elapsed().as_millis() as u64,
+                attempted_at: timestamp,
+                error,
+            });
+        });
+    }
+
+    /// Settles the outbox entry and updates the endpoint's failure streak, disabling the
+    /// endpoint once it has failed for too long. Deliveries out of attempts are dead-lettered.
+    pub fn record(&self, store: &Store, delivery: WebhookAttempt, now: u64) {
+        if let Err(e) = store.log_webhook_attempt(&delivery) {
+            error!("{e}");
+        }
+        let outbox = match delivery.error {
+            None => store.webhook_delivery_succeeded(delivery.outbox_id),
+            Some(ref error) if self.is_last_attempt(delivery.attempt) => {
+                error!(
+                    {
+                        "url": delivery.url,
+                        "event_type": delivery.event_type,
+                        "status": delivery.status,
+                        "attempt": delivery.attempt,
+                        "delivery_id": delivery.outbox_id,
+                    },
+                    "[{now}] Webhook delivery {} to {} dead-lettered after {} attempts, redeliver with `orange webhooks dead-letter redeliver {}`",
+                    delivery.outbox_id,
+                    delivery.url,
+                    delivery.attempt,
+                    delivery.outbox_id
+                );
+                store.dead_letter_webhook(delivery.outbox_id, delivery.attempt, error, now)
+            }
+            Some(ref error) => {
+                let backoff = self
+                    .retry_base_secs
+                    .saturating_mul(1 << (delivery.attempt - 1).min(32))
+                    .min(self.retry_max_secs);
+                store.webhook_delivery_failed(
+                    delivery.outbox_id,
+                    delivery.attempt,
+                    error,
+                    now + backoff,
+                )
+            }
+        };
+        if let Err(e) = outbox {
+            error!("{e}");
+        }
+
+        let failing_since = match delivery.error {
+            None => store.webhook_delivered(&delivery.url).map(|_| None),
+            Some(ref error) => store.webhook_failed(&delivery.url, error, now).map(Some),
+        };
+        let failing_since = match failing_since {
+            Ok(Some(since)) => since,
+            Ok(None) => return,
+            Err(e) => {
+                error!("{e}");
+                return;
+            }
+        };
+        if self.disable_after_secs == 0
+            || now.saturating_sub(failing_since) < self.disable_after_secs
+        {
+            return;
+        }
+
+        match store.disable_webhook(&delivery.url, now) {
+            // Already disabled by an earlier in-flight delivery
+            Ok(false) => return,
+            Ok(true) => {}
+            Err(e) => {
+                error!("{e}");
+                return;
+            }
+        }
+        error!(
+            { "url": delivery.url, "failing_since": failing_since },
+            "[{now}] Webhook {} disabled after failing since {failing_since}, re-enable with `orange webhooks enable {}`",
+            delivery.url,
+            delivery.url
+        );
+        let alert = json!({
+            "type": "webhook_disabled",
+            "timestamp": now,
+            "url": delivery.url,
+            "failing_since": failing_since,
+            "last_error": delivery.error,
+        });
+        self.post(store, &alert);
+    }
+}
+
+/// Default severity of a posted event: critical when funds may be at stake and someone should
+/// look now, warning when something went wrong, info otherwise
+fn severity(event: &serde_json::Value) -> Severity {
+    match event["type"].as_str().unwrap_or_default() {
+        "dead_man_switch_triggered" => Severity::Critical,
+        // A force close locks funds until its timelocks expire
+        "channel_closed" => match event["reason"].as_str() {
+            Some(reason) if reason.contains("Cooperative") => Severity::Warning,
+            _ => Severity::Critical,
+        },
+        "channel_usable_changed" if event["is_usable"] == false => Severity::Warning,
+        "payment_failed" | "vault_send_failed" | "lsp_fee_exceeded" | "fiat_drift"
+        | "events_dropped" | "webhook_disabled" => Severity::Warning,
+        _ => Severity::Info,
+    }
+}
+
+/// Event types `webhooks test` can fake
+pub const SAMPLE_EVENT_TYPES: &[&str] = &[
+    "payment_received",
+    "payment_successful",
+    "payment_failed",
+    "onchain_payment_received",
+    "channel_opened",
+    "channel_closed",
+    "channel_pending",
+    "channel_ready",
+    "channel_usable_changed",
+    "invoice_created",
+    "invoice_expired",
+];
+
+/// A made-up event of `event_type` shaped like the daemon's real ones, marked `"test": true`
+pub fn sample_event(event_type: &str, timestamp: u64) -> Result<serde_json::Value, String> {
+    // Distinct per call, so receivers that deduplicate don't drop repeated tests
+    let hex = |tag: &str| {
+        let mut engine = sha256::Hash::engine();
+        engine.input(tag.as_bytes());
+        engine.input(&timestamp.to_be_bytes());
+        sha256::Hash::from_engine(engine)
+            .to_byte_array()
+            .to_lower_hex_string()
+    };
+    let node_id = format!("02{}", hex("node_id"));
+    let fields = match event_type {
+        "payment_received" => json!({
+            "payment_id": hex("payment_id"),
+            "payment_hash": hex("payment_hash"),
+            "amount_msat": 21_000_000,
+            "amount_sats": 21_000,
+            "custom_records_count": 0,
+            "custom_records": [],
+            "lsp_fee_msats": null,
+            "invoice": {
+                "receive_id": 1,
+                "invoice": "lnbc210u1p...",
+                "purpose": "test",
+                "meta": { "order_id": "test" },
+                "amount_sats": 21_000,
+                "fiat_amount": null,
+                "currency": null,
+                "created_at": timestamp,
+                "expires_at": timestamp + 86400,
+            },
+            "zap": null,
+        }),
+        "payment_successful" => json!({
+            "payment_id": hex("payment_id"),
+            "payment_hash": hex("payment_hash"),
+            "payment_preimage": hex("payment_preimage"),
+            "fee_paid_msat": 3_000,
+        }),
+        "payment_failed" => json!({
+            "payment_id": hex("payment_id"),
+            "payment_hash": hex("payment_hash"),
+            "reason": "RouteNotFound",
+        }),
+        "onchain_payment_received" => json!({
+            "payment_id": hex("payment_id"),
+            "txid": hex("txid"),
+            "amount_sat": 100_000,
+            "status": "Confirmed",
+        }),
+        "channel_opened" => json!({
+            "channel_id": hex("channel_id"),
+            "user_channel_id": "UserChannelId(1)",
+            "counterparty_node_id": node_id,
+            "funding_txo": format!("{}:0", hex("txid")),
+        }),
+        "channel_closed" => json!({
+            "channel_id": hex("channel_id"),
+            "user_channel_id": "UserChannelId(1)",
+            "counterparty_node_id": node_id,
+            "reason": "CounterpartyInitiatedCooperativeClosure",
+        }),
+        "channel_pending" | "channel_ready" | "channel_usable_changed" => json!({
+            "channel_id": hex("channel_id"),
+            "counterparty_node_id": node_id,
+            "funding_txo": format!("{}:0", hex("txid")),
+            "is_channel_ready": event_type != "channel_pending",
+            "is_usable": event_type == "channel_ready",
+            "inbound_capacity_sats": 400_000,
+            "outbound_capacity_sats": 100_000,
+            "channel_value_sats": 500_000,
+        }),
+        "invoice_created" => json!({
+            "receive_id": 1,
+            "payment_hash": hex("payment_hash"),
+            "invoice": "lnbc210u1p...",
+            "address": "bc1q...",
+            "amount_sats": 21_000,
+            "purpose": "test",
+            "meta": { "order_id": "test" },
+            "fiat_amount": null,
+            "currency": null,
+            "expires_at": timestamp + 86400,
+        }),
+        "invoice_expired" => json!({
+            "receive_id": 1,
+            "payment_hash": hex("payment_hash"),
+            "address": "bc1q...",
+            "amount_sats": 21_000,
+            "purpose": "test",
+            "meta": { "order_id": "test" },
+            "expires_at": timestamp,
+        }),
+        other => {
+            return Err(format!(
+                "Unknown event type: {other}, supported: {}",
+                SAMPLE_EVENT_TYPES.join(", ")
+            ));
+        }
+    };
+    let mut value = json!({ "type": event_type, "timestamp": timestamp });
+    if let (Some(obj), serde_json::Value::Object(fields)) = (value.as_object_mut(), fields) {
+        obj.extend(fields);
+    }
+    value["test"] = json!(true);
+    Ok(value)
+}
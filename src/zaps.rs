@@ -0,0 +1,152 @@
+use nostr_sdk::{Client, Event, EventBuilder, JsonUtil, Keys, Kind, PublicKey};
+use serde_json::{Value, json};
+
+/// Relays of a zap request that get its receipt, a request listing more only gets the first
+const MAX_REQUEST_RELAYS: usize = 10;
+
+/// The first custom record of a payment holding a signed NIP-57 zap request (kind 9734), as
+/// sent with keysend zaps. Validity for this wallet is checked by `Zaps::publish_receipt`.
+pub fn find_request<'a>(records: impl IntoIterator<Item = &'a [u8]>) -> Option<Event> {
+    records
+        .into_iter()
+        .find_map(|value| parse_request(std::str::from_utf8(value).ok()?))
+}
+
+/// `json` as a signed zap request
+pub fn parse_request(json: &str) -> Option<Event> {
+    let event = Event::from_json(json).ok()?;
+    (event.kind == Kind::ZapRequest && event.verify().is_ok()).then_some(event)
+}
+
+/// What the `zap` field of a `payment_received` event says about the request
+pub fn metadata(request: &Event) -> Value {
+    json!({
+        "request_id": request.id.to_hex(),
+        "sender_pubkey": request.pubkey.to_hex(),
+        "event_id": tag_values(request, "e").first(),
+        "comment": request.content,
+    })
+}
+
+/// Values of every `name` tag of `event`
+fn tag_values<'a>(event: &'a Event, name: &str) -> Vec<&'a str> {
+    event
+        .tags
+        .iter()
+        .filter_map(|tag| match tag.as_slice() {
+            [tag_name, value, ..] if tag_name == name => Some(value.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Checks `request` the way NIP-57 asks the recipient to, for a payment of `amount_msat` to
+/// `recipient`, and returns the relays its receipt goes to
+fn validate(
+    request: &Event,
+    amount_msat: u64,
+    recipient: &PublicKey,
+) -> Result<Vec<String>, String> {
+    let recipients = tag_values(request, "p");
+    match recipients.as_slice() {
+        [pubkey] if *pubkey == recipient.to_hex() => {}
+        [pubkey] => return Err(format!("it zaps {pubkey}, not this wallet's nostr key")),
+        _ => return Err("it must have exactly one p tag".to_string()),
+    }
+    if tag_values(request, "e").len() > 1 {
+        return Err("it has more than one e tag".to_string());
+    }
+    if let Some(amount) = tag_values(request, "amount").first() {
+        if amount.parse::<u64>().ok() != Some(amount_msat) {
+            return Err(format!(
+                "it asks for {amount} msat, {amount_msat} were paid"
+            ));
+        }
+    }
+    let relays: Vec<String> = request
+        .tags
+        .iter()
+        .find(|tag| tag.as_slice().first().is_some_and(|name| name == "relays"))
+        .map(|tag| tag.as_slice()[1..].to_vec())
+        .unwrap_or_default();
+    if relays.is_empty() {
+        return Err("it lists no relays".to_string());
+    }
+    Ok(relays.into_iter().take(MAX_REQUEST_RELAYS).collect())
+}
+
+/// Publishes zap receipts signed with the `[nostr]` key
+pub struct Zaps {
+    client: Client,
+    pubkey: PublicKey,
+    relays: Vec<String>,
+}
+
+impl Zaps {
+    /// Connects to the `[zaps]` relays. A zap request's own relays are added as receipts go out.
+    pub async fn connect(secret_key: &str, relays: &[String]) -> Result<Self, String> {
+        let keys =
+            Keys::parse(secret_key).map_err(|e| format!("Invalid [nostr] secret_key: {e}"))?;
+        let pubkey = keys.public_key();
+        let client = Client::new(keys);
+        for relay in relays {
+            client
+                .add_relay(relay.as_str())
+                .await
+                .map_err(|e| format!("Invalid [zaps] relay {relay}: {e}"))?;
+        }
+        client.connect().await;
+        Ok(Zaps {
+            client,
+            pubkey,
+            relays: relays.to_vec(),
+        })
+    }
+
+    pub fn pubkey(&self) -> String {
+        self.pubkey.to_hex()
+    }
+
+    /// Validates `request` for a payment of `amount_msat` and publishes its receipt in the
+    /// background. `bolt11` is the paid invoice, empty for keysend, and must commit to the
+    /// request as its description hash.
+    pub fn publish_receipt(
+        &self,
+        request: &Event,
+        bolt11: &str,
+        amount_msat: u64,
+    ) -> Result<(), String> {
+        let mut relays = validate(request, amount_msat, &self.pubkey)?;
+        relays.extend(self.relays.iter().cloned());
+        relays.sort();
+        relays.dedup();
+
+        let receipt = EventBuilder::zap_receipt(bolt11, None::<String>, request);
+        let client = self.client.clone();
+        let request_id = request.id.to_hex();
+        tokio::spawn(async move {
+            let mut added = Vec::new();
+            for relay in relays {
+                if let Err(e) = client.add_relay(relay.as_str()).await {
+                    warn!("Skipping zap relay {relay}: {e}");
+                    continue;
+                }
+                let _ = client.connect_relay(relay.as_str()).await;
+                added.push(relay);
+            }
+            match client.send_event_builder_to(&added, receipt).await {
+                Ok(output) => info!(
+                    { "zap_request": request_id, "zap_receipt": output.val.to_hex() },
+                    "Published zap receipt {} to {} relays",
+                    output.val.to_hex(),
+                    output.success.len()
+                ),
+                Err(e) => warn!(
+                    { "zap_request": request_id },
+                    "Failed to publish zap receipt for {request_id}: {e}"
+                ),
+            }
+        });
+        Ok(())
+    }
+}